@@ -0,0 +1,290 @@
+#[cfg(test)]
+mod tests;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use crate::channel::{self, Input, Seed};
+use crate::message::{Connectivity, Event, Message, Reply};
+use crate::user::User;
+
+const DEFAULT_USERNAME: &str = "admin";
+
+// For poking at a running bot from the box it's on: connect with something
+// like `socat - UNIX-CONNECT:/path/to/socket`, and type. Anyone who can get
+// at the socket is trusted completely, so only we can: it's made readable
+// and writable by our user and nobody else.
+//
+// Every connection is its own conversation (and its own person, as far as
+// addresses go), so more than one person can be in at once without seeing
+// each other's replies. They all act as the same master user, though.
+pub struct AdminSocket {
+    pub name: String,
+    to_hub: mpsc::Sender<Message>,
+    from_hub: Option<mpsc::Receiver<Message>>, // until forward_hub takes it
+    path: PathBuf,
+    user: User,
+
+    // conversation address => where to write replies
+    sessions: HashMap<String, UnixStream>,
+}
+
+// What the listener and sessions have to say to the rest of the channel.
+pub enum FromSocket {
+    Opened(String, UnixStream),
+    Line(String, String),
+    Closed(String),
+}
+
+pub fn build(seed: Seed) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut channel = self::new(seed);
+        channel.start();
+    })
+}
+
+pub fn new(seed: Seed) -> AdminSocket {
+    let extra = &seed.config.extra;
+
+    let path = extra
+        .get("socket_path")
+        .and_then(|v| v.as_str())
+        .expect("no socket_path for admin socket in config!");
+
+    let username = extra
+        .get("username")
+        .and_then(|v| v.as_str())
+        .unwrap_or(DEFAULT_USERNAME);
+
+    AdminSocket {
+        name: seed.name.clone(),
+        to_hub: seed.output,
+        from_hub: Some(seed.input),
+        path: PathBuf::from(path),
+        user: User {
+            username: username.to_string(),
+            lp_id: None,
+            is_master: true,
+            is_virtual: true,
+            is_deleted: false,
+        },
+        sessions: HashMap::new(),
+    }
+}
+
+impl AdminSocket {
+    fn start(&mut self) {
+        let (inbox_tx, inbox) = mpsc::channel();
+
+        channel::forward_hub(self.from_hub.take().unwrap(), inbox_tx.clone());
+
+        let listener = bind(&self.path)
+            .unwrap_or_else(|e| panic!("couldn't listen on {}: {}", self.path.display(), e));
+
+        info!("listening for admins on {}", self.path.display());
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let accepter = accept(listener, Arc::clone(&stop), inbox_tx);
+
+        channel::set_connectivity(&self.to_hub, &self.name, Connectivity::Connected);
+
+        for input in inbox {
+            match input {
+                Input::Hub(Message::Reply(reply)) => self.send_reply(reply),
+                Input::Hub(Message::Hangup) => break,
+                Input::Hub(_) => (),
+                Input::Remote(FromSocket::Opened(address, mut stream)) => {
+                    info!("admin connected as {}", address);
+
+                    let banner = format!(
+                        "Hello, {}. You're in {}; say something.\n",
+                        self.user.username, address
+                    );
+
+                    stream.write_all(banner.as_bytes()).unwrap_or(());
+                    self.sessions.insert(address, stream);
+                }
+                Input::Remote(FromSocket::Line(address, text)) => {
+                    self.handle_line(address, text);
+                }
+                Input::Remote(FromSocket::Closed(address)) => {
+                    info!("admin {} went away", address);
+                    self.sessions.remove(&address);
+                }
+            }
+        }
+
+        // accept() won't look at the flag until somebody connects, so we do.
+        stop.store(true, Ordering::SeqCst);
+        UnixStream::connect(&self.path).map(drop).unwrap_or(());
+        accepter.join().unwrap_or(());
+
+        for (_, stream) in self.sessions.drain() {
+            stream.shutdown(std::net::Shutdown::Both).unwrap_or(());
+        }
+
+        fs::remove_file(&self.path).unwrap_or(());
+    }
+
+    fn handle_line(&mut self, address: String, text: String) {
+        let text = text.trim();
+
+        if text.is_empty() {
+            return;
+        }
+
+        if text == "/quit" {
+            if let Some(stream) = self.sessions.remove(&address) {
+                stream.shutdown(std::net::Shutdown::Both).unwrap_or(());
+            }
+            return;
+        }
+
+        // The hub would look up who this is, but we already know.
+        let event = Event {
+            was_targeted: true,
+            user: Some(self.user.clone()),
+            ..Event::new(&self.name, &address, &address, text)
+        };
+
+        self.to_hub.send(Message::Event(Arc::new(event))).unwrap();
+    }
+
+    // Every conversation is private already, so a private reply just goes to
+    // whoever it's for. If they've gone, so has the reply.
+    pub fn send_reply(&mut self, reply: Reply) {
+        let address = if reply.is_private || reply.is_ephemeral {
+            &reply.from_address
+        } else {
+            &reply.conversation_address
+        };
+
+        let stream = match self.sessions.get_mut(address) {
+            Some(s) => s,
+            None => {
+                warn!(
+                    "no admin at {}; dropping reply from {}",
+                    address, reply.origin
+                );
+                return;
+            }
+        };
+
+        let mut text = reply.text.clone();
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+
+        stream.write_all(text.as_bytes()).unwrap_or(());
+    }
+}
+
+// If there's a socket there already, it's either left over from last time,
+// or someone else is using it; we only want to clobber the first kind (and we
+// won't clobber anything that isn't a socket at all).
+fn bind(path: &Path) -> std::io::Result<UnixListener> {
+    if let Ok(meta) = fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                "something that isn't a socket is in the way",
+            ));
+        }
+
+        if UnixStream::connect(path).is_ok() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AddrInUse,
+                "someone else is listening there",
+            ));
+        }
+
+        fs::remove_file(path)?;
+    }
+
+    // Between binding and chmodding, anyone could connect, so we do both
+    // somewhere only we can get into, and then move the socket into place.
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let private_dir = path.with_file_name(format!(".{}.{}", file_name, process::id()));
+    fs::DirBuilder::new().mode(0o700).create(&private_dir)?;
+
+    let private_path = private_dir.join("socket");
+    let res = UnixListener::bind(&private_path).and_then(|listener| {
+        fs::set_permissions(&private_path, fs::Permissions::from_mode(0o600))?;
+        fs::rename(&private_path, path)?;
+        Ok(listener)
+    });
+
+    let _ = fs::remove_file(&private_path);
+    fs::remove_dir(&private_dir)?;
+
+    res
+}
+
+fn accept(
+    listener: UnixListener,
+    stop: Arc<AtomicBool>,
+    to_channel: mpsc::Sender<Input<FromSocket>>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut count = 0;
+
+        for stream in listener.incoming() {
+            if stop.load(Ordering::SeqCst) {
+                return;
+            }
+
+            let stream = match stream {
+                Ok(s) => s,
+                Err(e) => {
+                    warn!("error accepting admin connection: {}", e);
+                    continue;
+                }
+            };
+
+            count += 1;
+            let address = format!("admin-{}", count);
+
+            let writer = match stream.try_clone() {
+                Ok(w) => w,
+                Err(e) => {
+                    warn!("couldn't clone admin socket: {}", e);
+                    continue;
+                }
+            };
+
+            let opened = FromSocket::Opened(address.clone(), writer);
+            if to_channel.send(Input::Remote(opened)).is_err() {
+                return;
+            }
+
+            let to_channel = to_channel.clone();
+            thread::spawn(move || read_session(address, stream, to_channel));
+        }
+    })
+}
+
+fn read_session(address: String, stream: UnixStream, to_channel: mpsc::Sender<Input<FromSocket>>) {
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(l) => l,
+            Err(_) => break,
+        };
+
+        let msg = FromSocket::Line(address.clone(), line);
+        if to_channel.send(Input::Remote(msg)).is_err() {
+            return;
+        }
+    }
+
+    to_channel
+        .send(Input::Remote(FromSocket::Closed(address)))
+        .unwrap_or(());
+}
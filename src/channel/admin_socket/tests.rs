@@ -0,0 +1,122 @@
+use super::*;
+use crate::channel::testing;
+use crate::channel::Type;
+use std::env;
+use std::time::Duration;
+
+// A running admin channel, and where its socket is.
+type Harness = testing::Harness<Fakes>;
+
+struct Fakes {
+    path: PathBuf,
+}
+
+impl testing::Fakes for Fakes {}
+
+fn start_channel() -> Harness {
+    let path = env::temp_dir().join(format!("synergy-admin-{}.sock", Event::new_id()));
+
+    let config = testing::config(vec![("socket_path", path.to_str().unwrap().into())], vec![]);
+
+    let channel = testing::start(
+        "channel/admin",
+        Type::AdminSocketChannel,
+        config,
+        ":memory:",
+    );
+    channel.expect_connectivity(Connectivity::Connected);
+
+    Harness {
+        channel,
+        fakes: Fakes { path },
+    }
+}
+
+impl Harness {
+    // Connect, and get past the banner.
+    fn connect(&self) -> (UnixStream, BufReader<UnixStream>) {
+        let stream = UnixStream::connect(&self.fakes.path).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut banner = String::new();
+        reader.read_line(&mut banner).unwrap();
+        assert!(banner.starts_with("Hello, admin."), "{:?}", banner);
+
+        (stream, reader)
+    }
+}
+
+fn read_line(reader: &mut BufReader<UnixStream>) -> String {
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    line
+}
+
+#[test]
+fn sessions() {
+    let h = start_channel();
+
+    let mode = fs::metadata(&h.fakes.path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let (mut one, mut one_reader) = h.connect();
+    let (mut two, mut two_reader) = h.connect();
+
+    one.write_all(b"echo from one\n").unwrap();
+    let first = h.next_event();
+    assert_eq!(first.text, "echo from one");
+    assert!(first.was_targeted);
+    assert!(!first.is_public);
+    assert_eq!(first.origin, "channel/admin");
+
+    let user = first.user.as_ref().unwrap();
+    assert_eq!(user.username, "admin");
+    assert!(user.is_master);
+
+    two.write_all(b"\n  \necho from two\n").unwrap();
+    let second = h.next_event();
+    assert_eq!(second.text, "echo from two");
+    assert_ne!(first.conversation_address, second.conversation_address);
+
+    // replies only go where they're meant to
+    h.reply(&second, "for two\nonly");
+    h.reply(&first, "for one");
+
+    assert_eq!(read_line(&mut one_reader), "for one\n");
+    assert_eq!(read_line(&mut two_reader), "for two\n");
+    assert_eq!(read_line(&mut two_reader), "only\n");
+
+    // and /quit is just for us
+    one.write_all(b"/quit\n").unwrap();
+    assert_eq!(read_line(&mut one_reader), "");
+
+    let path = h.hangup().path;
+
+    assert_eq!(read_line(&mut two_reader), "");
+    assert!(!path.exists());
+}
+
+#[test]
+fn stale_sockets() {
+    let path = env::temp_dir().join(format!("synergy-admin-{}.sock", Event::new_id()));
+
+    // one that's still in use is left alone
+    let first = bind(&path).unwrap();
+    assert!(bind(&path).is_err());
+
+    // and one that's left over isn't
+    drop(first);
+    let second = bind(&path).unwrap();
+    drop(second);
+
+    // and neither is anything that isn't a socket
+    fs::remove_file(&path).unwrap();
+    fs::write(&path, "precious").unwrap();
+    assert!(bind(&path).is_err());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "precious");
+
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,31 @@
+use std::time::Duration;
+
+// Exponential backoff, for channels that have to keep trying to reach
+// something that isn't answering. Each call to next_delay() doubles the wait,
+// up to max; reset() once things are working again.
+#[derive(Debug)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+pub fn new(initial: Duration, max: Duration) -> Backoff {
+    Backoff {
+        initial,
+        max,
+        next: initial,
+    }
+}
+
+impl Backoff {
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = std::cmp::min(self.next * 2, self.max);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.next = self.initial;
+    }
+}
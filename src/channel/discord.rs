@@ -0,0 +1,371 @@
+#[cfg(test)]
+mod fake_server;
+mod gateway;
+mod markup;
+mod reader;
+mod rest;
+#[cfg(test)]
+mod tests;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use regex::Regex;
+use serde_json::Value;
+
+use crate::channel::backoff;
+use crate::channel::{self, split, Input, Seed};
+use crate::message::{Connectivity, Event, Message, Reply};
+use gateway::{Gateway, Identity};
+use reader::FromDiscord;
+use rest::Rest;
+
+const DEFAULT_GATEWAY_URL: &str = "wss://gateway.discord.gg";
+const DEFAULT_API_URL: &str = "https://discord.com/api/v10";
+
+// Discord won't take anything longer.
+const MAX_MESSAGE_LENGTH: usize = 2000;
+
+// GUILDS, GUILD_MESSAGES, DIRECT_MESSAGES, and MESSAGE_CONTENT, which is
+// privileged, and has to be turned on for the bot in the developer portal.
+const INTENTS: u64 = (1 << 0) | (1 << 9) | (1 << 12) | (1 << 15);
+
+// Message types worth reading: plain messages, and replies.
+const MESSAGE_TYPES: &[u64] = &[0, 19];
+
+// The permission bit for seeing a channel at all.
+const VIEW_CHANNEL: u64 = 1 << 10;
+
+// Like slack, this has a reader thread (see reader.rs) that owns the gateway
+// websocket, and this one, which turns what comes down it into Events. Replies
+// go out over REST (see rest.rs), from this thread. Everyone and everything
+// is known by id: users, guild channels, and DMs (which are just channels).
+pub struct Discord {
+    pub name: String,
+    gateway: Option<Gateway>, // until the reader takes it
+    rest: Rest,
+    to_hub: mpsc::Sender<Message>,
+    from_hub: Option<mpsc::Receiver<Message>>, // until forward_hub takes it
+
+    // cached data
+    our_id: Option<String>,
+    targeted_re: Regex,
+    users: HashMap<String, String>,    // id => name
+    channels: HashMap<String, String>, // id => name, for guild channels
+    public_channels: HashSet<String>,  // ids of the ones everyone can see
+    roles: HashMap<String, String>,    // id => name
+    dms: HashMap<String, String>,      // user id => channel id
+}
+
+pub fn build(seed: Seed) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut channel = self::new(seed);
+        channel.start();
+    })
+}
+
+pub fn new(seed: Seed) -> Discord {
+    let extra = &seed.config.extra;
+
+    let token = extra
+        .get("token")
+        .and_then(|v| v.as_str())
+        .expect("no token for discord in config!");
+
+    // Discord tells bots where the gateway is (GET /gateway/bot), but it's
+    // been the same place for years; these are settable for the tests'
+    // fake_server, not because it moves.
+    let url_config = |key, default| {
+        extra
+            .get(key)
+            .and_then(|v| v.as_str())
+            .unwrap_or(default)
+            .to_string()
+    };
+
+    let gateway_url = url_config("gateway_url", DEFAULT_GATEWAY_URL);
+    let api_url = url_config("api_url", DEFAULT_API_URL);
+
+    Discord {
+        name: seed.name.clone(),
+        gateway: Some(gateway::new(&gateway_url, token, INTENTS)),
+        rest: rest::new(&api_url, token),
+        to_hub: seed.output,
+        from_hub: Some(seed.input),
+        our_id: None,
+        targeted_re: Regex::new("^$").unwrap(),
+        users: HashMap::new(),
+        channels: HashMap::new(),
+        public_channels: HashSet::new(),
+        roles: HashMap::new(),
+        dms: HashMap::new(),
+    }
+}
+
+// What we'd call someone: their display name if they've set one, or their
+// username if not.
+fn user_name(user: &Value) -> Option<String> {
+    user["global_name"]
+        .as_str()
+        .or_else(|| user["username"].as_str())
+        .map(String::from)
+}
+
+// A guild channel is as public as its permissions say: if the @everyone
+// role (which has the guild's id) isn't kept out, anyone in the guild can
+// read along. If we don't know the guild, we can't tell, so it isn't.
+fn is_visible_to_everyone(channel: &Value, guild_id: Option<&str>) -> bool {
+    let guild_id = match guild_id {
+        Some(id) => id,
+        None => return false,
+    };
+
+    let denied = channel["permission_overwrites"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|o| o["id"].as_str() == Some(guild_id))
+        .filter_map(|o| o["deny"].as_str()?.parse::<u64>().ok())
+        .any(|deny| deny & VIEW_CHANNEL != 0);
+
+    !denied
+}
+
+impl Discord {
+    fn start(&mut self) {
+        let (inbox_tx, inbox) = mpsc::channel();
+
+        channel::forward_hub(self.from_hub.take().unwrap(), inbox_tx.clone());
+
+        let _stop_reader = reader::spawn(
+            self.gateway.take().unwrap(),
+            backoff::new(Duration::from_secs(1), Duration::from_secs(120)),
+            inbox_tx,
+        );
+
+        for input in inbox {
+            match input {
+                Input::Hub(Message::Reply(reply)) => self.send_reply(reply),
+                Input::Hub(Message::Hangup) => break,
+                Input::Hub(_) => (),
+                Input::Remote(FromDiscord::Connected(me)) => {
+                    self.set_identity(me);
+                    channel::set_connectivity(&self.to_hub, &self.name, Connectivity::Connected);
+                }
+                Input::Remote(FromDiscord::Disconnected) => {
+                    channel::set_connectivity(&self.to_hub, &self.name, Connectivity::Disconnected);
+                }
+                Input::Remote(FromDiscord::Event(kind, d)) => self.handle_dispatch(&kind, d),
+            }
+        }
+    }
+
+    fn set_identity(&mut self, me: Identity) {
+        if self.our_id.as_ref() == Some(&me.id) {
+            return;
+        }
+
+        info!("we are {} ({}) on discord", me.name, me.id);
+
+        // Mentions come in as <@id>, which we've decoded by the time this
+        // sees it, but people type the name too.
+        let name = regex::escape(&me.name);
+        self.targeted_re =
+            Regex::new(&format!(r"^(?i)(?:@{}[:,]?|{}[:,])\s*", name, name)).unwrap();
+
+        self.users.insert(me.id.clone(), me.name);
+        self.our_id = Some(me.id);
+    }
+
+    fn handle_dispatch(&mut self, kind: &str, d: Value) {
+        match kind {
+            // This comes once for each guild after we connect, and has just
+            // about everything we need to know about it.
+            "GUILD_CREATE" => {
+                let guild_id = d["id"].as_str();
+                for channel in d["channels"].as_array().into_iter().flatten() {
+                    self.note_channel(channel, guild_id);
+                }
+
+                for role in d["roles"].as_array().into_iter().flatten() {
+                    if let (Some(id), Some(name)) = (role["id"].as_str(), role["name"].as_str()) {
+                        self.roles.insert(id.into(), name.into());
+                    }
+                }
+
+                for member in d["members"].as_array().into_iter().flatten() {
+                    self.note_user(&member["user"]);
+                }
+            }
+            "CHANNEL_CREATE" | "CHANNEL_UPDATE" => self.note_channel(&d, d["guild_id"].as_str()),
+            "CHANNEL_DELETE" => {
+                if let Some(id) = d["id"].as_str() {
+                    self.channels.remove(id);
+                    self.public_channels.remove(id);
+                    self.dms.retain(|_, dm| dm != id);
+                }
+            }
+            "MESSAGE_CREATE" => {
+                if let Some(event) = self.event_from_message(&d) {
+                    self.to_hub.send(Message::Event(Arc::new(event))).unwrap();
+                }
+            }
+            _ => trace!("ignoring discord {}", kind),
+        }
+    }
+
+    fn note_user(&mut self, user: &Value) {
+        if let (Some(id), Some(name)) = (user["id"].as_str(), user_name(user)) {
+            self.users.insert(id.into(), name);
+        }
+    }
+
+    // Channels in a guild's listing don't say which guild they're in, so
+    // that comes separately.
+    fn note_channel(&mut self, channel: &Value, guild_id: Option<&str>) {
+        let id = match channel["id"].as_str() {
+            Some(id) => id.to_string(),
+            None => return,
+        };
+
+        // type 1 is a DM, with one recipient (who isn't us)
+        if channel["type"] == 1 {
+            if let Some(user) = channel["recipients"][0]["id"].as_str() {
+                self.dms.insert(user.into(), id);
+            }
+            return;
+        }
+
+        if is_visible_to_everyone(channel, guild_id) {
+            self.public_channels.insert(id.clone());
+        } else {
+            self.public_channels.remove(&id);
+        }
+
+        if let Some(name) = channel["name"].as_str() {
+            self.channels.insert(id, name.into());
+        }
+    }
+
+    fn event_from_message(&mut self, d: &Value) -> Option<Event> {
+        let author = &d["author"];
+        let author_id = author["id"].as_str()?.to_string();
+
+        // bots (including us) talking to each other is how you get loops
+        if author["bot"].as_bool().unwrap_or(false) || Some(&author_id) == self.our_id.as_ref() {
+            return None;
+        }
+
+        if !MESSAGE_TYPES.contains(&d["type"].as_u64().unwrap_or(0)) {
+            return None;
+        }
+
+        let channel_id = d["channel_id"].as_str()?.to_string();
+        let guild_id = d["guild_id"].as_str().map(String::from);
+
+        // Messages tell us about everyone they mention, so we know their
+        // names before we decode.
+        self.note_user(author);
+        for user in d["mentions"].as_array().into_iter().flatten() {
+            self.note_user(user);
+        }
+
+        // No guild means a DM, which is always meant for us.
+        let is_dm = guild_id.is_none();
+        if is_dm {
+            self.dms.insert(author_id.clone(), channel_id.clone());
+        }
+
+        let mentioned = d["mentions"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .any(|u| u["id"].as_str() == self.our_id.as_deref());
+
+        let mut text = markup::decode(d["content"].as_str().unwrap_or_default(), &self.names());
+        let mut was_targeted = is_dm || mentioned;
+
+        if self.targeted_re.is_match(&text) {
+            text = self.targeted_re.replace(&text, "").to_string();
+            was_targeted = true;
+        }
+
+        Some(Event {
+            is_public: self.public_channels.contains(&channel_id),
+            was_targeted,
+            workspace: guild_id,
+            ..Event::new(&self.name, &author_id, &channel_id, text.trim())
+        })
+    }
+
+    // Private replies go to a DM, which we'll open if we don't know of one.
+    // Long ones go out as a few messages, since Discord won't split them.
+    pub fn send_reply(&mut self, reply: Reply) {
+        let channel_id = if reply.is_private || reply.is_ephemeral {
+            match self.dm_with(&reply.from_address) {
+                Some(id) => id,
+                None => {
+                    error!(
+                        "no dm with {}; dropping private reply from {}",
+                        reply.from_address, reply.origin
+                    );
+                    return;
+                }
+            }
+        } else {
+            reply.conversation_address.clone()
+        };
+
+        for text in split::split(&reply.text, MAX_MESSAGE_LENGTH) {
+            // Rate limits are handled in rest, so what's left is mostly
+            // missing permissions in this channel, and those won't be any
+            // different for the next piece.
+            if let Err(e) = self.rest.create_message(&channel_id, &text) {
+                error!("couldn't send to discord channel {}: {}", channel_id, e);
+                return;
+            }
+        }
+    }
+
+    fn dm_with(&mut self, user_id: &str) -> Option<String> {
+        if let Some(id) = self.dms.get(user_id) {
+            return Some(id.clone());
+        }
+
+        match self.rest.create_dm(user_id) {
+            Ok(id) => {
+                self.dms.insert(user_id.into(), id.clone());
+                Some(id)
+            }
+            Err(e) => {
+                warn!("couldn't open a dm with {}: {}", user_id, e);
+                None
+            }
+        }
+    }
+
+    fn names(&self) -> Directory<'_> {
+        Directory { discord: self }
+    }
+}
+
+// what the markup decoder gets to know about the guild
+struct Directory<'a> {
+    discord: &'a Discord,
+}
+
+impl markup::Names for Directory<'_> {
+    fn user_name(&self, id: &str) -> Option<String> {
+        self.discord.users.get(id).cloned()
+    }
+
+    fn channel_name(&self, id: &str) -> Option<String> {
+        self.discord.channels.get(id).cloned()
+    }
+
+    fn role_name(&self, id: &str) -> Option<String> {
+        self.discord.roles.get(id).cloned()
+    }
+}
@@ -0,0 +1,280 @@
+// A pretend discord, for tests: a gateway (via tungstenite's server half) that
+// says HELLO to everyone who connects and acks their heartbeats, and a REST
+// API (over tiny_http) that records what it's asked to do. Tests play the
+// rest of discord's part by hand: READY, dispatches, hanging up. Point a
+// channel's gateway_url and api_url at these and off you go.
+
+use std::collections::VecDeque;
+use std::io::ErrorKind::WouldBlock;
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use tiny_http::Server;
+use tungstenite::protocol::frame::coding::CloseCode;
+use tungstenite::protocol::frame::CloseFrame;
+
+use crate::channel::testing;
+
+pub const BOT_ID: &str = "100";
+pub const BOT_NAME: &str = "synergy";
+pub const TOKEN: &str = "sekrit";
+
+// One call to the REST API, as we received it.
+#[derive(Debug, Clone)]
+pub struct Call {
+    pub method: String,
+    pub path: String,
+    pub body: Value,
+    pub auth: Option<String>,
+    pub at: Instant, // when it showed up
+}
+
+#[derive(Debug, Clone)]
+pub struct Canned {
+    pub status: u16,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Value,
+}
+
+enum Outgoing {
+    Frame(Value),
+    Close(u16),
+    Drop,
+}
+
+pub struct FakeDiscord {
+    pub gateway_url: String,
+    pub api_url: String,
+    frames_in: mpsc::Receiver<Value>,
+    frames_out: mpsc::Sender<Outgoing>,
+    calls: mpsc::Receiver<Call>,
+    canned: Arc<Mutex<VecDeque<Canned>>>,
+    acking: Arc<AtomicBool>,
+    seq: AtomicU64,
+}
+
+pub fn start(heartbeat_interval: u64) -> FakeDiscord {
+    let ws_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let gateway_url = format!("ws://{}", ws_listener.local_addr().unwrap());
+
+    let server = Server::http("127.0.0.1:0").unwrap();
+    let api_url = format!("http://{}/api/v10", server.server_addr().to_ip().unwrap());
+
+    let (frames_in_tx, frames_in) = mpsc::channel();
+    let (frames_out, frames_out_rx) = mpsc::channel();
+    let (calls_tx, calls) = mpsc::channel();
+    let canned = Arc::new(Mutex::new(VecDeque::new()));
+    let acking = Arc::new(AtomicBool::new(true));
+
+    let http_canned = Arc::clone(&canned);
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            serve_api(request, &http_canned, &calls_tx);
+        }
+    });
+
+    let ws_acking = Arc::clone(&acking);
+    thread::spawn(move || {
+        serve_gateway(
+            ws_listener,
+            heartbeat_interval,
+            &ws_acking,
+            frames_in_tx,
+            frames_out_rx,
+        )
+    });
+
+    FakeDiscord {
+        gateway_url,
+        api_url,
+        frames_in,
+        frames_out,
+        calls,
+        canned,
+        acking,
+        seq: AtomicU64::new(0),
+    }
+}
+
+impl FakeDiscord {
+    // Wait for the client to send this op, skipping any others.
+    pub fn recv_op(&self, op: u64) -> Option<Value> {
+        loop {
+            match self.frames_in.recv_timeout(Duration::from_secs(5)) {
+                Ok(frame) if frame["op"] == op => return Some(frame),
+                Ok(_) => continue,
+                Err(_) => return None,
+            }
+        }
+    }
+
+    pub fn send(&self, frame: Value) {
+        self.frames_out.send(Outgoing::Frame(frame)).unwrap();
+    }
+
+    // Send a dispatch, with the next sequence number.
+    pub fn dispatch(&self, kind: &str, d: Value) {
+        let seq = self.seq.fetch_add(1, Ordering::SeqCst) + 1;
+        self.send(json!({ "op": 0, "t": kind, "s": seq, "d": d }));
+    }
+
+    pub fn ready(&self, session_id: &str) {
+        self.dispatch(
+            "READY",
+            json!({
+                "v": 10,
+                "session_id": session_id,
+                "resume_gateway_url": self.gateway_url,
+                "user": { "id": BOT_ID, "username": BOT_NAME, "bot": true },
+                "guilds": [],
+            }),
+        );
+    }
+
+    pub fn last_seq(&self) -> u64 {
+        self.seq.load(Ordering::SeqCst)
+    }
+
+    // Hang up properly, with a close code.
+    pub fn close(&self, code: u16) {
+        self.frames_out.send(Outgoing::Close(code)).unwrap();
+    }
+
+    // Hang up without saying anything, the way a network might.
+    pub fn drop_connection(&self) {
+        self.frames_out.send(Outgoing::Drop).unwrap();
+    }
+
+    pub fn set_acking(&self, acking: bool) {
+        self.acking.store(acking, Ordering::SeqCst);
+    }
+
+    // Answer the next REST call with this, instead of the default.
+    pub fn respond_with(&self, canned: Canned) {
+        self.canned.lock().unwrap().push_back(canned);
+    }
+
+    pub fn recv_call(&self) -> Option<Call> {
+        self.calls.recv_timeout(Duration::from_secs(5)).ok()
+    }
+}
+
+fn serve_api(
+    mut request: tiny_http::Request,
+    canned: &Mutex<VecDeque<Canned>>,
+    calls: &mpsc::Sender<Call>,
+) {
+    let at = Instant::now();
+
+    let mut body = String::new();
+    request.as_reader().read_to_string(&mut body).unwrap();
+
+    let call = Call {
+        method: request.method().to_string(),
+        path: request.url().trim_start_matches("/api/v10").to_string(),
+        body: serde_json::from_str(&body).unwrap_or(Value::Null),
+        auth: request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Authorization"))
+            .map(|h| h.value.as_str().to_string()),
+        at,
+    };
+
+    let response = canned.lock().unwrap().pop_front().unwrap_or_else(|| {
+        let body = if call.path == "/users/@me/channels" {
+            let recipient = call.body["recipient_id"].as_str().unwrap_or_default();
+            json!({ "id": format!("dm-{}", recipient), "type": 1 })
+        } else {
+            json!({ "id": "1", "content": call.body["content"] })
+        };
+
+        Canned {
+            status: 200,
+            headers: vec![],
+            body,
+        }
+    });
+
+    // (unless it's done with us, and hung up)
+    let _ = calls.send(call);
+
+    testing::respond_json(request, response.status, response.headers, &response.body);
+}
+
+// We only ever expect one client at a time, but it'll reconnect, so keep
+// accepting.
+fn serve_gateway(
+    listener: TcpListener,
+    heartbeat_interval: u64,
+    acking: &AtomicBool,
+    frames_in: mpsc::Sender<Value>,
+    frames_out: mpsc::Receiver<Outgoing>,
+) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+
+        let mut ws = match tungstenite::accept(stream) {
+            Ok(ws) => ws,
+            Err(_) => continue,
+        };
+
+        ws.get_mut()
+            .set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+
+        let hello = json!({ "op": 10, "d": { "heartbeat_interval": heartbeat_interval } });
+        if ws
+            .write_message(tungstenite::Message::Text(hello.to_string()))
+            .is_err()
+        {
+            continue;
+        }
+
+        'connection: loop {
+            while let Ok(outgoing) = frames_out.try_recv() {
+                let res = match outgoing {
+                    Outgoing::Frame(frame) => {
+                        ws.write_message(tungstenite::Message::Text(frame.to_string()))
+                    }
+                    Outgoing::Close(code) => ws.close(Some(CloseFrame {
+                        code: CloseCode::from(code),
+                        reason: "".into(),
+                    })),
+                    Outgoing::Drop => break 'connection,
+                };
+
+                if res.is_err() {
+                    break 'connection;
+                }
+            }
+
+            match ws.read_message() {
+                Ok(tungstenite::Message::Text(s)) => {
+                    let frame: Value = match serde_json::from_str(&s) {
+                        Ok(v) => v,
+                        Err(_) => continue,
+                    };
+
+                    if frame["op"] == 1 && acking.load(Ordering::SeqCst) {
+                        let ack = json!({ "op": 11 }).to_string();
+                        let _ = ws.write_message(tungstenite::Message::Text(ack));
+                    }
+
+                    let _ = frames_in.send(frame);
+                }
+                Ok(tungstenite::Message::Close(_)) => break,
+                Ok(_) => (),
+                Err(tungstenite::Error::Io(ref e)) if e.kind() == WouldBlock => (),
+                Err(_) => break,
+            }
+        }
+    }
+}
@@ -0,0 +1,343 @@
+use std::error::Error;
+use std::fmt;
+use std::io::ErrorKind::WouldBlock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+type Websocket = tungstenite::protocol::WebSocket<tungstenite::client::AutoStream>;
+
+// Reads only time out so that we get a chance to heartbeat (and notice if
+// we've been asked to stop); nothing else is waiting on them.
+const READ_TIMEOUT: Duration = Duration::from_millis(250);
+
+// how long we'll wait for HELLO before giving up on a connection
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Discord says to wait a bit before identifying again after a session's been
+// invalidated, or it might just do it again.
+const INVALID_SESSION_DELAY: Duration = Duration::from_secs(1);
+
+// Close codes that mean something's wrong with us, not the connection. We'll
+// keep trying (someone might fix the config), but it's worth shouting about.
+const FATAL_CLOSE_CODES: &[u16] = &[4004, 4010, 4011, 4012, 4013, 4014];
+
+// Close codes after which the session is gone, and we have to start over.
+const SESSION_CLOSE_CODES: &[u16] = &[4007, 4009];
+
+// The gateway is a websocket that Discord sends events down. Once it's said
+// HELLO, we identify (or resume, if we have a session to go back to), and
+// then heartbeat every so often to prove we're still here; if it stops
+// acknowledging the heartbeats, the connection's dead even if the socket
+// isn't. We never send anything else, since replies go out over REST.
+//
+// https://discord.com/developers/docs/topics/gateway
+pub struct Gateway {
+    url: String,
+    token: String,
+    intents: u64,
+    ws: Option<Websocket>,
+    heartbeat_interval: Duration,
+    last_heartbeat: Instant,
+    awaiting_ack: bool,
+    seq: Option<u64>, // of the last dispatch, which heartbeats and resumes need
+    session: Option<Session>,
+    me: Option<Identity>,
+}
+
+#[derive(Debug, Clone)]
+struct Session {
+    id: String,
+    resume_url: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub id: String,
+    pub name: String,
+}
+
+// What you get back from recv(). Ready comes after both identifying and
+// resuming; Disconnected means you need to connect() again.
+pub enum Incoming {
+    Ready(Identity),
+    Dispatch(String, Value),
+    Nothing,
+    Disconnected,
+}
+
+#[derive(Debug)]
+pub struct GatewayError(String);
+
+impl Error for GatewayError {}
+
+impl fmt::Display for GatewayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct Payload {
+    op: u64,
+    #[serde(default)]
+    d: Value,
+    s: Option<u64>,
+    t: Option<String>,
+}
+
+pub fn new(url: &str, token: &str, intents: u64) -> Gateway {
+    Gateway {
+        url: url.trim_end_matches('/').to_string(),
+        token: token.to_string(),
+        intents,
+        ws: None,
+        heartbeat_interval: Duration::from_secs(45),
+        last_heartbeat: Instant::now(),
+        awaiting_ack: false,
+        seq: None,
+        session: None,
+        me: None,
+    }
+}
+
+impl Gateway {
+    pub fn connect(&mut self) -> Result<(), Box<dyn Error>> {
+        self.ws = None;
+        self.awaiting_ack = false;
+
+        // Resuming has to happen wherever READY told us to.
+        let base = match &self.session {
+            Some(session) => &session.resume_url,
+            None => &self.url,
+        };
+
+        let url = format!("{}/?v=10&encoding=json", base.trim_end_matches('/'));
+        let (mut ws, _resp) = tungstenite::client::connect(url.as_str())?;
+
+        let timeout = Some(READ_TIMEOUT);
+        match ws.get_mut() {
+            tungstenite::stream::Stream::Plain(stream) => stream.set_read_timeout(timeout)?,
+            tungstenite::stream::Stream::Tls(stream) => {
+                stream.get_mut().set_read_timeout(timeout)?
+            }
+        };
+
+        let hello = read_hello(&mut ws)?;
+        self.heartbeat_interval = Duration::from_millis(
+            hello["heartbeat_interval"]
+                .as_u64()
+                .ok_or_else(|| GatewayError("no heartbeat_interval in HELLO".into()))?,
+        );
+
+        // Discord would rather everyone's first heartbeat didn't land at
+        // once; halfway is jitter enough.
+        self.last_heartbeat = Instant::now()
+            .checked_sub(self.heartbeat_interval / 2)
+            .unwrap_or_else(Instant::now);
+
+        let hello_back = match (&self.session, self.seq) {
+            (Some(session), Some(seq)) => {
+                info!("resuming discord session {}", session.id);
+                json!({
+                    "op": 6,
+                    "d": { "token": self.token, "session_id": session.id, "seq": seq },
+                })
+            }
+            _ => json!({
+                "op": 2,
+                "d": {
+                    "token": self.token,
+                    "intents": self.intents,
+                    "properties": { "os": std::env::consts::OS, "browser": "synergy", "device": "synergy" },
+                },
+            }),
+        };
+
+        ws.write_message(tungstenite::Message::Text(hello_back.to_string()))?;
+        self.ws = Some(ws);
+
+        Ok(())
+    }
+
+    pub fn recv(&mut self) -> Incoming {
+        if self.ws.is_none() {
+            return Incoming::Disconnected;
+        }
+
+        if !self.heartbeat() {
+            warn!("discord stopped acking our heartbeats; reconnecting");
+            return self.disconnect();
+        }
+
+        let ws = self.ws.as_mut().unwrap();
+
+        let text = match ws.read_message() {
+            Ok(tungstenite::Message::Text(s)) => s,
+            Ok(tungstenite::Message::Close(frame)) => {
+                let code: u16 = frame.as_ref().map_or(0, |f| (&f.code).into());
+                return self.closed(code);
+            }
+            Ok(_) => return Incoming::Nothing,
+            Err(tungstenite::error::Error::Io(ref e)) if e.kind() == WouldBlock => {
+                return Incoming::Nothing;
+            }
+            Err(e) => {
+                info!("error reading from discord gateway: {:?}", e);
+                return self.disconnect();
+            }
+        };
+
+        let payload: Payload = match serde_json::from_str(&text) {
+            Ok(p) => p,
+            Err(e) => {
+                trace!("error deserializing gateway payload {}: {}", text, e);
+                return Incoming::Nothing;
+            }
+        };
+
+        if payload.s.is_some() {
+            self.seq = payload.s;
+        }
+
+        match payload.op {
+            0 => self.dispatch(payload.t.unwrap_or_default(), payload.d),
+            1 => {
+                // they want one now
+                self.last_heartbeat = Instant::now()
+                    .checked_sub(self.heartbeat_interval)
+                    .unwrap_or_else(Instant::now);
+                self.awaiting_ack = false;
+                Incoming::Nothing
+            }
+            7 => {
+                info!("discord asked us to reconnect");
+                self.disconnect()
+            }
+            9 => {
+                // d says whether the session's worth resuming
+                if !payload.d.as_bool().unwrap_or(false) {
+                    info!("discord invalidated our session; starting over");
+                    self.forget_session();
+                }
+                thread::sleep(INVALID_SESSION_DELAY);
+                self.disconnect()
+            }
+            11 => {
+                self.awaiting_ack = false;
+                Incoming::Nothing
+            }
+            other => {
+                trace!("ignoring gateway op {}", other);
+                Incoming::Nothing
+            }
+        }
+    }
+
+    fn dispatch(&mut self, kind: String, d: Value) -> Incoming {
+        match kind.as_str() {
+            "READY" => {
+                let me = Identity {
+                    id: d["user"]["id"].as_str().unwrap_or_default().to_string(),
+                    name: d["user"]["username"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                };
+
+                self.session = match (d["session_id"].as_str(), d["resume_gateway_url"].as_str()) {
+                    (Some(id), Some(url)) => Some(Session {
+                        id: id.to_string(),
+                        resume_url: url.to_string(),
+                    }),
+                    // we can still go, but we can't come back
+                    _ => None,
+                };
+
+                self.me = Some(me.clone());
+                Incoming::Ready(me)
+            }
+            "RESUMED" => match &self.me {
+                Some(me) => Incoming::Ready(me.clone()),
+                None => Incoming::Nothing,
+            },
+            _ => Incoming::Dispatch(kind, d),
+        }
+    }
+
+    // Send a heartbeat if it's time, and return false if the last one never
+    // got acked.
+    fn heartbeat(&mut self) -> bool {
+        if self.last_heartbeat.elapsed() < self.heartbeat_interval {
+            return true;
+        }
+
+        if self.awaiting_ack {
+            return false;
+        }
+
+        let beat = json!({ "op": 1, "d": self.seq }).to_string();
+        let ws = self.ws.as_mut().unwrap();
+
+        if let Err(e) = ws.write_message(tungstenite::Message::Text(beat)) {
+            info!("error sending heartbeat to discord: {:?}", e);
+            return false;
+        }
+
+        self.last_heartbeat = Instant::now();
+        self.awaiting_ack = true;
+        true
+    }
+
+    fn closed(&mut self, code: u16) -> Incoming {
+        if FATAL_CLOSE_CODES.contains(&code) {
+            error!(
+                "discord closed the gateway with {}; check the token and intents",
+                code
+            );
+        } else {
+            info!("discord closed the gateway ({})", code);
+        }
+
+        if SESSION_CLOSE_CODES.contains(&code) || FATAL_CLOSE_CODES.contains(&code) {
+            self.forget_session();
+        }
+
+        self.disconnect()
+    }
+
+    // Just dropping the socket (rather than closing it properly) means the
+    // session lives on, for us to resume.
+    fn disconnect(&mut self) -> Incoming {
+        self.ws = None;
+        Incoming::Disconnected
+    }
+
+    fn forget_session(&mut self) {
+        self.session = None;
+        self.seq = None;
+    }
+}
+
+fn read_hello(ws: &mut Websocket) -> Result<Value, Box<dyn Error>> {
+    let started = Instant::now();
+
+    while started.elapsed() < HELLO_TIMEOUT {
+        let text = match ws.read_message() {
+            Ok(tungstenite::Message::Text(s)) => s,
+            Ok(_) => continue,
+            Err(tungstenite::error::Error::Io(ref e)) if e.kind() == WouldBlock => continue,
+            Err(e) => return Err(Box::new(e)),
+        };
+
+        let payload: Payload = serde_json::from_str(&text)?;
+
+        if payload.op == 10 {
+            return Ok(payload.d);
+        }
+    }
+
+    Err(Box::new(GatewayError("discord never said hello".into())))
+}
@@ -0,0 +1,97 @@
+use chrono::{TimeZone, Utc};
+use regex::{Captures, Regex};
+
+// Discord messages are markdown, plus angle-bracketed bits for the things
+// that aren't text: <@123> (or <@!123>) for users, <#123> for channels,
+// <@&123> for roles, <:name:123> for custom emoji, and <t:1618953630:R> for
+// timestamps. decode() turns those into what a person would have seen, so
+// reactors don't have to know about any of it.
+
+// The bits of the guild directory we need to do that. The channel implements
+// this on top of its caches; tests do it with a couple of hashmaps.
+pub trait Names {
+    fn user_name(&self, id: &str) -> Option<String>;
+    fn channel_name(&self, id: &str) -> Option<String>;
+    fn role_name(&self, id: &str) -> Option<String>;
+}
+
+lazy_static! {
+    static ref TOKEN_RE: Regex =
+        Regex::new(r"<(@!?|@&|#|a?:\w+:|t:)(\d+)(?::[tTdDfFR])?>").unwrap();
+}
+
+pub fn decode(text: &str, names: &dyn Names) -> String {
+    TOKEN_RE
+        .replace_all(text, |caps: &Captures| {
+            let id = &caps[2];
+
+            match &caps[1] {
+                "@" | "@!" => format!("@{}", names.user_name(id).unwrap_or_else(|| id.into())),
+                "@&" => format!("@{}", names.role_name(id).unwrap_or_else(|| id.into())),
+                "#" => format!("#{}", names.channel_name(id).unwrap_or_else(|| id.into())),
+                // if it's not a time we can show, leave it as it came
+                "t:" => id
+                    .parse::<i64>()
+                    .ok()
+                    .and_then(|ts| Utc.timestamp_opt(ts, 0).single())
+                    .map(|t| t.format("%Y-%m-%d %H:%M UTC").to_string())
+                    .unwrap_or_else(|| caps[0].to_string()),
+                // :name: is what you'd type to get it, so that's what it is
+                emoji => format!(":{}:", emoji.trim_start_matches('a').trim_matches(':')),
+            }
+        })
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    struct TestNames(HashMap<&'static str, &'static str>);
+
+    impl Names for TestNames {
+        fn user_name(&self, id: &str) -> Option<String> {
+            self.0.get(id).map(|s| s.to_string())
+        }
+        fn channel_name(&self, id: &str) -> Option<String> {
+            self.0.get(id).map(|s| s.to_string())
+        }
+        fn role_name(&self, id: &str) -> Option<String> {
+            self.0.get(id).map(|s| s.to_string())
+        }
+    }
+
+    #[test]
+    fn decoding() {
+        let names = TestNames(
+            vec![("1001", "alice"), ("2001", "general"), ("3001", "mods")]
+                .into_iter()
+                .collect(),
+        );
+
+        let cases = vec![
+            ("hello", "hello"),
+            ("<@1001> hi", "@alice hi"),
+            ("<@!1001> hi", "@alice hi"),
+            ("<@9999> hi", "@9999 hi"),
+            ("see <#2001>", "see #general"),
+            ("ping <@&3001>", "ping @mods"),
+            ("nice <:blobcat:123456>", "nice :blobcat:"),
+            ("nice <a:partyblob:123456>", "nice :partyblob:"),
+            ("at <t:1618953630:R>", "at 2021-04-20 21:20 UTC"),
+            ("at <t:1618953630>", "at 2021-04-20 21:20 UTC"),
+            ("at <t:99999999999999999:R>", "at <t:99999999999999999:R>"),
+            (
+                "at <t:999999999999999999999>",
+                "at <t:999999999999999999999>",
+            ),
+            ("1 <2 and 3> 2", "1 <2 and 3> 2"),
+            ("<@alice>", "<@alice>"),
+        ];
+
+        for (text, want) in cases {
+            assert_eq!(decode(text, &names), want, "decoding {:?}", text);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::sync::mpsc;
+use std::thread;
+
+use serde_json::Value;
+
+use crate::channel::backoff::Backoff;
+use crate::channel::{self, Input};
+
+use super::gateway::{Gateway, Identity, Incoming};
+
+// What the reader has to say to the rest of the channel. Connected comes
+// after a resume as well as a fresh start.
+pub enum FromDiscord {
+    Connected(Identity),
+    Event(String, Value), // the dispatch's name and data
+    Disconnected,
+}
+
+// The reader owns the gateway, and does nothing but (re)connect and pass
+// along whatever comes in, so it can block on reads as long as it likes.
+pub fn spawn(
+    mut gateway: Gateway,
+    mut backoff: Backoff,
+    to_channel: mpsc::Sender<Input<FromDiscord>>,
+) -> mpsc::Sender<()> {
+    channel::spawn_reader(move |should_stop| loop {
+        loop {
+            if should_stop() {
+                return;
+            }
+
+            match gateway.connect() {
+                Ok(()) => break,
+                Err(e) => {
+                    let delay = backoff.next_delay();
+                    warn!(
+                        "error connecting to discord ({}); retrying in {:?}",
+                        e, delay
+                    );
+                    thread::sleep(delay);
+                }
+            }
+        }
+
+        let mut was_ready = false;
+
+        loop {
+            if should_stop() {
+                return;
+            }
+
+            let msg = match gateway.recv() {
+                // Only now do we know discord will have us; a connection
+                // that gets closed straight away shouldn't reset anything.
+                Incoming::Ready(me) => {
+                    backoff.reset();
+                    was_ready = true;
+                    FromDiscord::Connected(me)
+                }
+                Incoming::Dispatch(kind, d) => FromDiscord::Event(kind, d),
+                Incoming::Nothing => continue,
+                Incoming::Disconnected => FromDiscord::Disconnected,
+            };
+
+            let disconnected = matches!(msg, FromDiscord::Disconnected);
+
+            if to_channel.send(Input::Remote(msg)).is_err() {
+                return;
+            }
+
+            // A connection that worked is worth going straight back to
+            // (that's what resuming is for); one that didn't, less so.
+            if disconnected {
+                if !was_ready {
+                    thread::sleep(backoff.next_delay());
+                }
+                break;
+            }
+        }
+    })
+}
@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use reqwest::blocking::{Client, Response};
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+
+// How many times we'll get told to slow down about one request before we
+// give up on it.
+const MAX_ATTEMPTS: usize = 3;
+
+// The REST API, or the two bits of it we need: sending messages and opening
+// DMs. Discord rate limits every route separately, and tells us about it in
+// headers on every response, so we keep track and wait our turn rather than
+// getting a 429 (though if we get one anyway, we wait that out too).
+//
+// https://discord.com/developers/docs/topics/rate-limits
+pub struct Rest {
+    api_url: String,
+    token: String,
+    http: Client,
+    limits: HashMap<String, Limit>, // route => what's left of it
+    global_reset: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Limit {
+    remaining: u64,
+    reset_at: Instant,
+}
+
+#[derive(Debug)]
+pub struct RestError(String);
+
+impl Error for RestError {}
+
+impl fmt::Display for RestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+pub fn new(api_url: &str, token: &str) -> Rest {
+    Rest {
+        api_url: api_url.trim_end_matches('/').to_string(),
+        token: token.to_string(),
+        http: Client::new(),
+        limits: HashMap::new(),
+        global_reset: None,
+    }
+}
+
+impl Rest {
+    // Nobody gets pinged by accident: @everyone and role mentions go out as
+    // plain text.
+    pub fn create_message(&mut self, channel_id: &str, content: &str) -> Result<(), RestError> {
+        let body = json!({
+            "content": content,
+            "allowed_mentions": { "parse": ["users"] },
+        });
+
+        let path = format!("/channels/{}/messages", channel_id);
+        self.request(Method::POST, &path, &body)?;
+        Ok(())
+    }
+
+    // Opening a DM that's already open just hands back the same one.
+    pub fn create_dm(&mut self, user_id: &str) -> Result<String, RestError> {
+        let body = json!({ "recipient_id": user_id });
+        let res = self.request(Method::POST, "/users/@me/channels", &body)?;
+
+        res["id"]
+            .as_str()
+            .map(String::from)
+            .ok_or_else(|| RestError("no id for new dm channel".into()))
+    }
+
+    // The route is the method and path, ids and all. Discord's idea of a
+    // route only keeps the channel (or guild) id, but none of our paths have
+    // any others.
+    fn request(&mut self, method: Method, path: &str, body: &Value) -> Result<Value, RestError> {
+        let route = format!("{} {}", method, path);
+
+        for _ in 0..MAX_ATTEMPTS {
+            self.wait_for(&route);
+
+            let res = self
+                .http
+                .request(method.clone(), &format!("{}{}", self.api_url, path))
+                .header("Authorization", format!("Bot {}", self.token))
+                .json(body)
+                .send()
+                .map_err(|e| RestError(e.to_string()))?;
+
+            self.note_limits(&route, &res);
+
+            let status = res.status();
+            let body: Value = res.json().unwrap_or(Value::Null);
+
+            if status.is_success() {
+                return Ok(body);
+            }
+
+            if status != StatusCode::TOO_MANY_REQUESTS {
+                return Err(RestError(format!(
+                    "{} {}: {} ({})",
+                    method,
+                    path,
+                    status,
+                    body["message"].as_str().unwrap_or("no explanation")
+                )));
+            }
+
+            let retry_after = body["retry_after"]
+                .as_f64()
+                .map_or(Duration::from_secs(1), Duration::from_secs_f64);
+            let reset_at = Instant::now() + retry_after;
+
+            if body["global"].as_bool().unwrap_or(false) {
+                warn!(
+                    "discord says we're sending too much; waiting {:?}",
+                    retry_after
+                );
+                self.global_reset = Some(reset_at);
+            } else {
+                debug!("rate limited on {}; waiting {:?}", route, retry_after);
+                self.limits.insert(
+                    route.clone(),
+                    Limit {
+                        remaining: 0,
+                        reset_at,
+                    },
+                );
+            }
+        }
+
+        Err(RestError(format!("still rate limited on {}", route)))
+    }
+
+    fn wait_for(&mut self, route: &str) {
+        let now = Instant::now();
+
+        let mut until = self.global_reset.filter(|&t| t > now);
+
+        if let Some(limit) = self.limits.get(route) {
+            if limit.remaining == 0 && limit.reset_at > now {
+                until = until.max(Some(limit.reset_at));
+            }
+        }
+
+        if let Some(until) = until {
+            thread::sleep(until - now);
+        }
+    }
+
+    fn note_limits(&mut self, route: &str, res: &Response) {
+        let header = |name| {
+            res.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<f64>().ok())
+        };
+
+        if let (Some(remaining), Some(reset_after)) = (
+            header("X-RateLimit-Remaining"),
+            header("X-RateLimit-Reset-After"),
+        ) {
+            self.limits.insert(
+                route.to_string(),
+                Limit {
+                    remaining: remaining as u64,
+                    reset_at: Instant::now() + Duration::from_secs_f64(reset_after),
+                },
+            );
+        }
+    }
+}
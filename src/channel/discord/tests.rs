@@ -0,0 +1,350 @@
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use super::fake_server::{self, Canned, FakeDiscord, BOT_ID, TOKEN};
+use crate::channel::testing;
+use crate::channel::Type;
+use crate::message::{Connectivity, Event};
+
+const GUILD: &str = "500";
+const GENERAL: &str = "600";
+const MODS_ONLY: &str = "601";
+const ALICE: &str = "1001";
+
+// A running discord channel, and the fake discord it's talking to.
+type Harness = testing::Harness<Fakes>;
+
+struct Fakes {
+    discord: FakeDiscord,
+}
+
+impl testing::Fakes for Fakes {}
+
+// Connected, identified, and READY, with one guild that has one channel.
+fn start_channel(heartbeat_interval: u64) -> Harness {
+    let discord = fake_server::start(heartbeat_interval);
+
+    let config = testing::config(
+        vec![
+            ("token", TOKEN.into()),
+            ("gateway_url", discord.gateway_url.clone().into()),
+            ("api_url", discord.api_url.clone().into()),
+        ],
+        vec![],
+    );
+
+    let channel = testing::start("channel/discord", Type::DiscordChannel, config, ":memory:");
+    let h = Harness {
+        channel,
+        fakes: Fakes { discord },
+    };
+
+    let identify = h.fakes.discord.recv_op(2).expect("never identified");
+    assert_eq!(identify["d"]["token"], TOKEN);
+    assert_eq!(identify["d"]["intents"], super::INTENTS);
+
+    h.fakes.discord.ready("session-1");
+    h.expect_connectivity(Connectivity::Connected);
+
+    h.fakes.discord.dispatch(
+        "GUILD_CREATE",
+        json!({
+            "id": GUILD,
+            "channels": [
+                { "id": GENERAL, "name": "general", "type": 0 },
+                {
+                    "id": MODS_ONLY,
+                    "name": "mods-only",
+                    "type": 0,
+                    "permission_overwrites": [
+                        { "id": GUILD, "type": 0, "allow": "0", "deny": "1024" },
+                        { "id": "700", "type": 0, "allow": "1024", "deny": "0" },
+                    ],
+                },
+            ],
+            "roles": [{ "id": "700", "name": "mods" }],
+            "members": [{ "user": { "id": ALICE, "username": "alice" } }],
+        }),
+    );
+
+    h
+}
+
+fn message(channel_id: &str, guild_id: Option<&str>, author: Value, content: &str) -> Value {
+    let mut d = json!({
+        "id": Event::new_id(),
+        "type": 0,
+        "channel_id": channel_id,
+        "author": author,
+        "content": content,
+        "mentions": [],
+    });
+
+    if let Some(guild_id) = guild_id {
+        d["guild_id"] = guild_id.into();
+    }
+
+    d
+}
+
+fn alice() -> Value {
+    json!({ "id": ALICE, "username": "alice" })
+}
+
+#[test]
+fn messages_become_events() {
+    let h = start_channel(45000);
+
+    let mut mentioned = message(
+        GENERAL,
+        Some(GUILD),
+        alice(),
+        &format!(
+            "<@{}> tell <@{}> about <#{}>, <@&700>",
+            BOT_ID, ALICE, GENERAL
+        ),
+    );
+    mentioned["mentions"] = json!([{ "id": BOT_ID, "username": "synergy" }, alice()]);
+    h.fakes.discord.dispatch("MESSAGE_CREATE", mentioned);
+
+    let event = h.next_event();
+    assert_eq!(event.text, "tell @alice about #general, @mods");
+    assert_eq!(event.from_address, ALICE);
+    assert_eq!(event.conversation_address, GENERAL);
+    assert_eq!(event.workspace.as_deref(), Some(GUILD));
+    assert_eq!(event.origin, "channel/discord");
+    assert!(event.was_targeted);
+    assert!(event.is_public);
+
+    h.fakes.discord.dispatch(
+        "MESSAGE_CREATE",
+        message(GENERAL, Some(GUILD), alice(), "synergy: clox"),
+    );
+    let event = h.next_event();
+    assert_eq!(event.text, "clox");
+    assert!(event.was_targeted);
+
+    h.fakes.discord.dispatch(
+        "MESSAGE_CREATE",
+        message(GENERAL, Some(GUILD), alice(), "just chatting"),
+    );
+    let event = h.next_event();
+    assert!(!event.was_targeted);
+
+    // DMs are always for us, and never public
+    h.fakes.discord.dispatch(
+        "MESSAGE_CREATE",
+        message("800", None, alice(), "what time is it"),
+    );
+    let event = h.next_event();
+    assert_eq!(event.conversation_address, "800");
+    assert!(event.was_targeted);
+    assert!(!event.is_public);
+
+    // nor are channels not everyone can see, or ones we don't know about
+    for channel in &[MODS_ONLY, "901"] {
+        h.fakes.discord.dispatch(
+            "MESSAGE_CREATE",
+            message(channel, Some(GUILD), alice(), "synergy: clox"),
+        );
+        let event = h.next_event();
+        assert!(!event.is_public, "{} is public", channel);
+    }
+
+    // and bots we ignore, including ourselves
+    let bot = json!({ "id": "1002", "username": "otherbot", "bot": true });
+    h.fakes.discord.dispatch(
+        "MESSAGE_CREATE",
+        message(GENERAL, Some(GUILD), bot, "synergy: clox"),
+    );
+    let us = json!({ "id": BOT_ID, "username": "synergy" });
+    h.fakes.discord.dispatch(
+        "MESSAGE_CREATE",
+        message(GENERAL, Some(GUILD), us, "synergy: clox"),
+    );
+    h.nothing_happened();
+
+    h.hangup();
+}
+
+#[test]
+fn heartbeats_carry_the_sequence() {
+    let h = start_channel(100);
+
+    h.fakes.discord.dispatch(
+        "MESSAGE_CREATE",
+        message(GENERAL, Some(GUILD), alice(), "hi"),
+    );
+    h.next_event();
+
+    // There might be one in flight from before the message.
+    let seq = h.fakes.discord.last_seq();
+    let mut beat = h.fakes.discord.recv_op(1).expect("no heartbeat");
+    if beat["d"] != seq {
+        beat = h.fakes.discord.recv_op(1).expect("no heartbeat");
+    }
+    assert_eq!(beat["d"], seq);
+
+    h.hangup();
+}
+
+#[test]
+fn dropped_connections_resume() {
+    let h = start_channel(45000);
+
+    h.fakes.discord.dispatch(
+        "MESSAGE_CREATE",
+        message(GENERAL, Some(GUILD), alice(), "hi"),
+    );
+    h.next_event();
+
+    h.fakes.discord.drop_connection();
+    h.expect_connectivity(Connectivity::Disconnected);
+
+    let resume = h.fakes.discord.recv_op(6).expect("never resumed");
+    assert_eq!(resume["d"]["token"], TOKEN);
+    assert_eq!(resume["d"]["session_id"], "session-1");
+    assert_eq!(resume["d"]["seq"], h.fakes.discord.last_seq());
+
+    // anything we missed comes before RESUMED
+    h.fakes.discord.dispatch(
+        "MESSAGE_CREATE",
+        message(GENERAL, Some(GUILD), alice(), "while you were out"),
+    );
+    h.fakes.discord.dispatch("RESUMED", json!({}));
+
+    assert_eq!(h.next_event().text, "while you were out");
+    h.expect_connectivity(Connectivity::Connected);
+
+    h.hangup();
+}
+
+#[test]
+fn unacked_heartbeats_mean_reconnecting() {
+    let h = start_channel(100);
+
+    h.fakes.discord.set_acking(false);
+    h.expect_connectivity(Connectivity::Disconnected);
+
+    h.fakes.discord.set_acking(true);
+    h.fakes.discord.recv_op(6).expect("never resumed");
+
+    h.hangup();
+}
+
+#[test]
+fn invalid_sessions_start_over() {
+    let h = start_channel(45000);
+
+    // resumable: try again with the same session
+    h.fakes.discord.send(json!({ "op": 9, "d": true }));
+    h.expect_connectivity(Connectivity::Disconnected);
+    let resume = h.fakes.discord.recv_op(6).expect("never resumed");
+    assert_eq!(resume["d"]["session_id"], "session-1");
+
+    // not resumable: identify all over again
+    h.fakes.discord.send(json!({ "op": 9, "d": false }));
+    h.expect_connectivity(Connectivity::Disconnected);
+    h.fakes.discord.recv_op(2).expect("never identified again");
+
+    // and some close codes mean the same thing
+    h.fakes.discord.ready("session-2");
+    h.expect_connectivity(Connectivity::Connected);
+    h.fakes.discord.close(4009);
+    h.expect_connectivity(Connectivity::Disconnected);
+    h.fakes.discord.recv_op(2).expect("never identified again");
+
+    h.hangup();
+}
+
+#[test]
+fn replies_go_over_rest() {
+    let h = start_channel(45000);
+
+    h.fakes.discord.dispatch(
+        "MESSAGE_CREATE",
+        message(GENERAL, Some(GUILD), alice(), "synergy: hi"),
+    );
+    let event = h.next_event();
+
+    h.reply(&event, "hello @everyone");
+
+    let call = h.fakes.discord.recv_call().unwrap();
+    assert_eq!(call.method, "POST");
+    assert_eq!(call.path, format!("/channels/{}/messages", GENERAL));
+    assert_eq!(call.auth, Some(format!("Bot {}", TOKEN)));
+    assert_eq!(call.body["content"], "hello @everyone");
+    assert_eq!(call.body["allowed_mentions"], json!({ "parse": ["users"] }));
+
+    // private replies need a dm, which we only open once
+    for _ in 0..2 {
+        h.to_channel
+            .send(event.private_reply("psst", "reactor/test").unwrap())
+            .unwrap();
+    }
+
+    let call = h.fakes.discord.recv_call().unwrap();
+    assert_eq!(call.path, "/users/@me/channels");
+    assert_eq!(call.body["recipient_id"], ALICE);
+
+    for _ in 0..2 {
+        let call = h.fakes.discord.recv_call().unwrap();
+        assert_eq!(call.path, format!("/channels/dm-{}/messages", ALICE));
+        assert_eq!(call.body["content"], "psst");
+    }
+
+    h.hangup();
+}
+
+#[test]
+fn replies_wait_for_rate_limits() {
+    let h = start_channel(45000);
+
+    h.fakes.discord.dispatch(
+        "MESSAGE_CREATE",
+        message(GENERAL, Some(GUILD), alice(), "synergy: hi"),
+    );
+    let event = h.next_event();
+
+    // That's the last one for a bit, in this channel only.
+    h.fakes.discord.respond_with(Canned {
+        status: 200,
+        headers: vec![
+            ("X-RateLimit-Remaining", "0".into()),
+            ("X-RateLimit-Reset-After", "0.5".into()),
+        ],
+        body: json!({ "id": "1" }),
+    });
+
+    h.reply(&event, "one");
+    h.to_channel
+        .send(event.reply_in("601", "elsewhere", "reactor/test"))
+        .unwrap();
+    h.reply(&event, "two");
+
+    let one = h.fakes.discord.recv_call().unwrap();
+    let elsewhere = h.fakes.discord.recv_call().unwrap();
+    let two = h.fakes.discord.recv_call().unwrap();
+
+    assert_eq!(elsewhere.body["content"], "elsewhere");
+    assert!(elsewhere.at - one.at < Duration::from_millis(250));
+    assert_eq!(two.body["content"], "two");
+    assert!(two.at - one.at >= Duration::from_millis(450));
+
+    // And if we get a 429 anyway, we wait and try again.
+    h.fakes.discord.respond_with(Canned {
+        status: 429,
+        headers: vec![],
+        body: json!({ "message": "slow down", "retry_after": 0.3, "global": false }),
+    });
+
+    h.reply(&event, "three");
+
+    let first = h.fakes.discord.recv_call().unwrap();
+    let second = h.fakes.discord.recv_call().unwrap();
+    assert_eq!(second.body["content"], "three");
+    assert!(second.at - first.at >= Duration::from_millis(250));
+
+    h.hangup();
+}
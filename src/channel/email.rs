@@ -0,0 +1,300 @@
+mod auth;
+mod client;
+mod mime;
+mod quoting;
+mod server;
+#[cfg(test)]
+mod tests;
+
+use std::collections::HashMap;
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use crate::channel::{self, Input, Seed};
+use crate::message::{Connectivity, Event, Message, Reply};
+use mime::Mail;
+use server::Envelope;
+
+// for replies in conversations we haven't seen mail in, like after a restart
+const DEFAULT_SUBJECT: &str = "A message from synergy";
+
+// Mail comes in over SMTP, to a little listener of our own (see server.rs),
+// and replies go out through a relay (see client.rs). Everyone is their
+// address, and a conversation is a thread: the first message in it, by
+// message id, or the subject if it somehow hasn't got one. Everything's
+// private, so every reply goes to whoever it's for, in the thread it's for.
+pub struct Email {
+    pub name: String,
+    to_hub: mpsc::Sender<Message>,
+    from_hub: Option<mpsc::Receiver<Message>>, // until forward_hub takes it
+    listen_address: String,
+    relay_address: String,
+    address: String,  // ours, lowercase
+    hostname: String, // for saying hello, and making message ids
+    allowed_senders: Vec<String>,
+    authserv_id: Option<String>, // whose Authentication-Results we believe
+
+    // conversation address => what we need to reply in it
+    threads: HashMap<String, Thread>,
+}
+
+#[derive(Debug, Clone)]
+struct Thread {
+    subject: String,
+    last_id: Option<String>, // of the last message we got
+    references: Vec<String>,
+}
+
+pub fn build(seed: Seed) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut channel = self::new(seed);
+        channel.start();
+    })
+}
+
+pub fn new(seed: Seed) -> Email {
+    let extra = &seed.config.extra;
+
+    let str_config = |key| {
+        extra
+            .get(key)
+            .and_then(|v| v.as_str())
+            .map(String::from)
+            .unwrap_or_else(|| panic!("no {} for email channel in config!", key))
+    };
+
+    let address = str_config("address").to_lowercase();
+
+    let hostname = extra
+        .get("hostname")
+        .and_then(|v| v.as_str())
+        .map(String::from)
+        .unwrap_or_else(|| domain_of(&address).to_string());
+
+    // Anyone can say they're anyone in email, so this only keeps out people
+    // who aren't lying; see auth.rs for who we believe. Entries are
+    // addresses, or @domains.
+    let allowed_senders = extra
+        .get("allowed_senders")
+        .and_then(|v| v.as_array())
+        .map(|a| {
+            a.iter()
+                .filter_map(|v| v.as_str())
+                .map(|s| s.to_lowercase())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Email {
+        name: seed.name.clone(),
+        to_hub: seed.output,
+        from_hub: Some(seed.input),
+        listen_address: str_config("listen_address"),
+        relay_address: str_config("relay_address"),
+        address,
+        hostname,
+        allowed_senders,
+        authserv_id: extra
+            .get("authserv_id")
+            .and_then(|v| v.as_str())
+            .map(String::from),
+        threads: HashMap::new(),
+    }
+}
+
+fn domain_of(address: &str) -> &str {
+    address.rsplit('@').next().unwrap_or(address)
+}
+
+// "Re: Fwd: RE: clox" => "clox"
+fn bare_subject(subject: &str) -> &str {
+    let mut subject = subject.trim();
+
+    loop {
+        let lower = subject.to_lowercase();
+        match ["re:", "fwd:", "fw:"]
+            .iter()
+            .find(|p| lower.starts_with(*p))
+        {
+            Some(prefix) => subject = subject[prefix.len()..].trim_start(),
+            None => return subject,
+        }
+    }
+}
+
+impl Email {
+    fn start(&mut self) {
+        let (inbox_tx, inbox) = mpsc::channel();
+
+        channel::forward_hub(self.from_hub.take().unwrap(), inbox_tx.clone());
+
+        let _listener = server::spawn(
+            &self.listen_address,
+            &self.hostname,
+            std::slice::from_ref(&self.address),
+            inbox_tx,
+        )
+        .unwrap_or_else(|e| panic!("couldn't listen on {}: {}", self.listen_address, e));
+
+        info!(
+            "listening for mail to {} on {}",
+            self.address, self.listen_address
+        );
+
+        if self.authserv_id.is_none() {
+            warn!("no authserv_id for email, so no mail will count as from any user");
+        }
+
+        channel::set_connectivity(&self.to_hub, &self.name, Connectivity::Connected);
+
+        for input in inbox {
+            match input {
+                Input::Hub(Message::Reply(reply)) => self.send_reply(reply),
+                Input::Hub(Message::Hangup) => break,
+                Input::Hub(_) => (),
+                Input::Remote(envelope) => self.handle_mail(envelope),
+            }
+        }
+    }
+
+    fn handle_mail(&mut self, envelope: Envelope) {
+        trace!("mail from <{}> for {:?}", envelope.from, envelope.to);
+
+        let mail = mime::parse(&envelope.data);
+
+        // Bounces come from nobody; answering them (or other robots) only
+        // ever makes more mail.
+        if envelope.from.is_empty() || mail.is_automatic {
+            debug!("ignoring automatic mail from {:?}", mail.from);
+            return;
+        }
+
+        let sender = mail
+            .from
+            .clone()
+            .unwrap_or_else(|| envelope.from.to_lowercase());
+
+        if sender == self.address {
+            return;
+        }
+
+        if !self.is_allowed(&sender) {
+            info!("ignoring mail from {}, who isn't allowed", sender);
+            return;
+        }
+
+        // Empty mail with something in the subject is how a lot of people
+        // send things from their phones.
+        let mut text = quoting::strip(&mail.text);
+        if text.is_empty() {
+            text = bare_subject(&mail.subject).to_string();
+        }
+
+        if text.is_empty() {
+            debug!("ignoring empty mail from {}", sender);
+            return;
+        }
+
+        let conversation = conversation_of(&mail);
+        self.remember_thread(&conversation, &mail);
+
+        // Without a mail server to vouch for them, they could be anyone, so
+        // they don't get to be any user in particular.
+        let is_verified = self
+            .authserv_id
+            .as_ref()
+            .is_some_and(|id| auth::verifies(&mail.authentication_results, id, &sender));
+
+        if !is_verified {
+            debug!("can't tell if mail from {} is really from them", sender);
+        }
+
+        let event = Event {
+            was_targeted: true,
+            is_unverified: !is_verified,
+            ..Event::new(&self.name, &sender, &conversation, &text)
+        };
+
+        self.to_hub.send(Message::Event(Arc::new(event))).unwrap();
+    }
+
+    fn is_allowed(&self, sender: &str) -> bool {
+        if self.allowed_senders.is_empty() {
+            return true;
+        }
+
+        self.allowed_senders.iter().any(|allowed| {
+            if allowed.starts_with('@') {
+                sender.ends_with(allowed.as_str())
+            } else {
+                sender == allowed
+            }
+        })
+    }
+
+    // Replying to the last thing we got in the thread means it shows up in
+    // the right place for everyone, even if the thread has forked.
+    fn remember_thread(&mut self, conversation: &str, mail: &Mail) {
+        let mut references = mail.references.clone();
+        if references.is_empty() {
+            references.extend(mail.in_reply_to.clone());
+        }
+        references.extend(mail.message_id.clone());
+
+        let thread = Thread {
+            subject: mail.subject.clone(),
+            last_id: mail.message_id.clone(),
+            references,
+        };
+
+        self.threads.insert(conversation.to_string(), thread);
+    }
+
+    fn send_reply(&mut self, reply: Reply) {
+        let to = &reply.from_address;
+
+        let thread = self.threads.get(&reply.conversation_address);
+
+        let subject = match thread {
+            Some(t) if !bare_subject(&t.subject).is_empty() => {
+                format!("Re: {}", bare_subject(&t.subject))
+            }
+            _ => DEFAULT_SUBJECT.to_string(),
+        };
+
+        let message_id = format!("<{}@{}>", uuid::Uuid::new_v4(), self.hostname);
+        let no_references = vec![];
+
+        let message = mime::compose(&mime::Outgoing {
+            from: &self.address,
+            to,
+            subject: &subject,
+            message_id: &message_id,
+            in_reply_to: thread.and_then(|t| t.last_id.as_deref()),
+            references: thread.map_or(&no_references, |t| &t.references),
+            text: &reply.text,
+        });
+
+        if let Err(e) = client::send(
+            &self.relay_address,
+            &self.hostname,
+            &self.address,
+            to,
+            &message,
+        ) {
+            error!("couldn't send mail to {}: {}", to, e);
+        }
+    }
+}
+
+// The first message in the thread is the first thing in References; if
+// there aren't any, this is a reply to the first message, or is the first
+// message.
+fn conversation_of(mail: &Mail) -> String {
+    mail.references
+        .first()
+        .or(mail.in_reply_to.as_ref())
+        .or(mail.message_id.as_ref())
+        .cloned()
+        .unwrap_or_else(|| format!("subject:{}", bare_subject(&mail.subject).to_lowercase()))
+}
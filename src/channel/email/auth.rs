@@ -0,0 +1,159 @@
+use regex::Regex;
+
+// Anybody can put anything in From:, so we only believe it when the mail
+// server in front of us says so, in an Authentication-Results header (RFC
+// 8601). That server is named by its authserv-id, and it's meant to remove
+// any headers claiming to be from it before adding its own; we look at the
+// topmost one with its name, which is the one it added last.
+//
+// The sender's believable if DMARC passed for their domain, or DKIM did, or
+// SPF did for an envelope sender in the same domain.
+pub fn verifies(results: &[String], authserv_id: &str, sender: &str) -> bool {
+    lazy_static! {
+        static ref COMMENT_RE: Regex = Regex::new(r"\([^()]*\)").unwrap();
+    }
+
+    let domain = match sender.rsplit_once('@') {
+        Some((_, domain)) => domain.to_lowercase(),
+        None => return false,
+    };
+
+    let ours = results.iter().find_map(|header| {
+        let header = COMMENT_RE.replace_all(header, "");
+        let mut parts = header.split(';');
+        let id = parts.next()?.split_whitespace().next()?;
+
+        if id.eq_ignore_ascii_case(authserv_id) {
+            Some(parts.map(String::from).collect::<Vec<_>>())
+        } else {
+            None
+        }
+    });
+
+    let ours = match ours {
+        Some(results) => results,
+        None => return false,
+    };
+
+    ours.iter().any(|result| {
+        let mut words = result.split_whitespace();
+
+        let (method, outcome) = match words.next().and_then(|w| w.split_once('=')) {
+            Some(pair) => pair,
+            None => return false,
+        };
+
+        if !outcome.eq_ignore_ascii_case("pass") {
+            return false;
+        }
+
+        let property = |name: &str| {
+            result.split_whitespace().find_map(|w| {
+                let (k, v) = w.split_once('=')?;
+                if k.eq_ignore_ascii_case(name) {
+                    Some(v.trim_matches('"').to_lowercase())
+                } else {
+                    None
+                }
+            })
+        };
+
+        // A domain, or an address (or @domain) in it.
+        let is_ours = |value: Option<String>| {
+            value.is_some_and(|v| v.rsplit('@').next() == Some(domain.as_str()))
+        };
+
+        match method.to_lowercase().as_str() {
+            "dmarc" => is_ours(property("header.from")),
+            "dkim" => is_ours(property("header.d")) || is_ours(property("header.i")),
+            "spf" => is_ours(property("smtp.mailfrom")),
+            _ => false,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifying() {
+        let cases = vec![
+            // header, sender, believable?
+            (
+                "mx.example.com; dkim=pass header.d=example.com header.s=sel",
+                "alice@example.com",
+                true,
+            ),
+            (
+                "mx.example.com; dkim=pass header.i=@Example.com",
+                "alice@example.com",
+                true,
+            ),
+            (
+                "mx.example.com; spf=pass smtp.mailfrom=bounces@example.com",
+                "alice@example.com",
+                true,
+            ),
+            (
+                "mx.example.com (our mx); dmarc=pass (p=reject) header.from=example.com",
+                "alice@example.com",
+                true,
+            ),
+            (
+                "mx.example.com; spf=fail smtp.mailfrom=example.com; dkim=none",
+                "alice@example.com",
+                false,
+            ),
+            // passing, but for somebody else's domain
+            (
+                "mx.example.com; dkim=pass header.d=evil.example",
+                "alice@example.com",
+                false,
+            ),
+            (
+                "mx.example.com; dkim=pass header.d=mail.example.com",
+                "alice@example.com",
+                false,
+            ),
+            // from some other server, which could be anybody
+            (
+                "mx.evil.example; dkim=pass header.d=example.com",
+                "alice@example.com",
+                false,
+            ),
+            ("mx.example.com; none", "alice@example.com", false),
+            ("mx.example.com", "alice@example.com", false),
+            ("", "alice@example.com", false),
+        ];
+
+        for (header, sender, want) in cases {
+            let results = vec![header.to_string()];
+            assert_eq!(
+                verifies(&results, "mx.example.com", sender),
+                want,
+                "{:?} for {}",
+                header,
+                sender
+            );
+        }
+    }
+
+    // Only the topmost of ours counts: anything under it came with the mail.
+    #[test]
+    fn only_the_latest_results_count() {
+        let results = vec![
+            "mx.example.com; dkim=fail header.d=example.com".to_string(),
+            "mx.example.com; dkim=pass header.d=example.com".to_string(),
+        ];
+
+        assert!(!verifies(&results, "mx.example.com", "alice@example.com"));
+
+        let results = vec![
+            "mx.evil.example; dkim=fail".to_string(),
+            "mx.example.com; dkim=pass header.d=example.com".to_string(),
+        ];
+
+        assert!(verifies(&results, "mx.example.com", "alice@example.com"));
+    }
+}
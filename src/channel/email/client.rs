@@ -0,0 +1,195 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+// how long we'll wait on the relay for anything: connecting, or an answer
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub struct SmtpError(String);
+
+impl Error for SmtpError {}
+
+impl fmt::Display for SmtpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<io::Error> for SmtpError {
+    fn from(e: io::Error) -> Self {
+        SmtpError(e.to_string())
+    }
+}
+
+struct Session {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+// Hands one message to an SMTP relay, and hangs up. Like the listener (see
+// server.rs) there's no TLS or AUTH, so the relay should be something close
+// by, like the MTA on the same box, that will take mail from us and worry
+// about getting it the rest of the way.
+pub fn send(
+    relay: &str,
+    hostname: &str,
+    from: &str,
+    to: &str,
+    message: &[u8],
+) -> Result<(), SmtpError> {
+    let addr = relay
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| SmtpError(format!("couldn't find relay {}", relay)))?;
+
+    let stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let mut session = Session {
+        reader: BufReader::new(stream.try_clone()?),
+        writer: stream,
+    };
+
+    session.expect(&[220])?;
+
+    // Anything that doesn't speak ESMTP will at least speak SMTP.
+    if session
+        .command(&format!("EHLO {}", hostname), &[250])
+        .is_err()
+    {
+        session.command(&format!("HELO {}", hostname), &[250])?;
+    }
+
+    session.command(&format!("MAIL FROM:<{}>", from), &[250])?;
+    session.command(&format!("RCPT TO:<{}>", to), &[250, 251])?;
+    session.command("DATA", &[354])?;
+
+    session.writer.write_all(&dot_stuff(message))?;
+    session.command(".", &[250])?;
+
+    // It's got it; whatever it says now doesn't matter.
+    session.command("QUIT", &[221]).unwrap_or(());
+
+    Ok(())
+}
+
+impl Session {
+    fn command(&mut self, line: &str, ok: &[u16]) -> Result<(), SmtpError> {
+        self.writer.write_all(format!("{}\r\n", line).as_bytes())?;
+        self.expect(ok).map_err(|e| {
+            let verb = line.split([' ', ':']).next().unwrap_or(line);
+            SmtpError(format!("{}: {}", verb, e))
+        })
+    }
+
+    // Replies can go on for more than one line, like "250-this", "250 that".
+    fn expect(&mut self, ok: &[u16]) -> Result<(), SmtpError> {
+        let mut text = String::new();
+
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(SmtpError("relay hung up".into()));
+            }
+
+            text.push_str(line.trim_end());
+
+            if line.as_bytes().get(3) != Some(&b'-') {
+                break;
+            }
+
+            text.push(' ');
+        }
+
+        let code = text.get(..3).and_then(|c| c.parse::<u16>().ok());
+
+        match code {
+            Some(code) if ok.contains(&code) => Ok(()),
+            _ => Err(SmtpError(format!("relay said {:?}", text))),
+        }
+    }
+}
+
+// CRLF line endings, a dot in front of every line that starts with one (so
+// it's not mistaken for the end), and a line ending at the end.
+fn dot_stuff(message: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(message.len() + 64);
+
+    let message = message.strip_suffix(b"\n").unwrap_or(message);
+
+    for line in message.split(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        if line.starts_with(b".") {
+            out.push(b'.');
+        }
+
+        out.extend_from_slice(line);
+        out.extend_from_slice(b"\r\n");
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::email::server;
+    use crate::channel::Input;
+    use std::sync::mpsc;
+
+    #[test]
+    fn stuffing() {
+        assert_eq!(dot_stuff(b"a\nb"), b"a\r\nb\r\n".to_vec());
+        assert_eq!(dot_stuff(b"a\r\n.b\r\n"), b"a\r\n..b\r\n".to_vec());
+        assert_eq!(dot_stuff(b".\n"), b"..\r\n".to_vec());
+    }
+
+    #[test]
+    fn sending() {
+        let (tx, rx) = mpsc::channel();
+        let recipients = vec!["bob@example.com".to_string()];
+        let listener = server::spawn("127.0.0.1:0", "relay", &recipients, tx).unwrap();
+        let relay = listener.address.to_string();
+
+        let message = b"Subject: hi\n\nline one\n.\nline three\n";
+        send(
+            &relay,
+            "synergy",
+            "alice@example.com",
+            "bob@example.com",
+            message,
+        )
+        .unwrap();
+
+        let envelope = match rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+            Input::Remote(envelope) => envelope,
+            _ => panic!("expected an envelope"),
+        };
+
+        assert_eq!(envelope.from, "alice@example.com");
+        assert_eq!(envelope.to, vec!["bob@example.com"]);
+        assert_eq!(
+            envelope.data,
+            b"Subject: hi\r\n\r\nline one\r\n.\r\nline three\r\n".to_vec()
+        );
+
+        let err = send(
+            &relay,
+            "synergy",
+            "alice@example.com",
+            "eve@example.com",
+            message,
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string().starts_with("RCPT: relay said \"550"),
+            "{}",
+            err
+        );
+    }
+}
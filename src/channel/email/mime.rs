@@ -0,0 +1,532 @@
+use std::collections::HashMap;
+
+use regex::{Captures, Regex};
+
+// Just the parts of a message we care about, decoded. Addresses and message
+// ids are as they'd appear in headers: addresses lowercase, without the
+// display name; message ids with their angle brackets.
+#[derive(Debug, Clone, Default)]
+pub struct Mail {
+    pub from: Option<String>,
+    pub subject: String,
+    pub message_id: Option<String>,
+    pub in_reply_to: Option<String>,
+    pub references: Vec<String>,
+    pub is_automatic: bool, // an autoresponder, or a mailing list
+    pub authentication_results: Vec<String>, // topmost first
+    pub text: String,
+}
+
+// A reply of ours, to be turned into RFC 5322.
+pub struct Outgoing<'a> {
+    pub from: &'a str,
+    pub to: &'a str,
+    pub subject: &'a str,
+    pub message_id: &'a str,
+    pub in_reply_to: Option<&'a str>,
+    pub references: &'a [String],
+    pub text: &'a str,
+}
+
+// name (lowercase) => value, in order, with folded lines put back together
+type Headers = Vec<(String, String)>;
+
+lazy_static! {
+    static ref ENCODED_WORD_RE: Regex =
+        Regex::new(r"=\?([^?\s]+)\?([bBqQ])\?([^?\s]*)\?=").unwrap();
+    static ref BETWEEN_ENCODED_WORDS_RE: Regex = Regex::new(r"(\?=)\s+(=\?)").unwrap();
+    static ref MESSAGE_ID_RE: Regex = Regex::new(r"<[^<>\s]+>").unwrap();
+    static ref HTML_DROP_RE: Regex = Regex::new(concat!(
+        r"(?is)<(?:style|script|head)\b.*?</(?:style|script|head)>",
+        r"|<blockquote\b.*?</blockquote>|<!--.*?-->"
+    ))
+    .unwrap();
+    static ref HTML_BREAK_RE: Regex = Regex::new(r"(?i)<br\s*/?>|</(?:p|div|li|tr|h\d)>").unwrap();
+    static ref HTML_TAG_RE: Regex = Regex::new(r"<[^>]*>").unwrap();
+}
+
+pub fn parse(raw: &[u8]) -> Mail {
+    let (headers, body) = split_headers(raw);
+
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    };
+
+    let message_ids = |name| -> Vec<String> {
+        header(name)
+            .map(|v| {
+                MESSAGE_ID_RE
+                    .find_iter(v)
+                    .map(|m| m.as_str().to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    // RFC 3834 says Auto-Submitted is the thing to look at, but plenty of
+    // autoresponders only bother with Precedence. Either way, answering them
+    // is how mail loops start.
+    let is_automatic = header("auto-submitted")
+        .is_some_and(|v| !v.trim().eq_ignore_ascii_case("no"))
+        || header("precedence").is_some_and(|v| {
+            let v = v.trim().to_lowercase();
+            v == "bulk" || v == "list" || v == "junk"
+        })
+        || header("list-id").is_some();
+
+    // Text first, then HTML if that's all there is.
+    let text = find_text(&headers, body, "text/plain")
+        .or_else(|| find_text(&headers, body, "text/html").map(|html| html_to_text(&html)))
+        .unwrap_or_default();
+
+    Mail {
+        from: header("from").and_then(address_of),
+        subject: header("subject").map(decode_words).unwrap_or_default(),
+        message_id: message_ids("message-id").into_iter().next(),
+        in_reply_to: message_ids("in-reply-to").into_iter().next(),
+        references: message_ids("references"),
+        is_automatic,
+        authentication_results: headers
+            .iter()
+            .filter(|(k, _)| k == "authentication-results")
+            .map(|(_, v)| v.clone())
+            .collect(),
+        text,
+    }
+}
+
+// Plain text, in UTF-8, as quoted-printable so that no line is too long for
+// anyone (and nobody has to support 8BITMIME).
+pub fn compose(mail: &Outgoing) -> Vec<u8> {
+    let mut headers = vec![
+        ("Date", chrono::Utc::now().to_rfc2822()),
+        ("From", mail.from.to_string()),
+        ("To", mail.to.to_string()),
+        ("Subject", encode_words(mail.subject)),
+        ("Message-ID", mail.message_id.to_string()),
+    ];
+
+    if let Some(id) = mail.in_reply_to {
+        headers.push(("In-Reply-To", id.to_string()));
+    }
+
+    if !mail.references.is_empty() {
+        headers.push(("References", mail.references.join("\r\n ")));
+    }
+
+    headers.extend(vec![
+        // so that other robots know not to answer
+        ("Auto-Submitted", "auto-replied".to_string()),
+        ("MIME-Version", "1.0".to_string()),
+        ("Content-Type", "text/plain; charset=utf-8".to_string()),
+        ("Content-Transfer-Encoding", "quoted-printable".to_string()),
+    ]);
+
+    let mut out = String::new();
+
+    for (name, value) in headers {
+        out.push_str(&format!("{}: {}\r\n", name, value));
+    }
+
+    out.push_str("\r\n");
+    out.push_str(&encode_quoted_printable(mail.text));
+    out.push_str("\r\n");
+
+    out.into_bytes()
+}
+
+fn split_headers(raw: &[u8]) -> (Headers, &[u8]) {
+    let mut headers: Headers = vec![];
+    let mut rest = raw;
+
+    while !rest.is_empty() {
+        let end = rest
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(rest.len(), |i| i + 1);
+        let (line, after) = rest.split_at(end);
+        rest = after;
+
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end_matches(['\r', '\n']);
+
+        if line.is_empty() {
+            break;
+        }
+
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim_start());
+            }
+            continue;
+        }
+
+        if let Some(i) = line.find(':') {
+            let name = line[..i].trim().to_lowercase();
+            let value = line[i + 1..].trim().to_string();
+            headers.push((name, value));
+        }
+    }
+
+    (headers, rest)
+}
+
+// The first part of this type anywhere in the message that isn't an
+// attachment, as text.
+fn find_text(headers: &Headers, body: &[u8], want: &str) -> Option<String> {
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    };
+
+    let (mime_type, params) = content_type(header("content-type").unwrap_or("text/plain"));
+
+    if mime_type.starts_with("multipart/") {
+        let boundary = params.get("boundary")?;
+
+        return split_multipart(body, boundary)
+            .into_iter()
+            .find_map(|part| {
+                let (headers, body) = split_headers(part);
+                find_text(&headers, body, want)
+            });
+    }
+
+    let is_attachment = header("content-disposition")
+        .is_some_and(|v| v.trim().to_lowercase().starts_with("attachment"));
+
+    if mime_type != want || is_attachment {
+        return None;
+    }
+
+    let bytes = match header("content-transfer-encoding").map(|v| v.trim().to_lowercase()) {
+        Some(ref e) if e == "base64" => decode_base64(body),
+        Some(ref e) if e == "quoted-printable" => decode_quoted_printable(body),
+        _ => body.to_vec(),
+    };
+
+    let charset = params.get("charset").map_or("us-ascii", String::as_str);
+
+    Some(decode_charset(&bytes, charset).replace("\r\n", "\n"))
+}
+
+// "text/plain; charset=\"utf-8\"" => ("text/plain", {"charset": "utf-8"})
+fn content_type(value: &str) -> (String, HashMap<String, String>) {
+    let mut pieces = value.split(';');
+    let mime_type = pieces.next().unwrap_or_default().trim().to_lowercase();
+
+    let params = pieces
+        .filter_map(|p| {
+            let i = p.find('=')?;
+            let name = p[..i].trim().to_lowercase();
+            let value = p[i + 1..].trim().trim_matches('"').to_string();
+            Some((name, value))
+        })
+        .collect();
+
+    (mime_type, params)
+}
+
+// What's between the boundaries, without the line endings that belong to
+// them. The preamble and epilogue aren't parts.
+fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Vec<&'a [u8]> {
+    let delimiter = format!("--{}", boundary);
+    let mut parts = vec![];
+    let mut start: Option<usize> = None;
+    let mut pos = 0;
+
+    for line in body.split_inclusive(|&b| b == b'\n') {
+        let trimmed = line.strip_suffix(b"\n").unwrap_or(line);
+        let trimmed = trimmed.strip_suffix(b"\r").unwrap_or(trimmed);
+
+        if trimmed.starts_with(delimiter.as_bytes()) {
+            if let Some(start) = start {
+                let mut end = pos;
+                if body[..end].ends_with(b"\n") {
+                    end -= 1;
+                }
+                if body[..end].ends_with(b"\r") {
+                    end -= 1;
+                }
+                parts.push(&body[start..end.max(start)]);
+            }
+
+            if trimmed[delimiter.len()..].starts_with(b"--") {
+                break;
+            }
+
+            start = Some(pos + line.len());
+        }
+
+        pos += line.len();
+    }
+
+    parts
+}
+
+fn decode_base64(body: &[u8]) -> Vec<u8> {
+    let compact: Vec<u8> = body
+        .iter()
+        .copied()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+
+    base64::decode(&compact).unwrap_or_default()
+}
+
+fn decode_quoted_printable(body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(body.len());
+    let mut i = 0;
+
+    while i < body.len() {
+        if body[i] != b'=' {
+            out.push(body[i]);
+            i += 1;
+            continue;
+        }
+
+        let rest = &body[i + 1..];
+
+        // = at the end of a line means the line goes on
+        if rest.starts_with(b"\r\n") {
+            i += 3;
+        } else if rest.starts_with(b"\n") {
+            i += 2;
+        } else if let Some(b) = rest
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            out.push(b);
+            i += 3;
+        } else {
+            out.push(b'=');
+            i += 1;
+        }
+    }
+
+    out
+}
+
+// Anything that's not UTF-8 these days is almost certainly Latin-1 (or
+// Windows' idea of it, which is close enough), where every byte is the
+// character with that number.
+fn decode_charset(bytes: &[u8], charset: &str) -> String {
+    match charset.to_lowercase().as_str() {
+        "iso-8859-1" | "latin1" | "windows-1252" => bytes.iter().map(|&b| b as char).collect(),
+        _ => String::from_utf8_lossy(bytes).to_string(),
+    }
+}
+
+// =?utf-8?B?aGk=?= and =?utf-8?Q?h=C3=A9?= in headers, from RFC 2047.
+fn decode_words(value: &str) -> String {
+    // whitespace between two encoded words doesn't count
+    let value = BETWEEN_ENCODED_WORDS_RE.replace_all(value, "$1$2");
+
+    ENCODED_WORD_RE
+        .replace_all(&value, |caps: &Captures| {
+            let bytes = if caps[2].eq_ignore_ascii_case("b") {
+                decode_base64(caps[3].as_bytes())
+            } else {
+                decode_quoted_printable(caps[3].replace('_', " ").as_bytes())
+            };
+
+            decode_charset(&bytes, &caps[1])
+        })
+        .to_string()
+}
+
+fn encode_words(value: &str) -> String {
+    if value.is_ascii() {
+        return value.to_string();
+    }
+
+    format!("=?utf-8?B?{}?=", base64::encode(value.as_bytes()))
+}
+
+// "Alice <Alice@Example.com>" => "alice@example.com"
+fn address_of(value: &str) -> Option<String> {
+    let address = match (value.rfind('<'), value.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &value[start + 1..end],
+        _ => value,
+    };
+
+    let address = address.trim().to_lowercase();
+
+    if address.contains('@') {
+        Some(address)
+    } else {
+        None
+    }
+}
+
+fn html_to_text(html: &str) -> String {
+    let text = HTML_DROP_RE.replace_all(html, "");
+    let text = HTML_BREAK_RE.replace_all(&text, "\n");
+    let text = HTML_TAG_RE.replace_all(&text, "");
+
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+// Lines no longer than 76, with anything that's not plain ASCII (or is a
+// space at the end of a line, which might get eaten) as =XX.
+fn encode_quoted_printable(text: &str) -> String {
+    let mut lines = vec![];
+
+    for line in text.lines() {
+        let bytes = line.as_bytes();
+        let mut out = String::new();
+        let mut width = 0;
+
+        for (i, &b) in bytes.iter().enumerate() {
+            let at_end = i == bytes.len() - 1;
+
+            let encoded = match b {
+                b'=' => format!("={:02X}", b),
+                b' ' | b'\t' if at_end => format!("={:02X}", b),
+                b' ' | b'\t' | 33..=126 => (b as char).to_string(),
+                _ => format!("={:02X}", b),
+            };
+
+            // room for the = that says the line goes on
+            if width + encoded.len() > 75 {
+                out.push_str("=\r\n");
+                width = 0;
+            }
+
+            width += encoded.len();
+            out.push_str(&encoded);
+        }
+
+        lines.push(out);
+    }
+
+    lines.join("\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing_plain_mail() {
+        let raw = b"From: \"Alice Example\" <Alice@Example.com>\r\n\
+            To: synergy@example.com\r\n\
+            Subject: =?utf-8?Q?caf=C3=A9?=\r\n \
+            =?utf-8?B?IHRpbWU=?=\r\n\
+            Message-ID: <3@example.com>\r\n\
+            In-Reply-To: <2@example.com>\r\n\
+            References: <1@example.com>\r\n\t<2@example.com>\r\n\
+            \r\n\
+            hello\r\nthere\r\n";
+
+        let mail = parse(raw);
+        assert_eq!(mail.from.as_deref(), Some("alice@example.com"));
+        assert_eq!(mail.subject, "café time");
+        assert_eq!(mail.message_id.as_deref(), Some("<3@example.com>"));
+        assert_eq!(mail.in_reply_to.as_deref(), Some("<2@example.com>"));
+        assert_eq!(mail.references, vec!["<1@example.com>", "<2@example.com>"]);
+        assert_eq!(mail.text, "hello\nthere\n");
+        assert!(!mail.is_automatic);
+    }
+
+    #[test]
+    fn parsing_multipart_mail() {
+        let raw = b"From: alice@example.com\n\
+            Content-Type: multipart/mixed; boundary=\"outer\"\n\
+            \n\
+            This is a multi-part message in MIME format.\n\
+            --outer\n\
+            Content-Type: multipart/alternative; boundary=inner\n\
+            \n\
+            --inner\n\
+            Content-Type: text/html\n\
+            \n\
+            <p>html</p>\n\
+            --inner\n\
+            Content-Type: text/plain; charset=utf-8\n\
+            Content-Transfer-Encoding: quoted-printable\n\
+            \n\
+            caf=C3=A9 is a very long line that goes on and on and on and on and on =\n\
+            and on\n\
+            --inner--\n\
+            --outer\n\
+            Content-Type: text/plain\n\
+            Content-Disposition: attachment; filename=notes.txt\n\
+            \n\
+            not this\n\
+            --outer--\n";
+
+        let mail = parse(raw);
+        assert_eq!(
+            mail.text,
+            "café is a very long line that goes on and on and on and on and on and on"
+        );
+
+        // HTML only, in base64, and nothing in the quote
+        let raw = b"From: alice@example.com\n\
+            Content-Type: text/html; charset=utf-8\n\
+            Content-Transfer-Encoding: base64\n\
+            \n\
+            PHA+aGkgJmFtcDsgYnllPC9wPjxibG9ja3F1b3RlPm9sZDwvYmxvY2txdW90ZT4=\n";
+
+        assert_eq!(parse(raw).text, "hi & bye\n");
+    }
+
+    #[test]
+    fn noticing_robots() {
+        let cases = vec![
+            ("Auto-Submitted: auto-replied", true),
+            ("Auto-Submitted: no", false),
+            ("Precedence: bulk", true),
+            ("List-Id: <chat.lists.example.com>", true),
+            ("X-Whatever: yes", false),
+        ];
+
+        for (header, want) in cases {
+            let raw = format!("From: a@example.com\n{}\n\nhi\n", header);
+            assert_eq!(parse(raw.as_bytes()).is_automatic, want, "with {}", header);
+        }
+    }
+
+    #[test]
+    fn composing() {
+        let references = vec!["<1@example.com>".to_string(), "<2@example.com>".to_string()];
+        let text = format!("Ça va = ok  \n{}", "x".repeat(100));
+
+        let raw = compose(&Outgoing {
+            from: "synergy@example.com",
+            to: "alice@example.com",
+            subject: "Re: café",
+            message_id: "<3@example.com>",
+            in_reply_to: Some("<2@example.com>"),
+            references: &references,
+            text: &text,
+        });
+
+        for line in String::from_utf8_lossy(&raw).split("\r\n") {
+            assert!(line.len() <= 76, "line too long: {:?}", line);
+            assert!(line.is_ascii(), "line not ascii: {:?}", line);
+        }
+
+        let mail = parse(&raw);
+        assert_eq!(mail.from.as_deref(), Some("synergy@example.com"));
+        assert_eq!(mail.subject, "Re: café");
+        assert_eq!(mail.message_id.as_deref(), Some("<3@example.com>"));
+        assert_eq!(mail.in_reply_to.as_deref(), Some("<2@example.com>"));
+        assert_eq!(mail.references, references);
+        assert_eq!(mail.text, format!("{}\n", text));
+        assert!(mail.is_automatic);
+    }
+}
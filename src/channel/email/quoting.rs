@@ -0,0 +1,96 @@
+use regex::Regex;
+
+lazy_static! {
+    // "On Tue, Oct 6, 2026 at 9:00 AM Alice <alice@example.com> wrote:"
+    static ref ATTRIBUTION_RE: Regex = Regex::new(r"^On\b.*\bwrote:$").unwrap();
+
+    // Past any of these, it's somebody's signature or the whole message
+    // we're replying to, quoted the Outlook way (without >s).
+    static ref THE_END_RE: Regex = Regex::new(
+        r"(?i)^(?:--|-{3,}\s*original message\s*-{3,}|_{10,}|sent from my \w+.*|get outlook for \w+.*)$"
+    )
+    .unwrap();
+}
+
+// What someone actually wrote, without the message they're replying to or
+// their signature. Quoted lines go wherever they are, so that people who
+// answer inline (or at the bottom) don't lose what they said, but anything
+// after a signature marker goes too.
+pub fn strip(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().map(str::trim_end).collect();
+    let mut kept: Vec<&str> = vec![];
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = lines[i];
+
+        if THE_END_RE.is_match(line) {
+            break;
+        }
+
+        if line.starts_with('>') || ATTRIBUTION_RE.is_match(line) {
+            i += 1;
+            continue;
+        }
+
+        // Long attributions get wrapped.
+        if line.starts_with("On ")
+            && lines
+                .get(i + 1)
+                .is_some_and(|next| ATTRIBUTION_RE.is_match(&format!("{} {}", line, next)))
+        {
+            i += 2;
+            continue;
+        }
+
+        // one blank line between paragraphs is plenty
+        if !(line.is_empty() && kept.last().is_some_and(|l| l.is_empty())) {
+            kept.push(line);
+        }
+
+        i += 1;
+    }
+
+    kept.join("\n").trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stripping() {
+        let cases = vec![
+            ("clox", "clox"),
+            ("  clox  \n\n", "clox"),
+            (
+                "clox please\n\nOn Tue, Oct 6, 2026 at 9:00 AM Synergy <synergy@example.com> wrote:\n> It is 9:00.\n> \n",
+                "clox please",
+            ),
+            (
+                "clox please\n\nOn Tue, Oct 6, 2026 at 9:00 AM Synergy\n<synergy@example.com> wrote:\n> It is 9:00.\n",
+                "clox please",
+            ),
+            (
+                "On Tue, Oct 6, 2026, Synergy wrote:\n> What now?\n\nclox\n\n> Anything else?\n\nno",
+                "clox\n\nno",
+            ),
+            ("clox\n\n-- \nAlice\nAlice's Widgets", "clox"),
+            ("clox\n\nSent from my iPhone", "clox"),
+            (
+                "clox\n\n-----Original Message-----\nFrom: Synergy\nIt is 9:00.",
+                "clox",
+            ),
+            (
+                "clox\n\n________________________________\nFrom: Synergy\nIt is 9:00.",
+                "clox",
+            ),
+            ("On second thought,\nnever mind", "On second thought,\nnever mind"),
+            ("a - b\n-- c", "a - b\n-- c"),
+        ];
+
+        for (text, want) in cases {
+            assert_eq!(strip(text), want, "stripping {:?}", text);
+        }
+    }
+}
@@ -0,0 +1,379 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use crate::channel::Input;
+
+// RFC 5321 says a command line is at most 512 bytes and a text line 1000,
+// but plenty of things send longer; this is just so nobody can make us read
+// forever.
+const MAX_LINE: u64 = 8192;
+
+// Anything bigger than this is somebody's photos, not something for us.
+pub const MAX_MESSAGE_SIZE: usize = 10 * 1024 * 1024;
+
+// how long a connection can sit there saying nothing
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+// Just enough of an SMTP server for a mail server (or anything else) to hand
+// us mail: no TLS, no AUTH, no relaying. It ought to live behind a real MTA
+// that does all that, or at least somewhere nobody's pretending to be
+// anybody they're not.
+//
+// Every message anyone gives us, the channel gets as an Envelope: who the
+// envelope said it was from, who it was for, and the message itself.
+#[derive(Debug, Clone)]
+pub struct Envelope {
+    pub from: String, // empty for bounces
+    pub to: Vec<String>,
+    pub data: Vec<u8>,
+}
+
+// Dropping the listener shuts it down.
+pub struct Listener {
+    pub address: SocketAddr,
+    stop: Arc<AtomicBool>,
+    accepter: Option<thread::JoinHandle<()>>,
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        // Wake the accepter with a connection of our own, so it sees the flag.
+        self.stop.store(true, Ordering::SeqCst);
+        TcpStream::connect(self.address).map(drop).unwrap_or(());
+
+        if let Some(accepter) = self.accepter.take() {
+            accepter.join().unwrap_or(());
+        }
+    }
+}
+
+struct Context {
+    hostname: String,
+    recipients: Vec<String>, // lowercase; empty means anyone
+    to_channel: mpsc::Sender<Input<Envelope>>,
+}
+
+pub fn spawn(
+    address: &str,
+    hostname: &str,
+    recipients: &[String],
+    to_channel: mpsc::Sender<Input<Envelope>>,
+) -> io::Result<Listener> {
+    let listener = TcpListener::bind(address)?;
+    let address = listener.local_addr()?;
+
+    let context = Arc::new(Context {
+        hostname: hostname.to_string(),
+        recipients: recipients.iter().map(|r| r.to_lowercase()).collect(),
+        to_channel,
+    });
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopping = Arc::clone(&stop);
+
+    let accepter = thread::spawn(move || {
+        for stream in listener.incoming() {
+            if stopping.load(Ordering::SeqCst) {
+                break;
+            }
+
+            let stream = match stream {
+                Ok(s) => s,
+                Err(e) => {
+                    info!("error accepting smtp connection: {}", e);
+                    continue;
+                }
+            };
+
+            let context = Arc::clone(&context);
+            thread::spawn(move || {
+                if let Err(e) = converse(stream, &context) {
+                    debug!("smtp connection ended badly: {}", e);
+                }
+            });
+        }
+    });
+
+    Ok(Listener {
+        address,
+        stop,
+        accepter: Some(accepter),
+    })
+}
+
+fn converse(stream: TcpStream, context: &Context) -> io::Result<()> {
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    let mut say = |text: &str| writer.write_all(format!("{}\r\n", text).as_bytes());
+
+    say(&format!("220 {} ESMTP synergy", context.hostname))?;
+
+    let mut from: Option<String> = None;
+    let mut to: Vec<String> = vec![];
+
+    loop {
+        let line = match read_line(&mut reader)? {
+            Some(line) => String::from_utf8_lossy(&line).trim_end().to_string(),
+            None => return Ok(()),
+        };
+
+        let (verb, arg) = match line.find(' ') {
+            Some(i) => (&line[..i], line[i + 1..].trim()),
+            None => (line.as_str(), ""),
+        };
+
+        match verb.to_uppercase().as_str() {
+            "HELO" => {
+                from = None;
+                to.clear();
+                say(&format!("250 {}", context.hostname))?;
+            }
+            "EHLO" => {
+                from = None;
+                to.clear();
+                say(&format!(
+                    "250-{}\r\n250-8BITMIME\r\n250-SMTPUTF8\r\n250 SIZE {}",
+                    context.hostname, MAX_MESSAGE_SIZE
+                ))?;
+            }
+            "MAIL" => match path_arg(arg, "FROM:") {
+                Some(path) => {
+                    from = Some(path);
+                    to.clear();
+                    say("250 OK")?;
+                }
+                None => say("501 Syntax: MAIL FROM:<address>")?,
+            },
+            "RCPT" if from.is_none() => say("503 MAIL first")?,
+            "RCPT" => match path_arg(arg, "TO:") {
+                Some(path) if context.accepts(&path) => {
+                    to.push(path);
+                    say("250 OK")?;
+                }
+                Some(_) => say("550 No such mailbox here")?,
+                None => say("501 Syntax: RCPT TO:<address>")?,
+            },
+            "DATA" if to.is_empty() => say("503 RCPT first")?,
+            "DATA" => {
+                say("354 End data with <CR><LF>.<CR><LF>")?;
+
+                let data = read_data(&mut reader)?;
+                let from = from.take().unwrap_or_default();
+                let to = std::mem::take(&mut to);
+
+                match data {
+                    Some(data) => {
+                        let envelope = Envelope { from, to, data };
+
+                        if context.to_channel.send(Input::Remote(envelope)).is_ok() {
+                            say("250 OK")?;
+                        } else {
+                            say("451 Not taking mail right now")?;
+                        }
+                    }
+                    None => say("552 Message too big")?,
+                }
+            }
+            "RSET" => {
+                from = None;
+                to.clear();
+                say("250 OK")?;
+            }
+            "NOOP" => say("250 OK")?,
+            "VRFY" => say("252 Send some mail and see")?,
+            "QUIT" => {
+                say(&format!("221 {} Bye", context.hostname))?;
+                return Ok(());
+            }
+            _ => say("502 Command not implemented")?,
+        }
+    }
+}
+
+impl Context {
+    fn accepts(&self, recipient: &str) -> bool {
+        self.recipients.is_empty() || self.recipients.contains(&recipient.to_lowercase())
+    }
+}
+
+// The <address> from "FROM:<address> SIZE=1234", or None if it's not there.
+// Bounces come from <>, which is the empty string.
+fn path_arg(arg: &str, prefix: &str) -> Option<String> {
+    // (get, because whoever sent it might have put something multibyte in)
+    if !arg
+        .get(..prefix.len())
+        .is_some_and(|p| p.eq_ignore_ascii_case(prefix))
+    {
+        return None;
+    }
+
+    let rest = arg[prefix.len()..].trim_start();
+
+    let path = if rest.starts_with('<') {
+        &rest[1..rest.find('>')?]
+    } else {
+        rest.split_whitespace().next()?
+    };
+
+    Some(path.to_string())
+}
+
+// None at the end of the stream.
+fn read_line(reader: &mut impl BufRead) -> io::Result<Option<Vec<u8>>> {
+    let mut line = vec![];
+    let n = reader.take(MAX_LINE).read_until(b'\n', &mut line)?;
+
+    Ok(if n == 0 { None } else { Some(line) })
+}
+
+// Everything up to the line with just a dot on it, with the dots people put
+// in front of lines starting with a dot taken back out. None if it was too
+// big, though we read it all anyway, so the conversation can go on.
+fn read_data(reader: &mut impl BufRead) -> io::Result<Option<Vec<u8>>> {
+    let mut data = vec![];
+    let mut too_big = false;
+
+    loop {
+        let line = read_line(reader)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "hung up in DATA"))?;
+
+        if line == b".\r\n" || line == b".\n" {
+            break;
+        }
+
+        let line = if line.starts_with(b".") {
+            &line[1..]
+        } else {
+            &line[..]
+        };
+
+        if data.len() + line.len() > MAX_MESSAGE_SIZE {
+            too_big = true;
+        }
+
+        if !too_big {
+            data.extend_from_slice(line);
+        }
+    }
+
+    Ok(if too_big { None } else { Some(data) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Talk to the server line by line, and get back what it said to each.
+    fn dialogue(listener: &Listener, lines: &[&str]) -> Vec<String> {
+        let stream = TcpStream::connect(listener.address).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+
+        let mut responses = vec![];
+        let mut response = String::new();
+        reader.read_line(&mut response).unwrap();
+        responses.push(response.trim_end().to_string());
+
+        for line in lines {
+            writer
+                .write_all(format!("{}\r\n", line).as_bytes())
+                .unwrap();
+
+            // Lines in DATA get no answer, except the last.
+            if responses.last().unwrap().starts_with("354") && *line != "." {
+                continue;
+            }
+
+            let mut response = String::new();
+            loop {
+                let mut part = String::new();
+                reader.read_line(&mut part).unwrap();
+                response.push_str(&part);
+                if part.as_bytes().get(3) != Some(&b'-') {
+                    break;
+                }
+            }
+            responses.push(response.trim_end().to_string());
+        }
+
+        responses
+    }
+
+    fn codes(responses: &[String]) -> Vec<&str> {
+        responses.iter().map(|r| &r[..3]).collect()
+    }
+
+    #[test]
+    fn taking_mail() {
+        let (tx, rx) = mpsc::channel();
+        let recipients = vec!["Synergy@example.com".to_string()];
+        let listener = spawn("127.0.0.1:0", "mx.example.com", &recipients, tx).unwrap();
+
+        let responses = dialogue(
+            &listener,
+            &[
+                "EHLO client.example.com",
+                "RCPT TO:<synergy@example.com>",
+                "MAIL FROM:<alice@example.com> SIZE=100",
+                "RCPT TO:<someone-else@example.com>",
+                "DATA",
+                "rcpt to:<SYNERGY@example.com>",
+                "DATA",
+                "Subject: hi",
+                "",
+                "..leading dot",
+                ".",
+                "QUIT",
+            ],
+        );
+
+        assert!(responses[0].starts_with("220 mx.example.com"));
+        assert!(responses[1].contains("250-8BITMIME"));
+        assert_eq!(
+            codes(&responses),
+            vec!["220", "250", "503", "250", "550", "503", "250", "354", "250", "221"]
+        );
+
+        let envelope = match rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+            Input::Remote(envelope) => envelope,
+            _ => panic!("expected an envelope"),
+        };
+
+        assert_eq!(envelope.from, "alice@example.com");
+        assert_eq!(envelope.to, vec!["SYNERGY@example.com"]);
+        assert_eq!(
+            envelope.data,
+            b"Subject: hi\r\n\r\n.leading dot\r\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn paths() {
+        let cases = vec![
+            ("FROM:<alice@example.com>", Some("alice@example.com")),
+            (
+                "from: <alice@example.com> BODY=8BITMIME",
+                Some("alice@example.com"),
+            ),
+            ("FROM:alice@example.com", Some("alice@example.com")),
+            ("FROM:<>", Some("")),
+            ("TO:<alice@example.com>", None),
+            ("FROM:<alice@example.com", None),
+            ("", None),
+            ("FRÖM:<alice@example.com>", None),
+            ("FROMÖ:<alice@example.com>", None),
+            ("FRO", None),
+        ];
+
+        for (arg, want) in cases {
+            assert_eq!(path_arg(arg, "FROM:").as_deref(), want, "parsing {:?}", arg);
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use std::sync::mpsc;
+use std::time::Duration;
+
+use super::server::{self, Envelope, Listener};
+use super::{client, mime};
+use crate::channel::testing;
+use crate::channel::{Input, Type};
+use crate::message::Connectivity;
+
+const OUR_ADDRESS: &str = "synergy@example.com";
+
+// A running email channel, and a relay for it to send mail through.
+type Harness = testing::Harness<Fakes>;
+
+struct Fakes {
+    address: String,  // where the channel takes mail
+    _relay: Listener, // stops when we do
+    sent: mpsc::Receiver<Input<Envelope>>,
+}
+
+impl testing::Fakes for Fakes {}
+
+fn start_channel(extra: Vec<(&str, toml::Value)>) -> Harness {
+    // The relay takes anything, and tells us what it took.
+    let (sent_tx, sent) = mpsc::channel();
+    let relay = server::spawn("127.0.0.1:0", "relay.example.com", &[], sent_tx).unwrap();
+
+    let address = testing::free_address();
+
+    let config = testing::config(
+        vec![
+            ("listen_address", address.clone().into()),
+            ("relay_address", relay.address.to_string().into()),
+            ("address", OUR_ADDRESS.into()),
+        ],
+        extra,
+    );
+
+    let channel = testing::start("channel/email", Type::EmailChannel, config, ":memory:");
+    channel.expect_connectivity(Connectivity::Connected);
+
+    Harness {
+        channel,
+        fakes: Fakes {
+            address,
+            _relay: relay,
+            sent,
+        },
+    }
+}
+
+impl Harness {
+    fn mail(&self, from: &str, raw: &str) {
+        client::send(
+            &self.fakes.address,
+            "mx.example.com",
+            from,
+            OUR_ADDRESS,
+            raw.as_bytes(),
+        )
+        .expect("channel wouldn't take mail");
+    }
+
+    fn next_sent(&self) -> Envelope {
+        match self.fakes.sent.recv_timeout(Duration::from_secs(5)) {
+            Ok(Input::Remote(envelope)) => envelope,
+            _ => panic!("nothing went to the relay"),
+        }
+    }
+}
+
+#[test]
+fn mail_becomes_events() {
+    let h = start_channel(vec![]);
+
+    h.mail(
+        "alice@example.com",
+        "From: Alice <Alice@Example.com>\n\
+         To: synergy@example.com\n\
+         Subject: what time is it\n\
+         Message-ID: <1@mail.example.com>\n\
+         \n\
+         clox please\n\
+         \n\
+         -- \n\
+         Alice\n",
+    );
+
+    let event = h.next_event();
+    assert_eq!(event.text, "clox please");
+    assert_eq!(event.from_address, "alice@example.com");
+    assert_eq!(event.conversation_address, "<1@mail.example.com>");
+    assert_eq!(event.origin, "channel/email");
+    assert!(event.was_targeted);
+    assert!(!event.is_public);
+    assert!(event.is_unverified); // nobody to vouch for alice
+
+    // A reply in the same thread is the same conversation, and we only hear
+    // the new bit.
+    h.mail(
+        "alice@example.com",
+        "From: alice@example.com\n\
+         Subject: Re: what time is it\n\
+         Message-ID: <3@mail.example.com>\n\
+         In-Reply-To: <2@example.com>\n\
+         References: <1@mail.example.com> <2@example.com>\n\
+         \n\
+         and the date?\n\
+         \n\
+         On Tue, Oct 6, 2026 at 9:00 AM <synergy@example.com> wrote:\n\
+         > It is 9:00.\n",
+    );
+
+    let event = h.next_event();
+    assert_eq!(event.text, "and the date?");
+    assert_eq!(event.conversation_address, "<1@mail.example.com>");
+
+    // Nothing in it but the subject is fine.
+    h.mail(
+        "bob@example.com",
+        "From: bob@example.com\n\
+         Subject: Fwd: clox\n\
+         \n",
+    );
+
+    let event = h.next_event();
+    assert_eq!(event.text, "clox");
+    assert_eq!(event.from_address, "bob@example.com");
+    assert_eq!(event.conversation_address, "subject:clox");
+
+    h.hangup();
+}
+
+#[test]
+fn senders_are_vouched_for() {
+    let h = start_channel(vec![("authserv_id", "mx.example.com".into())]);
+
+    h.mail(
+        "alice@example.com",
+        "Authentication-Results: mx.example.com; dkim=pass header.d=example.com\n\
+         From: alice@example.com\n\
+         \n\
+         clox\n",
+    );
+
+    let event = h.next_event();
+    assert_eq!(event.from_address, "alice@example.com");
+    assert!(!event.is_unverified);
+
+    // Saying so yourself doesn't count.
+    h.mail(
+        "eve@evil.example",
+        "Authentication-Results: mx.evil.example; dkim=pass header.d=example.com\n\
+         From: alice@example.com\n\
+         \n\
+         clox\n",
+    );
+    h.mail(
+        "eve@evil.example",
+        "Authentication-Results: mx.example.com; dkim=pass header.d=evil.example\n\
+         From: alice@example.com\n\
+         \n\
+         clox\n",
+    );
+
+    for _ in 0..2 {
+        let event = h.next_event();
+        assert_eq!(event.from_address, "alice@example.com");
+        assert!(event.is_unverified);
+    }
+
+    h.hangup();
+}
+
+#[test]
+fn some_mail_is_ignored() {
+    let h = start_channel(vec![(
+        "allowed_senders",
+        vec!["alice@example.com", "@friends.example.com"].into(),
+    )]);
+
+    // robots
+    h.mail(
+        "alice@example.com",
+        "From: alice@example.com\nAuto-Submitted: auto-replied\nSubject: Out of office\n\nBack Monday.\n",
+    );
+    h.mail(
+        "",
+        "From: mailer-daemon@example.com\nSubject: Undeliverable\n\nIt didn't go.\n",
+    );
+
+    // strangers
+    h.mail("eve@example.com", "From: eve@example.com\n\nclox\n");
+    h.mail(
+        "eve@example.com",
+        "From: eve@notfriends.example.com\n\nclox\n",
+    );
+
+    // ourselves
+    h.mail("synergy@example.com", "From: synergy@example.com\n\nclox\n");
+
+    h.nothing_happened();
+
+    h.mail(
+        "carol@friends.example.com",
+        "From: carol@friends.example.com\n\nclox\n",
+    );
+    assert_eq!(h.next_event().from_address, "carol@friends.example.com");
+
+    // And mail for anyone else doesn't get past RCPT.
+    assert!(client::send(
+        &h.fakes.address,
+        "mx.example.com",
+        "alice@example.com",
+        "someone-else@example.com",
+        b"From: alice@example.com\n\nclox\n",
+    )
+    .is_err());
+
+    h.hangup();
+}
+
+#[test]
+fn replies_go_through_the_relay() {
+    let h = start_channel(vec![]);
+
+    h.mail(
+        "alice@example.com",
+        "From: alice@example.com\n\
+         Subject: Re: what time is it\n\
+         Message-ID: <3@mail.example.com>\n\
+         References: <1@mail.example.com>\n  <2@example.com>\n\
+         \n\
+         and the date?\n",
+    );
+
+    let event = h.next_event();
+
+    h.reply(&event, "It's Tuesday.");
+
+    let envelope = h.next_sent();
+    assert_eq!(envelope.from, OUR_ADDRESS);
+    assert_eq!(envelope.to, vec!["alice@example.com"]);
+
+    let mail = mime::parse(&envelope.data);
+    assert_eq!(mail.from.as_deref(), Some(OUR_ADDRESS));
+    assert_eq!(mail.subject, "Re: what time is it");
+    assert_eq!(mail.in_reply_to.as_deref(), Some("<3@mail.example.com>"));
+    assert_eq!(
+        mail.references,
+        vec![
+            "<1@mail.example.com>",
+            "<2@example.com>",
+            "<3@mail.example.com>"
+        ]
+    );
+    assert_eq!(mail.text, "It's Tuesday.\n");
+    assert!(mail
+        .message_id
+        .as_deref()
+        .is_some_and(|id| id.ends_with("@example.com>")));
+
+    // Anything we don't know the thread for starts a new one.
+    h.to_channel
+        .send(event.reply_in("somewhere-else", "hello", "reactor/test"))
+        .unwrap();
+
+    let mail = mime::parse(&h.next_sent().data);
+    assert_eq!(mail.subject, super::DEFAULT_SUBJECT);
+    assert_eq!(mail.in_reply_to, None);
+    assert!(mail.references.is_empty());
+
+    h.hangup();
+}
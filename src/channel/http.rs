@@ -0,0 +1,181 @@
+mod listener;
+#[cfg(test)]
+mod tests;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::channel::{self, Input, Seed};
+use crate::message::{Connectivity, Event, Message};
+use listener::FromHttp;
+
+// How long a request waits for everything to be said, by default; streaming
+// requests are for things that take a while, so they get longer.
+const DEFAULT_TIMEOUT: u64 = 30;
+const DEFAULT_STREAM_TIMEOUT: u64 = 300;
+
+// How often we clear out requests that have stopped waiting, if nothing else
+// wakes us up first.
+const IDLE_WAKEUP: Duration = Duration::from_secs(60);
+
+// For scripts (and CI, and anything else with curl): POST some text, and get
+// back whatever the reactors had to say about it. The listener (see
+// listener.rs) handles each request on its own thread, and tells us about the
+// event it made; we send that to the hub and pass along the ack, any replies,
+// and word that every reactor's done, which is when the request finishes.
+//
+// Replies don't say what event they're for, just where they're going, so a
+// reply goes to every request waiting on that conversation. If you make two
+// requests in the same conversation at once, they'll both hear everything.
+pub struct Http {
+    pub name: String,
+    to_hub: mpsc::Sender<Message>,
+    from_hub: Option<mpsc::Receiver<Message>>, // until forward_hub takes it
+    address: String,
+    token: Option<String>,
+    timeouts: listener::Timeouts,
+
+    // event id => whoever's waiting on it
+    waiting: HashMap<String, Waiting>,
+}
+
+struct Waiting {
+    conversation: String,
+    from: String,
+    to_request: mpsc::Sender<Message>,
+    until: Instant,
+}
+
+pub fn build(seed: Seed) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut channel = self::new(seed);
+        channel.start();
+    })
+}
+
+pub fn new(seed: Seed) -> Http {
+    let extra = &seed.config.extra;
+
+    let address = extra
+        .get("listen_address")
+        .and_then(|v| v.as_str())
+        .expect("no listen_address for http channel in config!")
+        .to_string();
+
+    // This is meant for the box it's on; anyone can say anything through it.
+    match address.parse::<SocketAddr>() {
+        Ok(addr) if addr.ip().is_loopback() => (),
+        _ => warn!("http channel listening on {}, not just localhost!", address),
+    }
+
+    let seconds = |key, default| {
+        let n = extra
+            .get(key)
+            .and_then(|v| v.as_integer())
+            .map_or(default, |n| n as u64);
+        Duration::from_secs(n)
+    };
+
+    Http {
+        name: seed.name.clone(),
+        to_hub: seed.output,
+        from_hub: Some(seed.input),
+        address,
+        token: extra
+            .get("token")
+            .and_then(|v| v.as_str())
+            .map(String::from),
+        timeouts: listener::Timeouts {
+            reply: seconds("timeout", DEFAULT_TIMEOUT),
+            stream: seconds("stream_timeout", DEFAULT_STREAM_TIMEOUT),
+        },
+        waiting: HashMap::new(),
+    }
+}
+
+impl Http {
+    fn start(&mut self) {
+        let (inbox_tx, inbox) = mpsc::channel();
+
+        channel::forward_hub(self.from_hub.take().unwrap(), inbox_tx.clone());
+
+        let _listener = listener::spawn(
+            &self.address,
+            &self.name,
+            self.token.take(),
+            self.timeouts,
+            inbox_tx,
+        )
+        .unwrap_or_else(|e| panic!("couldn't listen on {}: {}", self.address, e));
+
+        channel::set_connectivity(&self.to_hub, &self.name, Connectivity::Connected);
+
+        loop {
+            match inbox.recv_timeout(IDLE_WAKEUP) {
+                Ok(Input::Hub(Message::Reply(reply))) => {
+                    // Private replies go to whoever asked, wherever they were.
+                    self.waiting.retain(|_, w| {
+                        let for_this = if reply.is_private {
+                            w.from == reply.from_address
+                        } else {
+                            w.conversation == reply.conversation_address
+                        };
+
+                        !for_this || w.to_request.send(Message::Reply(reply.clone())).is_ok()
+                    });
+                }
+                Ok(Input::Hub(Message::Ack(id, will_respond))) => {
+                    if let Some(w) = self.waiting.get(&id) {
+                        w.to_request
+                            .send(Message::Ack(id, will_respond))
+                            .unwrap_or(());
+                    }
+                }
+                // That's everything, so the request can stop listening.
+                Ok(Input::Hub(Message::Done(id))) => {
+                    if let Some(w) = self.waiting.remove(&id) {
+                        w.to_request.send(Message::Done(id)).unwrap_or(());
+                    }
+                }
+                Ok(Input::Hub(Message::Hangup)) => break,
+                Ok(Input::Hub(_)) => (),
+                Ok(Input::Remote(FromHttp::Event(event, to_request, timeout))) => {
+                    self.waiting.insert(
+                        event.id.clone(),
+                        Waiting {
+                            conversation: event.conversation_address.clone(),
+                            from: event.from_address.clone(),
+                            to_request,
+                            until: Instant::now() + timeout,
+                        },
+                    );
+
+                    self.to_hub.send(Message::Event(Arc::new(event))).unwrap();
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => (),
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    panic!("hub hung up on us?");
+                }
+            }
+
+            // Nobody's going to be listening for these anymore.
+            let now = Instant::now();
+            self.waiting.retain(|_, w| w.until > now);
+        }
+    }
+}
+
+// The event for a request: whatever the caller says, it's from whoever they
+// say it is. It's for us unless they say otherwise.
+fn event_for(request: &listener::ChatRequest, origin: &str) -> Event {
+    let conversation = request.conversation.as_ref().unwrap_or(&request.from);
+
+    Event {
+        is_public: request.public,
+        was_targeted: request.targeted,
+        ..Event::new(origin, &request.from, conversation, &request.text)
+    }
+}
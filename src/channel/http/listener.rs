@@ -0,0 +1,264 @@
+use std::io::Write;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response};
+
+use crate::channel::{self, Input, Listener};
+use crate::message::{Event, Message, Reply};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    pub reply: Duration,
+    pub stream: Duration,
+}
+
+// What the listener has to say to the rest of the channel: here's an event,
+// and somewhere to send its ack and replies, until it stops waiting.
+pub enum FromHttp {
+    Event(Event, mpsc::Sender<Message>, Duration),
+}
+
+// POST /message {"text": "clox", "from": "ci"}
+#[derive(Debug, Deserialize)]
+pub struct ChatRequest {
+    pub text: String,
+    pub from: String,
+    pub conversation: Option<String>,
+    #[serde(default)]
+    pub public: bool,
+    #[serde(default = "yes")]
+    pub targeted: bool,
+}
+
+fn yes() -> bool {
+    true
+}
+
+// Everything a request needs to know, shared between them.
+struct Context {
+    origin: String,
+    token: Option<String>,
+    timeouts: Timeouts,
+    to_channel: mpsc::Sender<Input<FromHttp>>,
+}
+
+pub fn spawn(
+    address: &str,
+    origin: &str,
+    token: Option<String>,
+    timeouts: Timeouts,
+    to_channel: mpsc::Sender<Input<FromHttp>>,
+) -> Result<Listener, Box<dyn std::error::Error + Send + Sync>> {
+    let context = Arc::new(Context {
+        origin: origin.to_string(),
+        token,
+        timeouts,
+        to_channel,
+    });
+
+    // Some reactors take their time, and nobody should wait on them who
+    // doesn't have to.
+    let listener = channel::listen(address, move |request| {
+        let context = Arc::clone(&context);
+        thread::spawn(move || handle(request, &context));
+    })?;
+
+    info!("listening for http requests on {}", address);
+    Ok(listener)
+}
+
+fn handle(mut request: Request, context: &Context) {
+    if let Some(token) = &context.token {
+        let auth = request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Authorization"))
+            .map(|h| h.value.as_str().to_string())
+            .unwrap_or_default();
+
+        if !same_secret(&auth, &format!("Bearer {}", token)) {
+            return respond(request, 401, json!({"error": "bad token"}));
+        }
+    }
+
+    let (path, query) = match request.url().split_once('?') {
+        Some((path, query)) => (path.to_string(), query.to_string()),
+        None => (request.url().to_string(), String::new()),
+    };
+
+    if path != "/message" {
+        return respond(request, 404, json!({"error": "nothing here"}));
+    }
+
+    if *request.method() != Method::Post {
+        return respond(request, 405, json!({"error": "POST only"}));
+    }
+
+    let mut body = String::new();
+    if let Err(e) = request.as_reader().read_to_string(&mut body) {
+        warn!("error reading http request: {}", e);
+        return;
+    }
+
+    let chat: ChatRequest = match serde_json::from_str(&body) {
+        Ok(chat) => chat,
+        Err(e) => return respond(request, 400, json!({"error": e.to_string()})),
+    };
+
+    let stream = url::form_urlencoded::parse(query.as_bytes())
+        .any(|(k, v)| k == "stream" && v != "0" && v != "false");
+
+    let timeout = if stream {
+        context.timeouts.stream
+    } else {
+        context.timeouts.reply
+    };
+
+    let event = super::event_for(&chat, &context.origin);
+    let (to_request, from_channel) = mpsc::channel();
+
+    let sent = context.to_channel.send(Input::Remote(FromHttp::Event(
+        event.dupe(),
+        to_request,
+        timeout,
+    )));
+
+    if sent.is_err() {
+        return respond(request, 503, json!({"error": "shutting down"}));
+    }
+
+    if stream {
+        stream_replies(request, &event, from_channel, timeout);
+    } else {
+        let mut replies = vec![];
+        let outcome = wait(&event, &from_channel, timeout, |r| {
+            replies.push(reply_json(r))
+        });
+
+        let mut body = outcome.to_json(&event);
+        body["replies"] = Value::Array(replies);
+        respond(request, 200, body);
+    }
+}
+
+// Streaming responses are newline-delimited json: one line per reply as it
+// comes in, and then one to say we're done. tiny_http would hold on to those
+// until it had a few kilobytes, so we do the chunking ourselves.
+fn stream_replies(
+    request: Request,
+    event: &Event,
+    from_channel: mpsc::Receiver<Message>,
+    timeout: Duration,
+) {
+    let mut writer = request.into_writer();
+
+    let head = "HTTP/1.1 200 OK\r\n\
+                Content-Type: application/x-ndjson\r\n\
+                Transfer-Encoding: chunked\r\n\r\n";
+
+    // If the caller's gone, there's no point going on, but there's nothing
+    // else to do about it either.
+    let mut ok = writer.write_all(head.as_bytes()).is_ok();
+
+    let outcome = wait(event, &from_channel, timeout, |r| {
+        let mut line = reply_json(r);
+        line["type"] = json!("reply");
+        ok = ok && send_chunk(&mut writer, &line);
+    });
+
+    let mut done = outcome.to_json(event);
+    done["type"] = json!("done");
+
+    if ok && send_chunk(&mut writer, &done) {
+        let _ = writer.write_all(b"0\r\n\r\n").and_then(|_| writer.flush());
+    }
+}
+
+fn send_chunk(writer: &mut impl Write, value: &Value) -> bool {
+    let line = format!("{}\n", value);
+    write!(writer, "{:x}\r\n{}\r\n", line.len(), line).is_ok() && writer.flush().is_ok()
+}
+
+fn respond(request: Request, status: u16, body: Value) {
+    let header = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+    let response = Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(header);
+
+    let _ = request.respond(response);
+}
+
+fn reply_json(reply: &Reply) -> Value {
+    json!({
+        "text": reply.text,
+        "conversation": reply.conversation_address,
+        "private": reply.is_private,
+        "origin": reply.origin,
+    })
+}
+
+struct Outcome {
+    will_respond: Option<bool>, // None if nobody got back to us
+    timed_out: bool,
+}
+
+impl Outcome {
+    fn to_json(&self, event: &Event) -> Value {
+        json!({
+            "id": event.id,
+            "will_respond": self.will_respond,
+            "timed_out": self.timed_out,
+        })
+    }
+}
+
+// Wait for the hub to say every reactor's done with the event, passing
+// along replies as they come. However many replies there are, and however
+// long the reactors take over them, they all come before that, unless we run
+// out of time first.
+fn wait(
+    event: &Event,
+    from_channel: &mpsc::Receiver<Message>,
+    timeout: Duration,
+    mut on_reply: impl FnMut(&Reply),
+) -> Outcome {
+    let deadline = Instant::now() + timeout;
+    let mut will_respond = None;
+
+    loop {
+        let wait_for = deadline.saturating_duration_since(Instant::now());
+
+        match from_channel.recv_timeout(wait_for) {
+            Ok(Message::Ack(_, w)) => will_respond = Some(w),
+            Ok(Message::Reply(reply)) => on_reply(&reply),
+            Ok(Message::Done(id)) if id == event.id => {
+                return Outcome {
+                    will_respond,
+                    timed_out: false,
+                };
+            }
+            Ok(_) => (),
+            // (If the channel's given up on us, it's for the same reason.)
+            Err(_) => {
+                return Outcome {
+                    will_respond,
+                    timed_out: true,
+                };
+            }
+        }
+    }
+}
+
+// Comparing tokens a byte at a time would tell anyone timing us how much of
+// theirs was right.
+fn same_secret(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
@@ -0,0 +1,283 @@
+use std::io::{BufRead, BufReader};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::channel::testing;
+use crate::channel::Type;
+use crate::message::{Connectivity, Event, Message};
+
+type Harness = testing::Harness<Fakes>;
+
+// Where the channel's listening; the tests themselves play the clients.
+struct Fakes {
+    address: String,
+}
+
+impl testing::Fakes for Fakes {}
+
+fn start_channel(extra: Vec<(&str, toml::Value)>) -> Harness {
+    let address = testing::free_address();
+
+    let config = testing::config(
+        vec![
+            ("listen_address", address.clone().into()),
+            ("timeout", 2.into()),
+        ],
+        extra,
+    );
+
+    let channel = testing::start("channel/http", Type::HttpChannel, config, ":memory:");
+    channel.expect_connectivity(Connectivity::Connected);
+
+    Harness {
+        channel,
+        fakes: Fakes { address },
+    }
+}
+
+impl Harness {
+    fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.fakes.address, path)
+    }
+
+    // Makes the request in the background, since it won't come back until
+    // we've played the hub.
+    fn post(&self, path: &str, body: Value) -> thread::JoinHandle<(u16, Value)> {
+        self.post_with(path, body, None)
+    }
+
+    fn post_with(
+        &self,
+        path: &str,
+        body: Value,
+        token: Option<&str>,
+    ) -> thread::JoinHandle<(u16, Value)> {
+        let url = self.url(path);
+        let token = token.map(String::from);
+
+        thread::spawn(move || {
+            let mut req = reqwest::blocking::Client::new().post(&url).json(&body);
+
+            if let Some(token) = token {
+                req = req.bearer_auth(token);
+            }
+
+            let res = req.send().unwrap();
+            let status = res.status().as_u16();
+            (status, res.json().unwrap())
+        })
+    }
+
+    fn ack(&self, event: &Event, will_respond: bool) {
+        self.to_channel
+            .send(Message::Ack(event.id.clone(), will_respond))
+            .unwrap();
+    }
+
+    fn done(&self, event: &Event) {
+        self.to_channel
+            .send(Message::Done(event.id.clone()))
+            .unwrap();
+    }
+}
+
+#[test]
+fn request_and_response() {
+    let h = start_channel(vec![]);
+
+    let res = h.post(
+        "/message",
+        json!({"text": "echo hi", "from": "ci", "conversation": "builds"}),
+    );
+
+    let event = h.next_event();
+    assert_eq!(event.text, "echo hi");
+    assert_eq!(event.from_address, "ci");
+    assert_eq!(event.conversation_address, "builds");
+    assert_eq!(event.origin, "channel/http");
+    assert!(event.was_targeted);
+    assert!(!event.is_public);
+
+    // replies can come before or after the ack
+    h.reply(&event, "one");
+    h.ack(&event, true);
+    h.reply(&event, "two");
+    h.done(&event);
+
+    let (status, body) = res.join().unwrap();
+    assert_eq!(status, 200);
+    assert_eq!(body["id"], json!(event.id));
+    assert_eq!(body["will_respond"], json!(true));
+    assert_eq!(body["timed_out"], json!(false));
+    assert_eq!(body["replies"][0]["text"], "one");
+    assert_eq!(body["replies"][0]["conversation"], "builds");
+    assert_eq!(body["replies"][0]["origin"], "reactor/test");
+    assert_eq!(body["replies"][1]["text"], "two");
+
+    h.hangup();
+}
+
+#[test]
+fn waiting() {
+    let h = start_channel(vec![]);
+
+    // nobody's interested, and we weren't talking to anyone: done right away
+    let res = h.post(
+        "/message",
+        json!({"text": "just saying", "from": "ci", "targeted": false, "public": true}),
+    );
+    let event = h.next_event();
+    assert!(!event.was_targeted);
+    assert!(event.is_public);
+    assert_eq!(event.conversation_address, "ci");
+    h.ack(&event, false);
+    h.done(&event);
+
+    let (_, body) = res.join().unwrap();
+    assert_eq!(body["will_respond"], json!(false));
+    assert_eq!(body["timed_out"], json!(false));
+    assert_eq!(body["replies"], json!([]));
+
+    // somebody said they'd respond, but never finished
+    let res = h.post("/message", json!({"text": "clox", "from": "ci"}));
+    let event = h.next_event();
+    h.ack(&event, true);
+
+    let (_, body) = res.join().unwrap();
+    assert_eq!(body["will_respond"], json!(true));
+    assert_eq!(body["timed_out"], json!(true));
+
+    // and nobody acked at all
+    let res = h.post("/message", json!({"text": "hello?", "from": "ci"}));
+    h.next_event();
+
+    let (_, body) = res.join().unwrap();
+    assert_eq!(body["will_respond"], json!(null));
+    assert_eq!(body["timed_out"], json!(true));
+
+    h.hangup();
+}
+
+// However long the reactors take, and however much they say, we wait until
+// they're all finished.
+#[test]
+fn slow_reactors() {
+    let h = start_channel(vec![]);
+
+    let res = h.post("/message", json!({"text": "deploy", "from": "ci"}));
+    let event = h.next_event();
+    h.ack(&event, true);
+
+    h.reply(&event, "starting");
+    thread::sleep(Duration::from_millis(750));
+    h.reply(&event, "finished");
+    thread::sleep(Duration::from_millis(250));
+    h.done(&event);
+
+    let (_, body) = res.join().unwrap();
+    assert_eq!(body["timed_out"], json!(false));
+    assert_eq!(body["replies"][0]["text"], "starting");
+    assert_eq!(body["replies"][1]["text"], "finished");
+    assert_eq!(body["replies"].as_array().unwrap().len(), 2);
+
+    h.hangup();
+}
+
+#[test]
+fn tokens() {
+    let h = start_channel(vec![("token", "sekrit".into())]);
+
+    let body = json!({"text": "hi", "from": "ci"});
+
+    let (status, _) = h.post("/message", body.clone()).join().unwrap();
+    assert_eq!(status, 401);
+
+    let (status, _) = h
+        .post_with("/message", body.clone(), Some("wrong"))
+        .join()
+        .unwrap();
+    assert_eq!(status, 401);
+
+    let res = h.post_with("/message", body, Some("sekrit"));
+    let event = h.next_event();
+    h.reply(&event, "hello");
+    h.ack(&event, true);
+    h.done(&event);
+
+    let (status, body) = res.join().unwrap();
+    assert_eq!(status, 200);
+    assert_eq!(body["replies"][0]["text"], "hello");
+
+    h.hangup();
+}
+
+#[test]
+fn bad_requests() {
+    let h = start_channel(vec![]);
+
+    let (status, _) = h.post("/elsewhere", json!({})).join().unwrap();
+    assert_eq!(status, 404);
+
+    let (status, body) = h.post("/message", json!({"from": "ci"})).join().unwrap();
+    assert_eq!(status, 400);
+    assert!(body["error"].as_str().unwrap().contains("text"));
+
+    let status = reqwest::blocking::get(&h.url("/message"))
+        .unwrap()
+        .status()
+        .as_u16();
+    assert_eq!(status, 405);
+
+    h.hangup();
+}
+
+#[test]
+fn streaming() {
+    let h = start_channel(vec![]);
+
+    let url = h.url("/message?stream=1");
+    let (lines_tx, lines) = mpsc::channel();
+
+    let res = thread::spawn(move || {
+        let res = reqwest::blocking::Client::new()
+            .post(&url)
+            .json(&json!({"text": "deploy", "from": "ci"}))
+            .send()
+            .unwrap();
+
+        assert_eq!(res.status().as_u16(), 200);
+
+        for line in BufReader::new(res).lines() {
+            let line: Value = serde_json::from_str(&line.unwrap()).unwrap();
+            lines_tx.send(line).unwrap();
+        }
+    });
+
+    let next_line = || lines.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    let event = h.next_event();
+    h.ack(&event, true);
+
+    // each reply shows up as it happens, not all at the end
+    h.reply(&event, "starting");
+    let line = next_line();
+    assert_eq!(line["type"], "reply");
+    assert_eq!(line["text"], "starting");
+
+    h.reply(&event, "done!");
+    let line = next_line();
+    assert_eq!(line["text"], "done!");
+
+    h.done(&event);
+
+    let line = next_line();
+    assert_eq!(line["type"], "done");
+    assert_eq!(line["id"], json!(event.id));
+    assert_eq!(line["timed_out"], json!(false));
+
+    res.join().unwrap();
+    h.hangup();
+}
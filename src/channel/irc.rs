@@ -0,0 +1,437 @@
+#[cfg(test)]
+mod fake_server;
+mod protocol;
+mod reader;
+#[cfg(test)]
+mod tests;
+
+use std::io::Write;
+use std::net::{Shutdown, TcpStream};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+
+use crate::channel::backoff;
+use crate::channel::{self, split, Input, Seed};
+use crate::message::{Connectivity, Event, Message, Reply};
+use protocol::IrcMessage;
+use reader::FromIrc;
+
+// How often we wake up to see how the connection's doing, if nothing else
+// wakes us up first.
+const IDLE_WAKEUP: Duration = Duration::from_secs(30);
+
+// If we haven't heard anything from the server in this long, we ask it if
+// it's still there, and if we go twice as long, we assume it isn't.
+const PING_AFTER: Duration = Duration::from_secs(120);
+
+// If someone else has our nick, we'll keep asking for it back this often,
+// in case they've gone without our noticing.
+const REGAIN_EVERY: Duration = Duration::from_secs(300);
+
+// Servers tell everyone else that our messages are from "nick!user@host",
+// and that counts against the line length; until we find out what our host
+// is, we assume the worst.
+const MAX_HOST_LENGTH: usize = 63;
+
+// Replies that turn up while we're not connected wait for us, up to a point.
+const MAX_PENDING: usize = 100;
+
+// Like slack, the reader (see reader.rs) sits on the socket and sends us
+// everything it hears, and the hub's messages come to the same place, so we
+// can just block. We do all the talking, though, since IRC is the kind of
+// protocol where you have to answer to stay connected.
+pub struct Irc {
+    pub name: String,
+    to_hub: mpsc::Sender<Message>,
+    from_hub: Option<mpsc::Receiver<Message>>, // until forward_hub takes it
+    address: String,
+    password: Option<String>,
+    nick: String, // the one we want
+    username: String,
+    realname: String,
+    channels: Vec<String>,
+    max_line_length: Option<usize>, // overrides working it out
+
+    // the connection, as far as we know
+    stream: Option<TcpStream>,
+    registered: bool,
+    current_nick: String,
+    our_prefix: Option<String>,
+    last_heard: Instant,
+    last_regain: Instant,
+    targeted_re: Regex,
+    pending: Vec<Reply>,
+    stop_reader: Option<mpsc::Sender<()>>, // dropping it stops the reader
+}
+
+pub fn build(seed: Seed) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut channel = self::new(seed);
+        channel.start();
+    })
+}
+
+pub fn new(seed: Seed) -> Irc {
+    let extra = &seed.config.extra;
+
+    let config_str = |key| extra.get(key).and_then(|v| v.as_str()).map(String::from);
+
+    let address = config_str("server").expect("no irc server in config!");
+    let nick = config_str("nick").expect("no irc nick in config!");
+
+    let channels = extra
+        .get("channels")
+        .and_then(|v| v.as_array())
+        .map(|a| {
+            a.iter()
+                .filter_map(|v| v.as_str())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Irc {
+        name: seed.name.clone(),
+        to_hub: seed.output,
+        from_hub: Some(seed.input),
+        address,
+        password: config_str("password"),
+        username: config_str("username").unwrap_or_else(|| nick.clone()),
+        realname: config_str("realname").unwrap_or_else(|| "synergy".to_string()),
+        channels,
+        max_line_length: extra
+            .get("max_line_length")
+            .and_then(|v| v.as_integer())
+            .map(|n| n as usize),
+        stream: None,
+        registered: false,
+        current_nick: nick.clone(),
+        our_prefix: None,
+        last_heard: Instant::now(),
+        last_regain: Instant::now(),
+        targeted_re: targeted_re(&nick),
+        pending: vec![],
+        stop_reader: None,
+        nick,
+    }
+}
+
+// "synergy: clox" or "synergy, clox"
+fn targeted_re(nick: &str) -> Regex {
+    Regex::new(&format!(r"^(?i)@?{}[:,]\s*", regex::escape(nick))).unwrap()
+}
+
+// Nicks don't care about case. (Strictly, they think [ and { are the same
+// letter too, but nobody's nick has those.)
+fn same_nick(a: &str, b: &str) -> bool {
+    a.eq_ignore_ascii_case(b)
+}
+
+impl Irc {
+    fn start(&mut self) {
+        let (inbox_tx, inbox) = mpsc::channel();
+
+        channel::forward_hub(self.from_hub.take().unwrap(), inbox_tx.clone());
+
+        self.stop_reader = Some(reader::spawn(
+            self.address.clone(),
+            backoff::new(Duration::from_secs(1), Duration::from_secs(120)),
+            inbox_tx,
+        ));
+
+        loop {
+            match inbox.recv_timeout(IDLE_WAKEUP) {
+                Ok(Input::Hub(Message::Reply(reply))) => self.send_reply(reply),
+                Ok(Input::Hub(Message::Hangup)) => break,
+                Ok(Input::Hub(_)) => (),
+                Ok(Input::Remote(FromIrc::Connected(stream))) => self.register(stream),
+                Ok(Input::Remote(FromIrc::Message(msg))) => {
+                    self.last_heard = Instant::now();
+                    self.handle_message(msg);
+                }
+                Ok(Input::Remote(FromIrc::Disconnected)) => self.disconnected(),
+                Err(mpsc::RecvTimeoutError::Timeout) => (),
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    panic!("hub hung up on us?");
+                }
+            }
+
+            self.check_in();
+        }
+
+        self.stop_reader = None;
+
+        self.send_line("QUIT", &["goodbye!"]);
+
+        if let Some(stream) = &self.stream {
+            stream.shutdown(Shutdown::Both).unwrap_or(());
+        }
+    }
+
+    // Private replies go to whoever we're replying to, and so do ephemeral
+    // ones, since that's as close as IRC gets. IRC has no line breaks, so
+    // every line is its own message, and long ones get split to fit.
+    pub fn send_reply(&mut self, reply: Reply) {
+        if !self.registered {
+            if self.pending.len() >= MAX_PENDING {
+                warn!("too many replies waiting on irc; dropping one");
+                self.pending.remove(0);
+            }
+
+            self.pending.push(reply);
+            return;
+        }
+
+        let target = if reply.is_private || reply.is_ephemeral {
+            &reply.from_address
+        } else {
+            &reply.conversation_address
+        };
+
+        let max = self.max_text_length(target);
+
+        // IRC can't send line breaks at all, so each line is its own message.
+        for piece in split::split_with(&reply.text, max, char::len_utf8) {
+            for line in piece.lines().filter(|l| !l.trim().is_empty()) {
+                self.send_line("PRIVMSG", &[target, line]);
+            }
+        }
+    }
+
+    // What's left of a line for text, once we've paid for everything else
+    // that goes in it (including what the server adds on for everyone else).
+    fn max_text_length(&self, target: &str) -> usize {
+        if let Some(n) = self.max_line_length {
+            return n;
+        }
+
+        let prefix_length = match &self.our_prefix {
+            Some(p) => p.len(),
+            None => self.current_nick.len() + self.username.len() + MAX_HOST_LENGTH + 4, // !~@
+        };
+
+        // ":prefix PRIVMSG target :text\r\n"
+        let overhead = 1 + prefix_length + " PRIVMSG ".len() + target.len() + " :\r\n".len();
+
+        protocol::MAX_LINE_BYTES.saturating_sub(overhead)
+    }
+
+    fn send_line(&mut self, command: &str, params: &[&str]) {
+        let stream = match &mut self.stream {
+            Some(s) => s,
+            None => return,
+        };
+
+        let line = protocol::format(command, params);
+
+        if let Err(e) = stream.write_all(format!("{}\r\n", line).as_bytes()) {
+            // The reader will notice soon enough, and tell us.
+            warn!("couldn't write to irc: {}", e);
+        }
+    }
+
+    fn register(&mut self, stream: TcpStream) {
+        info!("connected to irc at {}; registering", self.address);
+
+        self.stream = Some(stream);
+        self.registered = false;
+        self.current_nick = self.nick.clone();
+        self.our_prefix = None;
+        self.last_heard = Instant::now();
+
+        if let Some(password) = self.password.clone() {
+            self.send_line("PASS", &[&password]);
+        }
+
+        let (nick, username, realname) = (
+            self.nick.clone(),
+            self.username.clone(),
+            self.realname.clone(),
+        );
+
+        self.send_line("NICK", &[&nick]);
+        self.send_line("USER", &[&username, "0", "*", &realname]);
+    }
+
+    fn disconnected(&mut self) {
+        self.stream = None;
+
+        if self.registered {
+            self.registered = false;
+            channel::set_connectivity(&self.to_hub, &self.name, Connectivity::Disconnected);
+        }
+    }
+
+    fn handle_message(&mut self, msg: IrcMessage) {
+        match msg.command.as_str() {
+            "PING" => self.send_line("PONG", &[msg.param(0)]),
+
+            // RPL_WELCOME: we're in, and now we know our nick for sure.
+            "001" => {
+                self.registered = true;
+                self.set_nick(msg.param(0).to_string());
+                info!("registered on irc as {}", self.current_nick);
+
+                for channel in self.channels.clone() {
+                    self.send_line("JOIN", &[&channel]);
+                }
+
+                channel::set_connectivity(&self.to_hub, &self.name, Connectivity::Connected);
+
+                for reply in std::mem::take(&mut self.pending) {
+                    self.send_reply(reply);
+                }
+            }
+
+            // The server won't let anybody have that nick, and adding
+            // underscores won't change its mind, so there's no point going
+            // on (or coming back) until somebody fixes the config.
+            "432" if !self.registered => {
+                error!(
+                    "irc says {} isn't a valid nick (we're configured as {}); giving up",
+                    msg.param(1),
+                    self.nick
+                );
+
+                self.send_line("QUIT", &["bad nick"]);
+                self.stop_reader = None;
+
+                if let Some(stream) = self.stream.take() {
+                    stream.shutdown(Shutdown::Both).unwrap_or(());
+                }
+            }
+
+            // Somebody has our nick (or it's otherwise off limits). Before
+            // we're registered, we have to pick another one; after, it's just
+            // a failed attempt to get ours back.
+            "432" | "433" | "436" | "437" => {
+                if self.registered {
+                    debug!("still can't have nick {}", self.nick);
+                    return;
+                }
+
+                let tried = msg.param(1);
+                let next = format!("{}_", tried);
+                warn!("irc nick {} is taken; trying {}", tried, next);
+                self.send_line("NICK", &[&next]);
+            }
+
+            "NICK" => {
+                let who = msg.nick().unwrap_or_default().to_string();
+                let new_nick = msg.param(0).to_string();
+
+                if same_nick(&who, &self.current_nick) {
+                    info!("we're {} on irc now", new_nick);
+                    self.set_nick(new_nick);
+                } else if same_nick(&who, &self.nick) {
+                    self.regain_nick();
+                }
+            }
+
+            "QUIT" if msg.nick().is_some_and(|n| same_nick(n, &self.nick)) => {
+                self.regain_nick();
+            }
+
+            "JOIN" if msg.nick().is_some_and(|n| same_nick(n, &self.current_nick)) => {
+                info!("joined {}", msg.param(0));
+                self.our_prefix = msg.prefix.clone();
+            }
+
+            "KICK" if same_nick(msg.param(1), &self.current_nick) => {
+                let channel = msg.param(0).to_string();
+                warn!("kicked from {} ({}); rejoining", channel, msg.param(2));
+                self.send_line("JOIN", &[&channel]);
+            }
+
+            // Banned, invite only, full, or needs a key.
+            "471" | "473" | "474" | "475" => {
+                warn!("couldn't join {}: {}", msg.param(1), msg.param(2));
+            }
+
+            "ERROR" => warn!("irc server says: {}", msg.param(0)),
+
+            "PRIVMSG" => {
+                if let Some(event) = self.event_from_privmsg(&msg) {
+                    self.to_hub.send(Message::Event(Arc::new(event))).unwrap();
+                }
+            }
+
+            _ => (),
+        }
+    }
+
+    // Messages to a channel are for us if they start with our nick; messages
+    // to us are always for us, and the conversation is with whoever sent them.
+    fn event_from_privmsg(&self, msg: &IrcMessage) -> Option<Event> {
+        let from = msg.nick()?.to_string();
+        let target = msg.param(0);
+        let mut text = msg.param(1).to_string();
+
+        // Nobody else would send this, and we don't do CTCP.
+        if same_nick(&from, &self.current_nick) || text.starts_with('\u{1}') {
+            return None;
+        }
+
+        let (conversation, is_public, was_targeted) = if protocol::is_channel(target) {
+            let was_targeted = self.targeted_re.is_match(&text);
+
+            if was_targeted {
+                text = self.targeted_re.replace(&text, "").to_string();
+            }
+
+            (target.to_string(), true, was_targeted)
+        } else {
+            (from.clone(), false, true)
+        };
+
+        Some(Event {
+            is_public,
+            was_targeted,
+            ..Event::new(&self.name, &from, &conversation, &text)
+        })
+    }
+
+    fn set_nick(&mut self, nick: String) {
+        self.targeted_re = targeted_re(&nick);
+        self.our_prefix = None; // it has the old nick in it
+        self.current_nick = nick;
+    }
+
+    fn regain_nick(&mut self) {
+        if self.registered && !same_nick(&self.current_nick, &self.nick) {
+            self.last_regain = Instant::now();
+            let nick = self.nick.clone();
+            self.send_line("NICK", &[&nick]);
+        }
+    }
+
+    // Keep the connection honest, and try for our nick back now and then.
+    fn check_in(&mut self) {
+        if self.stream.is_none() {
+            return;
+        }
+
+        let quiet = self.last_heard.elapsed();
+
+        if quiet >= PING_AFTER * 2 {
+            warn!("irc server has gone quiet; reconnecting");
+
+            // The reader will find out, and tell us it's disconnected.
+            if let Some(stream) = &self.stream {
+                stream.shutdown(Shutdown::Both).unwrap_or(());
+            }
+
+            self.last_heard = Instant::now(); // don't do this again
+        } else if quiet >= PING_AFTER {
+            let address = self.address.clone();
+            self.send_line("PING", &[&address]);
+        }
+
+        if self.last_regain.elapsed() >= REGAIN_EVERY {
+            self.regain_nick();
+        }
+    }
+}
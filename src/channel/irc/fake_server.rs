@@ -0,0 +1,101 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+// Something pretending to be an IRC server, just enough for the channel to
+// talk to. It doesn't know the protocol at all: tests say what the server
+// says, and check what the channel says back, one line at a time.
+pub struct FakeServer {
+    listener: TcpListener,
+    pub address: String,
+}
+
+pub struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+pub fn start() -> FakeServer {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+
+    FakeServer { listener, address }
+}
+
+impl FakeServer {
+    // Wait for the channel to (re)connect.
+    pub fn accept(&self) -> Client {
+        self.try_accept(Duration::from_secs(10))
+            .expect("nobody connected")
+    }
+
+    // Same, but it's fine if nobody does.
+    pub fn try_accept(&self, wait: Duration) -> Option<Client> {
+        self.listener.set_nonblocking(true).unwrap();
+        let deadline = Instant::now() + wait;
+
+        let stream = loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => break stream,
+                Err(_) if Instant::now() < deadline => {
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                Err(_) => return None,
+            }
+        };
+
+        stream.set_nonblocking(false).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        Some(Client {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            writer: stream,
+        })
+    }
+}
+
+impl Client {
+    pub fn send(&mut self, line: &str) {
+        self.writer
+            .write_all(format!("{}\r\n", line).as_bytes())
+            .unwrap();
+    }
+
+    // The next thing the channel said, without the CRLF, or None if it hung
+    // up (or didn't say anything for a while).
+    pub fn recv(&mut self) -> Option<String> {
+        let mut line = String::new();
+
+        match self.reader.read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => {
+                assert!(line.ends_with("\r\n"), "no CRLF on {:?}", line);
+                Some(line.trim_end_matches("\r\n").to_string())
+            }
+        }
+    }
+
+    pub fn expect(&mut self, want: &str) {
+        assert_eq!(self.recv().as_deref(), Some(want));
+    }
+
+    // Like a real server: no nick collisions, no fuss.
+    pub fn register(&mut self, nick: &str) {
+        self.expect(&format!("NICK {}", nick));
+        self.expect(&format!("USER {} 0 * synergy", nick));
+        self.welcome(nick);
+    }
+
+    pub fn welcome(&mut self, nick: &str) {
+        self.send(&format!(
+            ":irc.test 001 {} :Welcome to the test network",
+            nick
+        ));
+    }
+
+    pub fn hang_up(&self) {
+        self.writer.shutdown(std::net::Shutdown::Both).unwrap();
+    }
+}
@@ -0,0 +1,184 @@
+// Just enough of RFC 1459 (and the bits of 2812 everyone uses) to get by:
+// lines are "[@tags] [:prefix] COMMAND params... [:trailing]", and that's all
+// there is to it.
+
+// Every line, including its CRLF, has to fit in this many bytes.
+pub const MAX_LINE_BYTES: usize = 512;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IrcMessage {
+    pub prefix: Option<String>,
+    pub command: String,
+    pub params: Vec<String>,
+}
+
+impl IrcMessage {
+    // "nick!user@host" => "nick"; servers don't have a !, so they're all nick.
+    pub fn nick(&self) -> Option<&str> {
+        self.prefix
+            .as_deref()
+            .map(|p| p.split('!').next().unwrap_or(p))
+    }
+
+    pub fn param(&self, i: usize) -> &str {
+        self.params.get(i).map_or("", |s| s.as_str())
+    }
+}
+
+pub fn parse(line: &str) -> Option<IrcMessage> {
+    let mut rest = line.trim_end_matches(['\r', '\n']);
+
+    // We don't ask for any capabilities, so we don't care about tags, but a
+    // server might send them anyway.
+    if rest.starts_with('@') {
+        rest = rest.split_once(' ')?.1.trim_start();
+    }
+
+    let prefix = if let Some(stripped) = rest.strip_prefix(':') {
+        let (prefix, after) = stripped.split_once(' ')?;
+        rest = after.trim_start();
+        Some(prefix.to_string())
+    } else {
+        None
+    };
+
+    let (middle, trailing) = match rest.find(" :") {
+        Some(i) => (&rest[..i], Some(&rest[i + 2..])),
+        None => match rest.strip_prefix(':') {
+            Some(t) => ("", Some(t)),
+            None => (rest, None),
+        },
+    };
+
+    let mut words = middle.split(' ').filter(|w| !w.is_empty());
+    let command = words.next()?.to_uppercase();
+    let mut params: Vec<String> = words.map(String::from).collect();
+
+    if let Some(t) = trailing {
+        params.push(t.to_string());
+    }
+
+    Some(IrcMessage {
+        prefix,
+        command,
+        params,
+    })
+}
+
+// A line to send, without the CRLF. Only the last param can have spaces in it
+// (or be empty), and nothing can have line breaks.
+pub fn format(command: &str, params: &[&str]) -> String {
+    let mut line = command.to_string();
+
+    for (i, param) in params.iter().enumerate() {
+        let is_last = i == params.len() - 1;
+        let param = param.replace(['\r', '\n'], " ");
+
+        if is_last && (param.is_empty() || param.contains(' ') || param.starts_with(':')) {
+            line.push_str(" :");
+        } else {
+            line.push(' ');
+        }
+
+        line.push_str(&param);
+    }
+
+    line
+}
+
+pub fn is_channel(target: &str) -> bool {
+    target.starts_with(['#', '&', '+', '!'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(prefix: Option<&str>, command: &str, params: &[&str]) -> IrcMessage {
+        IrcMessage {
+            prefix: prefix.map(String::from),
+            command: command.into(),
+            params: params.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn parsing() {
+        let cases = vec![
+            (
+                "PING :irc.example.com",
+                msg(None, "PING", &["irc.example.com"]),
+            ),
+            (
+                "PING irc.example.com\r\n",
+                msg(None, "PING", &["irc.example.com"]),
+            ),
+            (
+                ":alice!al@example.com PRIVMSG #general :synergy: echo hi",
+                msg(
+                    Some("alice!al@example.com"),
+                    "PRIVMSG",
+                    &["#general", "synergy: echo hi"],
+                ),
+            ),
+            (
+                ":irc.example.com 001 synergy :Welcome to IRC",
+                msg(
+                    Some("irc.example.com"),
+                    "001",
+                    &["synergy", "Welcome to IRC"],
+                ),
+            ),
+            (
+                ":irc.example.com 433 * synergy :Nickname is already in use",
+                msg(
+                    Some("irc.example.com"),
+                    "433",
+                    &["*", "synergy", "Nickname is already in use"],
+                ),
+            ),
+            (
+                "@time=2020-01-01T00:00:00Z :bob!b@h privmsg  bob  ::-)",
+                msg(Some("bob!b@h"), "PRIVMSG", &["bob", ":-)"]),
+            ),
+            (
+                ":bob!b@h PRIVMSG bob :",
+                msg(Some("bob!b@h"), "PRIVMSG", &["bob", ""]),
+            ),
+            (
+                ":bob!b@h NICK :robert",
+                msg(Some("bob!b@h"), "NICK", &["robert"]),
+            ),
+        ];
+
+        for (line, want) in cases {
+            assert_eq!(parse(line), Some(want), "parsing {:?}", line);
+        }
+
+        assert_eq!(parse(""), None);
+        assert_eq!(parse(":prefix-only"), None);
+        assert_eq!(
+            parse(":alice!al@example.com QUIT").unwrap().nick(),
+            Some("alice")
+        );
+    }
+
+    #[test]
+    fn formatting() {
+        assert_eq!(format("NICK", &["synergy"]), "NICK synergy");
+        assert_eq!(
+            format("PRIVMSG", &["#general", "hello there"]),
+            "PRIVMSG #general :hello there"
+        );
+        assert_eq!(format("PRIVMSG", &["bob", ":-)"]), "PRIVMSG bob ::-)");
+        assert_eq!(format("PRIVMSG", &["bob", ""]), "PRIVMSG bob :");
+        assert_eq!(
+            format("PRIVMSG", &["bob", "one\r\ntwo"]),
+            "PRIVMSG bob :one  two"
+        );
+        assert_eq!(
+            format("USER", &["synergy", "0", "*", "Synergy Bot"]),
+            "USER synergy 0 * :Synergy Bot"
+        );
+    }
+}
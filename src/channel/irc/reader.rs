@@ -0,0 +1,116 @@
+use std::io::{BufRead, BufReader};
+use std::net::TcpStream;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::channel::backoff::Backoff;
+use crate::channel::{self, Input};
+
+use super::protocol::{self, IrcMessage};
+
+// If a connection lasted at least this long, it was working, and losing it
+// isn't a reason to wait before trying again.
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+// What the reader has to say to the rest of the channel. Connected comes with
+// a handle on the same socket, for writing.
+pub enum FromIrc {
+    Connected(TcpStream),
+    Message(IrcMessage),
+    Disconnected,
+}
+
+// The reader owns the connection, and does nothing but (re)connect and pass
+// along whatever comes in; everything else, even PONGs, is up to the channel.
+// When it's time to stop, the channel can hurry it along by shutting the
+// socket.
+pub fn spawn(
+    address: String,
+    mut backoff: Backoff,
+    to_channel: mpsc::Sender<Input<FromIrc>>,
+) -> mpsc::Sender<()> {
+    channel::spawn_reader(move |should_stop| loop {
+        let stream = loop {
+            if should_stop() {
+                return;
+            }
+
+            match TcpStream::connect(&address) {
+                Ok(stream) => break stream,
+                Err(e) => {
+                    let delay = backoff.next_delay();
+                    warn!(
+                        "error connecting to irc at {} ({}); retrying in {:?}",
+                        address, e, delay
+                    );
+                    thread::sleep(delay);
+                }
+            }
+        };
+
+        let connected_at = Instant::now();
+
+        let writer = match stream.try_clone() {
+            Ok(w) => w,
+            Err(e) => {
+                error!("couldn't clone irc socket: {}", e);
+                continue;
+            }
+        };
+
+        if to_channel
+            .send(Input::Remote(FromIrc::Connected(writer)))
+            .is_err()
+        {
+            return;
+        }
+
+        // Servers are meant to send utf-8 these days, but not everyone got
+        // the memo, so we take what we can get.
+        let mut reader = BufReader::new(stream);
+        let mut buf = vec![];
+
+        loop {
+            buf.clear();
+
+            match reader.read_until(b'\n', &mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => (),
+            }
+
+            let line = String::from_utf8_lossy(&buf);
+
+            let msg = match protocol::parse(&line) {
+                Some(msg) => msg,
+                None => {
+                    if !line.trim().is_empty() {
+                        warn!("couldn't make sense of irc line {:?}", line);
+                    }
+                    continue;
+                }
+            };
+
+            if to_channel
+                .send(Input::Remote(FromIrc::Message(msg)))
+                .is_err()
+            {
+                return;
+            }
+        }
+
+        if should_stop()
+            || to_channel
+                .send(Input::Remote(FromIrc::Disconnected))
+                .is_err()
+        {
+            return;
+        }
+
+        if connected_at.elapsed() >= STABLE_AFTER {
+            backoff.reset();
+        } else {
+            thread::sleep(backoff.next_delay());
+        }
+    })
+}
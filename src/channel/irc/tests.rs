@@ -0,0 +1,276 @@
+use std::time::Duration;
+
+use super::fake_server::{self, Client, FakeServer};
+use super::protocol;
+use crate::channel::testing;
+use crate::channel::Type;
+use crate::message::{Connectivity, Event};
+
+// with the fake server it's connected to
+type Harness = testing::Harness<Fakes>;
+
+struct Fakes {
+    server: FakeServer,
+    client: Client,
+}
+
+// We always say goodbye.
+impl testing::Fakes for Fakes {
+    fn hung_up(&mut self) {
+        self.client.expect("QUIT goodbye!");
+    }
+}
+
+// Connects, but doesn't register: that's up to the test.
+fn start_unregistered(extra: Vec<(&str, toml::Value)>) -> Harness {
+    let server = fake_server::start();
+
+    let config = testing::config(
+        vec![
+            ("server", server.address.clone().into()),
+            ("nick", "synergy".into()),
+            ("channels", toml::Value::Array(vec!["#general".into()])),
+        ],
+        extra,
+    );
+
+    let channel = testing::start("channel/irc", Type::IrcChannel, config, ":memory:");
+    let client = server.accept();
+
+    Harness {
+        channel,
+        fakes: Fakes { server, client },
+    }
+}
+
+fn start_channel() -> Harness {
+    let mut h = start_unregistered(vec![]);
+    h.fakes.client.register("synergy");
+    h.fakes.client.expect("JOIN #general");
+    h.expect_connectivity(Connectivity::Connected);
+    h
+}
+
+impl Harness {
+    fn say(&mut self, from: &str, target: &str, text: &str) -> Event {
+        self.fakes.client.send(&format!(
+            ":{}!{}@example.com PRIVMSG {} :{}",
+            from, from, target, text
+        ));
+        self.next_event()
+    }
+}
+
+#[test]
+fn registration() {
+    let mut h = start_unregistered(vec![
+        ("password", "hunter2".into()),
+        ("username", "bot".into()),
+        ("realname", "Synergy Bot".into()),
+        (
+            "channels",
+            toml::Value::Array(vec!["#general".into(), "#alerts".into()]),
+        ),
+    ]);
+
+    h.fakes.client.expect("PASS hunter2");
+    h.fakes.client.expect("NICK synergy");
+    h.fakes.client.expect("USER bot 0 * :Synergy Bot");
+
+    // nothing happens until the server says we're in
+    h.fakes.client.welcome("synergy");
+    h.fakes.client.expect("JOIN #general");
+    h.fakes.client.expect("JOIN #alerts");
+    h.expect_connectivity(Connectivity::Connected);
+
+    h.fakes.client.send("PING :irc.test");
+    h.fakes.client.expect("PONG irc.test");
+
+    // and if we get kicked, we go right back
+    h.fakes
+        .client
+        .send(":op!op@example.com KICK #alerts synergy :go away");
+    h.fakes.client.expect("JOIN #alerts");
+
+    h.hangup();
+}
+
+#[test]
+fn privmsgs_become_events() {
+    let mut h = start_channel();
+
+    let event = h.say("alice", "#general", "synergy: echo hi");
+    assert_eq!(event.text, "echo hi");
+    assert!(event.was_targeted);
+    assert!(event.is_public);
+    assert_eq!(event.from_address, "alice");
+    assert_eq!(event.conversation_address, "#general");
+    assert_eq!(event.origin, "channel/irc");
+
+    let event = h.say("alice", "#general", "Synergy, clox");
+    assert_eq!(event.text, "clox");
+    assert!(event.was_targeted);
+
+    let event = h.say("alice", "#general", "synergy is great");
+    assert_eq!(event.text, "synergy is great");
+    assert!(!event.was_targeted);
+
+    // queries are always for us, and the conversation is with the sender
+    let event = h.say("bob", "synergy", "clox");
+    assert!(event.was_targeted);
+    assert!(!event.is_public);
+    assert_eq!(event.conversation_address, "bob");
+
+    // and CTCP isn't for anyone
+    h.fakes
+        .client
+        .send(":bob!bob@example.com PRIVMSG synergy :\u{1}VERSION\u{1}");
+    let event = h.say("bob", "synergy", "still there?");
+    assert_eq!(event.text, "still there?");
+
+    h.hangup();
+}
+
+#[test]
+fn replies() {
+    let mut h = start_channel();
+
+    let event = h.say("alice", "#general", "synergy: hi");
+
+    h.to_channel
+        .send(event.reply("hello\nthere", "reactor/test"))
+        .unwrap();
+    h.fakes.client.expect("PRIVMSG #general hello");
+    h.fakes.client.expect("PRIVMSG #general there");
+
+    h.to_channel
+        .send(event.private_reply("psst", "reactor/test").unwrap())
+        .unwrap();
+    h.fakes.client.expect("PRIVMSG alice psst");
+
+    h.to_channel
+        .send(event.ephemeral_reply("just you", "reactor/test"))
+        .unwrap();
+    h.fakes.client.expect("PRIVMSG alice :just you");
+
+    h.hangup();
+}
+
+#[test]
+fn long_replies_are_split() {
+    let mut h = start_channel();
+
+    let event = h.say("alice", "#general", "synergy: talk a lot");
+
+    let words: Vec<String> = (0..300).map(|i| format!("word{}", i)).collect();
+    let text = words.join(" ");
+    h.to_channel
+        .send(event.reply(&text, "reactor/test"))
+        .unwrap();
+
+    // what everyone else will see, with our prefix in front
+    let prefix = ":synergy!synergy@some-really-long-hostname-that-goes-on-and-on.example.com ";
+
+    let mut got = vec![];
+    while got.join(" ").len() < text.len() {
+        let line = h.fakes.client.recv().expect("ran out of lines");
+        assert!(
+            prefix.len() + line.len() + 2 <= protocol::MAX_LINE_BYTES,
+            "too long ({}): {:?}",
+            line.len(),
+            line
+        );
+
+        let msg = protocol::parse(&line).unwrap();
+        assert_eq!(msg.params[0], "#general");
+        got.push(msg.params[1].clone());
+    }
+
+    assert!(got.len() > 1);
+    assert_eq!(got.join(" "), text);
+
+    h.hangup();
+}
+
+#[test]
+fn nick_collisions() {
+    let mut h = start_unregistered(vec![]);
+
+    h.fakes.client.expect("NICK synergy");
+    h.fakes.client.expect("USER synergy 0 * synergy");
+    h.fakes
+        .client
+        .send(":irc.test 433 * synergy :Nickname is already in use");
+    h.fakes.client.expect("NICK synergy_");
+    h.fakes
+        .client
+        .send(":irc.test 433 * synergy_ :Nickname is already in use");
+    h.fakes.client.expect("NICK synergy__");
+
+    h.fakes.client.welcome("synergy__");
+    h.fakes.client.expect("JOIN #general");
+    h.expect_connectivity(Connectivity::Connected);
+
+    // we answer to the nick we've got
+    let event = h.say("alice", "#general", "synergy__: hi");
+    assert!(event.was_targeted);
+    assert_eq!(event.text, "hi");
+
+    // when whoever has our nick leaves, we take it back
+    h.fakes
+        .client
+        .send(":synergy!someone@example.com QUIT :bye");
+    h.fakes.client.expect("NICK synergy");
+    h.fakes
+        .client
+        .send(":synergy__!synergy@example.com NICK :synergy");
+
+    let event = h.say("alice", "#general", "synergy: hi again");
+    assert!(event.was_targeted);
+    assert_eq!(event.text, "hi again");
+
+    let event = h.say("alice", "#general", "synergy__: anyone?");
+    assert!(!event.was_targeted);
+
+    h.hangup();
+}
+
+#[test]
+fn invalid_nicks() {
+    let mut h = start_unregistered(vec![]);
+
+    h.fakes.client.expect("NICK synergy");
+    h.fakes.client.expect("USER synergy 0 * synergy");
+    h.fakes
+        .client
+        .send(":irc.test 432 * synergy :Erroneous nickname");
+
+    // no synergy_, and no coming back for more
+    h.fakes.client.expect("QUIT :bad nick");
+    assert_eq!(h.fakes.client.recv(), None);
+    assert!(h.fakes.server.try_accept(Duration::from_secs(2)).is_none());
+
+    h.channel.hangup();
+}
+
+#[test]
+fn reconnects() {
+    let mut h = start_channel();
+    let event = h.say("alice", "#general", "synergy: hi");
+
+    h.fakes.client.hang_up();
+    h.expect_connectivity(Connectivity::Disconnected);
+
+    // replies wait until we're back
+    h.to_channel
+        .send(event.reply("welcome back", "reactor/test"))
+        .unwrap();
+
+    h.fakes.client = h.fakes.server.accept();
+    h.fakes.client.register("synergy");
+    h.fakes.client.expect("JOIN #general");
+    h.expect_connectivity(Connectivity::Connected);
+    h.fakes.client.expect("PRIVMSG #general :welcome back");
+
+    h.hangup();
+}
@@ -0,0 +1,387 @@
+mod client;
+#[cfg(test)]
+mod fake_server;
+mod html;
+mod reader;
+mod rooms;
+mod sync;
+#[cfg(test)]
+mod tests;
+
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use regex::Regex;
+use serde_json::{json, Value};
+
+use crate::channel::backoff;
+use crate::channel::{self, split, Input, Seed};
+use crate::message::{Connectivity, Event, Message, Reply};
+use crate::state::{self, StateDb};
+use client::{Client, ClientError};
+use reader::FromMatrix;
+use rooms::Rooms;
+use sync::{InvitedRoom, RoomEvent, SyncResponse};
+
+// How long the homeserver should hold a /sync open, waiting for something to
+// happen, before coming back empty-handed.
+const DEFAULT_SYNC_TIMEOUT: u64 = 30;
+
+// Events can be up to 64KiB, json and all, but nobody wants to read that.
+const DEFAULT_MAX_MESSAGE_LENGTH: usize = 8000;
+
+// Matrix has no DMs or channels, just rooms; a room's id ("!abc:example.org")
+// is its conversation address, and people are known by their user ids
+// ("@alice:example.org"). The reader (see reader.rs) long-polls /sync, and
+// this thread turns what comes back into Events, and Replies into messages.
+pub struct Matrix {
+    pub name: String,
+    client: Client,
+    to_hub: mpsc::Sender<Message>,
+    from_hub: Option<mpsc::Receiver<Message>>, // until forward_hub takes it
+    state: Option<StateDb>,
+    sync_timeout: Duration,
+    auto_join: bool,
+    max_message_length: usize,
+
+    our_id: Option<String>,
+    targeted_re: Regex,
+    rooms: Rooms,
+}
+
+pub fn build(seed: Seed) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut channel = self::new(seed);
+        channel.start();
+    })
+}
+
+pub fn new(seed: Seed) -> Matrix {
+    let extra = &seed.config.extra;
+
+    let config_str = |key| extra.get(key).and_then(|v| v.as_str());
+
+    // This is the only way to find the homeserver; we don't do .well-known.
+    let homeserver = config_str("homeserver").expect("no homeserver for matrix in config!");
+    let access_token = config_str("access_token").expect("no access_token for matrix in config!");
+
+    // If we can't get at the state db, we can live without it, but we'll
+    // start from scratch every time.
+    let state = match state::open(&seed.state_dbfile) {
+        Ok(db) => Some(db),
+        Err(e) => {
+            warn!("couldn't open state db {}: {}", seed.state_dbfile, e);
+            None
+        }
+    };
+
+    Matrix {
+        name: seed.name.clone(),
+        client: client::new(homeserver, access_token),
+        to_hub: seed.output,
+        from_hub: Some(seed.input),
+        state,
+        sync_timeout: Duration::from_secs(
+            extra
+                .get("sync_timeout")
+                .and_then(|v| v.as_integer())
+                .map_or(DEFAULT_SYNC_TIMEOUT, |n| n as u64),
+        ),
+        auto_join: extra
+            .get("auto_join")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(true),
+        max_message_length: extra
+            .get("max_message_length")
+            .and_then(|v| v.as_integer())
+            .map_or(DEFAULT_MAX_MESSAGE_LENGTH, |n| n as usize),
+        our_id: None,
+        targeted_re: Regex::new("^$").unwrap(),
+        rooms: rooms::new(),
+    }
+}
+
+impl Matrix {
+    fn start(&mut self) {
+        let (inbox_tx, inbox) = mpsc::channel();
+
+        channel::forward_hub(self.from_hub.take().unwrap(), inbox_tx.clone());
+
+        let _stop_reader = reader::spawn(
+            self.client.clone(),
+            self.saved_next_batch(),
+            self.sync_timeout,
+            backoff::new(Duration::from_secs(1), Duration::from_secs(120)),
+            inbox_tx,
+        );
+
+        for input in inbox {
+            match input {
+                Input::Hub(Message::Reply(reply)) => self.send_reply(reply),
+                Input::Hub(Message::Hangup) => break,
+                Input::Hub(_) => (),
+                Input::Remote(FromMatrix::Connected(user_id)) => {
+                    self.set_identity(user_id);
+                    channel::set_connectivity(&self.to_hub, &self.name, Connectivity::Connected);
+                }
+                Input::Remote(FromMatrix::Disconnected) => {
+                    channel::set_connectivity(&self.to_hub, &self.name, Connectivity::Disconnected);
+                }
+                Input::Remote(FromMatrix::Synced { response, initial }) => {
+                    self.handle_sync(response, initial);
+                }
+            }
+        }
+    }
+
+    fn state_key(&self) -> String {
+        format!("{}/next_batch", self.name)
+    }
+
+    fn saved_next_batch(&self) -> Option<String> {
+        self.state
+            .as_ref()
+            .and_then(|db| db.fetch(&self.state_key()))
+            .and_then(|v| v.as_str().map(String::from))
+    }
+
+    // People mention us by display name, mostly, since that's what clients
+    // fill in, but the localpart or whole user id will do too.
+    fn set_identity(&mut self, user_id: String) {
+        if self.our_id.as_ref() == Some(&user_id) {
+            return;
+        }
+
+        info!("we are {} on matrix", user_id);
+
+        let localpart = user_id
+            .trim_start_matches('@')
+            .split(':')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+
+        // whole id first, or "@us:example.org:" only loses "@us:"
+        let mut names = vec![user_id.trim_start_matches('@').to_string(), localpart];
+
+        match self.client.display_name(&user_id) {
+            Ok(Some(name)) => names.push(name),
+            Ok(None) => (),
+            Err(e) => warn!("couldn't get our matrix display name: {}", e),
+        }
+
+        let names: Vec<_> = names.iter().map(|n| regex::escape(n)).collect();
+
+        self.targeted_re = Regex::new(&format!(r"^(?i)@?(?:{})[:,]\s*", names.join("|"))).unwrap();
+        self.our_id = Some(user_id);
+    }
+
+    // We save next_batch only once we've dealt with everything before it, so
+    // if we die halfway through, we'll hear it all again rather than miss
+    // anything.
+    fn handle_sync(&mut self, response: SyncResponse, initial: bool) {
+        for (room_id, invite) in &response.rooms.invite {
+            self.handle_invite(room_id, invite);
+        }
+
+        for (room_id, joined) in &response.rooms.join {
+            self.rooms.update(room_id, joined, self.our_id.as_deref());
+
+            // The first sync ever is a bunch of history that we don't want
+            // to go answering now.
+            if initial {
+                continue;
+            }
+
+            for raw in &joined.timeline.events {
+                if let Some(event) = self.event_from_raw(room_id, raw) {
+                    self.to_hub.send(Message::Event(Arc::new(event))).unwrap();
+                }
+            }
+        }
+
+        for room_id in response.rooms.leave.keys() {
+            self.rooms.forget(room_id);
+        }
+
+        if let Some(db) = &self.state {
+            db.save(&self.state_key(), &json!(response.next_batch));
+        }
+    }
+
+    fn handle_invite(&mut self, room_id: &str, invite: &InvitedRoom) {
+        let our_id = match &self.our_id {
+            Some(id) => id,
+            None => return,
+        };
+
+        // the invite itself is our own membership event
+        let event = invite
+            .invite_state
+            .events
+            .iter()
+            .find(|e| e.kind == "m.room.member" && e.state_key.as_ref() == Some(our_id));
+
+        let (inviter, is_direct) = match event {
+            Some(e) => (
+                e.sender.clone(),
+                e.content["is_direct"].as_bool().unwrap_or(false),
+            ),
+            None => return,
+        };
+
+        if !self.auto_join {
+            info!(
+                "{} invited us to {}, but we're not joining",
+                inviter, room_id
+            );
+            return;
+        }
+
+        match self.client.join(room_id) {
+            Ok(()) => {
+                info!("joined {} at the invitation of {}", room_id, inviter);
+                if is_direct {
+                    self.rooms.note_dm(&inviter, room_id);
+                }
+            }
+            Err(e) => warn!("couldn't join {}: {}", room_id, e),
+        }
+    }
+
+    fn event_from_raw(&self, room_id: &str, raw: &RoomEvent) -> Option<Event> {
+        if raw.kind != "m.room.message" || Some(&raw.sender) == self.our_id.as_ref() {
+            return None;
+        }
+
+        // Notices are what bots send, so we don't answer them, and edits are
+        // a second copy of something we've already seen.
+        let content = &raw.content;
+        if raw.content_str("msgtype") != Some("m.text")
+            || content["m.relates_to"]["rel_type"] == "m.replace"
+        {
+            return None;
+        }
+
+        let mut text = raw.content_str("body")?.to_string();
+
+        // Replies quote what they're replying to at the top, which isn't what
+        // anyone said just now.
+        if content["m.relates_to"]["m.in_reply_to"].is_object() {
+            text = strip_reply_fallback(&text);
+        }
+
+        let mentioned = self.our_id.as_ref().is_some_and(|us| {
+            let in_mentions = content["m.mentions"]["user_ids"]
+                .as_array()
+                .is_some_and(|ids| ids.iter().any(|id| id == us.as_str()));
+
+            let in_pill = raw
+                .content_str("formatted_body")
+                .is_some_and(|html| html.contains(&format!("matrix.to/#/{}", us)));
+
+            in_mentions || in_pill
+        });
+
+        let mut was_targeted = mentioned || self.rooms.is_dm(room_id);
+
+        if self.targeted_re.is_match(&text) {
+            text = self.targeted_re.replace(&text, "").to_string();
+            was_targeted = true;
+        }
+
+        Some(Event {
+            is_public: self.rooms.is_public(room_id),
+            was_targeted,
+            ..Event::new(&self.name, &raw.sender, room_id, text.trim())
+        })
+    }
+
+    // Private replies go to a DM, which we'll start if there isn't one; so
+    // does anything addressed to a user id instead of a room.
+    pub fn send_reply(&mut self, reply: Reply) {
+        let user_id = if reply.is_private || reply.is_ephemeral {
+            Some(&reply.from_address)
+        } else if reply.conversation_address.starts_with('@') {
+            Some(&reply.conversation_address)
+        } else {
+            None
+        };
+
+        let room_id = match user_id {
+            Some(user_id) => match self.dm_with(user_id) {
+                Some(room_id) => room_id,
+                None => {
+                    error!(
+                        "no dm with {}; dropping private reply from {}",
+                        user_id, reply.origin
+                    );
+                    return;
+                }
+            },
+            None => reply.conversation_address.clone(),
+        };
+
+        for text in split::split(&reply.text, self.max_message_length) {
+            let content = json!({
+                "msgtype": "m.text",
+                "body": text,
+                "format": "org.matrix.custom.html",
+                "formatted_body": html::render(&text),
+            });
+
+            // send_message has already waited out any rate limit, so this
+            // is the homeserver turning us away from the room, and it'll
+            // turn away the rest of the reply too.
+            if let Err(e) = self.send_message(&room_id, &content) {
+                error!("couldn't send to {}: {}", room_id, e);
+                return;
+            }
+        }
+    }
+
+    fn dm_with(&mut self, user_id: &str) -> Option<String> {
+        if let Some(room_id) = self.rooms.dm_for(user_id) {
+            return Some(room_id.to_string());
+        }
+
+        match self.client.create_dm(user_id) {
+            Ok(room_id) => {
+                info!("started a dm with {} in {}", user_id, room_id);
+                self.rooms.note_dm(user_id, &room_id);
+                Some(room_id)
+            }
+            Err(e) => {
+                warn!("couldn't start a dm with {}: {}", user_id, e);
+                None
+            }
+        }
+    }
+
+    // If the homeserver says slow down, we do as we're told, once.
+    fn send_message(&self, room_id: &str, content: &Value) -> Result<(), ClientError> {
+        let txn_id = Event::new_id();
+
+        match self.client.send(room_id, &txn_id, content) {
+            Err(ClientError::RateLimited(delay)) => {
+                thread::sleep(delay);
+                self.client.send(room_id, &txn_id, content)
+            }
+            res => res,
+        }
+    }
+}
+
+// A reply's body starts with the message it's replying to, as "> " lines,
+// then a blank line, then the reply itself.
+fn strip_reply_fallback(body: &str) -> String {
+    let mut lines = body.lines().peekable();
+
+    if !lines.peek().is_some_and(|l| l.starts_with("> ")) {
+        return body.to_string();
+    }
+
+    let rest: Vec<_> = lines.skip_while(|l| l.starts_with('>')).collect();
+    rest.join("\n").trim_start().to_string()
+}
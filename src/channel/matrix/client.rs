@@ -0,0 +1,183 @@
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+use reqwest::blocking::{Client as HttpClient, RequestBuilder};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+
+use super::sync::SyncResponse;
+
+// How long past the sync timeout we'll wait for the homeserver to answer
+// before deciding it's not going to.
+const SYNC_GRACE: Duration = Duration::from_secs(15);
+
+// Just enough of the client-server API to sit in rooms and talk. Everything
+// is authenticated with the access token, and everything's under the
+// homeserver's /_matrix/client/v3.
+#[derive(Clone)]
+pub struct Client {
+    api_url: String,
+    access_token: String,
+    http: HttpClient,
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    RateLimited(Duration),
+    Failed(String),
+}
+
+impl Error for ClientError {}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::RateLimited(d) => write!(f, "rate limited for {:?}", d),
+            ClientError::Failed(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+pub fn new(homeserver: &str, access_token: &str) -> Client {
+    Client {
+        api_url: format!("{}/_matrix/client/v3", homeserver.trim_end_matches('/')),
+        access_token: access_token.to_string(),
+        http: HttpClient::builder().timeout(None).build().unwrap(),
+    }
+}
+
+// Room ids look like "!abc:example.org", which needs escaping to go in a path.
+fn encode(s: &str) -> String {
+    url::form_urlencoded::byte_serialize(s.as_bytes()).collect()
+}
+
+impl Client {
+    // Who the access token says we are.
+    pub fn whoami(&self) -> Result<String, ClientError> {
+        let body = self.call(self.get("/account/whoami"))?;
+
+        body["user_id"]
+            .as_str()
+            .map(String::from)
+            .ok_or_else(|| ClientError::Failed("no user_id from whoami".into()))
+    }
+
+    pub fn display_name(&self, user_id: &str) -> Result<Option<String>, ClientError> {
+        let path = format!("/profile/{}/displayname", encode(user_id));
+        let body = self.call(self.get(&path))?;
+        Ok(body["displayname"].as_str().map(String::from))
+    }
+
+    // Without since, this is an initial sync: everything as of now. With it,
+    // rooms' state only comes back if it's changed, unless we ask for
+    // full_state.
+    pub fn sync(
+        &self,
+        since: Option<&str>,
+        full_state: bool,
+        timeout: Duration,
+    ) -> Result<SyncResponse, ClientError> {
+        let mut query = vec![("timeout", timeout.as_millis().to_string())];
+
+        if let Some(since) = since {
+            query.push(("since", since.to_string()));
+        }
+
+        if full_state {
+            query.push(("full_state", "true".to_string()));
+        }
+
+        let req = self
+            .get("/sync")
+            .query(&query)
+            .timeout(timeout + SYNC_GRACE);
+
+        let body = self.call(req)?;
+
+        serde_json::from_value(body)
+            .map_err(|e| ClientError::Failed(format!("couldn't parse sync: {}", e)))
+    }
+
+    pub fn join(&self, room_id: &str) -> Result<(), ClientError> {
+        let path = format!("/join/{}", encode(room_id));
+        self.call(self.post(&path).json(&json!({})))?;
+        Ok(())
+    }
+
+    // Start a DM, and hand back its room id.
+    pub fn create_dm(&self, user_id: &str) -> Result<String, ClientError> {
+        let body = json!({
+            "is_direct": true,
+            "invite": [user_id],
+            "preset": "trusted_private_chat",
+        });
+
+        let res = self.call(self.post("/createRoom").json(&body))?;
+
+        res["room_id"]
+            .as_str()
+            .map(String::from)
+            .ok_or_else(|| ClientError::Failed("no room_id from createRoom".into()))
+    }
+
+    // Transaction ids are how the homeserver knows a retry isn't a second
+    // message, so a retry needs to use the same one.
+    pub fn send(&self, room_id: &str, txn_id: &str, content: &Value) -> Result<(), ClientError> {
+        let path = format!(
+            "/rooms/{}/send/m.room.message/{}",
+            encode(room_id),
+            encode(txn_id)
+        );
+
+        let url = format!("{}{}", self.api_url, path);
+        let req = self
+            .http
+            .put(&url)
+            .bearer_auth(&self.access_token)
+            .timeout(Duration::from_secs(30))
+            .json(content);
+
+        self.call(req)?;
+        Ok(())
+    }
+
+    fn get(&self, path: &str) -> RequestBuilder {
+        self.http
+            .get(&format!("{}{}", self.api_url, path))
+            .bearer_auth(&self.access_token)
+            .timeout(Duration::from_secs(30))
+    }
+
+    fn post(&self, path: &str) -> RequestBuilder {
+        self.http
+            .post(&format!("{}{}", self.api_url, path))
+            .bearer_auth(&self.access_token)
+            .timeout(Duration::from_secs(30))
+    }
+
+    // Errors come back as {"errcode": "M_WHATEVER", "error": "words"}, and
+    // rate limits say how long to wait.
+    fn call(&self, req: RequestBuilder) -> Result<Value, ClientError> {
+        let res = req.send().map_err(|e| ClientError::Failed(e.to_string()))?;
+
+        let status = res.status();
+        let body: Value = res.json().unwrap_or(Value::Null);
+
+        if status.is_success() {
+            return Ok(body);
+        }
+
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            let ms = body["retry_after_ms"].as_u64().unwrap_or(1000);
+            return Err(ClientError::RateLimited(Duration::from_millis(ms)));
+        }
+
+        Err(ClientError::Failed(format!(
+            "{} ({}: {})",
+            status,
+            body["errcode"].as_str().unwrap_or("?"),
+            body["error"].as_str().unwrap_or("no explanation"),
+        )))
+    }
+}
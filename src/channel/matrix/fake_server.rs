@@ -0,0 +1,211 @@
+// A pretend homeserver, for tests. It answers whoami and displayname, hands
+// out whatever syncs the test queues up (or nothing, once the client's
+// timeout is up), and records every call so the test can look at it. Like a
+// real one, it only sends rooms' state (whatever the test set) on an initial
+// sync, or when asked for full_state.
+// Point a channel's homeserver at server.url and off you go.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tiny_http::{Request, Response};
+
+use crate::channel::{self, testing, Listener};
+
+pub const BOT_ID: &str = "@synergy:localhost";
+pub const BOT_NAME: &str = "Synergy";
+pub const DM_ROOM: &str = "!dm:localhost"; // what createRoom hands out
+
+// One call to the API, as we received it, with the /_matrix/client/v3 taken
+// off the front and the path decoded.
+#[derive(Debug, Clone)]
+pub struct Call {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    pub body: Value,
+    pub auth: Option<String>,
+}
+
+pub struct FakeServer {
+    pub url: String,
+    calls: mpsc::Receiver<Call>,
+    syncs: mpsc::Sender<Value>,
+    send_statuses: Arc<Mutex<VecDeque<(u16, Value)>>>,
+    room_state: Arc<Mutex<HashMap<String, Vec<Value>>>>,
+    _listener: Listener,
+}
+
+struct Shared {
+    syncs: Mutex<mpsc::Receiver<Value>>,
+    batch: AtomicUsize,
+    calls: Mutex<mpsc::Sender<Call>>,
+    send_statuses: Arc<Mutex<VecDeque<(u16, Value)>>>,
+    room_state: Arc<Mutex<HashMap<String, Vec<Value>>>>,
+}
+
+pub fn start() -> FakeServer {
+    let address = testing::free_address();
+
+    let (calls_tx, calls) = mpsc::channel();
+    let (syncs, syncs_rx) = mpsc::channel();
+    let send_statuses = Arc::new(Mutex::new(VecDeque::new()));
+    let room_state = Arc::new(Mutex::new(HashMap::new()));
+
+    let shared = Arc::new(Shared {
+        syncs: Mutex::new(syncs_rx),
+        batch: AtomicUsize::new(0),
+        calls: Mutex::new(calls_tx),
+        send_statuses: Arc::clone(&send_statuses),
+        room_state: Arc::clone(&room_state),
+    });
+
+    // Syncs hang around, so everything gets its own thread.
+    let listener = channel::listen(&address, move |request| {
+        let shared = Arc::clone(&shared);
+        thread::spawn(move || serve(request, &shared));
+    })
+    .unwrap();
+
+    FakeServer {
+        url: format!("http://{}", address),
+        calls,
+        syncs,
+        send_statuses,
+        room_state,
+        _listener: listener,
+    }
+}
+
+impl FakeServer {
+    // Queue up the rooms part of a sync response; next_batch is taken care of.
+    pub fn sync(&self, rooms: Value) {
+        self.syncs.send(rooms).unwrap();
+    }
+
+    // The state of a room we're in, for syncs that want all of it.
+    pub fn set_state(&self, room_id: &str, events: Vec<Value>) {
+        self.room_state
+            .lock()
+            .unwrap()
+            .insert(room_id.to_string(), events);
+    }
+
+    // Answer the next send with this, instead of success.
+    pub fn fail_send(&self, status: u16, body: Value) {
+        self.send_statuses.lock().unwrap().push_back((status, body));
+    }
+
+    // Wait for the next call whose path starts with this, skipping others.
+    pub fn recv_call(&self, method: &str, path: &str) -> Option<Call> {
+        loop {
+            match self.calls.recv_timeout(Duration::from_secs(5)) {
+                Ok(call) if call.method == method && call.path.starts_with(path) => {
+                    return Some(call)
+                }
+                Ok(_) => continue,
+                Err(_) => return None,
+            }
+        }
+    }
+
+    // Nothing like this gets called for a little while.
+    pub fn no_call(&self, method: &str, path: &str) -> bool {
+        while let Ok(call) = self.calls.recv_timeout(Duration::from_millis(300)) {
+            if call.method == method && call.path.starts_with(path) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+fn serve(mut request: Request, shared: &Shared) {
+    let mut body = String::new();
+    let _ = request.as_reader().read_to_string(&mut body);
+
+    let (path, query) = match request.url().split_once('?') {
+        Some((p, q)) => (p.to_string(), q.to_string()),
+        None => (request.url().to_string(), String::new()),
+    };
+
+    let path: String = percent_decode(path.trim_start_matches("/_matrix/client/v3"));
+
+    let call = Call {
+        method: request.method().to_string(),
+        path: path.clone(),
+        query: url::form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect(),
+        body: serde_json::from_str(&body).unwrap_or(Value::Null),
+        auth: request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Authorization"))
+            .map(|h| h.value.to_string()),
+    };
+
+    let (status, response) = match (call.method.as_str(), path.as_str()) {
+        ("GET", "/account/whoami") => (200, json!({ "user_id": BOT_ID })),
+        ("GET", p) if p.starts_with("/profile/") => (200, json!({ "displayname": BOT_NAME })),
+        ("GET", "/sync") => {
+            let timeout = call
+                .query
+                .get("timeout")
+                .and_then(|t| t.parse().ok())
+                .unwrap_or(0);
+
+            let mut rooms = shared
+                .syncs
+                .lock()
+                .unwrap()
+                .recv_timeout(Duration::from_millis(timeout))
+                .unwrap_or_else(|_| json!({}));
+
+            let wants_state = !call.query.contains_key("since")
+                || call.query.get("full_state").map(String::as_str) == Some("true");
+
+            if wants_state {
+                for (room_id, events) in shared.room_state.lock().unwrap().iter() {
+                    rooms["join"][room_id]["state"]["events"] = json!(events);
+                }
+            }
+
+            let batch = shared.batch.fetch_add(1, Ordering::SeqCst) + 1;
+            (
+                200,
+                json!({ "next_batch": format!("s{}", batch), "rooms": rooms }),
+            )
+        }
+        ("POST", p) if p.starts_with("/join/") => {
+            (200, json!({ "room_id": p.trim_start_matches("/join/") }))
+        }
+        ("POST", "/createRoom") => (200, json!({ "room_id": DM_ROOM })),
+        ("PUT", p) if p.starts_with("/rooms/") => {
+            match shared.send_statuses.lock().unwrap().pop_front() {
+                Some(canned) => canned,
+                None => (200, json!({ "event_id": "$sent" })),
+            }
+        }
+        _ => (
+            404,
+            json!({ "errcode": "M_UNRECOGNIZED", "error": "no idea" }),
+        ),
+    };
+
+    let _ = shared.calls.lock().unwrap().send(call);
+
+    let _ = request.respond(Response::from_string(response.to_string()).with_status_code(status));
+}
+
+fn percent_decode(s: &str) -> String {
+    url::form_urlencoded::parse(format!("x={}", s.replace('+', "%2B")).as_bytes())
+        .next()
+        .map(|(_, v)| v.into_owned())
+        .unwrap_or_default()
+}
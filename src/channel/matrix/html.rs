@@ -0,0 +1,121 @@
+// Replies go out with both a plain body and an HTML formatted_body, so that
+// clients that can render the markdown-ish things reactors write (code, mostly)
+// do. This is nowhere near all of markdown: ``` blocks become <pre>, `spans`
+// become <code>, *bold* and _italic_ do what you'd think, and line breaks are
+// kept. Everything else is escaped, and stays as written.
+
+use regex::Regex;
+
+lazy_static! {
+    static ref BOLD_RE: Regex = Regex::new(r"(^|\W)\*(\S|\S.*?\S)\*($|\W)").unwrap();
+    static ref ITALIC_RE: Regex = Regex::new(r"(^|\W)_(\S|\S.*?\S)_($|\W)").unwrap();
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(c),
+        }
+    }
+
+    out
+}
+
+pub fn render(text: &str) -> String {
+    let fences = text.matches("```").count();
+    let mut out = String::new();
+
+    for (i, part) in text.split("```").enumerate() {
+        // the odd parts are inside a block, unless it was never closed
+        let is_code = i % 2 == 1 && fences > i;
+
+        if i % 2 == 1 && !is_code {
+            out.push_str("```");
+        }
+
+        if is_code {
+            // ```rust is a language, not code
+            let code = part
+                .split_once('\n')
+                .filter(|(first, _)| !first.contains(' ') && !first.is_empty())
+                .map_or(part, |(_, rest)| rest);
+
+            out.push_str("<pre><code>");
+            out.push_str(&escape(code.trim_matches('\n')));
+            out.push_str("</code></pre>");
+        } else {
+            out.push_str(&render_inline(part));
+        }
+    }
+
+    out
+}
+
+fn render_inline(text: &str) -> String {
+    let ticks = text.matches('`').count();
+    let mut out = String::new();
+
+    for (i, part) in text.split('`').enumerate() {
+        if i % 2 == 1 && ticks <= i {
+            out.push('`');
+        }
+
+        if i % 2 == 1 && ticks > i {
+            out.push_str("<code>");
+            out.push_str(&escape(part));
+            out.push_str("</code>");
+        } else {
+            out.push_str(&emphasize(&escape(part)).replace('\n', "<br>"));
+        }
+    }
+
+    out
+}
+
+// *this* and _this_, as long as they're words, so that 2*3*4 and snake_case
+// come through alone.
+fn emphasize(text: &str) -> String {
+    let text = BOLD_RE.replace_all(text, "$1<strong>$2</strong>$3");
+    ITALIC_RE.replace_all(&text, "$1<em>$2</em>$3").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rendering() {
+        let cases = vec![
+            ("hello", "hello"),
+            (
+                "<b>not bold</b> & such",
+                "&lt;b&gt;not bold&lt;/b&gt; &amp; such",
+            ),
+            ("one\ntwo", "one<br>two"),
+            ("run `ls -l <dir>`", "run <code>ls -l &lt;dir&gt;</code>"),
+            ("a *big* deal", "a <strong>big</strong> deal"),
+            ("an _important_ one", "an <em>important</em> one"),
+            ("2*3*4 and snake_case_name", "2*3*4 and snake_case_name"),
+            (
+                "look:\n```\nfn main() {}\n```\nok",
+                "look:<br><pre><code>fn main() {}</code></pre><br>ok",
+            ),
+            (
+                "```rust\nlet x = 1 < 2;\n```",
+                "<pre><code>let x = 1 &lt; 2;</code></pre>",
+            ),
+            ("unclosed ` tick", "unclosed ` tick"),
+            ("unclosed ``` fence", "unclosed ``` fence"),
+        ];
+
+        for (text, want) in cases {
+            assert_eq!(render(text), want, "rendering {:?}", text);
+        }
+    }
+}
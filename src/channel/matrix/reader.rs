@@ -0,0 +1,97 @@
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use crate::channel::backoff::Backoff;
+use crate::channel::{self, Input};
+
+use super::client::{Client, ClientError};
+use super::sync::SyncResponse;
+
+// What the reader has to say to the rest of the channel. Initial is true for
+// a sync that didn't pick up from anywhere, whose timeline is old news.
+pub enum FromMatrix {
+    Connected(String), // our user id
+    Synced {
+        response: SyncResponse,
+        initial: bool,
+    },
+    Disconnected,
+}
+
+// The reader does nothing but long-poll /sync and pass along whatever comes
+// back; it's the channel's job to make sense of it (and to save next_batch,
+// once it has). Since the reader keeps its own place, a restart is the only
+// time it needs to be told where to start, and then it asks for every room's
+// full state, since the channel has forgotten everything it knew about them.
+pub fn spawn(
+    client: Client,
+    since: Option<String>,
+    timeout: Duration,
+    mut backoff: Backoff,
+    to_channel: mpsc::Sender<Input<FromMatrix>>,
+) -> mpsc::Sender<()> {
+    channel::spawn_reader(move |should_stop| {
+        let mut full_state = since.is_some();
+        let mut since = since;
+        let mut connected = false;
+
+        loop {
+            if should_stop() {
+                return;
+            }
+
+            if !connected {
+                match client.whoami() {
+                    Ok(user_id) => {
+                        if to_channel
+                            .send(Input::Remote(FromMatrix::Connected(user_id)))
+                            .is_err()
+                        {
+                            return;
+                        }
+                        connected = true;
+                    }
+                    Err(e) => {
+                        let delay = backoff.next_delay();
+                        warn!("error reaching matrix ({}); retrying in {:?}", e, delay);
+                        thread::sleep(delay);
+                        continue;
+                    }
+                }
+            }
+
+            match client.sync(since.as_deref(), full_state, timeout) {
+                Ok(response) => {
+                    backoff.reset();
+                    full_state = false;
+
+                    let initial = since.is_none();
+                    since = Some(response.next_batch.clone());
+
+                    let synced = FromMatrix::Synced { response, initial };
+                    if to_channel.send(Input::Remote(synced)).is_err() {
+                        return;
+                    }
+                }
+                Err(ClientError::RateLimited(delay)) => thread::sleep(delay),
+                Err(e) => {
+                    let delay = backoff.next_delay();
+                    warn!("error syncing with matrix ({}); retrying in {:?}", e, delay);
+
+                    // We'll check who we are again on the way back, in case
+                    // that's what changed.
+                    connected = false;
+                    if to_channel
+                        .send(Input::Remote(FromMatrix::Disconnected))
+                        .is_err()
+                    {
+                        return;
+                    }
+
+                    thread::sleep(delay);
+                }
+            }
+        }
+    })
+}
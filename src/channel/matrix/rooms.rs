@@ -0,0 +1,98 @@
+use std::collections::{HashMap, HashSet};
+
+use super::sync::{JoinedRoom, RoomEvent};
+
+// What we know about the rooms we're in, pieced together from sync: who's
+// there, who can get in, and which ones are DMs. Matrix doesn't really have
+// DMs, just rooms, so a DM is a room someone invited us to as one, or any
+// room with just two of us in it.
+#[derive(Debug, Default)]
+pub struct Rooms {
+    rooms: HashMap<String, Room>,
+    dms: HashMap<String, String>, // user id => room id
+}
+
+#[derive(Debug, Default)]
+struct Room {
+    members: HashSet<String>,
+    joined_count: Option<u64>, // the server's count beats ours, if we have it
+    join_rule: Option<String>,
+    is_direct: bool,
+}
+
+impl Room {
+    fn member_count(&self) -> u64 {
+        self.joined_count.unwrap_or(self.members.len() as u64)
+    }
+}
+
+pub fn new() -> Rooms {
+    Rooms::default()
+}
+
+impl Rooms {
+    pub fn update(&mut self, room_id: &str, joined: &JoinedRoom, our_id: Option<&str>) {
+        let room = self.rooms.entry(room_id.to_string()).or_default();
+
+        if let Some(n) = joined.summary.joined_member_count {
+            room.joined_count = Some(n);
+        }
+
+        // State changes can show up in either place.
+        for event in joined.state.events.iter().chain(&joined.timeline.events) {
+            update_room(room, event);
+        }
+
+        if room.member_count() == 2 {
+            for member in room.members.iter().filter(|m| Some(m.as_str()) != our_id) {
+                self.dms
+                    .entry(member.clone())
+                    .or_insert_with(|| room_id.to_string());
+            }
+        }
+    }
+
+    pub fn forget(&mut self, room_id: &str) {
+        self.rooms.remove(room_id);
+        self.dms.retain(|_, room| room != room_id);
+    }
+
+    pub fn note_dm(&mut self, user_id: &str, room_id: &str) {
+        self.rooms.entry(room_id.to_string()).or_default().is_direct = true;
+
+        self.dms.insert(user_id.to_string(), room_id.to_string());
+    }
+
+    pub fn dm_for(&self, user_id: &str) -> Option<&str> {
+        self.dms.get(user_id).map(String::as_str)
+    }
+
+    pub fn is_dm(&self, room_id: &str) -> bool {
+        self.rooms
+            .get(room_id)
+            .is_some_and(|r| r.is_direct || r.member_count() == 2)
+    }
+
+    // Anyone can walk in, so anything said there might as well be public.
+    pub fn is_public(&self, room_id: &str) -> bool {
+        self.rooms
+            .get(room_id)
+            .is_some_and(|r| r.join_rule.as_deref() == Some("public"))
+    }
+}
+
+fn update_room(room: &mut Room, event: &RoomEvent) {
+    match (event.kind.as_str(), event.state_key.as_deref()) {
+        ("m.room.join_rules", Some("")) => {
+            room.join_rule = event.content_str("join_rule").map(String::from);
+        }
+        ("m.room.member", Some(user_id)) => {
+            if event.content_str("membership") == Some("join") {
+                room.members.insert(user_id.to_string());
+            } else {
+                room.members.remove(user_id);
+            }
+        }
+        _ => (),
+    }
+}
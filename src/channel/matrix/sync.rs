@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+// The parts of a /sync response we care about. The homeserver sends a great
+// deal more, which serde is happy to ignore, and leaves out anything that
+// hasn't changed, hence all the defaults.
+//
+// https://spec.matrix.org/latest/client-server-api/#get_matrixclientv3sync
+#[derive(Debug, Default, Deserialize)]
+pub struct SyncResponse {
+    pub next_batch: String,
+    #[serde(default)]
+    pub rooms: Rooms,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Rooms {
+    #[serde(default)]
+    pub join: HashMap<String, JoinedRoom>,
+    #[serde(default)]
+    pub invite: HashMap<String, InvitedRoom>,
+    #[serde(default)]
+    pub leave: HashMap<String, Value>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct JoinedRoom {
+    #[serde(default)]
+    pub summary: Summary,
+    #[serde(default)]
+    pub state: Events,
+    #[serde(default)]
+    pub timeline: Events,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Summary {
+    #[serde(rename = "m.joined_member_count")]
+    pub joined_member_count: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct InvitedRoom {
+    #[serde(default)]
+    pub invite_state: Events,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Events {
+    #[serde(default)]
+    pub events: Vec<RoomEvent>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RoomEvent {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub sender: String,
+    #[serde(default)]
+    pub state_key: Option<String>,
+    #[serde(default)]
+    pub content: Value,
+}
+
+impl RoomEvent {
+    pub fn content_str(&self, key: &str) -> Option<&str> {
+        self.content.get(key).and_then(|v| v.as_str())
+    }
+}
@@ -0,0 +1,381 @@
+use serde_json::{json, Value};
+
+use super::fake_server::{self, FakeServer, BOT_ID, DM_ROOM};
+use crate::channel::testing;
+use crate::channel::Type;
+use crate::message::{Connectivity, Event};
+
+const ROOM: &str = "!general:localhost";
+
+// A running matrix channel, and the fake homeserver it's talking to.
+type Harness = testing::Harness<Fakes>;
+
+struct Fakes {
+    server: FakeServer,
+}
+
+impl testing::Fakes for Fakes {}
+
+fn start_channel(state_dbfile: &str) -> Harness {
+    let server = fake_server::start();
+
+    let config = testing::config(
+        vec![
+            ("homeserver", server.url.clone().into()),
+            ("access_token", "sekrit".into()),
+            ("sync_timeout", 1.into()),
+        ],
+        vec![],
+    );
+
+    let channel = testing::start("channel/matrix", Type::MatrixChannel, config, state_dbfile);
+    channel.expect_connectivity(Connectivity::Connected);
+
+    Harness {
+        channel,
+        fakes: Fakes { server },
+    }
+}
+
+// Connected, and past the first sync, in which ROOM has three of us in it.
+fn start_synced() -> Harness {
+    let h = start_channel(":memory:");
+
+    h.fakes.server.sync(json!({
+        "join": {
+            ROOM: {
+                "summary": { "m.joined_member_count": 3 },
+                "state": { "events": [
+                    state_event("m.room.join_rules", "", json!({ "join_rule": "public" })),
+                ] },
+                "timeline": { "events": [
+                    message("@alice:localhost", json!({ "msgtype": "m.text", "body": "Synergy: old news" })),
+                ] },
+            },
+        },
+    }));
+
+    h.fakes.server.recv_call("GET", "/sync").unwrap();
+    h.fakes.server.recv_call("GET", "/sync").unwrap(); // so the first one's done
+    h
+}
+
+fn message(sender: &str, content: Value) -> Value {
+    json!({
+        "type": "m.room.message",
+        "sender": sender,
+        "event_id": format!("${}", Event::new_id()),
+        "content": content,
+    })
+}
+
+fn text(sender: &str, body: &str) -> Value {
+    message(sender, json!({ "msgtype": "m.text", "body": body }))
+}
+
+fn state_event(kind: &str, state_key: &str, content: Value) -> Value {
+    json!({
+        "type": kind,
+        "sender": "@alice:localhost",
+        "state_key": state_key,
+        "content": content,
+    })
+}
+
+// Just the two of us.
+fn dm_state(with: &str) -> Vec<Value> {
+    vec![
+        state_event("m.room.member", with, json!({ "membership": "join" })),
+        state_event("m.room.member", BOT_ID, json!({ "membership": "join" })),
+    ]
+}
+
+fn timeline(room: &str, events: Vec<Value>) -> Value {
+    json!({ "join": { room: { "timeline": { "events": events } } } })
+}
+
+#[test]
+fn messages_become_events() {
+    let h = start_synced();
+
+    // The old news from the first sync shouldn't have come through.
+    h.fakes.server.sync(timeline(
+        ROOM,
+        vec![
+            text("@alice:localhost", "Synergy: clox"),
+            text(BOT_ID, "talking to myself"),
+            text("@alice:localhost", "just chatting"),
+            message(
+                "@alice:localhost",
+                json!({ "msgtype": "m.notice", "body": "Synergy: from a bot" }),
+            ),
+        ],
+    ));
+
+    let event = h.next_event();
+    assert_eq!(event.text, "clox");
+    assert_eq!(event.from_address, "@alice:localhost");
+    assert_eq!(event.conversation_address, ROOM);
+    assert_eq!(event.origin, "channel/matrix");
+    assert!(event.was_targeted);
+    assert!(event.is_public);
+
+    let event = h.next_event();
+    assert_eq!(event.text, "just chatting");
+    assert!(!event.was_targeted);
+
+    h.nothing_happened();
+    h.hangup();
+}
+
+#[test]
+fn mentions_are_targeted() {
+    let h = start_synced();
+
+    let pill = format!(
+        "<a href=\"https://matrix.to/#/{}\">Synergy</a> what's up",
+        BOT_ID
+    );
+
+    h.fakes.server.sync(timeline(
+        ROOM,
+        vec![
+            text("@alice:localhost", "synergy, hi"),
+            text("@alice:localhost", &format!("{}: hi", BOT_ID)),
+            message(
+                "@alice:localhost",
+                json!({
+                    "msgtype": "m.text",
+                    "body": "hey Synergy what's up",
+                    "m.mentions": { "user_ids": [BOT_ID] },
+                }),
+            ),
+            message(
+                "@alice:localhost",
+                json!({
+                    "msgtype": "m.text",
+                    "body": "Synergy what's up",
+                    "format": "org.matrix.custom.html",
+                    "formatted_body": pill,
+                }),
+            ),
+            message(
+                "@alice:localhost",
+                json!({
+                    "msgtype": "m.text",
+                    "body": "> <@bob:localhost> what time is it?\n> really\n\nSynergy: clox",
+                    "m.relates_to": { "m.in_reply_to": { "event_id": "$earlier" } },
+                }),
+            ),
+        ],
+    ));
+
+    let texts = vec![
+        "hi",
+        "hi",
+        "hey Synergy what's up",
+        "Synergy what's up",
+        "clox",
+    ];
+
+    for want in texts {
+        let event = h.next_event();
+        assert!(event.was_targeted, "{:?} wasn't targeted", event.text);
+        assert_eq!(event.text, want);
+    }
+
+    h.hangup();
+}
+
+#[test]
+fn replies_are_formatted() {
+    let h = start_synced();
+
+    h.fakes.server.sync(timeline(
+        ROOM,
+        vec![text("@alice:localhost", "Synergy: help")],
+    ));
+    let event = h.next_event();
+
+    h.reply(&event, "try `clox` <now>");
+
+    let call = h.fakes.server.recv_call("PUT", "/rooms/").unwrap();
+    assert!(call
+        .path
+        .starts_with(&format!("/rooms/{}/send/m.room.message/", ROOM)));
+    assert_eq!(call.auth.as_deref(), Some("Bearer sekrit"));
+    assert_eq!(
+        call.body,
+        json!({
+            "msgtype": "m.text",
+            "body": "try `clox` <now>",
+            "format": "org.matrix.custom.html",
+            "formatted_body": "try <code>clox</code> &lt;now&gt;",
+        })
+    );
+
+    h.hangup();
+}
+
+#[test]
+fn invites_are_joined() {
+    let h = start_synced();
+
+    let invite = |room: &str, is_direct: bool| {
+        json!({
+            "invite": {
+                room: {
+                    "invite_state": { "events": [{
+                        "type": "m.room.member",
+                        "sender": "@alice:localhost",
+                        "state_key": BOT_ID,
+                        "content": { "membership": "invite", "is_direct": is_direct },
+                    }] },
+                },
+            },
+        })
+    };
+
+    h.fakes.server.sync(invite("!party:localhost", false));
+    h.fakes
+        .server
+        .recv_call("POST", "/join/!party:localhost")
+        .unwrap();
+
+    // Anything in a DM is for us, and that's where private replies go.
+    h.fakes.server.sync(invite("!alice:localhost", true));
+    h.fakes
+        .server
+        .recv_call("POST", "/join/!alice:localhost")
+        .unwrap();
+
+    h.fakes.server.sync(timeline(
+        "!alice:localhost",
+        vec![text("@alice:localhost", "hi")],
+    ));
+    let event = h.next_event();
+    assert!(event.was_targeted);
+
+    h.fakes.server.sync(timeline(
+        ROOM,
+        vec![text("@alice:localhost", "Synergy: secret")],
+    ));
+    let event = h.next_event();
+    h.to_channel
+        .send(event.private_reply("shh", "reactor/test").unwrap())
+        .unwrap();
+
+    let call = h.fakes.server.recv_call("PUT", "/rooms/").unwrap();
+    assert!(call.path.starts_with("/rooms/!alice:localhost/send/"));
+
+    h.hangup();
+}
+
+#[test]
+fn private_replies_can_start_a_dm() {
+    let h = start_synced();
+
+    h.fakes.server.sync(timeline(
+        ROOM,
+        vec![text("@bob:localhost", "Synergy: secret")],
+    ));
+    let event = h.next_event();
+
+    h.to_channel
+        .send(event.private_reply("shh", "reactor/test").unwrap())
+        .unwrap();
+
+    let call = h.fakes.server.recv_call("POST", "/createRoom").unwrap();
+    assert_eq!(call.body["invite"], json!(["@bob:localhost"]));
+    assert_eq!(call.body["is_direct"], true);
+
+    let call = h.fakes.server.recv_call("PUT", "/rooms/").unwrap();
+    assert!(call.path.starts_with(&format!("/rooms/{}/send/", DM_ROOM)));
+
+    // and the next time, we already have one
+    h.to_channel
+        .send(event.private_reply("shh again", "reactor/test").unwrap())
+        .unwrap();
+    let call = h.fakes.server.recv_call("PUT", "/rooms/").unwrap();
+    assert_eq!(call.body["body"], "shh again");
+    assert!(h.fakes.server.no_call("POST", "/createRoom"));
+
+    h.hangup();
+}
+
+#[test]
+fn sending_waits_out_rate_limits() {
+    let h = start_synced();
+
+    h.fakes.server.sync(timeline(
+        ROOM,
+        vec![text("@alice:localhost", "Synergy: hi")],
+    ));
+    let event = h.next_event();
+
+    h.fakes.server.fail_send(
+        429,
+        json!({ "errcode": "M_LIMIT_EXCEEDED", "retry_after_ms": 100 }),
+    );
+    h.reply(&event, "hello");
+
+    // same transaction, so it can't turn into two messages
+    let first = h.fakes.server.recv_call("PUT", "/rooms/").unwrap();
+    let second = h.fakes.server.recv_call("PUT", "/rooms/").unwrap();
+    assert_eq!(first.path, second.path);
+    assert_eq!(second.body["body"], "hello");
+
+    h.hangup();
+}
+
+#[test]
+fn next_batch_is_saved() {
+    let dbfile = std::env::temp_dir().join(format!("synergy-matrix-{}.db", Event::new_id()));
+    let dbfile = dbfile.to_str().unwrap();
+
+    let h = start_channel(dbfile);
+    let first = h.fakes.server.recv_call("GET", "/sync").unwrap();
+    assert_eq!(first.query.get("since"), None);
+
+    h.fakes
+        .server
+        .sync(timeline(ROOM, vec![text("@alice:localhost", "whatever")]));
+    let second = h.fakes.server.recv_call("GET", "/sync").unwrap();
+    assert_eq!(second.query.get("since").map(String::as_str), Some("s1"));
+
+    // by the next sync, it's been dealt with (and saved)
+    h.next_event();
+    h.fakes.server.recv_call("GET", "/sync").unwrap();
+    h.hangup();
+
+    // A new homeserver counts from 1 again, but we should pick up from
+    // wherever the last one left us. Picking up where we left off means
+    // the homeserver only tells us about what's changed since, so we have to
+    // ask for everything else we knew, like which rooms are DMs.
+    let h = start_channel(dbfile);
+    h.fakes
+        .server
+        .set_state(DM_ROOM, dm_state("@alice:localhost"));
+
+    let call = h.fakes.server.recv_call("GET", "/sync").unwrap();
+    let since = call.query.get("since").expect("no since after a restart");
+    assert!(since != "s1", "picked up from {}, which is stale", since);
+
+    h.fakes.server.sync(timeline(
+        DM_ROOM,
+        vec![text("@alice:localhost", "still here?")],
+    ));
+    let event = h.next_event();
+    assert!(event.was_targeted);
+    assert!(!event.is_public);
+
+    // and we know the DM is one, so private replies go there
+    h.to_channel
+        .send(event.private_reply("yes", "reactor/test").unwrap())
+        .unwrap();
+    let sent = h.fakes.server.recv_call("PUT", "/rooms/").unwrap();
+    assert!(sent.path.starts_with(&format!("/rooms/{}/", DM_ROOM)));
+    h.hangup();
+
+    let _ = std::fs::remove_file(dbfile);
+}
@@ -1,18 +1,39 @@
+pub mod admin_socket;
+pub mod backoff;
+pub mod discord;
+pub mod email;
+pub mod http;
+pub mod irc;
+pub mod matrix;
 pub mod slack;
+pub mod sms;
+pub mod split;
 pub mod term;
+#[cfg(test)]
+pub mod testing;
 
-use std::sync::mpsc;
+use std::error::Error;
+use std::sync::{mpsc, Arc};
 use std::thread;
 
 use serde::Deserialize;
+use tiny_http::{Request, Server};
 
 use crate::config;
-use crate::message::{Message, Reply};
+use crate::message::{Connectivity, Message, Reply};
 
-// known channels
+// known channels (these are the names in config files)
+#[allow(clippy::enum_variant_names)]
 #[derive(Deserialize, Debug)]
 pub enum Type {
+    AdminSocketChannel,
+    DiscordChannel,
+    EmailChannel,
+    HttpChannel,
+    IrcChannel,
+    MatrixChannel,
     SlackChannel,
+    SmsChannel,
     TermChannel,
 }
 
@@ -21,17 +42,26 @@ pub type ChannelConfig = config::ComponentConfig<Type>;
 pub fn build(
     name: String,
     config: ChannelConfig,
+    state_dbfile: String,
     output: mpsc::Sender<Message>,
     input: mpsc::Receiver<Message>,
 ) -> thread::JoinHandle<()> {
     let builder = match config.class {
+        Type::AdminSocketChannel => admin_socket::build,
+        Type::DiscordChannel => discord::build,
+        Type::EmailChannel => email::build,
+        Type::HttpChannel => http::build,
+        Type::IrcChannel => irc::build,
+        Type::MatrixChannel => matrix::build,
         Type::SlackChannel => slack::build,
+        Type::SmsChannel => sms::build,
         Type::TermChannel => term::build,
     };
 
     let seed = Seed {
         name,
         config,
+        state_dbfile,
         input,
         output,
     };
@@ -46,9 +76,90 @@ pub enum ReplyResponse {
     Sent,
 }
 
+// Channels that have their own thread reading from the outside world can't
+// just poll the hub's receiver in between reads without either busy-looping or
+// making replies wait. Instead, they can have everything delivered to one
+// place: forward_hub() moves messages from the hub into the same mpsc that the
+// reader thread writes to, and then there's only one thing to block on.
+// (These only ever pass through, so there's no point boxing a big Message.)
+#[allow(clippy::large_enum_variant)]
+pub enum Input<T> {
+    Hub(Message),
+    Remote(T),
+}
+
+pub fn forward_hub<T: Send + 'static>(
+    from_hub: mpsc::Receiver<Message>,
+    to_channel: mpsc::Sender<Input<T>>,
+) {
+    thread::spawn(move || {
+        for msg in from_hub {
+            let is_hangup = matches!(msg, Message::Hangup);
+
+            if to_channel.send(Input::Hub(msg)).is_err() || is_hangup {
+                break;
+            }
+        }
+    });
+}
+
+// Channels that talk to the outside world tell the hub when they lose (and
+// regain) their connection.
+pub fn set_connectivity(to_hub: &mpsc::Sender<Message>, name: &str, state: Connectivity) {
+    let msg = Message::Connectivity(name.to_string(), state);
+    to_hub.send(msg).unwrap();
+}
+
+// A reader thread spends most of its time blocked on the outside world, so
+// it can't be told to stop so much as asked: body gets a should_stop to
+// check whenever it wakes up, and dropping the returned sender makes it true.
+pub fn spawn_reader<F>(body: F) -> mpsc::Sender<()>
+where
+    F: FnOnce(&dyn Fn() -> bool) + Send + 'static,
+{
+    let (stop_tx, stop) = mpsc::channel::<()>();
+
+    thread::spawn(move || {
+        let should_stop = || !matches!(stop.try_recv(), Err(mpsc::TryRecvError::Empty));
+        body(&should_stop);
+    });
+
+    stop_tx
+}
+
+// For channels that get told things over http (like slack's interactions,
+// or twilio's texts): every request to address goes to handle, on a thread
+// of its own. Dropping the listener shuts it down.
+pub struct Listener {
+    server: Arc<Server>,
+}
+
+pub fn listen<F>(address: &str, handle: F) -> Result<Listener, Box<dyn Error + Send + Sync>>
+where
+    F: Fn(Request) + Send + 'static,
+{
+    let server = Arc::new(Server::http(address)?);
+
+    let ours = Arc::clone(&server);
+    thread::spawn(move || {
+        for request in ours.incoming_requests() {
+            handle(request);
+        }
+    });
+
+    Ok(Listener { server })
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.server.unblock();
+    }
+}
+
 pub struct Seed {
     pub name: String,
     pub config: ChannelConfig,
+    pub state_dbfile: String,
     pub output: mpsc::Sender<Message>,
     pub input: mpsc::Receiver<Message>,
 }
@@ -58,6 +169,10 @@ pub trait Channel {
 
     fn send_reply(&mut self, r: Reply);
 
+    // The words reactors respond to, for channels that can do something with
+    // them (like tab completion). Most can't.
+    fn note_commands(&mut self, _commands: Vec<String>) {}
+
     fn catch_replies(&mut self) -> ReplyResponse {
         let mut did_send = false;
 
@@ -68,6 +183,7 @@ pub trait Channel {
                     self.send_reply(reply);
                     did_send = true;
                 }
+                Ok(Message::Commands(commands)) => self.note_commands(commands),
                 Err(mpsc::TryRecvError::Empty) => break,
                 Err(mpsc::TryRecvError::Disconnected) => {
                     panic!("hub hung up on us?");
@@ -1,45 +1,83 @@
 mod api_client;
+mod blocks;
+mod conversation_cache;
+mod directory;
+mod last_seen;
+mod listener;
+#[cfg(test)]
+mod mock_server;
+mod mrkdwn;
+mod reader;
 mod rtm_client;
+mod send_queue;
+mod socket_mode_client;
+#[cfg(test)]
+mod tests;
+mod transport;
+mod user_cache;
+mod writer;
 
-use std::collections::HashMap;
-use std::error::Error;
-use std::fmt;
 use std::sync::{mpsc, Arc};
 use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use regex::{Captures, Regex};
+use regex::Regex;
 
-use crate::channel::{Channel, ReplyResponse, Seed};
-use crate::message::{Event, Message, Reply};
+use crate::channel::backoff;
+use crate::channel::{self, Input, Seed};
+use crate::message::{Callback, Connectivity, Event, Message};
+use crate::state::{self, StateDb};
 use api_client::ApiClient;
-use rtm_client::{RawEvent, RtmClient};
-
+use directory::Directory;
+use last_seen::LastSeen;
+use reader::FromSlack;
+use transport::{Interaction, RawEvent, SlackEvent, SlackIdentity, SlashCommand, Transport};
+use writer::Writer;
+
+const DEFAULT_API_URL: &str = "https://slack.com/api";
+
+// Slack will take messages a lot longer than this, but recommends against
+// it; past the snippet length, nobody wants to scroll through it inline.
+const DEFAULT_MAX_MESSAGE_LENGTH: usize = 4000;
+const DEFAULT_SNIPPET_LENGTH: usize = 12000;
+
+// How often we wake up to see if the caches are stale, if nothing else wakes
+// us up first.
+const IDLE_WAKEUP: Duration = Duration::from_secs(60);
+
+// After a disconnect (or a restart), we go back and pick up anything we missed,
+// but only this far back (in seconds); past that, it's old news.
+const DEFAULT_MAX_BACKFILL_AGE: u64 = 60 * 60;
+
+// The slash command that's just a way to talk to us, as set up in the slack
+// app, which needn't have anything to do with what the bot user is called.
+const DEFAULT_SLASH_COMMAND: &str = "synergy";
+
+// Unlike term, slack doesn't poll the hub in between other work. There are
+// three threads: the reader (see reader.rs) owns the websocket, the writer
+// (see writer.rs) takes Replies from the hub and sends them, and this one
+// turns slack events into Events, looking up whatever it needs to along the
+// way. Everything coming in from slack (and anything from the hub that isn't
+// a reply) arrives on one mpsc, so we can just block on that.
 pub struct Slack {
     pub name: String,
-    api_token: String,
-    rtm_client: RtmClient,
+    transport: Option<Box<dyn Transport + Send>>, // until the reader takes it
+    writer: Option<Writer>,                       // until it gets its own thread
     api_client: ApiClient,
     to_hub: mpsc::Sender<Message>,
-    from_hub: mpsc::Receiver<Message>,
+    from_hub: Option<mpsc::Receiver<Message>>, // until the writer takes it
+    state: Option<StateDb>,
+    listen: Option<(String, String)>, // address and signing secret, for requests over http
+    max_backfill_age: u64,            // 0 means don't bother
+    slash_command: String,            // without the slash
 
     // cached data
     our_name: Option<String>,
     our_id: Option<String>,
+    team_id: Option<String>,
     targeted_re: Regex, // I could use an option here, but.
-    users: Option<HashMap<String, String>>,
-}
-
-// XXX clean me up
-
-#[derive(Debug)]
-struct SlackInternalError(String);
-
-impl Error for SlackInternalError {}
-
-impl fmt::Display for SlackInternalError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Error while talking to slack: {}", self.0)
-    }
+    directory: Directory,
+    last_seen: LastSeen,
 }
 
 pub fn build(seed: Seed) -> thread::JoinHandle<()> {
@@ -50,67 +88,317 @@ pub fn build(seed: Seed) -> thread::JoinHandle<()> {
 }
 
 pub fn new(seed: Seed) -> Slack {
-    let api_token = &seed.config.extra["api_token"]
+    let extra = &seed.config.extra;
+
+    let api_token = extra["api_token"]
         .as_str()
         .expect("no api token in config!");
 
+    // You'd only change this to talk to something pretending to be slack.
+    let api_url = extra
+        .get("api_url")
+        .and_then(|v| v.as_str())
+        .unwrap_or(DEFAULT_API_URL)
+        .trim_end_matches('/');
+
+    let transport: Box<dyn Transport + Send> = match extra.get("transport").and_then(|v| v.as_str())
+    {
+        None | Some("rtm") => Box::new(rtm_client::new(api_url, api_token)),
+        Some("socket_mode") => {
+            let app_token = extra
+                .get("app_token")
+                .and_then(|v| v.as_str())
+                .expect("socket mode needs an app_token in config!");
+
+            Box::new(socket_mode_client::new(api_url, app_token, api_token))
+        }
+        Some(other) => panic!("unknown slack transport {}", other),
+    };
+
+    let broadcasters = extra
+        .get("allow_broadcast_from")
+        .and_then(|v| v.as_array())
+        .map(|a| {
+            a.iter()
+                .filter_map(|v| v.as_str())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let length_config = |key, default| {
+        extra
+            .get(key)
+            .and_then(|v| v.as_integer())
+            .map_or(default, |n| n as usize)
+    };
+
+    // Socket mode gets interactions and slash commands over the websocket;
+    // otherwise slack has to POST them to us, and we need the signing secret
+    // to believe them.
+    let listen = extra
+        .get("listen_address")
+        .and_then(|v| v.as_str())
+        .map(|addr| {
+            let secret = extra
+                .get("signing_secret")
+                .and_then(|v| v.as_str())
+                .expect("listening for slack requests needs a signing_secret in config!");
+
+            (addr.to_string(), secret.to_string())
+        });
+
+    // If we can't get at the state db, we can live without it.
+    let state = match state::open(&seed.state_dbfile) {
+        Ok(db) => Some(db),
+        Err(e) => {
+            warn!("couldn't open state db {}: {}", seed.state_dbfile, e);
+            None
+        }
+    };
+
+    let api_client = api_client::new(api_token.to_string(), api_url);
+    let directory = directory::new(&seed.name, api_client.clone());
+
+    let writer = writer::new(
+        api_client.clone(),
+        directory.clone(),
+        broadcasters,
+        length_config("max_message_length", DEFAULT_MAX_MESSAGE_LENGTH),
+        length_config("snippet_length", DEFAULT_SNIPPET_LENGTH),
+    );
+
     Slack {
         name: seed.name.clone(),
-        api_token: api_token.to_string(),
+        transport: Some(transport),
+        writer: Some(writer),
         to_hub: seed.output,
-        from_hub: seed.input,
-        rtm_client: rtm_client::new(),
-        api_client: api_client::new(api_token.to_string()),
+        from_hub: Some(seed.input),
+        api_client,
+        state,
+        listen,
+        max_backfill_age: extra
+            .get("max_backfill_age")
+            .and_then(|v| v.as_integer())
+            .map_or(DEFAULT_MAX_BACKFILL_AGE, |n| n as u64),
+        slash_command: extra
+            .get("slash_command")
+            .and_then(|v| v.as_str())
+            .unwrap_or(DEFAULT_SLASH_COMMAND)
+            .trim_start_matches('/')
+            .to_string(),
         our_id: None,
         our_name: None,
+        team_id: None,
         targeted_re: Regex::new("").unwrap(),
-        users: None,
+        directory,
+        last_seen: last_seen::new(&seed.name),
     }
 }
 
-impl Channel for Slack {
-    fn receiver(&self) -> &mpsc::Receiver<Message> {
-        &self.from_hub
+impl Slack {
+    fn start(&mut self) {
+        let (inbox_tx, inbox) = mpsc::channel();
+
+        let writer = writer::spawn(
+            self.writer.take().unwrap(),
+            self.from_hub.take().unwrap(),
+            inbox_tx.clone(),
+        );
+
+        let _stop_reader = reader::spawn(
+            self.transport.take().unwrap(),
+            backoff::new(Duration::from_secs(1), Duration::from_secs(120)),
+            inbox_tx.clone(),
+        );
+
+        let _listener = self.listen.take().map(|(address, secret)| {
+            listener::spawn(&address, secret, inbox_tx)
+                .unwrap_or_else(|e| panic!("couldn't listen on {}: {}", address, e))
+        });
+
+        // If we have users saved from last time, we'll go with those until
+        // they get stale, rather than blocking on a full load now.
+        if !self.directory.users().load(self.state.as_ref()) {
+            self.directory.refresh_users(self.state.as_ref());
+        }
+
+        self.directory.refresh_conversations();
+        self.last_seen.load(self.state.as_ref());
+
+        loop {
+            match inbox.recv_timeout(IDLE_WAKEUP) {
+                Ok(Input::Hub(Message::Hangup)) => break,
+                Ok(Input::Hub(_)) => (),
+                Ok(Input::Remote(FromSlack::Connected(me))) => {
+                    self.set_identity(me);
+                    channel::set_connectivity(&self.to_hub, &self.name, Connectivity::Connected);
+                    self.backfill();
+                }
+                Ok(Input::Remote(FromSlack::Disconnected)) => {
+                    channel::set_connectivity(&self.to_hub, &self.name, Connectivity::Disconnected);
+                }
+                Ok(Input::Remote(FromSlack::Event(event))) => self.handle_slack_event(event),
+                Err(mpsc::RecvTimeoutError::Timeout) => (),
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    panic!("hub hung up on us?");
+                }
+            }
+
+            if self.directory.users().is_stale() {
+                self.directory.refresh_users(self.state.as_ref());
+            }
+
+            if self.directory.conversations().is_stale() {
+                self.directory.refresh_conversations();
+            }
+        }
+
+        // The writer saw the hangup first, and is finishing up what it can.
+        writer.join().unwrap();
     }
 
-    fn send_reply(&mut self, reply: Reply) {
-        self.rtm_client.send(reply);
+    fn handle_slack_event(&mut self, event: SlackEvent) {
+        let raw_event = match event {
+            SlackEvent::Message(raw) => raw,
+            SlackEvent::UserChange(member) => {
+                self.directory.users().update(member, self.state.as_ref());
+                return;
+            }
+            SlackEvent::ConversationChange(convo) => {
+                self.directory.conversations().update(convo);
+                return;
+            }
+            SlackEvent::ConversationRename(id, name) => {
+                self.directory.conversations().rename(&id, name);
+                return;
+            }
+            SlackEvent::Membership {
+                channel,
+                user,
+                joined,
+            } => {
+                if user.is_none() || user == self.our_id {
+                    self.directory
+                        .conversations()
+                        .set_membership(&channel, joined);
+                }
+                return;
+            }
+            SlackEvent::Interaction(interaction) => {
+                let event = self.event_from_interaction(interaction);
+                self.to_hub.send(Message::Event(Arc::new(event))).unwrap();
+                return;
+            }
+            SlackEvent::SlashCommand(command) => {
+                let event = self.event_from_slash_command(command);
+                self.to_hub.send(Message::Event(Arc::new(event))).unwrap();
+                return;
+            }
+        };
+
+        // We might have already picked this up in a backfill.
+        if !self
+            .last_seen
+            .saw(&raw_event.channel, &raw_event.ts, self.state.as_ref())
+        {
+            return;
+        }
+
+        if let Some(event) = self.event_from_raw(raw_event) {
+            self.to_hub.send(Message::Event(Arc::new(event))).unwrap();
+        }
     }
-}
 
-impl Slack {
-    fn start(&mut self) {
-        let me = self.rtm_client.connect(&self.api_token);
+    // Catch up on everything said since we last heard from each conversation,
+    // as long as it's not too old. These go to the hub like anything else, but
+    // marked as backfilled, so reactors can decide if it's too late to act.
+    fn backfill(&mut self) {
+        if self.max_backfill_age == 0 {
+            return;
+        }
 
-        self.targeted_re = Regex::new(&format!(r"^(?i)@?{}:?\s+", me.name)).unwrap();
-        self.our_name = Some(me.name);
-        self.our_id = Some(me.id);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
 
-        // this block: maybe it would be better not to do so.
-        self.users = self.api_client.load_users();
+        let oldest = now.saturating_sub(self.max_backfill_age);
 
-        loop {
-            match self.catch_replies() {
-                ReplyResponse::Hangup => break,
-                _ => (),
-            };
+        for (conversation, last) in self.last_seen.conversations() {
+            let from = last_seen::backfill_from(&last, oldest);
 
-            let raw_event = match self.rtm_client.recv() {
-                Some(raw) => raw,
-                None => continue,
+            let messages = match self.api_client.history(&conversation, &from) {
+                Ok(messages) => messages,
+                Err(e) => {
+                    warn!("couldn't backfill {}: {}", conversation, e);
+                    continue;
+                }
             };
 
-            let event = match self.event_from_raw(raw_event) {
-                Some(e) => e,
-                None => continue,
-            };
+            let mut count = 0;
+
+            for message in messages {
+                let raw = match transport::event_from_json(message) {
+                    Some(SlackEvent::Message(raw)) => raw,
+                    _ => continue,
+                };
+
+                if !self
+                    .last_seen
+                    .saw(&raw.channel, &raw.ts, self.state.as_ref())
+                {
+                    continue;
+                }
+
+                if let Some(mut event) = self.event_from_raw(raw) {
+                    event.is_backfilled = true;
+                    self.to_hub.send(Message::Event(Arc::new(event))).unwrap();
+                    count += 1;
+                }
+            }
+
+            if count > 0 {
+                info!("backfilled {} messages in {}", count, conversation);
+            }
+        }
+    }
 
-            self.to_hub.send(Message::Event(Arc::new(event))).unwrap();
+    fn set_identity(&mut self, me: SlackIdentity) {
+        if self.our_id.as_ref() == Some(&me.id)
+            && self.our_name.as_ref() == Some(&me.name)
+            && self.team_id.as_ref() == Some(&me.team_id)
+        {
+            return;
+        }
+
+        // This would mean someone swapped the token out from under us, and
+        // everything we've cached is about the wrong workspace.
+        if let Some(old) = &self.team_id {
+            if *old != me.team_id {
+                warn!("slack workspace changed from {} to {}!", old, me.team_id);
+            }
         }
+
+        info!(
+            "we are {} ({}) on slack workspace {}",
+            me.name, me.id, me.team_id
+        );
+
+        self.team_id = Some(me.team_id);
+        self.targeted_re =
+            Regex::new(&format!(r"^(?i)@?{}:?\s+", regex::escape(&me.name))).unwrap();
+        self.our_name = Some(me.name);
+        self.our_id = Some(me.id);
     }
 
-    fn event_from_raw(&self, raw: RawEvent) -> Option<Event> {
-        let mut text = self.decode_slack_formatting(raw.text);
+    fn event_from_raw(&mut self, raw: RawEvent) -> Option<Event> {
+        // look up anyone we haven't heard of before we need their names
+        let mut ids = mrkdwn::mentioned_users(&raw.text);
+        ids.push(&raw.user);
+        self.directory.ensure_users(&ids, self.state.as_ref());
+
+        let mut text = mrkdwn::decode(&raw.text, &self.directory);
 
         let mut was_targeted = self.targeted_re.is_match(&text);
 
@@ -118,63 +406,80 @@ impl Slack {
             text = self.targeted_re.replace(&text, "").to_string();
         }
 
+        // If we can't find out anything about the conversation, guess from the
+        // id: better safe than sorry about what's public.
+        let (is_public, is_dm) = match self.directory.conversation(&raw.channel) {
+            Some(convo) => (convo.is_public(), convo.is_im),
+            None => (false, raw.channel.starts_with('D')),
+        };
+
         // anything in DM is targeted
-        if raw.channel.starts_with("D") {
+        if is_dm {
             was_targeted = true;
         }
 
-        let is_public = raw.channel.starts_with("C");
-
         Some(Event {
-            text,
             is_public,
             was_targeted,
-            from_address: raw.user,
-            conversation_address: raw.channel,
-            origin: self.name.clone(),
-            user: None,
-            id: Event::new_id(),
+            workspace: self.team_id.clone(),
+            ..Event::new(&self.name, &raw.user, &raw.channel, &text)
         })
     }
 
-    fn decode_slack_formatting(&self, text: String) -> String {
-        lazy_static! {
-            static ref USERNAME_RE: Regex = Regex::new(r"<@(U[A-Z0-9]+)>").unwrap();
-            static ref CHANNEL_RE: Regex = Regex::new(r"<#[CD](?:[A-Z0-9]+)\|(.*?)>").unwrap();
-            static ref MAILTO_RE: Regex = Regex::new(r"<mailto:\S+?\|([^>]+)>").unwrap();
-            static ref URL_RE: Regex = Regex::new(r"<[^|]+\|([^>]+)>").unwrap();
-        };
+    // Clicking a button is always meant for us; the text is whatever the
+    // button said it was worth, for anyone who doesn't care about callbacks.
+    fn event_from_interaction(&mut self, interaction: Interaction) -> Event {
+        let is_public = self
+            .directory
+            .conversation(&interaction.channel)
+            .is_some_and(|convo| convo.is_public());
 
-        // TODO: here, swap slack userids for slack usernames (which we need to
-        // look up)
-        let subbed_users = USERNAME_RE.replace_all(&text, |caps: &Captures| {
-            let name = self.username_for(&caps[1]);
-            format!("@{}", name)
-        });
-
-        let subbed_channels = CHANNEL_RE.replace_all(&subbed_users, "#$1");
-        let subbed_mailto = MAILTO_RE.replace_all(&subbed_channels, "$1");
-        let subbed_url = URL_RE.replace_all(&subbed_mailto, "$1");
-
-        // switch to a string to do the rest.
-        let mut out = subbed_url.replace("<", "");
-        out = out.replace(">", "");
+        Event {
+            is_public,
+            was_targeted: true,
+            workspace: self.team_id.clone(),
+            callback: Some(Callback {
+                callback_id: interaction.callback_id,
+                value: interaction.value.clone(),
+            }),
+            ..Event::new(
+                &self.name,
+                &interaction.user,
+                &interaction.channel,
+                &interaction.value,
+            )
+        }
+    }
 
-        // re-encode html
-        out = out.replace("&lt;", "<");
-        out = out.replace("&gt;", ">");
-        out = out.replace("&amp;", "&");
+    // "/synergy clox" (or whatever our slash_command is) is just "clox", but we
+    // might have other commands (like "/clox"), which become the first word.
+    // Either way, it's meant for us.
+    fn event_from_slash_command(&mut self, command: SlashCommand) -> Event {
+        let mut ids = mrkdwn::mentioned_users(&command.text);
+        ids.push(&command.user);
+        self.directory.ensure_users(&ids, self.state.as_ref());
+
+        let args = mrkdwn::decode(&command.text, &self.directory);
+        let name = command.command.trim_start_matches('/');
+
+        let text = if name == self.slash_command {
+            args.trim().to_string()
+        } else {
+            format!("{} {}", name, args).trim().to_string()
+        };
 
-        String::from(out)
-    }
+        let is_public = self
+            .directory
+            .conversation(&command.channel)
+            .is_some_and(|convo| convo.is_public());
 
-    fn username_for(&self, slackid: &str) -> String {
-        // TODO stop unwrap()ing so much
-        self.users
-            .as_ref()
-            .unwrap()
-            .get(slackid)
-            .unwrap()
-            .to_string()
+        Event {
+            is_public,
+            was_targeted: true,
+            workspace: self.team_id.clone(),
+            is_slash_command: true,
+            response_url: Some(command.response_url),
+            ..Event::new(&self.name, &command.user, &command.channel, &text)
+        }
     }
 }
@@ -1,18 +1,52 @@
 use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::thread;
+use std::time::Duration;
 
 use reqwest::{
     blocking::Client,
     header::{self, HeaderMap, HeaderValue},
+    StatusCode,
 };
 
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
+use serde_json::{json, Value};
 
+use crate::message::Persona;
+
+#[derive(Clone)]
 pub struct ApiClient {
-    // token: String,
+    api_url: String,
     http: Client,
 }
 
-pub fn new(token: String) -> ApiClient {
+// Things that can go wrong when calling a web API method. The distinction
+// matters to callers: rate limits and transient failures are worth another
+// try later, but permanent ones (bad channel, missing scope) never will work.
+#[derive(Debug)]
+pub enum ApiError {
+    RateLimited(Duration),
+    Transient(String),
+    Permanent(String),
+    MissingScope(String), // a permanent error, but one we can sometimes work around
+}
+
+impl Error for ApiError {}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApiError::RateLimited(d) => write!(f, "rate limited for {:?}", d),
+            ApiError::Transient(s) => write!(f, "transient error: {}", s),
+            ApiError::Permanent(s) => write!(f, "permanent error: {}", s),
+            ApiError::MissingScope(s) => write!(f, "missing scope {}", s),
+        }
+    }
+}
+
+pub fn new(token: String, api_url: &str) -> ApiClient {
     let mut headers = HeaderMap::new();
     headers.insert(
         header::AUTHORIZATION,
@@ -24,42 +58,361 @@ pub fn new(token: String) -> ApiClient {
         .build()
         .unwrap();
 
-    ApiClient { http }
-}
-
-fn url_for(method: &str) -> String {
-    format!("https://slack.com/api/{}", method)
+    ApiClient {
+        api_url: api_url.to_string(),
+        http,
+    }
 }
 
 impl ApiClient {
-    pub fn load_users(&self) -> Option<HashMap<String, String>> {
-        let res = self.http.get(&url_for("users.list")).send();
+    fn url_for(&self, method: &str) -> String {
+        format!("{}/{}", self.api_url, method)
+    }
 
-        #[derive(Debug, Deserialize)]
-        struct Member {
-            id: String,
-            name: String,
+    // POST some json to a method, and hand back the response body if slack
+    // says it's ok.
+    pub fn call(&self, method: &str, body: &Value) -> Result<Value, ApiError> {
+        let res = self.http.post(&self.url_for(method)).json(body).send();
+        handle_response(method, res)
+    }
+
+    // Read methods want their arguments as query params, not json.
+    pub fn get(&self, method: &str, params: &[(&str, &str)]) -> Result<Value, ApiError> {
+        let res = self.http.get(&self.url_for(method)).query(params).send();
+        handle_response(method, res)
+    }
+
+    // If there are blocks, slack shows those, and the text is the fallback for
+    // notifications and such. A persona needs the chat:write.customize scope.
+    pub fn post_message(
+        &self,
+        channel: &str,
+        text: &str,
+        blocks: Option<Value>,
+        persona: Option<&Persona>,
+    ) -> Result<(), ApiError> {
+        let mut body = json!({
+            "channel": channel,
+            "text": text,
+        });
+
+        if let Some(blocks) = blocks {
+            body["blocks"] = blocks;
+        }
+
+        if let Some(persona) = persona {
+            if let Some(username) = &persona.username {
+                body["username"] = json!(username);
+            }
+
+            if let Some(emoji) = &persona.icon_emoji {
+                body["icon_emoji"] = json!(emoji);
+            }
         }
 
+        debug!("posting message: {}", body);
+        self.call("chat.postMessage", &body)?;
+
+        Ok(())
+    }
+
+    // Only user sees it, and only until they reload.
+    pub fn post_ephemeral(
+        &self,
+        channel: &str,
+        user: &str,
+        text: &str,
+        blocks: Option<Value>,
+    ) -> Result<(), ApiError> {
+        let mut body = json!({
+            "channel": channel,
+            "user": user,
+            "text": text,
+        });
+
+        if let Some(blocks) = blocks {
+            body["blocks"] = blocks;
+        }
+
+        debug!("posting ephemeral message: {}", body);
+        self.call("chat.postEphemeral", &body)?;
+
+        Ok(())
+    }
+
+    // Answer a slash command by way of its response url. That's not the web
+    // API, so slack just says "ok" (or doesn't), rather than sending json.
+    // They only last so long, and only take so many responses.
+    pub fn respond(
+        &self,
+        response_url: &str,
+        text: &str,
+        blocks: Option<Value>,
+        ephemeral: bool,
+    ) -> Result<(), ApiError> {
+        let mut body = json!({
+            "text": text,
+            "response_type": if ephemeral { "ephemeral" } else { "in_channel" },
+        });
+
+        if let Some(blocks) = blocks {
+            body["blocks"] = blocks;
+        }
+
+        debug!("responding to {}: {}", response_url, body);
+
+        let res = self
+            .http
+            .post(response_url)
+            .json(&body)
+            .send()
+            .map_err(|e| ApiError::Transient(format!("error responding: {}", e)))?;
+
+        let status = res.status();
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            return Err(ApiError::RateLimited(Duration::from_secs(1)));
+        } else if status.is_server_error() {
+            return Err(ApiError::Transient(format!("response failed: {}", status)));
+        } else if !status.is_success() {
+            return Err(ApiError::Permanent(format!("response failed: {}", status)));
+        }
+
+        Ok(())
+    }
+
+    // Long things go up as a snippet (really a text file) instead. This is
+    // slack's three-step dance: get somewhere to put the file, put it there,
+    // then tell slack to share it.
+    pub fn upload_snippet(
+        &self,
+        channel: &str,
+        content: &str,
+        title: &str,
+    ) -> Result<(), ApiError> {
+        let length = content.len().to_string();
+        let params = [("filename", "reply.txt"), ("length", length.as_str())];
+        let data = self.get("files.getUploadURLExternal", &params)?;
+
+        let (upload_url, file_id) = match (data["upload_url"].as_str(), data["file_id"].as_str()) {
+            (Some(url), Some(id)) => (url, id),
+            _ => {
+                return Err(ApiError::Permanent(
+                    "bad files.getUploadURLExternal response".into(),
+                ))
+            }
+        };
+
+        debug!("uploading {} byte snippet to {}", content.len(), upload_url);
+
+        let res = self
+            .http
+            .post(upload_url)
+            .body(content.to_string())
+            .send()
+            .map_err(|e| ApiError::Transient(format!("error uploading file: {}", e)))?;
+
+        let status = res.status();
+        if status.is_server_error() {
+            return Err(ApiError::Transient(format!("upload failed: {}", status)));
+        } else if !status.is_success() {
+            return Err(ApiError::Permanent(format!("upload failed: {}", status)));
+        }
+
+        let body = json!({
+            "files": [{ "id": file_id, "title": title }],
+            "channel_id": channel,
+        });
+
+        self.call("files.completeUploadExternal", &body)?;
+
+        Ok(())
+    }
+
+    // Fetch every page of a list method, following pagination cursors, and
+    // collect up everything under key. If we get rate limited partway
+    // through, we wait it out rather than starting over.
+    pub fn paginate<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: &[(&str, &str)],
+        key: &str,
+    ) -> Result<Vec<T>, ApiError> {
         #[derive(Debug, Deserialize)]
-        struct UserResponse {
-            ok: bool,
-            members: Vec<Member>,
+        struct ResponseMetadata {
+            #[serde(default)]
+            next_cursor: String,
         }
 
-        let got: UserResponse = res.unwrap().json().unwrap();
+        let mut items = vec![];
+        let mut cursor = String::new();
 
-        if !got.ok {
-            return None;
+        loop {
+            let mut page_params = vec![("limit", "200"), ("cursor", cursor.as_str())];
+            page_params.extend_from_slice(params);
+
+            let mut data = match self.get(method, &page_params) {
+                Ok(data) => data,
+                Err(ApiError::RateLimited(delay)) => {
+                    info!("rate limited calling {}; waiting {:?}", method, delay);
+                    thread::sleep(delay);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            let page: Vec<T> = serde_json::from_value(data[key].take())
+                .map_err(|e| ApiError::Permanent(format!("bad {} response: {}", method, e)))?;
+
+            items.extend(page);
+
+            let meta: Option<ResponseMetadata> =
+                serde_json::from_value(data["response_metadata"].take()).unwrap_or(None);
+
+            cursor = meta.map(|m| m.next_cursor).unwrap_or_default();
+
+            if cursor.is_empty() {
+                break;
+            }
+        }
+
+        Ok(items)
+    }
+
+    pub fn list_users(&self) -> Result<HashMap<String, String>, ApiError> {
+        let members: Vec<Member> = self.paginate("users.list", &[], "members")?;
+
+        let hash: HashMap<_, _> = members.into_iter().map(|m| (m.id, m.name)).collect();
+
+        info!("loaded {} slack users", hash.len());
+        Ok(hash)
+    }
+
+    // Everything in a conversation since (not including) oldest, oldest first.
+    // Slack hands them back newest first, and without saying which
+    // conversation they're from, so we fix both.
+    pub fn history(&self, channel: &str, oldest: &str) -> Result<Vec<Value>, ApiError> {
+        let params = [("channel", channel), ("oldest", oldest)];
+        let mut messages: Vec<Value> =
+            self.paginate("conversations.history", &params, "messages")?;
+
+        messages.reverse();
+
+        for message in &mut messages {
+            message["channel"] = json!(channel);
         }
 
-        let mut hash = HashMap::new();
+        Ok(messages)
+    }
+
+    pub fn list_conversations(&self) -> Result<Vec<Conversation>, ApiError> {
+        let params = [
+            ("types", "public_channel,private_channel,mpim,im"),
+            ("exclude_archived", "true"),
+        ];
+
+        let convos: Vec<Conversation> = self.paginate("conversations.list", &params, "channels")?;
+
+        info!("loaded {} slack conversations", convos.len());
+        Ok(convos)
+    }
+
+    pub fn conversation_info(&self, id: &str) -> Result<Conversation, ApiError> {
+        let data = self.get("conversations.info", &[("channel", id)])?;
+
+        serde_json::from_value(data["channel"].clone())
+            .map_err(|e| ApiError::Permanent(format!("bad conversations.info response: {}", e)))
+    }
+
+    // Gets us the DM with a user, creating it if it doesn't exist yet.
+    pub fn open_dm(&self, user: &str) -> Result<Conversation, ApiError> {
+        let data = self.call("conversations.open", &json!({ "users": user }))?;
+
+        let id = data["channel"]["id"]
+            .as_str()
+            .ok_or_else(|| ApiError::Permanent("bad conversations.open response".to_string()))?;
+
+        Ok(Conversation {
+            id: id.to_string(),
+            user: Some(user.to_string()),
+            is_im: true,
+            is_member: true,
+            ..Conversation::default()
+        })
+    }
+
+    pub fn user_info(&self, id: &str) -> Result<Member, ApiError> {
+        let data = self.get("users.info", &[("user", id)])?;
+
+        serde_json::from_value(data["user"].clone())
+            .map_err(|e| ApiError::Permanent(format!("bad users.info response: {}", e)))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Member {
+    pub id: String,
+    pub name: String,
+}
+
+// What slack calls a conversation: a channel (public or private), a DM, or a
+// group DM. DMs don't have names, but do have the user on the other end.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Conversation {
+    pub id: String,
+    pub name: Option<String>,
+    pub user: Option<String>,
+    #[serde(default)]
+    pub is_private: bool,
+    #[serde(default)]
+    pub is_im: bool,
+    #[serde(default)]
+    pub is_mpim: bool,
+    #[serde(default)]
+    pub is_member: bool,
+}
+
+impl Conversation {
+    pub fn is_public(&self) -> bool {
+        !(self.is_private || self.is_im || self.is_mpim)
+    }
+}
+
+fn handle_response(
+    method: &str,
+    res: reqwest::Result<reqwest::blocking::Response>,
+) -> Result<Value, ApiError> {
+    let res = res.map_err(|e| ApiError::Transient(e.to_string()))?;
+    let status = res.status();
+
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        let retry_after = res
+            .headers()
+            .get(header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1);
+
+        return Err(ApiError::RateLimited(Duration::from_secs(retry_after)));
+    }
+
+    if status.is_server_error() {
+        return Err(ApiError::Transient(format!("{} from {}", status, method)));
+    }
 
-        for mem in got.members {
-            hash.insert(mem.id, mem.name);
+    let data: Value = res
+        .json()
+        .map_err(|e| ApiError::Transient(format!("bad json from {}: {}", method, e)))?;
+
+    if data["ok"].as_bool() != Some(true) {
+        let error = data["error"].as_str().unwrap_or("unknown error");
+
+        if error == "missing_scope" {
+            let needed = data["needed"].as_str().unwrap_or("(slack didn't say)");
+            return Err(ApiError::MissingScope(needed.to_string()));
         }
 
-        info!("loaded slack users");
-        Some(hash)
+        return Err(ApiError::Permanent(format!("{}: {}", method, error)));
     }
+
+    Ok(data)
 }
@@ -0,0 +1,54 @@
+use serde_json::{json, Value};
+
+use crate::message::{Element, Interactive};
+
+// The most a section's text can be; slack says invalid_blocks past this, even
+// though a plain message could be longer.
+pub const MAX_SECTION_TEXT: usize = 3000;
+
+// Block Kit for an interactive reply: the text in a section, and then all the
+// buttons and menus in one actions block. The block id is the callback id,
+// which is how we know where to send things when someone clicks.
+pub fn render(text: &str, interactive: &Interactive) -> Value {
+    let elements: Vec<Value> = interactive
+        .elements
+        .iter()
+        .enumerate()
+        .map(|(i, element)| match element {
+            Element::Button { label, value } => json!({
+                "type": "button",
+                "action_id": format!("button-{}", i),
+                "text": plain_text(label),
+                "value": value,
+            }),
+            Element::Menu {
+                placeholder,
+                options,
+            } => json!({
+                "type": "static_select",
+                "action_id": format!("menu-{}", i),
+                "placeholder": plain_text(placeholder),
+                "options": options
+                    .iter()
+                    .map(|(label, value)| json!({ "text": plain_text(label), "value": value }))
+                    .collect::<Vec<_>>(),
+            }),
+        })
+        .collect();
+
+    json!([
+        {
+            "type": "section",
+            "text": { "type": "mrkdwn", "text": text },
+        },
+        {
+            "type": "actions",
+            "block_id": interactive.callback_id,
+            "elements": elements,
+        },
+    ])
+}
+
+fn plain_text(text: &str) -> Value {
+    json!({ "type": "plain_text", "text": text, "emoji": true })
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use super::api_client::{ApiError, Conversation};
+
+// Like the user cache, this gets a full refresh every so often, with events
+// and one-off lookups filling in the gaps.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// ...but if someone keeps asking for a channel that doesn't exist, we don't
+// want to reload everything every time.
+const MIN_RELOAD_GAP: Duration = Duration::from_secs(60);
+
+// slack conversation id => everything we know about it
+#[derive(Debug)]
+pub struct ConversationCache {
+    by_id: HashMap<String, Conversation>,
+    refreshed_at: Option<Instant>,
+}
+
+pub fn new() -> ConversationCache {
+    ConversationCache {
+        by_id: HashMap::new(),
+        refreshed_at: None,
+    }
+}
+
+impl ConversationCache {
+    pub fn get(&self, id: &str) -> Option<&Conversation> {
+        self.by_id.get(id)
+    }
+
+    // by name, without the leading #
+    pub fn find_by_name(&self, name: &str) -> Option<&Conversation> {
+        let id = self.find_name(name)?;
+        self.by_id.get(&id)
+    }
+
+    pub fn find_name(&self, name: &str) -> Option<String> {
+        self.by_id
+            .values()
+            .find(|c| c.name.as_deref() == Some(name))
+            .map(|c| c.id.clone())
+    }
+
+    pub fn dm_with(&self, user: &str) -> Option<String> {
+        self.by_id
+            .values()
+            .find(|c| c.is_im && c.user.as_deref() == Some(user))
+            .map(|c| c.id.clone())
+    }
+
+    pub fn name_for(&self, id: &str) -> Option<&str> {
+        self.by_id.get(id).and_then(|c| c.name.as_deref())
+    }
+
+    pub fn update(&mut self, convo: Conversation) {
+        debug!(
+            "updating slack conversation {} ({:?})",
+            convo.id, convo.name
+        );
+        self.by_id.insert(convo.id.clone(), convo);
+    }
+
+    pub fn rename(&mut self, id: &str, name: String) {
+        if let Some(convo) = self.by_id.get_mut(id) {
+            debug!("slack conversation {} is now #{}", id, name);
+            convo.name = Some(name);
+        }
+    }
+
+    pub fn set_membership(&mut self, id: &str, is_member: bool) {
+        if let Some(convo) = self.by_id.get_mut(id) {
+            convo.is_member = is_member;
+        }
+    }
+
+    pub fn is_stale(&self) -> bool {
+        match self.refreshed_at {
+            Some(t) => t.elapsed() > REFRESH_INTERVAL,
+            None => true,
+        }
+    }
+
+    pub fn reloaded_recently(&self) -> bool {
+        self.refreshed_at
+            .is_some_and(|t| t.elapsed() < MIN_RELOAD_GAP)
+    }
+
+    pub fn refreshed(&mut self, convos: Result<Vec<Conversation>, ApiError>) {
+        // Either way, we'll wait a full interval before trying this again.
+        self.refreshed_at = Some(Instant::now());
+
+        match convos {
+            Ok(convos) => {
+                self.by_id = convos.into_iter().map(|c| (c.id.clone(), c)).collect();
+            }
+            Err(e) => warn!("couldn't refresh slack conversations: {}", e),
+        }
+    }
+}
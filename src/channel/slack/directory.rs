@@ -0,0 +1,141 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use super::api_client::{ApiClient, Conversation};
+use super::conversation_cache::{self, ConversationCache};
+use super::mrkdwn;
+use super::user_cache::{self, UserCache};
+use crate::state::StateDb;
+
+// Who and what is in the workspace, as far as we know. The channel keeps it
+// up to date, and the writer needs it too: for names, to encode replies, and
+// to find (or open) the conversations they're going to. Both of them sometimes
+// have to ask slack about something, but nobody holds a lock while they do, so
+// neither ever waits on the other's web API calls.
+#[derive(Clone)]
+pub struct Directory {
+    api: ApiClient,
+    users: Arc<Mutex<UserCache>>,
+    conversations: Arc<Mutex<ConversationCache>>,
+}
+
+pub fn new(channel_name: &str, api: ApiClient) -> Directory {
+    Directory {
+        api,
+        users: Arc::new(Mutex::new(user_cache::new(channel_name))),
+        conversations: Arc::new(Mutex::new(conversation_cache::new())),
+    }
+}
+
+impl Directory {
+    pub fn users(&self) -> MutexGuard<'_, UserCache> {
+        self.users.lock().unwrap()
+    }
+
+    pub fn conversations(&self) -> MutexGuard<'_, ConversationCache> {
+        self.conversations.lock().unwrap()
+    }
+
+    // Make sure we know about all of these ids, asking slack about any we
+    // don't. If slack doesn't know either, we just carry on without them.
+    pub fn ensure_users(&self, ids: &[&str], db: Option<&StateDb>) {
+        let missing = self.users().missing(ids);
+        let mut learned = vec![];
+
+        for id in missing {
+            match self.api.user_info(&id) {
+                Ok(member) => {
+                    debug!("learned about new slack user {} ({})", member.name, id);
+                    learned.push(member);
+                }
+                Err(e) => warn!("couldn't look up slack user {}: {}", id, e),
+            }
+        }
+
+        if !learned.is_empty() {
+            self.users().learn(learned, db);
+        }
+    }
+
+    pub fn refresh_users(&self, db: Option<&StateDb>) {
+        let users = self.api.list_users();
+        self.users().refreshed(users, db);
+    }
+
+    // What we know about a conversation, asking slack if we haven't heard of
+    // it before.
+    pub fn conversation(&self, id: &str) -> Option<Conversation> {
+        let known = self.conversations().get(id).cloned();
+        if known.is_some() {
+            return known;
+        }
+
+        match self.api.conversation_info(id) {
+            Ok(convo) => {
+                self.conversations().update(convo.clone());
+                Some(convo)
+            }
+            Err(e) => {
+                warn!("couldn't look up slack conversation {}: {}", id, e);
+                None
+            }
+        }
+    }
+
+    // Find a channel by name (without the leading #). If we don't know it,
+    // maybe it's new, so we'll reload before giving up.
+    pub fn find_conversation(&self, name: &str) -> Option<Conversation> {
+        let known = self.conversations().find_by_name(name).cloned();
+        if known.is_some() || self.conversations().reloaded_recently() {
+            return known;
+        }
+
+        self.refresh_conversations();
+        self.conversations().find_by_name(name).cloned()
+    }
+
+    // The DM conversation with a user, which we'll open if we don't already
+    // have one.
+    pub fn dm_for(&self, user: &str) -> Option<String> {
+        let existing = self.conversations().dm_with(user);
+        if existing.is_some() {
+            return existing;
+        }
+
+        match self.api.open_dm(user) {
+            Ok(convo) => {
+                let id = convo.id.clone();
+                debug!("opened slack dm {} with {}", id, user);
+                self.conversations().update(convo);
+                Some(id)
+            }
+            Err(e) => {
+                warn!("couldn't open a slack dm with {}: {}", user, e);
+                None
+            }
+        }
+    }
+
+    pub fn refresh_conversations(&self) {
+        let convos = self.api.list_conversations();
+        self.conversations().refreshed(convos);
+    }
+}
+
+// what the mrkdwn codec gets to know about the workspace
+impl mrkdwn::Names for Directory {
+    fn user_name(&self, id: &str) -> Option<String> {
+        self.users().name_for(id).map(String::from)
+    }
+
+    fn user_id(&self, name: &str) -> Option<String> {
+        self.users().id_for(name).map(String::from)
+    }
+
+    fn channel_name(&self, id: &str) -> Option<String> {
+        self.conversations().name_for(id).map(String::from)
+    }
+
+    fn channel_id(&self, name: &str) -> Option<String> {
+        self.conversations().find_name(name)
+    }
+}
@@ -0,0 +1,130 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use serde_json::json;
+
+use crate::state::StateDb;
+
+// The ts of the last message we saw in each conversation, so that after a
+// disconnect (or a restart) we know where to pick up from. It lives in the
+// state db, and gets saved every time it changes; that's one little write per
+// message, which sqlite can take.
+#[derive(Debug)]
+pub struct LastSeen {
+    state_key: String,
+    seen: HashMap<String, String>, // conversation id => ts
+}
+
+pub fn new(channel_name: &str) -> LastSeen {
+    LastSeen {
+        state_key: format!("{}/last_seen", channel_name),
+        seen: HashMap::new(),
+    }
+}
+
+// Slack timestamps are "seconds.micros", as a string. They're unique per
+// conversation, but too precise to be trusted to an f64.
+pub fn compare_ts(a: &str, b: &str) -> Ordering {
+    let parse = |ts: &str| {
+        let mut parts = ts.splitn(2, '.');
+        let secs: u64 = parts.next().and_then(|s| s.parse().ok()).unwrap_or(0);
+        let micros: u64 = parts.next().and_then(|s| s.parse().ok()).unwrap_or(0);
+        (secs, micros)
+    };
+
+    parse(a).cmp(&parse(b))
+}
+
+impl LastSeen {
+    pub fn load(&mut self, db: Option<&StateDb>) {
+        let saved = match db.and_then(|db| db.fetch(&self.state_key)) {
+            Some(v) => v,
+            None => return,
+        };
+
+        match serde_json::from_value(saved) {
+            Ok(seen) => self.seen = seen,
+            Err(_) => warn!("ignoring bogus saved state for {}", self.state_key),
+        }
+    }
+
+    // Remember that we've seen this message. Returns false if we'd already
+    // seen it (or something newer) in this conversation, which happens when
+    // a message shows up both live and in a backfill.
+    pub fn saw(&mut self, conversation: &str, ts: &str, db: Option<&StateDb>) -> bool {
+        if let Some(last) = self.seen.get(conversation) {
+            if compare_ts(ts, last) != Ordering::Greater {
+                return false;
+            }
+        }
+
+        self.seen.insert(conversation.to_string(), ts.to_string());
+
+        if let Some(db) = db {
+            db.save(&self.state_key, &json!(self.seen));
+        }
+
+        true
+    }
+
+    pub fn conversations(&self) -> Vec<(String, String)> {
+        self.seen
+            .iter()
+            .map(|(c, ts)| (c.clone(), ts.clone()))
+            .collect()
+    }
+}
+
+// The ts to ask for history after: wherever we left off, but no further back
+// than the oldest thing anyone would still care about.
+pub fn backfill_from(last: &str, oldest: u64) -> String {
+    let floor = format!("{}.000000", oldest);
+
+    match compare_ts(last, &floor) {
+        Ordering::Less => floor,
+        _ => last.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps() {
+        let cases = vec![
+            ("1500000000.000001", "1500000000.000002", Ordering::Less),
+            ("1500000000.000010", "1500000000.000009", Ordering::Greater),
+            ("1500000000.999999", "1500000001.000000", Ordering::Less),
+            ("999999999.000001", "1000000000.000001", Ordering::Less),
+            ("1500000000.000001", "1500000000.000001", Ordering::Equal),
+        ];
+
+        for (a, b, want) in cases {
+            assert_eq!(compare_ts(a, b), want, "{} vs {}", a, b);
+        }
+    }
+
+    #[test]
+    fn backfill_goes_back_only_so_far() {
+        assert_eq!(
+            backfill_from("1500000000.000123", 1400000000),
+            "1500000000.000123"
+        );
+        assert_eq!(
+            backfill_from("1500000000.000123", 1600000000),
+            "1600000000.000000"
+        );
+    }
+
+    #[test]
+    fn repeats_are_ignored() {
+        let mut seen = new("channel/slack");
+
+        assert!(seen.saw("C0001", "1500000000.000002", None));
+        assert!(!seen.saw("C0001", "1500000000.000002", None));
+        assert!(!seen.saw("C0001", "1500000000.000001", None));
+        assert!(seen.saw("C0002", "1500000000.000001", None));
+        assert!(seen.saw("C0001", "1500000000.000003", None));
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use sha2::Sha256;
+use tiny_http::{Request, Response};
+
+use super::reader::FromSlack;
+use super::transport;
+use crate::channel::{self, Input, Listener};
+
+// Slack won't sign anything older than this, so neither will we accept it;
+// that keeps anyone from replaying requests they've seen.
+const MAX_REQUEST_AGE: u64 = 60 * 5;
+
+// Without socket mode, slack delivers interactions and slash commands by
+// POSTing to urls we give it (this doesn't care which, so they can be the
+// same). This listens for those, checks that they really came from slack
+// (by way of the signing secret), and passes them along the same way the
+// reader does.
+pub fn spawn(
+    address: &str,
+    signing_secret: String,
+    to_channel: mpsc::Sender<Input<FromSlack>>,
+) -> Result<Listener, Box<dyn std::error::Error + Send + Sync>> {
+    let listener = channel::listen(address, move |request| {
+        handle(request, &signing_secret, &to_channel)
+    })?;
+
+    info!("listening for slack requests on {}", address);
+    Ok(listener)
+}
+
+fn handle(mut request: Request, signing_secret: &str, to_channel: &mpsc::Sender<Input<FromSlack>>) {
+    let header = |name: &'static str| {
+        request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv(name))
+            .map(|h| h.value.as_str().to_string())
+    };
+
+    let timestamp = header("X-Slack-Request-Timestamp").unwrap_or_default();
+    let signature = header("X-Slack-Signature").unwrap_or_default();
+
+    let mut body = vec![];
+    if let Err(e) = request.as_reader().read_to_end(&mut body) {
+        warn!("error reading slack request: {}", e);
+        return;
+    }
+
+    if !verify(signing_secret, &timestamp, &body, &signature, now()) {
+        warn!("ignoring slack request with a bad signature");
+        let _ = request.respond(Response::empty(401));
+        return;
+    }
+
+    let form: HashMap<String, String> = url::form_urlencoded::parse(&body).into_owned().collect();
+
+    // Interactions are json stuffed in a form field; slash commands are just
+    // a form.
+    let event = if let Some(payload) = form.get("payload") {
+        serde_json::from_str(payload)
+            .ok()
+            .as_ref()
+            .and_then(transport::interaction_from_json)
+    } else if form.contains_key("command") {
+        transport::slash_command_from_json(json!(form))
+    } else {
+        None
+    };
+
+    // Slack wants to hear back right away, whatever we make of it.
+    let _ = request.respond(Response::empty(200));
+
+    if let Some(event) = event {
+        let _ = to_channel.send(Input::Remote(FromSlack::Event(event)));
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+// https://api.slack.com/authentication/verifying-requests-from-slack
+pub fn verify(secret: &str, timestamp: &str, body: &[u8], signature: &str, now: u64) -> bool {
+    let ts: u64 = match timestamp.parse() {
+        Ok(ts) => ts,
+        Err(_) => return false,
+    };
+
+    if now.saturating_sub(ts) > MAX_REQUEST_AGE || ts.saturating_sub(now) > MAX_REQUEST_AGE {
+        return false;
+    }
+
+    let expected = match signature.strip_prefix("v0=").map(hex::decode) {
+        Some(Ok(bytes)) => bytes,
+        _ => return false,
+    };
+
+    let mut mac = match Hmac::<Sha256>::new_from_slice(secret.as_bytes()) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+
+    mac.update(format!("v0:{}:", timestamp).as_bytes());
+    mac.update(body);
+
+    // constant time, so nobody can work it out a byte at a time
+    mac.verify_slice(&expected).is_ok()
+}
+
+// Sign something the way slack would; the tests need to pretend to be slack.
+#[cfg(test)]
+pub fn sign(secret: &str, timestamp: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(format!("v0:{}:", timestamp).as_bytes());
+    mac.update(body);
+    format!("v0={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verification() {
+        // the example from slack's docs
+        let secret = "8f742231b10e8888abcd99yyyzzz85a5";
+        let body = b"token=xyzz0WbapA4vBCDEFasx0q6G&team_id=T1DC2JH3J&team_domain=testteamnow&channel_id=G8PSS9T3V&channel_name=foobar&user_id=U2CERLKJA&user_name=roadrunner&command=%2Fwebhook-collect&text=&response_url=https%3A%2F%2Fhooks.slack.com%2Fcommands%2FT1DC2JH3J%2F397700885554%2F96rGlfmibIGlgcZRskXaIFfN&trigger_id=398738663015.47445629121.803a0bc887a14d10d2c447fce8b6703c";
+        let ts = "1531420618";
+        let good = "v0=a2114d57b48eac39b9ad189dd8316235a7b4a8d21a10bd27519666489c69b503";
+        let now = 1531420618;
+
+        assert_eq!(sign(secret, ts, body), good);
+
+        let cases = vec![
+            // secret, timestamp, body, signature, now, ok?
+            (secret, ts, &body[..], good, now, true),
+            (secret, ts, &body[..], good, now + MAX_REQUEST_AGE, true),
+            (
+                secret,
+                ts,
+                &body[..],
+                good,
+                now + MAX_REQUEST_AGE + 1,
+                false,
+            ),
+            (
+                secret,
+                ts,
+                &body[..],
+                good,
+                now - MAX_REQUEST_AGE - 1,
+                false,
+            ),
+            ("wrong", ts, &body[..], good, now, false),
+            (secret, "1531420619", &body[..], good, now, false),
+            (secret, ts, &b"token=something-else"[..], good, now, false),
+            (secret, ts, &body[..], &good[3..], now, false),
+            (secret, ts, &body[..], "v0=nothex", now, false),
+            (secret, "", &body[..], good, now, false),
+        ];
+
+        for (secret, ts, body, sig, now, want) in cases {
+            assert_eq!(
+                verify(secret, ts, body, sig, now),
+                want,
+                "secret {:?}, ts {:?}, sig {:?}, now {}",
+                secret,
+                ts,
+                sig,
+                now
+            );
+        }
+    }
+}
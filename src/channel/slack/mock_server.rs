@@ -0,0 +1,354 @@
+// A pretend slack, for tests. It speaks just enough of the web API (over
+// tiny_http) and the websocket protocol (via tungstenite's server half) to get
+// a Slack channel connected and talking. Point a channel's api_url at
+// mock.api_url and off you go.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::ErrorKind::WouldBlock;
+use std::net::TcpListener;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use tiny_http::Server;
+
+use crate::channel::testing;
+
+pub const BOT_ID: &str = "UBOT";
+pub const BOT_NAME: &str = "synergy";
+pub const TEAM_ID: &str = "T0001";
+
+// One call to the web API, as we received it. Query params and form/json
+// bodies are all flattened into params, because tests mostly don't care how
+// something was sent. The raw body is there too, for file uploads.
+#[derive(Debug, Clone)]
+pub struct ApiCall {
+    pub method: String,
+    pub params: Value,
+    pub body: String,
+    pub auth: Option<String>,
+    pub at: Instant, // when it showed up
+}
+
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Value,
+}
+
+pub fn ok(body: Value) -> MockResponse {
+    MockResponse {
+        status: 200,
+        headers: vec![],
+        body,
+    }
+}
+
+enum Outgoing {
+    Frame(Value),
+    Close,
+}
+
+pub struct MockSlack {
+    pub api_url: String,
+    calls: mpsc::Receiver<ApiCall>,
+    frames_in: mpsc::Receiver<Value>,
+    frames_out: mpsc::Sender<Outgoing>,
+    canned: Arc<Mutex<HashMap<String, VecDeque<MockResponse>>>>,
+}
+
+pub fn start() -> MockSlack {
+    let ws_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let ws_url = format!("ws://{}/", ws_listener.local_addr().unwrap());
+
+    let server = Server::http("127.0.0.1:0").unwrap();
+    let api_url = format!("http://{}/api", server.server_addr().to_ip().unwrap());
+
+    let (calls_tx, calls) = mpsc::channel();
+    let (frames_in_tx, frames_in) = mpsc::channel();
+    let (frames_out, frames_out_rx) = mpsc::channel();
+    let canned = Arc::new(Mutex::new(HashMap::new()));
+
+    let http_canned = Arc::clone(&canned);
+    let urls = (api_url.clone(), ws_url);
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            serve_api(request, &urls, &http_canned, &calls_tx);
+        }
+    });
+
+    thread::spawn(move || serve_websocket(ws_listener, frames_in_tx, frames_out_rx));
+
+    MockSlack {
+        api_url,
+        calls,
+        frames_in,
+        frames_out,
+        canned,
+    }
+}
+
+impl MockSlack {
+    // Queue up a response for the next call to method, instead of the default.
+    pub fn respond_with(&self, method: &str, response: MockResponse) {
+        self.canned
+            .lock()
+            .unwrap()
+            .entry(method.to_string())
+            .or_default()
+            .push_back(response);
+    }
+
+    // Push a frame down the websocket, as if slack sent it.
+    pub fn send_frame(&self, frame: Value) {
+        self.frames_out.send(Outgoing::Frame(frame)).unwrap();
+    }
+
+    // Hang up on the client, politely.
+    pub fn close_socket(&self) {
+        self.frames_out.send(Outgoing::Close).unwrap();
+    }
+
+    // Wait for the next thing the client wrote to the websocket.
+    pub fn recv_frame(&self) -> Option<Value> {
+        self.frames_in.recv_timeout(Duration::from_secs(5)).ok()
+    }
+
+    // Everything that's been called since the last time we looked, without
+    // waiting around for more.
+    pub fn drain_calls(&self) -> Vec<ApiCall> {
+        self.calls.try_iter().collect()
+    }
+
+    // Wait for the next call to the named API method, skipping any others.
+    pub fn recv_call(&self, method: &str) -> Option<ApiCall> {
+        loop {
+            match self.calls.recv_timeout(Duration::from_secs(5)) {
+                Ok(call) if call.method == method => return Some(call),
+                Ok(_) => continue,
+                Err(_) => return None,
+            }
+        }
+    }
+}
+
+fn serve_api(
+    mut request: tiny_http::Request,
+    urls: &(String, String),
+    canned: &Mutex<HashMap<String, VecDeque<MockResponse>>>,
+    calls: &mpsc::Sender<ApiCall>,
+) {
+    let at = Instant::now();
+
+    let (path, query) = match request.url().find('?') {
+        Some(i) => (
+            &request.url()[..i],
+            Some(request.url()[i + 1..].to_string()),
+        ),
+        None => (request.url(), None),
+    };
+
+    let method = path.trim_start_matches("/api/").to_string();
+
+    let auth = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Authorization"))
+        .map(|h| h.value.as_str().to_string());
+
+    let mut body = String::new();
+    request.as_reader().read_to_string(&mut body).unwrap();
+
+    let mut params = serde_json::Map::new();
+    let form = [query.unwrap_or_default(), body.clone()];
+    for pair in form.iter().flat_map(|s| s.split('&')) {
+        let mut kv = pair.splitn(2, '=');
+        if let (Some(k), Some(v)) = (kv.next(), kv.next()) {
+            let v = percent_decode(v);
+            params.insert(percent_decode(k), Value::String(v));
+        }
+    }
+
+    if let Ok(Value::Object(map)) = serde_json::from_str(&body) {
+        params.extend(map);
+    }
+
+    let call = ApiCall {
+        method: method.clone(),
+        params: Value::Object(params),
+        body,
+        auth,
+        at,
+    };
+
+    let response = canned
+        .lock()
+        .unwrap()
+        .get_mut(&method)
+        .and_then(|q| q.pop_front())
+        .unwrap_or_else(|| default_response(&call, urls));
+
+    // The test might have gone away already; that's fine.
+    let _ = calls.send(call);
+
+    testing::respond_json(request, response.status, response.headers, &response.body);
+}
+
+pub fn conversations() -> Value {
+    json!([
+        { "id": "C0001", "name": "general", "is_member": true },
+        { "id": "C0002", "name": "secret", "is_private": true, "is_member": true },
+        { "id": "C0003", "name": "random", "is_member": false },
+        { "id": "G0001", "name": "mpdm-alice--bob-1", "is_mpim": true, "is_private": true },
+        { "id": "D0001", "is_im": true, "user": "U0001" },
+    ])
+}
+
+fn default_response(call: &ApiCall, urls: &(String, String)) -> MockResponse {
+    let (api_url, ws_url) = urls;
+    let me = json!({ "id": BOT_ID, "name": BOT_NAME });
+
+    let body = match call.method.as_str() {
+        "rtm.connect" => json!({
+            "ok": true,
+            "url": ws_url,
+            "self": me,
+            "team": { "id": TEAM_ID, "name": "Synergy Test" },
+        }),
+        "apps.connections.open" => json!({ "ok": true, "url": ws_url }),
+        "auth.test" => json!({
+            "ok": true,
+            "user_id": BOT_ID,
+            "user": BOT_NAME,
+            "team_id": TEAM_ID,
+        }),
+        "users.list" => json!({
+            "ok": true,
+            "members": [
+                { "id": BOT_ID, "name": BOT_NAME },
+                { "id": "U0001", "name": "alice" },
+                { "id": "U0002", "name": "bob" },
+            ],
+        }),
+        "users.info" => {
+            let id = call.params["user"].as_str().unwrap_or_default();
+            let name = format!("user-{}", id.to_lowercase());
+            json!({ "ok": true, "user": { "id": id, "name": name } })
+        }
+        "conversations.list" => json!({ "ok": true, "channels": conversations() }),
+        "conversations.info" => {
+            let all = conversations();
+            let found = all
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|c| c["id"] == call.params["channel"]);
+
+            match found {
+                Some(c) => json!({ "ok": true, "channel": c }),
+                None => json!({ "ok": false, "error": "channel_not_found" }),
+            }
+        }
+        "conversations.history" => json!({ "ok": true, "messages": [] }),
+        "conversations.open" => {
+            let user = call.params["users"].as_str().unwrap_or_default();
+            let id = format!("D{}", user.trim_start_matches('U'));
+            json!({ "ok": true, "channel": { "id": id } })
+        }
+        "chat.postMessage" => json!({
+            "ok": true,
+            "channel": call.params["channel"],
+            "ts": "1000000000.000100",
+        }),
+        "chat.postEphemeral" => json!({ "ok": true, "message_ts": "1000000000.000200" }),
+        // where we point slash commands' response_urls
+        m if m.starts_with("response.") => json!({ "ok": true }),
+        "files.getUploadURLExternal" => json!({
+            "ok": true,
+            "upload_url": format!("{}/upload.F0001", api_url),
+            "file_id": "F0001",
+        }),
+        "upload.F0001" => json!({ "ok": true }),
+        "files.completeUploadExternal" => json!({ "ok": true, "files": [] }),
+        _ => json!({ "ok": false, "error": "unknown_method" }),
+    };
+
+    ok(body)
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = vec![];
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+                match u8::from_str_radix(hex, 16) {
+                    Ok(b) => out.push(b),
+                    Err(_) => out.extend_from_slice(&bytes[i..i + 3]),
+                }
+                i += 2;
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&out).to_string()
+}
+
+// We only ever expect one client at a time, but it might reconnect, so keep
+// accepting.
+fn serve_websocket(
+    listener: TcpListener,
+    frames_in: mpsc::Sender<Value>,
+    frames_out: mpsc::Receiver<Outgoing>,
+) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+
+        let mut ws = match tungstenite::accept(stream) {
+            Ok(ws) => ws,
+            Err(_) => continue,
+        };
+
+        ws.get_mut()
+            .set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+
+        loop {
+            while let Ok(outgoing) = frames_out.try_recv() {
+                let res = match outgoing {
+                    Outgoing::Frame(frame) => {
+                        ws.write_message(tungstenite::Message::Text(frame.to_string()))
+                    }
+                    Outgoing::Close => ws.close(None),
+                };
+
+                if res.is_err() {
+                    break;
+                }
+            }
+
+            match ws.read_message() {
+                Ok(tungstenite::Message::Text(s)) => {
+                    if let Ok(v) = serde_json::from_str(&s) {
+                        let _ = frames_in.send(v);
+                    }
+                }
+                Ok(tungstenite::Message::Close(_)) => break,
+                Ok(_) => (),
+                Err(tungstenite::Error::Io(ref e)) if e.kind() == WouldBlock => (),
+                Err(_) => break,
+            }
+        }
+    }
+}
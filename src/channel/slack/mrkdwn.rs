@@ -0,0 +1,366 @@
+use chrono::{TimeZone, Utc};
+use regex::{Captures, Regex};
+
+// Slack's "mrkdwn" is mostly markdown, except for the angle-bracketed bits:
+// <@U123> for users, <#C123|general> for channels, <!here> and friends for
+// broadcasts, <https://...|label> for links, and so on. Also, &, < and > are
+// always escaped. Everything here goes in both directions: decode() turns what
+// slack sends us into plain text for reactors, and encode() turns reactor
+// output back into something slack will render properly.
+
+// The bits of the slack directory we need to do that. The channel implements
+// this on top of its caches; tests do it with a couple of hashmaps.
+pub trait Names {
+    fn user_name(&self, id: &str) -> Option<String>;
+    fn user_id(&self, name: &str) -> Option<String>;
+    fn channel_name(&self, id: &str) -> Option<String>;
+    fn channel_id(&self, name: &str) -> Option<String>;
+}
+
+lazy_static! {
+    static ref TOKEN_RE: Regex = Regex::new(r"<([^<>]*)>").unwrap();
+    static ref USER_TOKEN_RE: Regex = Regex::new(r"<@([UW][A-Z0-9]+)(?:\|[^>]*)?>").unwrap();
+
+    // In the outbound direction, a mention has to start a word, so that
+    // email addresses and the like are left alone.
+    static ref MENTION_RE: Regex =
+        Regex::new(r"(^|[^\w@#&])([@#])([A-Za-z0-9](?:[A-Za-z0-9._-]*[A-Za-z0-9])?)").unwrap();
+
+    // code spans and blocks are left exactly as they are (except for escaping)
+    static ref CODE_RE: Regex = Regex::new(r"(?s)```.*?```|`[^`\n]*`").unwrap();
+}
+
+// Broadcasts slack knows about, which reactors may or may not be allowed to
+// send.
+const BROADCASTS: &[&str] = &["here", "channel", "everyone"];
+
+// All the user ids mentioned in some raw slack text, so the caller can make
+// sure it knows about them before decoding.
+pub fn mentioned_users(text: &str) -> Vec<&str> {
+    USER_TOKEN_RE
+        .captures_iter(text)
+        .map(|c| c.get(1).unwrap().as_str())
+        .collect()
+}
+
+pub fn decode(text: &str, names: &dyn Names) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+
+    for caps in TOKEN_RE.captures_iter(text) {
+        let whole = caps.get(0).unwrap();
+        out.push_str(&unescape(&text[last..whole.start()]));
+        out.push_str(&decode_token(&caps[1], names));
+        last = whole.end();
+    }
+
+    out.push_str(&unescape(&text[last..]));
+    out
+}
+
+// The inside of a <...> token. Most of them look like "thing|label", where
+// the label is optional and is what slack would show the user.
+fn decode_token(token: &str, names: &dyn Names) -> String {
+    let (target, label) = match token.find('|') {
+        Some(i) => (&token[..i], Some(unescape(&token[i + 1..]))),
+        None => (token, None),
+    };
+
+    if let Some(id) = target.strip_prefix('@') {
+        let name = names.user_name(id).or(label).unwrap_or_else(|| id.into());
+        return format!("@{}", name);
+    }
+
+    if let Some(id) = target.strip_prefix('#') {
+        let name = label
+            .or_else(|| names.channel_name(id))
+            .unwrap_or_else(|| id.into());
+        return format!("#{}", name);
+    }
+
+    if let Some(special) = target.strip_prefix('!') {
+        return decode_special(special, label);
+    }
+
+    if let Some(address) = target.strip_prefix("mailto:") {
+        return label.unwrap_or_else(|| unescape(address));
+    }
+
+    // anything else is a link
+    label.unwrap_or_else(|| unescape(target))
+}
+
+// <!here>, <!subteam^S123|@team>, <!date^1392734382^{date}|Feb 18, 2014>...
+fn decode_special(special: &str, label: Option<String>) -> String {
+    let mut parts = special.split('^');
+    let kind = parts.next().unwrap_or_default();
+
+    match kind {
+        "here" | "channel" | "everyone" => format!("@{}", kind),
+        "subteam" => label.unwrap_or_else(|| format!("@{}", parts.next().unwrap_or(kind))),
+        "date" => {
+            if let Some(fallback) = label {
+                return fallback;
+            }
+
+            // The fallback is supposed to be required, but just in case; if
+            // the timestamp's nonsense too, it's all we've got.
+            let ts = parts.next().unwrap_or_default();
+            ts.parse::<i64>()
+                .ok()
+                .and_then(|n| Utc.timestamp_opt(n, 0).single())
+                .map(|t| t.format("%Y-%m-%d %H:%M UTC").to_string())
+                .unwrap_or_else(|| ts.to_string())
+        }
+        _ => label.unwrap_or_default(),
+    }
+}
+
+// allow_broadcast says whether @here and friends should actually notify
+// people; if not, they go out as plain text. Names we don't know about also
+// go out as plain text.
+pub fn encode(text: &str, names: &dyn Names, allow_broadcast: bool) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+
+    for code in CODE_RE.find_iter(text) {
+        out.push_str(&encode_prose(
+            &text[last..code.start()],
+            names,
+            allow_broadcast,
+        ));
+        out.push_str(&escape(code.as_str()));
+        last = code.end();
+    }
+
+    out.push_str(&encode_prose(&text[last..], names, allow_broadcast));
+    out
+}
+
+fn encode_prose(text: &str, names: &dyn Names, allow_broadcast: bool) -> String {
+    let escaped = escape(text);
+
+    MENTION_RE
+        .replace_all(&escaped, |caps: &Captures| {
+            let (prefix, sigil, name) = (&caps[1], &caps[2], &caps[3]);
+            let lower = name.to_lowercase();
+
+            let encoded = match sigil {
+                "@" if BROADCASTS.contains(&lower.as_str()) => {
+                    if allow_broadcast {
+                        Some(format!("<!{}>", lower))
+                    } else {
+                        None
+                    }
+                }
+                "@" => names.user_id(&lower).map(|id| format!("<@{}>", id)),
+                _ => names.channel_id(&lower).map(|id| format!("<#{}>", id)),
+            };
+
+            match encoded {
+                Some(s) => format!("{}{}", prefix, s),
+                None => caps[0].to_string(),
+            }
+        })
+        .into_owned()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    struct FakeNames {
+        users: HashMap<&'static str, &'static str>,
+        channels: HashMap<&'static str, &'static str>,
+    }
+
+    fn names() -> FakeNames {
+        FakeNames {
+            users: vec![
+                ("U0001", "alice"),
+                ("U0002", "bob.smith"),
+                ("W0003", "carol"),
+            ]
+            .into_iter()
+            .collect(),
+            channels: vec![("C0001", "general"), ("G0002", "secret")]
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    impl Names for FakeNames {
+        fn user_name(&self, id: &str) -> Option<String> {
+            self.users.get(id).map(|s| s.to_string())
+        }
+
+        fn user_id(&self, name: &str) -> Option<String> {
+            self.users
+                .iter()
+                .find(|(_, n)| **n == name)
+                .map(|(id, _)| id.to_string())
+        }
+
+        fn channel_name(&self, id: &str) -> Option<String> {
+            self.channels.get(id).map(|s| s.to_string())
+        }
+
+        fn channel_id(&self, name: &str) -> Option<String> {
+            self.channels
+                .iter()
+                .find(|(_, n)| **n == name)
+                .map(|(id, _)| id.to_string())
+        }
+    }
+
+    #[test]
+    fn decoding() {
+        let cases = vec![
+            ("plain text", "plain text"),
+            // escapes
+            ("a &lt;b&gt; &amp; c", "a <b> & c"),
+            ("&amp;lt; stays put", "&lt; stays put"),
+            // users
+            ("hi <@U0001>", "hi @alice"),
+            ("hi <@W0003>", "hi @carol"),
+            ("hi <@U0001|someone>", "hi @alice"),
+            ("hi <@U9999|zed>", "hi @zed"),
+            ("hi <@U9999>", "hi @U9999"),
+            // channels
+            ("see <#C0001|general>", "see #general"),
+            ("see <#C0001>", "see #general"),
+            ("see <#C9999>", "see #C9999"),
+            // broadcasts
+            ("<!here> lunch", "@here lunch"),
+            ("<!here|@here> lunch", "@here lunch"),
+            ("<!channel>", "@channel"),
+            ("<!everyone>", "@everyone"),
+            ("<!subteam^S0001|@ops> help", "@ops help"),
+            ("<!subteam^S0001> help", "@S0001 help"),
+            // dates
+            (
+                "<!date^1392734382^{date_short}|Feb 18, 2014>",
+                "Feb 18, 2014",
+            ),
+            ("<!date^1392734382^{date_short}>", "2014-02-18 14:39 UTC"),
+            (
+                "<!date^99999999999999999^{date_short}>",
+                "99999999999999999",
+            ),
+            ("<!date^99999999999999999^{date}|someday>", "someday"),
+            // links
+            ("<https://example.com>", "https://example.com"),
+            ("<https://example.com|example>", "example"),
+            (
+                "<https://example.com?a=1&amp;b=2>",
+                "https://example.com?a=1&b=2",
+            ),
+            ("<mailto:a@example.com|a@example.com>", "a@example.com"),
+            ("<mailto:a@example.com>", "a@example.com"),
+            // several at once
+            (
+                "<@U0001>: see <#C0001|general> &amp; <https://x.com|x>",
+                "@alice: see #general & x",
+            ),
+        ];
+
+        let names = names();
+
+        for (input, want) in cases {
+            assert_eq!(decode(input, &names), want, "decoding {:?}", input);
+        }
+    }
+
+    #[test]
+    fn encoding() {
+        let cases = vec![
+            ("plain text", "plain text"),
+            // escapes
+            ("a <b> & c", "a &lt;b&gt; &amp; c"),
+            // users
+            ("hi @alice", "hi <@U0001>"),
+            ("hi @Alice!", "hi <@U0001>!"),
+            ("(@alice)", "(<@U0001>)"),
+            ("ask @bob.smith.", "ask <@U0002>."),
+            ("hi @nobody", "hi @nobody"),
+            ("mail alice@example.com", "mail alice@example.com"),
+            // channels
+            ("see #general", "see <#C0001>"),
+            ("see #secret, #nowhere", "see <#G0002>, #nowhere"),
+            ("issue#general", "issue#general"),
+            // code is left alone, apart from escaping
+            ("`@alice` @alice", "`@alice` <@U0001>"),
+            (
+                "```\n#general <x>\n``` #general",
+                "```\n#general &lt;x&gt;\n``` <#C0001>",
+            ),
+            // broadcasts are only plain text without permission
+            ("@here lunch", "@here lunch"),
+            ("@channel", "@channel"),
+        ];
+
+        let names = names();
+
+        for (input, want) in cases {
+            assert_eq!(encode(input, &names, false), want, "encoding {:?}", input);
+        }
+    }
+
+    #[test]
+    fn encoding_broadcasts() {
+        let cases = vec![
+            ("@here lunch", "<!here> lunch"),
+            ("@channel", "<!channel>"),
+            ("@Everyone!", "<!everyone>!"),
+            ("`@here`", "`@here`"),
+        ];
+
+        let names = names();
+
+        for (input, want) in cases {
+            assert_eq!(encode(input, &names, true), want, "encoding {:?}", input);
+        }
+    }
+
+    #[test]
+    fn mentions() {
+        let cases = vec![
+            ("nobody here", vec![]),
+            ("<@U0001> and <@W0003|carol>", vec!["U0001", "W0003"]),
+            ("<#C0001> <!here>", vec![]),
+        ];
+
+        for (input, want) in cases {
+            assert_eq!(mentioned_users(input), want, "mentions in {:?}", input);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let cases = vec![
+            "hi @alice, see #general",
+            "a <b> & c",
+            "`x < y` and ```@alice```",
+        ];
+
+        let names = names();
+
+        for input in cases {
+            let encoded = encode(input, &names, false);
+            assert_eq!(decode(&encoded, &names), input, "round trip {:?}", input);
+        }
+    }
+}
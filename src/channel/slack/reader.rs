@@ -0,0 +1,72 @@
+use std::sync::mpsc;
+use std::thread;
+
+use crate::channel::backoff::Backoff;
+use crate::channel::{self, Input};
+
+use super::transport::{Incoming, SlackEvent, SlackIdentity, Transport};
+
+// What the reader has to say to the rest of the channel.
+pub enum FromSlack {
+    Connected(SlackIdentity),
+    Event(SlackEvent),
+    Disconnected,
+}
+
+// The reader owns the websocket, and does nothing but (re)connect and pass
+// along whatever comes in, so it can block on reads as long as it likes.
+pub fn spawn(
+    mut transport: Box<dyn Transport + Send>,
+    mut backoff: Backoff,
+    to_channel: mpsc::Sender<Input<FromSlack>>,
+) -> mpsc::Sender<()> {
+    channel::spawn_reader(move |should_stop| loop {
+        // Keep trying until we get a connection. Replies don't care, since
+        // they don't go out this way.
+        let me = loop {
+            if should_stop() {
+                return;
+            }
+
+            match transport.connect() {
+                Ok(me) => break me,
+                Err(e) => {
+                    let delay = backoff.next_delay();
+                    warn!("error connecting to slack ({}); retrying in {:?}", e, delay);
+                    thread::sleep(delay);
+                }
+            }
+        };
+
+        backoff.reset();
+
+        if to_channel
+            .send(Input::Remote(FromSlack::Connected(me)))
+            .is_err()
+        {
+            return;
+        }
+
+        loop {
+            if should_stop() {
+                return;
+            }
+
+            let msg = match transport.recv() {
+                Incoming::Event(event) => FromSlack::Event(event),
+                Incoming::Nothing => continue,
+                Incoming::Disconnected => FromSlack::Disconnected,
+            };
+
+            let disconnected = matches!(msg, FromSlack::Disconnected);
+
+            if to_channel.send(Input::Remote(msg)).is_err() {
+                return;
+            }
+
+            if disconnected {
+                break;
+            }
+        }
+    })
+}
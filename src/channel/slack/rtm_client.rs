@@ -1,177 +1,104 @@
 use std::error::Error;
-use std::fmt;
-use std::io::ErrorKind::WouldBlock;
-use std::time::Duration;
 
 use reqwest::Url;
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 
-use crate::message::Reply;
-
-type Websocket = tungstenite::protocol::WebSocket<tungstenite::client::AutoStream>;
+use super::transport::{self, Frame, Incoming, SlackIdentity, SlackInternalError, Socket};
 
 // boxes up our websocket
 pub struct RtmClient {
-    ws: Option<Websocket>,
-}
-
-// This is a raw message event, and only matches messages, because that's the
-// only thing we care about. Other things will try to deserialize to this and
-// not be able to, in which case we'll just ignore it. That's not the "proper"
-// way to do it, but gets us up and running.
-#[derive(Deserialize, Debug)]
-pub struct RawEvent {
-    ts: String,
-    #[serde(rename = "type")]
-    pub kind: String,
-    pub subtype: Option<String>,
-    pub channel: String,
-    pub text: String,
-    pub user: String,
-    bot_id: Option<String>,
+    api_url: String,
+    api_token: String,
+    socket: Option<Socket>,
 }
 
-// guts
-
-#[derive(Deserialize, Debug)]
-pub struct SlackIdentity {
-    pub id: String,
-    pub name: String,
-}
-
-#[derive(Serialize, Debug)]
-struct OutgoingMessage {
-    #[serde(rename = "type")]
-    kind: String,
-    channel: String,
-    text: String,
-}
-
-#[derive(Debug)]
-struct SlackInternalError(String);
-
-impl Error for SlackInternalError {}
-
-impl fmt::Display for SlackInternalError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Error while talking to slack: {}", self.0)
+pub fn new(api_url: &str, api_token: &str) -> RtmClient {
+    RtmClient {
+        api_url: api_url.to_string(),
+        api_token: api_token.to_string(),
+        socket: None,
     }
 }
 
-pub fn new() -> RtmClient {
-    RtmClient { ws: None }
-}
-
-impl RtmClient {
-    pub fn connect(&mut self, api_token: &str) -> SlackIdentity {
-        let (ws, me) = get_websocket(api_token).expect("Error connecting to slack!");
+impl transport::Transport for RtmClient {
+    fn connect(&mut self) -> Result<SlackIdentity, Box<dyn Error>> {
+        // if we had an old socket lying around, it's useless now.
+        self.socket = None;
 
-        self.ws = Some(ws);
+        let (socket, me) = self.get_websocket()?;
+        self.socket = Some(socket);
 
-        me
+        Ok(me)
     }
 
-    pub fn send(&mut self, reply: Reply) {
-        let to_send = OutgoingMessage {
-            kind: "message".to_string(),
-            text: reply.text.clone(),
-            channel: reply.conversation_address.clone(),
+    fn recv(&mut self) -> Incoming {
+        let socket = match self.socket.as_mut() {
+            Some(s) => s,
+            None => return Incoming::Disconnected,
         };
 
-        let text = serde_json::to_string(&to_send).unwrap();
-
-        debug!("writing message: {:?}", to_send);
-        self.ws
-            .as_mut()
-            .unwrap()
-            .write_message(tungstenite::Message::Text(text))
-            .unwrap();
-    }
-
-    pub fn recv(&mut self) -> Option<RawEvent> {
-        let message = match self.ws.as_mut().unwrap().read_message() {
-            Ok(m) => m,
-            Err(tungstenite::error::Error::Io(e)) => {
-                if e.kind() == WouldBlock {
-                    return None;
-                }
-
-                info!("IO error reading from websocket: {:?}", e);
-                return None;
+        let frame = match socket.read() {
+            Frame::Text(s) => s,
+            Frame::Nothing => return Incoming::Nothing,
+            Frame::Closed => {
+                self.socket = None;
+                return Incoming::Disconnected;
             }
-            Err(e) => {
-                info!("error reading from websocket: {:?}", e);
-                return None;
-            }
-        };
-
-        self.process_ws_message(message)
-    }
-
-    fn process_ws_message(&self, message: tungstenite::Message) -> Option<RawEvent> {
-        let frame = match message {
-            tungstenite::Message::Text(ref s) => s,
-            tungstenite::Message::Close(_) => {
-                info!("got close message; figure out what to do here");
-                return None;
-            }
-            // ignore everything else (ping/pong/binary)
-            _ => return None,
         };
 
-        let event: RawEvent = match serde_json::from_str(frame) {
-            Ok(re) => re,
+        let value = match serde_json::from_str(&frame) {
+            Ok(v) => v,
             Err(e) => {
-                trace!("error derializing frame {}: {}", frame, e);
-                return None;
+                trace!("error deserializing frame {}: {}", frame, e);
+                return Incoming::Nothing;
             }
         };
 
-        if event.bot_id.is_some() {
-            return None;
+        match transport::event_from_json(value) {
+            Some(event) => Incoming::Event(event),
+            None => Incoming::Nothing,
         }
-
-        Some(event)
     }
 }
 
-fn get_websocket(api_token: &str) -> Result<(Websocket, SlackIdentity), Box<dyn Error>> {
-    // using blocking here because I think I'm going to do the concurrent
-    // stuff a different way.
-    let mut url = Url::parse("https://slack.com/api/rtm.connect")?;
-    url.query_pairs_mut().append_pair("token", api_token);
+impl RtmClient {
+    fn get_websocket(&self) -> Result<(Socket, SlackIdentity), Box<dyn Error>> {
+        // using blocking here because I think I'm going to do the concurrent
+        // stuff a different way.
+        let mut url = Url::parse(&format!("{}/rtm.connect", self.api_url))?;
+        url.query_pairs_mut().append_pair("token", &self.api_token);
 
-    let client = reqwest::blocking::Client::new();
+        let client = reqwest::blocking::Client::new();
 
-    #[derive(Deserialize, Debug)]
-    struct ConnectResp {
-        ok: bool,
-        url: String,
-        #[serde(rename = "self")]
-        me: SlackIdentity,
-    }
+        #[derive(Deserialize, Debug)]
+        struct Team {
+            id: String,
+        }
 
-    let data: ConnectResp = client.get(url).send()?.json()?;
+        #[derive(Deserialize, Debug)]
+        struct ConnectResp {
+            ok: bool,
+            url: String,
+            #[serde(rename = "self")]
+            me: SlackIdentity,
+            team: Team,
+        }
 
-    if !data.ok {
-        return Err(Box::new(SlackInternalError(
-            "bad data from connect".to_string(),
-        )));
-    }
+        let data: ConnectResp = client.get(url).send()?.json()?;
 
-    let me = data.me;
+        if !data.ok {
+            return Err(Box::new(SlackInternalError(
+                "bad data from connect".to_string(),
+            )));
+        }
 
-    let (mut websocket, _resp) = tungstenite::client::connect(data.url)?;
+        let socket = transport::connect_websocket(&data.url)?;
 
-    info!("connected to slack");
+        let me = SlackIdentity {
+            team_id: data.team.id,
+            ..data.me
+        };
 
-    debug!("setting slack websocket to non-blocking...");
-    if let tungstenite::stream::Stream::Tls(stream) = websocket.get_mut() {
-        stream
-            .get_mut()
-            .set_read_timeout(Some(Duration::from_millis(50)))
-            .unwrap();
+        Ok((socket, me))
     }
-
-    Ok((websocket, me))
 }
@@ -0,0 +1,200 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use super::api_client::{ApiClient, ApiError};
+use super::blocks;
+use crate::message::Reply;
+
+// How many times we'll try a reply that keeps failing for reasons that aren't
+// its fault (network trouble, slack having a bad day) before giving up on it.
+const MAX_ATTEMPTS: u32 = 5;
+
+// Replies waiting to go out, one queue per conversation. Within a
+// conversation, things go out in the order they came in: if the reply at the
+// front can't be sent yet, everything behind it waits too. Different
+// conversations don't hold each other up.
+#[derive(Debug, Default)]
+pub struct SendQueue {
+    queues: HashMap<String, VecDeque<Pending>>,
+    blocked_until: HashMap<String, Instant>,
+    no_personas: bool, // set once we find out our token can't do them
+}
+
+#[derive(Debug)]
+struct Pending {
+    reply: Reply,
+    attempts: u32,
+    as_snippet: bool,
+    is_notice: bool, // telling someone about a reply we couldn't send
+}
+
+pub fn new() -> SendQueue {
+    SendQueue::default()
+}
+
+impl SendQueue {
+    pub fn push(&mut self, reply: Reply) {
+        self.enqueue(reply, false);
+    }
+
+    // Same as push, but the text gets uploaded as a snippet instead.
+    pub fn push_snippet(&mut self, reply: Reply) {
+        self.enqueue(reply, true);
+    }
+
+    fn enqueue(&mut self, reply: Reply, as_snippet: bool) {
+        self.queues
+            .entry(reply.conversation_address.clone())
+            .or_default()
+            .push_back(Pending {
+                reply,
+                attempts: 0,
+                as_snippet,
+                is_notice: false,
+            });
+    }
+
+    // When we'll next have something we can try to send, if anything.
+    pub fn next_attempt(&self) -> Option<Instant> {
+        let now = Instant::now();
+
+        self.queues
+            .keys()
+            .map(|c| self.blocked_until.get(c).copied().unwrap_or(now))
+            .min()
+    }
+
+    // Send whatever we can right now.
+    pub fn flush(&mut self, api: &ApiClient) {
+        let now = Instant::now();
+
+        for (conversation, queue) in self.queues.iter_mut() {
+            if let Some(until) = self.blocked_until.get(conversation) {
+                if *until > now {
+                    continue;
+                }
+            }
+
+            self.blocked_until.remove(conversation);
+
+            while let Some(pending) = queue.front_mut() {
+                let reply = &pending.reply;
+
+                let blocks = reply
+                    .interactive
+                    .as_ref()
+                    .map(|i| blocks::render(&reply.text, i));
+
+                let res = if pending.as_snippet {
+                    let title = format!("reply from {}", reply.origin);
+                    api.upload_snippet(&reply.conversation_address, &reply.text, &title)
+                } else if let Some(url) = &reply.response_url {
+                    api.respond(url, &reply.text, blocks, reply.is_ephemeral)
+                } else if reply.is_ephemeral {
+                    api.post_ephemeral(
+                        &reply.conversation_address,
+                        &reply.from_address,
+                        &reply.text,
+                        blocks,
+                    )
+                } else {
+                    let persona = if self.no_personas {
+                        None
+                    } else {
+                        reply.persona.as_ref()
+                    };
+
+                    api.post_message(&reply.conversation_address, &reply.text, blocks, persona)
+                };
+
+                let err = match res {
+                    Ok(_) => {
+                        queue.pop_front();
+                        continue;
+                    }
+                    Err(e) => e,
+                };
+
+                match err {
+                    // Better to post as ourselves than not at all, and there's
+                    // no point asking again until someone fixes the scopes.
+                    ApiError::MissingScope(scope)
+                        if reply.persona.is_some() && !self.no_personas =>
+                    {
+                        warn!(
+                            "can't use personas without the {} scope; posting as ourselves",
+                            scope
+                        );
+                        self.no_personas = true;
+                    }
+                    // Response urls expire, but we can still get there the
+                    // usual way (as long as we're in the conversation).
+                    ApiError::Permanent(e) if reply.response_url.is_some() => {
+                        info!("couldn't use response url ({}); posting instead", e);
+                        pending.reply.response_url = None;
+                    }
+                    ApiError::RateLimited(delay) => {
+                        info!("rate limited in {}; waiting {:?}", conversation, delay);
+                        // from when we were told, not from when we started
+                        let until = Instant::now() + delay;
+                        self.blocked_until.insert(conversation.clone(), until);
+                        break;
+                    }
+                    ApiError::Transient(e) if pending.attempts + 1 < MAX_ATTEMPTS => {
+                        pending.attempts += 1;
+                        let delay = Duration::from_secs(u64::from(pending.attempts));
+                        warn!(
+                            "couldn't send to {} ({}); trying again in {:?}",
+                            conversation, e, delay
+                        );
+                        let until = Instant::now() + delay;
+                        self.blocked_until.insert(conversation.clone(), until);
+                        break;
+                    }
+                    // Whoever was waiting on this should hear that it isn't
+                    // coming, if slack will let us tell them; if the notice
+                    // doesn't make it either, the log is all that's left.
+                    e => {
+                        error!(
+                            "giving up on reply from {} to {}: {} (text was: {:?})",
+                            reply.origin, conversation, e, reply.text
+                        );
+
+                        let notice = if pending.is_notice {
+                            None
+                        } else {
+                            Some(failure_notice(reply, &e))
+                        };
+
+                        queue.pop_front();
+
+                        if let Some(notice) = notice {
+                            queue.push_front(notice);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.queues.retain(|_, q| !q.is_empty());
+    }
+}
+
+fn failure_notice(reply: &Reply, err: &ApiError) -> Pending {
+    let text = format!(
+        "I had a reply from {} for you, but slack wouldn't take it ({}).",
+        reply.origin, err
+    );
+
+    Pending {
+        reply: Reply {
+            text,
+            interactive: None,
+            persona: None,
+            ..reply.clone()
+        },
+        attempts: 0,
+        as_snippet: false,
+        is_notice: true,
+    }
+}
@@ -0,0 +1,185 @@
+use std::error::Error;
+
+use reqwest::blocking::Client;
+use serde::Deserialize;
+use serde_json::json;
+
+use super::transport::{self, Frame, Incoming, SlackIdentity, SlackInternalError, Socket};
+
+// Socket Mode: we ask apps.connections.open (with an app-level token) for a
+// websocket url, and then slack sends us everything wrapped in envelopes, each
+// of which we have to acknowledge. We can't send anything but acks down the
+// socket, but that's fine, because replies go out over the web API anyway.
+pub struct SocketModeClient {
+    api_url: String,
+    app_token: String,
+    bot_token: String,
+    http: Client,
+    socket: Option<Socket>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Envelope {
+    envelope_id: Option<String>,
+    #[serde(rename = "type")]
+    kind: String,
+    payload: Option<serde_json::Value>,
+    reason: Option<String>,
+}
+
+pub fn new(api_url: &str, app_token: &str, bot_token: &str) -> SocketModeClient {
+    SocketModeClient {
+        api_url: api_url.to_string(),
+        app_token: app_token.to_string(),
+        bot_token: bot_token.to_string(),
+        http: Client::new(),
+        socket: None,
+    }
+}
+
+impl transport::Transport for SocketModeClient {
+    fn connect(&mut self) -> Result<SlackIdentity, Box<dyn Error>> {
+        self.socket = None;
+
+        let me = self.auth_test()?;
+        let url = self.open_connection()?;
+        self.socket = Some(transport::connect_websocket(&url)?);
+
+        Ok(me)
+    }
+
+    fn recv(&mut self) -> Incoming {
+        let socket = match self.socket.as_mut() {
+            Some(s) => s,
+            None => return Incoming::Disconnected,
+        };
+
+        let frame = match socket.read() {
+            Frame::Text(s) => s,
+            Frame::Nothing => return Incoming::Nothing,
+            Frame::Closed => {
+                self.socket = None;
+                return Incoming::Disconnected;
+            }
+        };
+
+        let envelope: Envelope = match serde_json::from_str(&frame) {
+            Ok(e) => e,
+            Err(e) => {
+                trace!("error deserializing envelope {}: {}", frame, e);
+                return Incoming::Nothing;
+            }
+        };
+
+        // Ack before anything else, so slack doesn't send it again.
+        if let Some(id) = &envelope.envelope_id {
+            let ack = json!({ "envelope_id": id }).to_string();
+            if let Err(e) = socket.send_text(ack) {
+                warn!("error acking envelope {}: {:?}", id, e);
+            }
+        }
+
+        match envelope.kind.as_str() {
+            "hello" => {
+                debug!("slack says hello");
+                Incoming::Nothing
+            }
+            "disconnect" => {
+                info!(
+                    "slack asked us to reconnect ({})",
+                    envelope.reason.unwrap_or_default()
+                );
+                self.socket = None;
+                Incoming::Disconnected
+            }
+            "events_api" => {
+                let event = envelope
+                    .payload
+                    .and_then(|mut p| p.get_mut("event").map(|e| e.take()))
+                    .and_then(transport::event_from_json);
+
+                match event {
+                    Some(e) => Incoming::Event(e),
+                    None => Incoming::Nothing,
+                }
+            }
+            "interactive" => {
+                let event = envelope
+                    .payload
+                    .as_ref()
+                    .and_then(transport::interaction_from_json);
+
+                match event {
+                    Some(e) => Incoming::Event(e),
+                    None => Incoming::Nothing,
+                }
+            }
+            "slash_commands" => {
+                match envelope
+                    .payload
+                    .and_then(transport::slash_command_from_json)
+                {
+                    Some(e) => Incoming::Event(e),
+                    None => Incoming::Nothing,
+                }
+            }
+            other => {
+                trace!("ignoring socket mode envelope of type {}", other);
+                Incoming::Nothing
+            }
+        }
+    }
+}
+
+impl SocketModeClient {
+    // Socket mode doesn't tell us who we are, so we have to ask.
+    fn auth_test(&self) -> Result<SlackIdentity, Box<dyn Error>> {
+        #[derive(Deserialize, Debug)]
+        struct AuthResp {
+            ok: bool,
+            user_id: Option<String>,
+            user: Option<String>,
+            team_id: Option<String>,
+            error: Option<String>,
+        }
+
+        let data: AuthResp = self
+            .http
+            .post(&format!("{}/auth.test", self.api_url))
+            .bearer_auth(&self.bot_token)
+            .send()?
+            .json()?;
+
+        match (data.ok, data.user_id, data.user, data.team_id) {
+            (true, Some(id), Some(name), Some(team_id)) => Ok(SlackIdentity { id, name, team_id }),
+            _ => Err(Box::new(SlackInternalError(format!(
+                "auth.test failed: {}",
+                data.error.unwrap_or_default()
+            )))),
+        }
+    }
+
+    fn open_connection(&self) -> Result<String, Box<dyn Error>> {
+        #[derive(Deserialize, Debug)]
+        struct OpenResp {
+            ok: bool,
+            url: Option<String>,
+            error: Option<String>,
+        }
+
+        let data: OpenResp = self
+            .http
+            .post(&format!("{}/apps.connections.open", self.api_url))
+            .bearer_auth(&self.app_token)
+            .send()?
+            .json()?;
+
+        match (data.ok, data.url) {
+            (true, Some(url)) => Ok(url),
+            _ => Err(Box::new(SlackInternalError(format!(
+                "apps.connections.open failed: {}",
+                data.error.unwrap_or_default()
+            )))),
+        }
+    }
+}
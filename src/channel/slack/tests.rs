@@ -0,0 +1,1419 @@
+use std::env;
+use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json::json;
+
+use super::listener;
+use super::mock_server::{self, ApiCall, MockResponse, MockSlack};
+use crate::channel::testing;
+use crate::channel::Type;
+use crate::config::Config;
+use crate::hub;
+use crate::message::{Connectivity, Element, Event, Interactive, Message, Persona};
+
+type Harness = testing::Harness<Fakes>;
+
+struct Fakes {
+    mock: MockSlack,
+    state_dbfile: String,
+}
+
+// Every test gets a fresh state db (the channel caches users there), which
+// nobody needs once it's stopped.
+impl testing::Fakes for Fakes {
+    fn hung_up(&mut self) {
+        let _ = fs::remove_file(&self.state_dbfile);
+    }
+}
+
+fn start_channel(extra: Vec<(&str, toml::Value)>) -> Harness {
+    start_channel_with(mock_server::start(), extra)
+}
+
+fn temp_dbfile() -> String {
+    let path = env::temp_dir().join(format!("synergy-test-{}.sqlite", Event::new_id()));
+    path.to_str().unwrap().to_string()
+}
+
+fn start_channel_with(mock: MockSlack, extra: Vec<(&str, toml::Value)>) -> Harness {
+    start_channel_full(mock, extra, temp_dbfile())
+}
+
+fn start_channel_full(
+    mock: MockSlack,
+    extra: Vec<(&str, toml::Value)>,
+    state_dbfile: String,
+) -> Harness {
+    let config = testing::config(
+        vec![
+            ("api_token", "xoxb-test".into()),
+            ("api_url", mock.api_url.clone().into()),
+        ],
+        extra,
+    );
+
+    let channel = testing::start("channel/slack", Type::SlackChannel, config, &state_dbfile);
+    channel.expect_connectivity(Connectivity::Connected);
+
+    Harness {
+        channel,
+        fakes: Fakes { mock, state_dbfile },
+    }
+}
+
+// Every message gets its own ts, like on real slack; we ignore repeats.
+fn next_ts() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(1);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let n = COUNTER.fetch_add(1, Ordering::SeqCst);
+    format!("{}.{:06}", now.as_secs(), n)
+}
+
+fn message_frame(channel: &str, user: &str, text: &str) -> serde_json::Value {
+    json!({
+        "type": "message",
+        "channel": channel,
+        "user": user,
+        "text": text,
+        "ts": next_ts(),
+    })
+}
+
+#[test]
+fn rtm_round_trip() {
+    let h = start_channel(vec![]);
+
+    let connect = h.fakes.mock.recv_call("rtm.connect").unwrap();
+    assert_eq!(connect.params["token"], "xoxb-test");
+
+    h.fakes
+        .mock
+        .send_frame(message_frame("C0001", "U0001", "<@UBOT> echo hi &amp; bye"));
+
+    let event = h.next_event();
+    assert_eq!(event.text, "echo hi & bye");
+    assert!(event.was_targeted);
+    assert!(event.is_public);
+    assert_eq!(event.from_address, "U0001");
+    assert_eq!(event.conversation_address, "C0001");
+    assert_eq!(event.origin, "channel/slack");
+    assert_eq!(event.workspace.as_deref(), Some(mock_server::TEAM_ID));
+
+    h.reply(&event, "I heard you");
+
+    let post = h.fakes.mock.recv_call("chat.postMessage").unwrap();
+    assert_eq!(post.auth.as_deref(), Some("Bearer xoxb-test"));
+    assert_eq!(post.params["channel"], "C0001");
+    assert_eq!(post.params["text"], "I heard you");
+
+    h.hangup();
+}
+
+#[test]
+fn rtm_untargeted_and_dm() {
+    let h = start_channel(vec![]);
+
+    h.fakes
+        .mock
+        .send_frame(message_frame("C0001", "U0002", "just chatting"));
+    let event = h.next_event();
+    assert!(!event.was_targeted);
+    assert_eq!(event.text, "just chatting");
+
+    h.fakes
+        .mock
+        .send_frame(message_frame("D0001", "U0002", "clox"));
+    let event = h.next_event();
+    assert!(event.was_targeted);
+    assert!(!event.is_public);
+
+    h.hangup();
+}
+
+#[test]
+fn rtm_ignores_bots() {
+    let h = start_channel(vec![]);
+
+    let mut bot_frame = message_frame("C0001", "U0002", "beep");
+    bot_frame["bot_id"] = json!("B0001");
+    h.fakes.mock.send_frame(bot_frame);
+    h.fakes
+        .mock
+        .send_frame(message_frame("C0001", "U0002", "boop"));
+
+    let event = h.next_event();
+    assert_eq!(event.text, "boop");
+
+    h.hangup();
+}
+
+#[test]
+fn rtm_reconnects_after_close() {
+    let h = start_channel(vec![]);
+    h.fakes.mock.recv_call("rtm.connect").unwrap();
+
+    h.fakes.mock.close_socket();
+
+    match h.next_message() {
+        Message::Connectivity(_, Connectivity::Disconnected) => (),
+        other => panic!("expected to disconnect, got {:?}", other),
+    }
+
+    match h.next_message() {
+        Message::Connectivity(_, Connectivity::Connected) => (),
+        other => panic!("expected to reconnect, got {:?}", other),
+    }
+
+    h.fakes.mock.recv_call("rtm.connect").unwrap();
+
+    h.fakes
+        .mock
+        .send_frame(message_frame("C0001", "U0001", "still here"));
+    assert_eq!(h.next_event().text, "still here");
+
+    h.hangup();
+}
+
+#[test]
+fn rtm_retries_failed_connect() {
+    let mock = mock_server::start();
+    let failure = json!({ "ok": false, "error": "ratelimited" });
+    mock.respond_with("rtm.connect", mock_server::ok(failure));
+
+    // start_channel waits for us to be connected, which means we tried again
+    let h = start_channel_with(mock, vec![]);
+
+    h.fakes.mock.recv_call("rtm.connect").unwrap();
+    h.fakes.mock.recv_call("rtm.connect").unwrap();
+
+    h.hangup();
+}
+
+#[test]
+fn socket_mode_round_trip() {
+    let h = start_channel(vec![
+        ("transport", "socket_mode".into()),
+        ("app_token", "xapp-test".into()),
+    ]);
+
+    let open = h.fakes.mock.recv_call("apps.connections.open").unwrap();
+    assert_eq!(open.auth.as_deref(), Some("Bearer xapp-test"));
+
+    h.fakes.mock.send_frame(json!({ "type": "hello" }));
+    h.fakes.mock.send_frame(json!({
+        "envelope_id": "env-1",
+        "type": "events_api",
+        "payload": {
+            "event": message_frame("C0001", "U0001", "synergy: clox"),
+        },
+    }));
+
+    let ack = h.fakes.mock.recv_frame().expect("no ack");
+    assert_eq!(ack, json!({ "envelope_id": "env-1" }));
+
+    let event = h.next_event();
+    assert_eq!(event.text, "clox");
+    assert!(event.was_targeted);
+    assert_eq!(event.workspace.as_deref(), Some(mock_server::TEAM_ID));
+
+    h.reply(&event, "it is time");
+
+    let post = h.fakes.mock.recv_call("chat.postMessage").unwrap();
+    assert_eq!(post.auth.as_deref(), Some("Bearer xoxb-test"));
+    assert_eq!(post.params["channel"], "C0001");
+    assert_eq!(post.params["text"], "it is time");
+
+    h.hangup();
+}
+
+#[test]
+fn socket_mode_reconnects_on_request() {
+    let h = start_channel(vec![
+        ("transport", "socket_mode".into()),
+        ("app_token", "xapp-test".into()),
+    ]);
+    h.fakes.mock.recv_call("apps.connections.open").unwrap();
+
+    h.fakes.mock.send_frame(json!({
+        "type": "disconnect",
+        "reason": "refresh_requested",
+    }));
+
+    match h.next_message() {
+        Message::Connectivity(_, Connectivity::Disconnected) => (),
+        other => panic!("expected to disconnect, got {:?}", other),
+    }
+
+    match h.next_message() {
+        Message::Connectivity(_, Connectivity::Connected) => (),
+        other => panic!("expected to reconnect, got {:?}", other),
+    }
+
+    h.fakes.mock.recv_call("apps.connections.open").unwrap();
+
+    h.hangup();
+}
+
+#[test]
+fn replies_wait_out_rate_limits_in_order() {
+    let mock = mock_server::start();
+    mock.respond_with(
+        "chat.postMessage",
+        MockResponse {
+            status: 429,
+            headers: vec![("Retry-After".into(), "1".into())],
+            body: json!({ "ok": false, "error": "ratelimited" }),
+        },
+    );
+
+    let h = start_channel_with(mock, vec![]);
+
+    h.fakes
+        .mock
+        .send_frame(message_frame("C0001", "U0001", "synergy: clox"));
+    let event = h.next_event();
+
+    h.reply(&event, "first");
+    h.reply(&event, "second");
+
+    // the first try gets rate limited...
+    let limited = h.fakes.mock.recv_call("chat.postMessage").unwrap();
+    assert_eq!(limited.params["text"], "first");
+
+    // ...and then we wait, and send both, in order
+    let post = h.fakes.mock.recv_call("chat.postMessage").unwrap();
+    assert_eq!(post.params["text"], "first");
+    assert!(post.at.duration_since(limited.at) >= Duration::from_secs(1));
+
+    let post = h.fakes.mock.recv_call("chat.postMessage").unwrap();
+    assert_eq!(post.params["text"], "second");
+
+    h.hangup();
+}
+
+#[test]
+fn permanent_failures_are_reported() {
+    let mock = mock_server::start();
+    let failure = json!({ "ok": false, "error": "invalid_blocks" });
+    mock.respond_with("chat.postMessage", mock_server::ok(failure));
+
+    let h = start_channel_with(mock, vec![]);
+
+    h.fakes
+        .mock
+        .send_frame(message_frame("C0001", "U0001", "synergy: clox"));
+    let event = h.next_event();
+
+    h.reply(&event, "doomed");
+    h.reply(&event, "fine");
+
+    let post = h.fakes.mock.recv_call("chat.postMessage").unwrap();
+    assert_eq!(post.params["text"], "doomed");
+
+    // no retry, but whoever asked finds out, before anything else goes out
+    let post = h.fakes.mock.recv_call("chat.postMessage").unwrap();
+    assert_eq!(post.params["channel"], "C0001");
+    let notice = post.params["text"].as_str().unwrap();
+    assert!(notice.contains("reactor/test"), "notice was {:?}", notice);
+    assert!(notice.contains("invalid_blocks"), "notice was {:?}", notice);
+
+    let post = h.fakes.mock.recv_call("chat.postMessage").unwrap();
+    assert_eq!(post.params["text"], "fine");
+
+    h.hangup();
+}
+
+#[test]
+fn failure_notices_dont_fail_forever() {
+    let mock = mock_server::start();
+    let failure = json!({ "ok": false, "error": "channel_not_found" });
+    mock.respond_with("chat.postMessage", mock_server::ok(failure.clone()));
+    mock.respond_with("chat.postMessage", mock_server::ok(failure));
+
+    let h = start_channel_with(mock, vec![]);
+
+    h.fakes
+        .mock
+        .send_frame(message_frame("C0001", "U0001", "synergy: clox"));
+    let event = h.next_event();
+
+    h.reply(&event, "doomed");
+    h.reply(&event, "fine");
+
+    let texts: Vec<_> = (0..3)
+        .map(|_| h.fakes.mock.recv_call("chat.postMessage").unwrap())
+        .map(|post| post.params["text"].as_str().unwrap().to_string())
+        .collect();
+
+    assert_eq!(texts[0], "doomed");
+    assert!(texts[1].contains("channel_not_found"));
+    assert_eq!(texts[2], "fine");
+
+    h.hangup();
+}
+
+#[test]
+fn user_list_follows_cursors() {
+    let mock = mock_server::start();
+    mock.respond_with(
+        "users.list",
+        mock_server::ok(json!({
+            "ok": true,
+            "members": [{ "id": "U0009", "name": "zed" }],
+            "response_metadata": { "next_cursor": "page-two" },
+        })),
+    );
+
+    let h = start_channel_with(mock, vec![]);
+
+    let first = h.fakes.mock.recv_call("users.list").unwrap();
+    assert_eq!(first.params["cursor"], "");
+    let second = h.fakes.mock.recv_call("users.list").unwrap();
+    assert_eq!(second.params["cursor"], "page-two");
+
+    // both pages count
+    h.fakes
+        .mock
+        .send_frame(message_frame("C0001", "U0001", "hi <@U0009> and <@U0002>"));
+    assert_eq!(h.next_event().text, "hi @zed and @bob");
+
+    let lookups: Vec<_> = h
+        .fakes
+        .mock
+        .drain_calls()
+        .into_iter()
+        .filter(|c| c.method == "users.info")
+        .collect();
+    assert!(lookups.is_empty(), "looked up users: {:?}", lookups);
+
+    h.hangup();
+}
+
+#[test]
+fn unknown_users_are_looked_up() {
+    let h = start_channel(vec![]);
+
+    h.fakes
+        .mock
+        .send_frame(message_frame("C0001", "U0404", "hi <@U0405>"));
+
+    let event = h.next_event();
+    assert_eq!(event.text, "hi @user-u0405");
+    assert_eq!(event.from_address, "U0404");
+
+    let lookup = h.fakes.mock.recv_call("users.info").unwrap();
+    assert_eq!(lookup.params["user"], "U0405");
+    let lookup = h.fakes.mock.recv_call("users.info").unwrap();
+    assert_eq!(lookup.params["user"], "U0404");
+
+    h.hangup();
+}
+
+#[test]
+fn user_change_updates_cache() {
+    let h = start_channel(vec![]);
+
+    h.fakes.mock.send_frame(json!({
+        "type": "user_change",
+        "user": { "id": "U0001", "name": "alicia" },
+    }));
+    h.fakes.mock.send_frame(json!({
+        "type": "team_join",
+        "user": { "id": "U0003", "name": "carol" },
+    }));
+
+    h.fakes
+        .mock
+        .send_frame(message_frame("C0001", "U0002", "<@U0001> meet <@U0003>"));
+    assert_eq!(h.next_event().text, "@alicia meet @carol");
+
+    h.hangup();
+}
+
+#[test]
+fn users_survive_restart() {
+    let dbfile = temp_dbfile();
+
+    let h = start_channel_full(mock_server::start(), vec![], dbfile.clone());
+    h.fakes.mock.recv_call("users.list").unwrap();
+    h.fakes
+        .mock
+        .send_frame(message_frame("C0001", "U0002", "hi <@U0777>"));
+    assert_eq!(h.next_event().text, "hi @user-u0777");
+    h.channel.hangup();
+
+    let h = start_channel_full(mock_server::start(), vec![], dbfile);
+    h.fakes
+        .mock
+        .send_frame(message_frame("C0001", "U0001", "hi <@U0777>"));
+    assert_eq!(h.next_event().text, "hi @user-u0777");
+
+    let mock = h.hangup().mock;
+    let methods: Vec<_> = mock.drain_calls().into_iter().map(|c| c.method).collect();
+    assert!(!methods.contains(&"users.list".to_string()));
+    assert!(!methods.contains(&"users.info".to_string()));
+}
+
+#[test]
+fn privacy_comes_from_the_directory() {
+    let h = start_channel(vec![]);
+
+    let cases = vec![
+        // channel, is_public, was_targeted
+        ("C0001", true, false),
+        ("C0002", false, false),
+        ("G0001", false, false),
+        ("D0001", false, true),
+    ];
+
+    for (channel, is_public, was_targeted) in cases {
+        h.fakes
+            .mock
+            .send_frame(message_frame(channel, "U0001", "hello"));
+        let event = h.next_event();
+        assert_eq!(event.is_public, is_public, "is_public in {}", channel);
+        assert_eq!(event.was_targeted, was_targeted, "targeted in {}", channel);
+    }
+
+    h.hangup();
+}
+
+#[test]
+fn unknown_conversations_are_looked_up() {
+    let mock = mock_server::start();
+    mock.respond_with(
+        "conversations.info",
+        mock_server::ok(json!({
+            "ok": true,
+            "channel": { "id": "C0999", "name": "brand-new", "is_private": true },
+        })),
+    );
+
+    let h = start_channel_with(mock, vec![]);
+
+    h.fakes
+        .mock
+        .send_frame(message_frame("C0999", "U0001", "hello"));
+    let event = h.next_event();
+    assert!(!event.is_public);
+
+    let lookup = h.fakes.mock.recv_call("conversations.info").unwrap();
+    assert_eq!(lookup.params["channel"], "C0999");
+
+    h.hangup();
+}
+
+#[test]
+fn replies_can_be_addressed_by_name() {
+    let h = start_channel(vec![]);
+
+    h.fakes
+        .mock
+        .send_frame(message_frame("D0001", "U0001", "announce"));
+    let event = h.next_event();
+
+    let reply = event.reply_in("#secret", "psst", "reactor/test");
+    h.to_channel.send(reply).unwrap();
+
+    let post = h.fakes.mock.recv_call("chat.postMessage").unwrap();
+    assert_eq!(post.params["channel"], "C0002");
+    assert_eq!(post.params["text"], "psst");
+
+    // renames and new channels are picked up from events
+    h.fakes.mock.send_frame(json!({
+        "type": "channel_rename",
+        "channel": { "id": "C0002", "name": "classified", "created": 1 },
+    }));
+    h.fakes.mock.send_frame(json!({
+        "type": "channel_created",
+        "channel": { "id": "C0004", "name": "fresh", "created": 1 },
+    }));
+    h.fakes
+        .mock
+        .send_frame(message_frame("D0001", "U0001", "again"));
+    let event = h.next_event();
+
+    for (name, id) in &[("#classified", "C0002"), ("#fresh", "C0004")] {
+        let reply = event.reply_in(name, "hi", "reactor/test");
+        h.to_channel.send(reply).unwrap();
+
+        let post = h.fakes.mock.recv_call("chat.postMessage").unwrap();
+        assert_eq!(post.params["channel"], *id);
+    }
+
+    h.hangup();
+}
+
+#[test]
+fn replies_are_encoded() {
+    let broadcasters = toml::Value::Array(vec!["reactor/announce".into()]);
+    let h = start_channel(vec![("allow_broadcast_from", broadcasters)]);
+
+    h.fakes.mock.send_frame(message_frame(
+        "C0001",
+        "U0001",
+        "<!here> ask <@U0002> about <#C0002|secret>",
+    ));
+    let event = h.next_event();
+    assert_eq!(event.text, "@here ask @bob about #secret");
+
+    h.reply(&event, "@here: @alice & @bob should look in #general");
+    let post = h.fakes.mock.recv_call("chat.postMessage").unwrap();
+    assert_eq!(
+        post.params["text"],
+        "@here: <@U0001> &amp; <@U0002> should look in <#C0001>"
+    );
+
+    let reply = event.reply("@here lunch!", "reactor/announce");
+    h.to_channel.send(reply).unwrap();
+    let post = h.fakes.mock.recv_call("chat.postMessage").unwrap();
+    assert_eq!(post.params["text"], "<!here> lunch!");
+
+    h.hangup();
+}
+
+#[test]
+fn long_replies_are_split() {
+    let h = start_channel(vec![("max_message_length", 100.into())]);
+
+    h.fakes
+        .mock
+        .send_frame(message_frame("D0001", "U0001", "logs"));
+    let event = h.next_event();
+
+    let lines: Vec<String> = (0..30).map(|i| format!("line {} & more", i)).collect();
+    let text = format!("here you go:\n```\n{}\n```", lines.join("\n"));
+    h.reply(&event, &text);
+
+    // everything arrives, in order, in pieces that each stand on their own
+    let mut seen = vec![];
+    while seen.len() < lines.len() {
+        let post = h
+            .fakes
+            .mock
+            .recv_call("chat.postMessage")
+            .expect("missing piece");
+        let piece = post.params["text"].as_str().unwrap().to_string();
+
+        assert!(piece.chars().count() <= 100, "too long: {:?}", piece);
+        assert_eq!(
+            piece.matches("```").count() % 2,
+            0,
+            "unbalanced: {:?}",
+            piece
+        );
+
+        seen.extend(
+            piece
+                .lines()
+                .filter(|l| l.starts_with("line "))
+                .map(|l| l.replace("&amp;", "&")),
+        );
+    }
+
+    assert_eq!(seen, lines);
+
+    h.hangup();
+}
+
+#[test]
+fn huge_replies_become_snippets() {
+    let h = start_channel(vec![
+        ("max_message_length", 100.into()),
+        ("snippet_length", 500.into()),
+    ]);
+
+    h.fakes
+        .mock
+        .send_frame(message_frame("D0001", "U0001", "logs"));
+    let event = h.next_event();
+
+    let text = "<all the logs> ".repeat(50);
+    h.reply(&event, &text);
+
+    let get_url = h
+        .fakes
+        .mock
+        .recv_call("files.getUploadURLExternal")
+        .unwrap();
+    assert_eq!(get_url.params["length"], text.len().to_string());
+
+    // the file itself isn't mrkdwn, so it goes up exactly as it was
+    let upload = h.fakes.mock.recv_call("upload.F0001").unwrap();
+    assert_eq!(upload.body, text);
+
+    let complete = h
+        .fakes
+        .mock
+        .recv_call("files.completeUploadExternal")
+        .unwrap();
+    assert_eq!(complete.params["channel_id"], "D0001");
+    assert_eq!(complete.params["files"][0]["id"], "F0001");
+
+    let posts = h
+        .fakes
+        .mock
+        .drain_calls()
+        .into_iter()
+        .filter(|c| c.method == "chat.postMessage")
+        .count();
+    assert_eq!(posts, 0);
+
+    h.hangup();
+}
+
+#[test]
+fn private_replies_go_to_dms() {
+    let h = start_channel(vec![]);
+
+    // bob doesn't have a dm yet, but alice does, from the conversation list
+    let requests = [("U0002", "psst"), ("U0002", "again"), ("U0001", "sure")];
+
+    for (user, text) in &requests {
+        h.fakes
+            .mock
+            .send_frame(message_frame("C0001", user, "synergy: my token?"));
+        let event = h.next_event();
+
+        let reply = event.private_reply(text, "reactor/test").unwrap();
+        h.to_channel.send(reply).unwrap();
+    }
+
+    let mut calls = vec![];
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while calls
+        .iter()
+        .filter(|c: &&ApiCall| c.method == "chat.postMessage")
+        .count()
+        < 3
+    {
+        assert!(Instant::now() < deadline, "not enough posts: {:?}", calls);
+        calls.extend(h.fakes.mock.drain_calls());
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let opens: Vec<_> = calls
+        .iter()
+        .filter(|c| c.method == "conversations.open")
+        .map(|c| c.params["users"].clone())
+        .collect();
+    assert_eq!(opens, vec!["U0002"]);
+
+    let posts: Vec<_> = calls
+        .iter()
+        .filter(|c| c.method == "chat.postMessage")
+        .map(|c| (c.params["channel"].clone(), c.params["text"].clone()))
+        .collect();
+    assert_eq!(
+        posts,
+        vec![
+            (json!("D0002"), json!("psst")),
+            (json!("D0002"), json!("again")),
+            (json!("D0001"), json!("sure")),
+        ]
+    );
+
+    h.hangup();
+}
+
+#[test]
+fn replies_go_out_right_away() {
+    let h = start_channel(vec![]);
+
+    h.fakes
+        .mock
+        .send_frame(message_frame("C0001", "U0001", "hi"));
+    let event = h.next_event();
+
+    // The first one has to set up a connection, so doesn't count.
+    h.reply(&event, "warming up");
+    h.fakes.mock.recv_call("chat.postMessage").unwrap();
+
+    // once when things are quiet...
+    let sent = Instant::now();
+    h.reply(&event, "quiet");
+    let post = h.fakes.mock.recv_call("chat.postMessage").unwrap();
+    let quiet = post.at.duration_since(sent);
+
+    // ...and once while slack is shouting at us
+    for i in 0..200 {
+        h.fakes
+            .mock
+            .send_frame(message_frame("C0001", "U0002", &format!("noise {}", i)));
+    }
+
+    let sent = Instant::now();
+    h.reply(&event, "busy");
+    let post = h.fakes.mock.recv_call("chat.postMessage").unwrap();
+    let busy = post.at.duration_since(sent);
+
+    // This is a lot more than the millisecond it should take, but the machine
+    // running the tests might be busy too.
+    for elapsed in &[quiet, busy] {
+        assert!(
+            *elapsed < Duration::from_millis(20),
+            "reply took {:?}",
+            elapsed
+        );
+    }
+
+    h.hangup();
+}
+
+#[test]
+fn buttons_become_blocks() {
+    let h = start_channel(vec![("max_message_length", 100.into())]);
+
+    h.fakes
+        .mock
+        .send_frame(message_frame("C0001", "U0001", "synergy: lunch?"));
+    let event = h.next_event();
+
+    let interactive = Interactive::new(
+        "reactor/test",
+        vec![
+            Element::Button {
+                label: "yes".into(),
+                value: "y".into(),
+            },
+            Element::Menu {
+                placeholder: "where?".into(),
+                options: vec![("tacos".into(), "t".into()), ("pho".into(), "p".into())],
+            },
+        ],
+    );
+    let callback_id = interactive.callback_id.clone();
+
+    // too long for one message, so the buttons should end up under the last
+    let text = "lunch ".repeat(30);
+    let reply = event.interactive_reply(&text, "reactor/test", interactive);
+    h.to_channel.send(reply).unwrap();
+
+    let first = h.fakes.mock.recv_call("chat.postMessage").unwrap();
+    assert!(first.params.get("blocks").is_none());
+
+    let last = h.fakes.mock.recv_call("chat.postMessage").unwrap();
+    let blocks = &last.params["blocks"];
+    assert_eq!(blocks[0]["text"]["text"], last.params["text"]);
+
+    let actions = &blocks[1];
+    assert_eq!(actions["block_id"], callback_id.as_str());
+    assert_eq!(actions["elements"][0]["type"], "button");
+    assert_eq!(actions["elements"][0]["value"], "y");
+    assert_eq!(actions["elements"][1]["type"], "static_select");
+    assert_eq!(actions["elements"][1]["options"][1]["value"], "p");
+
+    h.hangup();
+}
+
+// Sections can't be as long as messages can, so interactive replies get split
+// to fit them.
+#[test]
+fn long_interactive_replies_fit_in_sections() {
+    let h = start_channel(vec![]);
+
+    h.fakes
+        .mock
+        .send_frame(message_frame("C0001", "U0001", "synergy: lunch?"));
+    let event = h.next_event();
+
+    let interactive = Interactive::new(
+        "reactor/test",
+        vec![Element::Button {
+            label: "yes".into(),
+            value: "y".into(),
+        }],
+    );
+
+    let text = "lunch ".repeat(600);
+    let reply = event.interactive_reply(&text, "reactor/test", interactive);
+    h.to_channel.send(reply).unwrap();
+
+    let first = h.fakes.mock.recv_call("chat.postMessage").unwrap();
+    let last = h.fakes.mock.recv_call("chat.postMessage").unwrap();
+
+    for post in &[first, last] {
+        let text = post.params["text"].as_str().unwrap();
+        assert!(text.chars().count() <= 3000, "{} chars", text.len());
+    }
+
+    h.hangup();
+}
+
+fn block_actions(callback_id: &str, value: &str) -> serde_json::Value {
+    json!({
+        "type": "block_actions",
+        "user": { "id": "U0001" },
+        "channel": { "id": "C0001" },
+        "actions": [{ "block_id": callback_id, "action_id": "button-0", "value": value }],
+    })
+}
+
+fn assert_callback(event: &Event, callback_id: &str, value: &str) {
+    assert_eq!(event.text, value);
+    assert!(event.was_targeted);
+    assert!(event.is_public);
+    assert_eq!(event.from_address, "U0001");
+    assert_eq!(event.conversation_address, "C0001");
+
+    let callback = event.callback.as_ref().expect("no callback");
+    assert_eq!(callback.callback_id, callback_id);
+    assert_eq!(callback.value, value);
+    assert_eq!(callback.reactor(), "reactor/test");
+}
+
+#[test]
+fn socket_mode_interactions() {
+    let h = start_channel(vec![
+        ("transport", "socket_mode".into()),
+        ("app_token", "xapp-test".into()),
+    ]);
+
+    h.fakes.mock.send_frame(json!({ "type": "hello" }));
+    h.fakes.mock.send_frame(json!({
+        "envelope_id": "env-1",
+        "type": "interactive",
+        "payload": block_actions("reactor/test:1234", "y"),
+    }));
+
+    let ack = h.fakes.mock.recv_frame().expect("no ack");
+    assert_eq!(ack, json!({ "envelope_id": "env-1" }));
+
+    assert_callback(&h.next_event(), "reactor/test:1234", "y");
+
+    h.hangup();
+}
+
+// A channel listening for http requests from slack, and where it's listening.
+fn start_listening(secret: &str) -> (Harness, String) {
+    let address = testing::free_address();
+
+    let h = start_channel(vec![
+        ("listen_address", address.clone().into()),
+        ("signing_secret", secret.into()),
+    ]);
+
+    (h, address)
+}
+
+// POST a form to the listener, signed with secret; returns the status.
+fn post_form(address: &str, secret: &str, form: &[(&str, &str)]) -> u16 {
+    let body = url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(form)
+        .finish();
+
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        .to_string();
+
+    reqwest::blocking::Client::new()
+        .post(&format!("http://{}/", address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("X-Slack-Request-Timestamp", ts.as_str())
+        .header(
+            "X-Slack-Signature",
+            listener::sign(secret, &ts, body.as_bytes()),
+        )
+        .body(body)
+        .send()
+        .unwrap()
+        .status()
+        .as_u16()
+}
+
+#[test]
+fn http_interactions() {
+    let (h, address) = start_listening("shh");
+
+    let payload = block_actions("reactor/test:1234", "y").to_string();
+    let form = [("payload", payload.as_str())];
+
+    assert_eq!(post_form(&address, "wrong", &form), 401);
+
+    assert_eq!(post_form(&address, "shh", &form), 200);
+    assert_callback(&h.next_event(), "reactor/test:1234", "y");
+
+    h.hangup();
+}
+
+fn reply_as(event: &Event, text: &str, username: &str) -> Message {
+    let mut msg = event.reply(text, "reactor/test");
+    if let Message::Reply(reply) = &mut msg {
+        reply.persona = Some(Persona {
+            username: Some(username.into()),
+            icon_emoji: Some(":robot_face:".into()),
+        });
+    }
+    msg
+}
+
+#[test]
+fn replies_use_personas() {
+    let h = start_channel(vec![]);
+
+    h.fakes
+        .mock
+        .send_frame(message_frame("C0001", "U0001", "synergy: clox"));
+    let event = h.next_event();
+
+    h.to_channel.send(reply_as(&event, "tick", "clox")).unwrap();
+    let post = h.fakes.mock.recv_call("chat.postMessage").unwrap();
+    assert_eq!(post.params["username"], "clox");
+    assert_eq!(post.params["icon_emoji"], ":robot_face:");
+
+    h.reply(&event, "plain");
+    let post = h.fakes.mock.recv_call("chat.postMessage").unwrap();
+    assert!(post.params.get("username").is_none());
+    assert!(post.params.get("icon_emoji").is_none());
+
+    h.hangup();
+}
+
+#[test]
+fn personas_need_a_scope() {
+    let mock = mock_server::start();
+    let failure =
+        json!({ "ok": false, "error": "missing_scope", "needed": "chat:write.customize" });
+    mock.respond_with("chat.postMessage", mock_server::ok(failure));
+
+    let h = start_channel_with(mock, vec![]);
+
+    h.fakes
+        .mock
+        .send_frame(message_frame("C0001", "U0001", "synergy: clox"));
+    let event = h.next_event();
+
+    h.to_channel.send(reply_as(&event, "tick", "clox")).unwrap();
+    h.to_channel.send(reply_as(&event, "tock", "clox")).unwrap();
+
+    let post = h.fakes.mock.recv_call("chat.postMessage").unwrap();
+    assert_eq!(post.params["username"], "clox");
+
+    // the same reply again, as ourselves
+    let post = h.fakes.mock.recv_call("chat.postMessage").unwrap();
+    assert_eq!(post.params["text"], "tick");
+    assert!(post.params.get("username").is_none());
+
+    // and we don't bother asking again
+    let post = h.fakes.mock.recv_call("chat.postMessage").unwrap();
+    assert_eq!(post.params["text"], "tock");
+    assert!(post.params.get("username").is_none());
+
+    h.hangup();
+}
+
+fn history(messages: Vec<serde_json::Value>) -> MockResponse {
+    // newest first, like slack, and without channels
+    let messages: Vec<_> = messages
+        .into_iter()
+        .rev()
+        .map(|mut m| {
+            m.as_object_mut().unwrap().remove("channel");
+            m
+        })
+        .collect();
+
+    mock_server::ok(json!({ "ok": true, "messages": messages }))
+}
+
+#[test]
+fn missed_messages_are_backfilled() {
+    let h = start_channel(vec![]);
+    h.fakes.mock.recv_call("rtm.connect").unwrap();
+
+    let first = message_frame("C0001", "U0001", "synergy: before");
+    h.fakes.mock.send_frame(first.clone());
+    assert!(!h.next_event().is_backfilled);
+
+    let missed = message_frame("C0001", "U0001", "synergy: during");
+    let mut from_bot = message_frame("C0001", "U0002", "beep");
+    from_bot["bot_id"] = json!("B0001");
+    let also_live = message_frame("C0001", "U0002", "synergy: just now");
+
+    h.fakes.mock.respond_with(
+        "conversations.history",
+        history(vec![missed, from_bot, also_live.clone()]),
+    );
+
+    h.fakes.mock.close_socket();
+
+    match h.next_message() {
+        Message::Connectivity(_, Connectivity::Disconnected) => (),
+        other => panic!("expected to disconnect, got {:?}", other),
+    }
+
+    match h.next_message() {
+        Message::Connectivity(_, Connectivity::Connected) => (),
+        other => panic!("expected to reconnect, got {:?}", other),
+    }
+
+    let call = h.fakes.mock.recv_call("conversations.history").unwrap();
+    assert_eq!(call.params["channel"], "C0001");
+    assert_eq!(call.params["oldest"], first["ts"]);
+
+    let event = h.next_event();
+    assert_eq!(event.text, "during");
+    assert!(event.is_backfilled);
+    assert_eq!(event.conversation_address, "C0001");
+
+    let event = h.next_event();
+    assert_eq!(event.text, "just now");
+    assert!(event.is_backfilled);
+
+    // we've had this one already, so it doesn't count twice
+    h.fakes.mock.send_frame(also_live);
+    h.fakes
+        .mock
+        .send_frame(message_frame("C0001", "U0001", "synergy: after"));
+
+    let event = h.next_event();
+    assert_eq!(event.text, "after");
+    assert!(!event.is_backfilled);
+
+    h.hangup();
+}
+
+// Catching up can take a while, if slack's busy, but replies don't wait for it.
+#[test]
+fn replies_dont_wait_for_backfill() {
+    let h = start_channel(vec![]);
+
+    h.fakes
+        .mock
+        .send_frame(message_frame("C0001", "U0001", "synergy: hi"));
+    let alice = h.next_event();
+    h.fakes
+        .mock
+        .send_frame(message_frame("C0001", "U0002", "synergy: hi"));
+    let bob = h.next_event();
+
+    h.fakes.mock.respond_with(
+        "conversations.history",
+        MockResponse {
+            status: 429,
+            headers: vec![("Retry-After".into(), "2".into())],
+            body: json!({ "ok": false, "error": "ratelimited" }),
+        },
+    );
+
+    h.fakes.mock.close_socket();
+    h.expect_connectivity(Connectivity::Disconnected);
+    h.expect_connectivity(Connectivity::Connected);
+    h.fakes.mock.recv_call("conversations.history").unwrap();
+
+    // Bob doesn't have a DM yet, so that one has to be opened on the way.
+    let sent = Instant::now();
+    h.reply(&alice, "still here");
+    h.to_channel
+        .send(alice.reply_in("#general", "over here", "reactor/test"))
+        .unwrap();
+    h.to_channel
+        .send(bob.private_reply("psst", "reactor/test").unwrap())
+        .unwrap();
+
+    for want in &["still here", "over here", "psst"] {
+        let post = h.fakes.mock.recv_call("chat.postMessage").unwrap();
+        assert_eq!(post.params["text"], *want);
+        assert!(post.at.duration_since(sent) < Duration::from_secs(1));
+    }
+
+    h.hangup();
+}
+
+#[test]
+fn backfill_survives_restart_but_not_forever() {
+    let dbfile = temp_dbfile();
+    let extra = || vec![("max_backfill_age", 60.into())];
+
+    // something from long ago
+    let h = start_channel_full(mock_server::start(), extra(), dbfile.clone());
+    let mut old = message_frame("C0001", "U0001", "ancient");
+    old["ts"] = json!("1000000000.000001");
+    h.fakes.mock.send_frame(old);
+    h.next_event();
+    h.channel.hangup();
+
+    let mock = mock_server::start();
+    mock.respond_with(
+        "conversations.history",
+        history(vec![message_frame("C0001", "U0001", "synergy: while away")]),
+    );
+
+    let before = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    let h = start_channel_full(mock, extra(), dbfile);
+
+    // only as far back as max_backfill_age
+    let call = h.fakes.mock.recv_call("conversations.history").unwrap();
+    let oldest: f64 = call.params["oldest"].as_str().unwrap().parse().unwrap();
+    assert!(oldest >= (before - 60) as f64);
+    assert!(oldest <= (before - 59) as f64);
+
+    let event = h.next_event();
+    assert_eq!(event.text, "while away");
+    assert!(event.is_backfilled);
+
+    h.hangup();
+}
+
+fn slash_command(mock: &MockSlack, command: &str, text: &str) -> serde_json::Value {
+    json!({
+        "command": command,
+        "text": text,
+        "user_id": "U0001",
+        "channel_id": "C0002",
+        "response_url": format!("{}/response.R0001", mock.api_url),
+    })
+}
+
+fn assert_slash_command(event: &Event, text: &str) {
+    assert_eq!(event.text, text);
+    assert!(event.was_targeted);
+    assert!(event.is_slash_command);
+    assert!(!event.is_public);
+    assert_eq!(event.from_address, "U0001");
+    assert_eq!(event.conversation_address, "C0002");
+    assert!(event
+        .response_url
+        .as_ref()
+        .unwrap()
+        .ends_with("/response.R0001"));
+}
+
+#[test]
+fn socket_mode_slash_commands() {
+    let h = start_channel(vec![
+        ("transport", "socket_mode".into()),
+        ("app_token", "xapp-test".into()),
+    ]);
+
+    h.fakes.mock.send_frame(json!({ "type": "hello" }));
+    h.fakes.mock.send_frame(json!({
+        "envelope_id": "env-1",
+        "type": "slash_commands",
+        "payload": slash_command(&h.fakes.mock, "/synergy", "clox"),
+    }));
+
+    let ack = h.fakes.mock.recv_frame().expect("no ack");
+    assert_eq!(ack, json!({ "envelope_id": "env-1" }));
+
+    let event = h.next_event();
+    assert_slash_command(&event, "clox");
+
+    // replies go back the way they came, for everyone (or not) to see
+    h.reply(&event, "it is time");
+    let call = h.fakes.mock.recv_call("response.R0001").unwrap();
+    assert_eq!(call.params["text"], "it is time");
+    assert_eq!(call.params["response_type"], "in_channel");
+
+    let reply = event.ephemeral_reply("just for you", "reactor/test");
+    h.to_channel.send(reply).unwrap();
+    let call = h.fakes.mock.recv_call("response.R0001").unwrap();
+    assert_eq!(call.params["text"], "just for you");
+    assert_eq!(call.params["response_type"], "ephemeral");
+
+    // other commands of ours are the first word
+    h.fakes.mock.send_frame(json!({
+        "envelope_id": "env-2",
+        "type": "slash_commands",
+        "payload": slash_command(&h.fakes.mock, "/clox", "  "),
+    }));
+    h.fakes.mock.recv_frame().expect("no ack");
+    assert_slash_command(&h.next_event(), "clox");
+
+    h.hangup();
+}
+
+// The command is whatever the slack app says it is, not what the bot's called.
+#[test]
+fn slash_commands_for_differently_named_bots() {
+    let mock = mock_server::start();
+    mock.respond_with(
+        "auth.test",
+        mock_server::ok(json!({
+            "ok": true,
+            "user_id": mock_server::BOT_ID,
+            "user": "bender",
+            "team_id": mock_server::TEAM_ID,
+        })),
+    );
+
+    let h = start_channel_with(
+        mock,
+        vec![
+            ("transport", "socket_mode".into()),
+            ("app_token", "xapp-test".into()),
+            ("slash_command", "/ask".into()),
+        ],
+    );
+
+    h.fakes.mock.send_frame(json!({ "type": "hello" }));
+
+    let commands = [
+        ("/ask", "clox", "clox"),
+        ("/bender", "clox", "bender clox"),
+        ("/synergy", "clox", "synergy clox"),
+    ];
+
+    for (i, (command, text, want)) in commands.iter().enumerate() {
+        h.fakes.mock.send_frame(json!({
+            "envelope_id": format!("env-{}", i),
+            "type": "slash_commands",
+            "payload": slash_command(&h.fakes.mock, command, text),
+        }));
+        h.fakes.mock.recv_frame().expect("no ack");
+        assert_slash_command(&h.next_event(), want);
+    }
+
+    h.hangup();
+}
+
+#[test]
+fn http_slash_commands() {
+    let (h, address) = start_listening("shh");
+
+    let command = slash_command(&h.fakes.mock, "/synergy", "echo <@U0002>");
+    let form: Vec<(&str, &str)> = command
+        .as_object()
+        .unwrap()
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.as_str().unwrap()))
+        .collect();
+
+    assert_eq!(post_form(&address, "wrong", &form), 401);
+    assert_eq!(post_form(&address, "shh", &form), 200);
+
+    assert_slash_command(&h.next_event(), "echo @bob");
+
+    h.hangup();
+}
+
+#[test]
+fn expired_response_urls_fall_back() {
+    let h = start_channel(vec![
+        ("transport", "socket_mode".into()),
+        ("app_token", "xapp-test".into()),
+    ]);
+
+    h.fakes.mock.respond_with(
+        "response.R0001",
+        MockResponse {
+            status: 404,
+            headers: vec![],
+            body: json!("expired_url"),
+        },
+    );
+
+    h.fakes.mock.send_frame(json!({ "type": "hello" }));
+    h.fakes.mock.send_frame(json!({
+        "envelope_id": "env-1",
+        "type": "slash_commands",
+        "payload": slash_command(&h.fakes.mock, "/synergy", "clox"),
+    }));
+
+    let event = h.next_event();
+
+    h.reply(&event, "it is time");
+    h.fakes.mock.recv_call("response.R0001").unwrap();
+    let post = h.fakes.mock.recv_call("chat.postMessage").unwrap();
+    assert_eq!(post.params["channel"], "C0002");
+    assert_eq!(post.params["text"], "it is time");
+
+    let reply = event.ephemeral_reply("psst", "reactor/test");
+    h.to_channel.send(reply).unwrap();
+    h.fakes.mock.recv_call("response.R0001").unwrap();
+
+    h.hangup();
+}
+
+#[test]
+fn ephemeral_replies() {
+    let h = start_channel(vec![]);
+
+    h.fakes
+        .mock
+        .send_frame(message_frame("C0001", "U0001", "synergy: clox"));
+    let event = h.next_event();
+    assert!(!event.is_slash_command);
+
+    let reply = event.ephemeral_reply("psst", "reactor/test");
+    h.to_channel.send(reply).unwrap();
+
+    let post = h.fakes.mock.recv_call("chat.postEphemeral").unwrap();
+    assert_eq!(post.params["channel"], "C0001");
+    assert_eq!(post.params["user"], "U0001");
+    assert_eq!(post.params["text"], "psst");
+
+    h.hangup();
+}
+
+// Catching up is for reading, not answering: a backfilled message for us that
+// no reactor wants shouldn't get "Does not compute." half an hour late. This
+// needs the real hub (and clox, which won't touch backfilled messages) behind
+// the channel.
+#[test]
+fn backfill_gets_no_fallback_replies() {
+    let mock = mock_server::start();
+    let state_dbfile = temp_dbfile();
+
+    let source = format!(
+        "state_dbfile = {:?}\n\
+         [channels.slack]\n\
+         class = \"SlackChannel\"\n\
+         api_token = \"xoxb-test\"\n\
+         api_url = {:?}\n\
+         [reactors.clox]\n\
+         class = \"CloxReactor\"\n",
+        state_dbfile, mock.api_url
+    );
+    let config: Config = toml::from_str(&source).unwrap();
+
+    let (pipes_tx, pipes_rx) = mpsc::channel();
+    let hub = thread::spawn(move || {
+        let mut hub = hub::new();
+        hub.assemble(config);
+        pipes_tx.send(hub.attach_channel("channel/test")).unwrap();
+        hub.listen();
+    });
+    let (to_hub, _from_hub) = pipes_rx.recv().unwrap();
+
+    mock.recv_call("rtm.connect").unwrap();
+
+    // Live, and nobody wants it, so the hub says so.
+    let first = message_frame("C0001", "U0001", "synergy: blorp");
+    mock.send_frame(first);
+    let post = mock.recv_call("chat.postMessage").unwrap();
+    assert_eq!(post.params["text"], "Does not compute.");
+
+    mock.respond_with(
+        "conversations.history",
+        history(vec![message_frame("C0001", "U0001", "synergy: clox")]),
+    );
+    mock.close_socket();
+    mock.recv_call("conversations.history").unwrap();
+
+    // The backfilled clox goes to the hub before this does, so if anything
+    // was going to answer it, it'd be here by the time this is.
+    mock.send_frame(message_frame("C0001", "U0001", "synergy: blorp again"));
+    let post = mock.recv_call("chat.postMessage").unwrap();
+    assert_eq!(post.params["text"], "Does not compute.");
+
+    thread::sleep(Duration::from_millis(300));
+    assert!(mock
+        .drain_calls()
+        .iter()
+        .all(|call| call.method != "chat.postMessage"));
+
+    to_hub.send(Message::Hangup).unwrap();
+    hub.join().unwrap();
+    let _ = fs::remove_file(&state_dbfile);
+}
@@ -0,0 +1,314 @@
+use std::error::Error;
+use std::fmt;
+use std::io::ErrorKind::WouldBlock;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use super::api_client::{Conversation, Member};
+
+// A transport is how we get events out of slack: either the (old) RTM API or
+// Socket Mode. Both of them hand us the same message events, so everything
+// after recv() doesn't need to care which one it's talking to.
+pub trait Transport {
+    fn connect(&mut self) -> Result<SlackIdentity, Box<dyn Error>>;
+    fn recv(&mut self) -> Incoming;
+}
+
+// What you get back from recv(). Disconnected means the websocket is gone (or
+// might as well be), and you need to connect() again.
+pub enum Incoming {
+    Event(SlackEvent),
+    Nothing,
+    Disconnected,
+}
+
+// The events we actually do something with. Everything else gets dropped on
+// the floor.
+pub enum SlackEvent {
+    Message(RawEvent),
+    UserChange(Member),
+    ConversationChange(Conversation),
+    ConversationRename(String, String),
+    // user is None when it's us (channel_left and friends don't say)
+    Membership {
+        channel: String,
+        user: Option<String>,
+        joined: bool,
+    },
+    Interaction(Interaction),
+    SlashCommand(SlashCommand),
+}
+
+// Someone clicked a button or picked from a menu. The callback id is the
+// block id we sent it with.
+#[derive(Debug)]
+pub struct Interaction {
+    pub user: String,
+    pub channel: String,
+    pub callback_id: String,
+    pub value: String,
+}
+
+// Someone typed /synergy (or some other command of ours). These come as a form
+// over http, or json over socket mode, with the same field names either way.
+#[derive(Deserialize, Debug)]
+pub struct SlashCommand {
+    #[serde(rename = "user_id")]
+    pub user: String,
+    #[serde(rename = "channel_id")]
+    pub channel: String,
+    pub command: String,
+    #[serde(default)]
+    pub text: String,
+    pub response_url: String,
+}
+
+// This is a raw message event, and only matches messages, because that's the
+// only thing we care about. Other things will try to deserialize to this and
+// not be able to, in which case we'll just ignore it. That's not the "proper"
+// way to do it, but gets us up and running.
+#[derive(Deserialize, Debug)]
+#[allow(dead_code)]
+pub struct RawEvent {
+    pub ts: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub subtype: Option<String>,
+    pub channel: String,
+    pub text: String,
+    pub user: String,
+    bot_id: Option<String>,
+}
+
+// Who we are, and in which workspace: the same bot can be in several of them,
+// one channel per workspace.
+#[derive(Deserialize, Debug)]
+pub struct SlackIdentity {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub team_id: String,
+}
+
+#[derive(Debug)]
+pub struct SlackInternalError(pub String);
+
+impl Error for SlackInternalError {}
+
+impl fmt::Display for SlackInternalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Error while talking to slack: {}", self.0)
+    }
+}
+
+// Turn some json into an event we care about, if it is one. Messages from
+// bots (which are likely to be us) don't count.
+pub fn event_from_json(value: serde_json::Value) -> Option<SlackEvent> {
+    let kind = value["type"].as_str().unwrap_or_default().to_string();
+
+    let res = match kind.as_str() {
+        "message" => serde_json::from_value(value).map(SlackEvent::Message),
+        "user_change" | "team_join" => {
+            serde_json::from_value(value["user"].clone()).map(SlackEvent::UserChange)
+        }
+        "channel_created" => {
+            serde_json::from_value(value["channel"].clone()).map(SlackEvent::ConversationChange)
+        }
+        "channel_joined" | "group_joined" => {
+            serde_json::from_value(value["channel"].clone()).map(|c: Conversation| {
+                SlackEvent::ConversationChange(Conversation {
+                    is_member: true,
+                    ..c
+                })
+            })
+        }
+        "channel_rename" | "group_rename" => {
+            let id = value["channel"]["id"].as_str();
+            let name = value["channel"]["name"].as_str();
+            match (id, name) {
+                (Some(id), Some(name)) => Ok(SlackEvent::ConversationRename(
+                    id.to_string(),
+                    name.to_string(),
+                )),
+                _ => return None,
+            }
+        }
+        "channel_left" | "group_left" => Ok(SlackEvent::Membership {
+            channel: value["channel"].as_str()?.to_string(),
+            user: None,
+            joined: false,
+        }),
+        "member_joined_channel" | "member_left_channel" => Ok(SlackEvent::Membership {
+            channel: value["channel"].as_str()?.to_string(),
+            user: Some(value["user"].as_str()?.to_string()),
+            joined: kind == "member_joined_channel",
+        }),
+        _ => return None,
+    };
+
+    let event = match res {
+        Ok(e) => e,
+        Err(e) => {
+            trace!("error deserializing {} event: {}", kind, e);
+            return None;
+        }
+    };
+
+    if let SlackEvent::Message(ref raw) = event {
+        if raw.bot_id.is_some() {
+            return None;
+        }
+    }
+
+    Some(event)
+}
+
+// Interaction payloads come over socket mode or over http, but look the same
+// either way. We only know what to do with block_actions.
+pub fn interaction_from_json(payload: &serde_json::Value) -> Option<SlackEvent> {
+    if payload["type"] != "block_actions" {
+        trace!("ignoring interaction of type {}", payload["type"]);
+        return None;
+    }
+
+    let action = &payload["actions"][0];
+
+    let value = action["value"]
+        .as_str()
+        .or_else(|| action["selected_option"]["value"].as_str())?;
+
+    // messages in channels have a channel; everything has a container
+    let channel = payload["channel"]["id"]
+        .as_str()
+        .or_else(|| payload["container"]["channel_id"].as_str())?;
+
+    Some(SlackEvent::Interaction(Interaction {
+        user: payload["user"]["id"].as_str()?.to_string(),
+        channel: channel.to_string(),
+        callback_id: action["block_id"].as_str()?.to_string(),
+        value: value.to_string(),
+    }))
+}
+
+pub fn slash_command_from_json(payload: serde_json::Value) -> Option<SlackEvent> {
+    match serde_json::from_value(payload) {
+        Ok(command) => Some(SlackEvent::SlashCommand(command)),
+        Err(e) => {
+            trace!("error deserializing slash command: {}", e);
+            None
+        }
+    }
+}
+
+// the websocket bits, shared by both transports
+
+type Websocket = tungstenite::protocol::WebSocket<tungstenite::client::AutoStream>;
+
+// How often we ping slack, and how long we'll wait to hear back before we
+// decide the connection is dead.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+const PONG_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Reads only time out so that we get a chance to do the above (and notice if
+// we've been asked to stop); nothing else is waiting on them.
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct Socket {
+    ws: Websocket,
+    last_ping: Instant,
+    awaiting_pong: bool,
+}
+
+pub enum Frame {
+    Text(String),
+    Nothing,
+    Closed,
+}
+
+pub fn connect_websocket(url: &str) -> Result<Socket, Box<dyn Error>> {
+    let (mut ws, _resp) = tungstenite::client::connect(url)?;
+
+    info!("connected to slack");
+
+    debug!("setting slack websocket read timeout...");
+    let timeout = Some(READ_TIMEOUT);
+    match ws.get_mut() {
+        tungstenite::stream::Stream::Plain(stream) => stream.set_read_timeout(timeout)?,
+        tungstenite::stream::Stream::Tls(stream) => stream.get_mut().set_read_timeout(timeout)?,
+    };
+
+    Ok(Socket {
+        ws,
+        last_ping: Instant::now(),
+        awaiting_pong: false,
+    })
+}
+
+impl Socket {
+    pub fn read(&mut self) -> Frame {
+        if !self.keepalive() {
+            return Frame::Closed;
+        }
+
+        let message = match self.ws.read_message() {
+            Ok(m) => m,
+            Err(tungstenite::error::Error::Io(ref e)) if e.kind() == WouldBlock => {
+                return Frame::Nothing;
+            }
+            Err(e) => {
+                info!("error reading from websocket: {:?}", e);
+                return Frame::Closed;
+            }
+        };
+
+        match message {
+            tungstenite::Message::Text(s) => Frame::Text(s),
+            tungstenite::Message::Pong(_) => {
+                trace!("got pong from slack");
+                self.awaiting_pong = false;
+                Frame::Nothing
+            }
+            tungstenite::Message::Close(frame) => {
+                info!("slack closed our websocket: {:?}", frame);
+                Frame::Closed
+            }
+            // ignore everything else (ping/binary); tungstenite answers pings
+            _ => Frame::Nothing,
+        }
+    }
+
+    pub fn send_text(&mut self, text: String) -> Result<(), tungstenite::Error> {
+        self.ws.write_message(tungstenite::Message::Text(text))
+    }
+
+    // Send a ping if it's time, and return false if we've been waiting too
+    // long for the last one to come back.
+    fn keepalive(&mut self) -> bool {
+        let elapsed = self.last_ping.elapsed();
+
+        if self.awaiting_pong {
+            if elapsed > PONG_TIMEOUT {
+                warn!("no pong from slack in {:?}; assuming we're dead", elapsed);
+                return false;
+            }
+
+            return true;
+        }
+
+        if elapsed < PING_INTERVAL {
+            return true;
+        }
+
+        trace!("pinging slack");
+
+        if let Err(e) = self.ws.write_message(tungstenite::Message::Ping(vec![])) {
+            info!("error pinging slack: {:?}", e);
+            return false;
+        }
+
+        self.last_ping = Instant::now();
+        self.awaiting_pong = true;
+        true
+    }
+}
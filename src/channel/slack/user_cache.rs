@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::json;
+
+use super::api_client::{ApiError, Member};
+use crate::state::StateDb;
+
+// How long a full user list is good for before we go get a new one. Between
+// refreshes, user_change and team_join events keep us mostly up to date, and
+// anyone we've never heard of gets looked up on the spot.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// slack id => slack username
+#[derive(Debug)]
+pub struct UserCache {
+    state_key: String,
+    users: HashMap<String, String>,
+    refreshed_at: u64,
+}
+
+pub fn new(channel_name: &str) -> UserCache {
+    UserCache {
+        state_key: format!("{}/users", channel_name),
+        users: HashMap::new(),
+        refreshed_at: 0,
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+impl UserCache {
+    pub fn name_for(&self, id: &str) -> Option<&str> {
+        self.users.get(id).map(|s| s.as_str())
+    }
+
+    pub fn id_for(&self, name: &str) -> Option<&str> {
+        self.users
+            .iter()
+            .find(|(_, n)| n.as_str() == name)
+            .map(|(id, _)| id.as_str())
+    }
+
+    // Which of these ids we've never heard of.
+    pub fn missing(&self, ids: &[&str]) -> Vec<String> {
+        ids.iter()
+            .filter(|id| !self.users.contains_key(**id))
+            .map(|id| id.to_string())
+            .collect()
+    }
+
+    // Everyone we had to go and ask slack about.
+    pub fn learn(&mut self, members: Vec<Member>, db: Option<&StateDb>) {
+        for member in members {
+            self.users.insert(member.id, member.name);
+        }
+
+        self.save(db);
+    }
+
+    pub fn update(&mut self, member: Member, db: Option<&StateDb>) {
+        debug!("updating slack user {} ({})", member.name, member.id);
+        self.users.insert(member.id, member.name);
+        self.save(db);
+    }
+
+    pub fn is_stale(&self) -> bool {
+        now().saturating_sub(self.refreshed_at) > REFRESH_INTERVAL.as_secs()
+    }
+
+    pub fn refreshed(
+        &mut self,
+        users: Result<HashMap<String, String>, ApiError>,
+        db: Option<&StateDb>,
+    ) {
+        match users {
+            Ok(users) => {
+                self.users = users;
+                self.refreshed_at = now();
+                self.save(db);
+            }
+            Err(e) => {
+                // Don't hammer slack if it's unhappy; we'll try again next
+                // interval, and we've still got whatever we had before.
+                warn!("couldn't refresh slack users: {}", e);
+                self.refreshed_at = now();
+            }
+        }
+    }
+
+    // Pick up wherever we left off last time, if we can. Returns false if
+    // there was nothing there.
+    pub fn load(&mut self, db: Option<&StateDb>) -> bool {
+        let saved = match db.and_then(|db| db.fetch(&self.state_key)) {
+            Some(v) => v,
+            None => return false,
+        };
+
+        let users = serde_json::from_value(saved["users"].clone());
+        let refreshed_at = saved["refreshed_at"].as_u64();
+
+        match (users, refreshed_at) {
+            (Ok(users), Some(refreshed_at)) => {
+                self.users = users;
+                self.refreshed_at = refreshed_at;
+                info!("loaded {} slack users from state", self.users.len());
+                true
+            }
+            _ => {
+                warn!("ignoring bogus saved state for {}", self.state_key);
+                false
+            }
+        }
+    }
+
+    fn save(&self, db: Option<&StateDb>) {
+        if let Some(db) = db {
+            let state = json!({
+                "users": self.users,
+                "refreshed_at": self.refreshed_at,
+            });
+
+            db.save(&self.state_key, &state);
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::api_client::ApiClient;
+use super::blocks;
+use super::directory::Directory;
+use super::mrkdwn;
+use super::reader::FromSlack;
+use super::send_queue::{self, SendQueue};
+use crate::channel::{split, Input};
+use crate::message::{Message, Reply};
+
+// If nothing's waiting on a rate limit, there's no reason to wake up until
+// something new comes along, but we do anyway, once in a while.
+const IDLE_WAKEUP: Duration = Duration::from_secs(60);
+
+pub struct Writer {
+    api: ApiClient,
+    directory: Directory,
+    queue: SendQueue,
+    broadcasters: Vec<String>, // reactors allowed to use @here and friends
+    max_message_length: usize,
+    snippet_length: usize,
+}
+
+pub fn new(
+    api: ApiClient,
+    directory: Directory,
+    broadcasters: Vec<String>,
+    max_message_length: usize,
+    snippet_length: usize,
+) -> Writer {
+    Writer {
+        api,
+        directory,
+        queue: send_queue::new(),
+        broadcasters,
+        max_message_length,
+        snippet_length,
+    }
+}
+
+// The writer is the one listening to the hub, so replies go out as soon as
+// they show up, and never wait behind anything the channel is doing with what
+// slack sends us (or the other way round). Everything from the hub that isn't
+// a reply gets passed along to the channel. Once the hub hangs up, we make one
+// last attempt at anything still queued and stop.
+pub fn spawn(
+    mut writer: Writer,
+    from_hub: mpsc::Receiver<Message>,
+    to_channel: mpsc::Sender<Input<FromSlack>>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || loop {
+        let timeout = writer
+            .queue
+            .next_attempt()
+            .map_or(IDLE_WAKEUP, |t| t.saturating_duration_since(Instant::now()));
+
+        match from_hub.recv_timeout(timeout) {
+            Ok(Message::Reply(reply)) => writer.send_reply(reply),
+            Ok(msg) => {
+                let is_hangup = matches!(msg, Message::Hangup);
+
+                if to_channel.send(Input::Hub(msg)).is_err() || is_hangup {
+                    writer.queue.flush(&writer.api);
+                    return;
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => (),
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                writer.queue.flush(&writer.api);
+                return;
+            }
+        }
+
+        writer.queue.flush(&writer.api);
+    })
+}
+
+impl Writer {
+    // Replies go out through the web API rather than the websocket, so that
+    // they get all of chat.postMessage's niceties; the queue takes care of
+    // rate limits and ordering.
+    //
+    // Reactors can address a reply to "#name" instead of a conversation id,
+    // which we'll sort out here, and private replies go to a DM. Replies to
+    // slash commands go back by way of the command's response url, which works
+    // even in conversations we're not in.
+    fn send_reply(&mut self, mut reply: Reply) {
+        if reply.is_private {
+            reply.response_url = None; // it can't get to a DM
+            match self.directory.dm_for(&reply.from_address) {
+                Some(id) => reply.conversation_address = id,
+                None => {
+                    error!(
+                        "no dm with {}; dropping private reply from {}",
+                        reply.from_address, reply.origin
+                    );
+                    return;
+                }
+            }
+        }
+
+        if reply.conversation_address.starts_with('#') {
+            let name = &reply.conversation_address[1..];
+
+            let convo = match self.directory.find_conversation(name) {
+                Some(c) => c,
+                None => {
+                    error!(
+                        "no slack conversation named #{}; dropping reply from {}",
+                        name, reply.origin
+                    );
+                    return;
+                }
+            };
+
+            if !convo.is_member {
+                warn!("we're not in #{}, so this probably won't work", name);
+            }
+
+            reply.conversation_address = convo.id;
+        }
+
+        // Really long things go up as a file, which doesn't need encoding.
+        // (Files can't have buttons, though, or be ephemeral.)
+        if reply.interactive.is_none()
+            && !reply.is_ephemeral
+            && reply.text.chars().count() > self.snippet_length
+        {
+            self.queue.push_snippet(reply);
+            return;
+        }
+
+        let allow_broadcast = self.broadcasters.contains(&reply.origin);
+
+        let text = mrkdwn::encode(&reply.text, &self.directory, allow_broadcast);
+
+        // Encoding first means escaping can't push a piece over the limit.
+        // Any buttons go on the last piece, under all the text, which then
+        // has to fit in a section.
+        let limit = if reply.interactive.is_some() {
+            self.max_message_length.min(blocks::MAX_SECTION_TEXT)
+        } else {
+            self.max_message_length
+        };
+
+        let pieces = split::split(&text, limit);
+        let last = pieces.len().saturating_sub(1);
+
+        for (i, text) in pieces.into_iter().enumerate() {
+            self.queue.push(Reply {
+                text,
+                interactive: if i == last {
+                    reply.interactive.clone()
+                } else {
+                    None
+                },
+                ..reply.clone()
+            });
+        }
+    }
+}
@@ -0,0 +1,258 @@
+mod segments;
+#[cfg(test)]
+mod tests;
+mod webhook;
+
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use reqwest::blocking::Client;
+use reqwest::StatusCode;
+
+use crate::channel::{self, Input, Seed};
+use crate::message::{Connectivity, Event, Message, Reply};
+use webhook::Inbound;
+
+const DEFAULT_API_URL: &str = "https://api.twilio.com";
+
+// Twilio would rather messages stay under 320 characters, which is about
+// three segments' worth.
+const DEFAULT_MAX_SEGMENTS: usize = 3;
+
+// If twilio says slow down (or has a bad moment), we wait this long and try
+// once more before giving up on a message.
+const RETRY_AFTER: Duration = Duration::from_secs(1);
+
+// Texts come in by way of a webhook (see webhook.rs), and replies go out by
+// way of twilio's REST API, or anything else that talks like it. Everybody's
+// known by their phone number, in E.164 ("+15555551234"), and every text is
+// a private conversation with us.
+pub struct Sms {
+    pub name: String,
+    to_hub: mpsc::Sender<Message>,
+    from_hub: Option<mpsc::Receiver<Message>>, // until forward_hub takes it
+    http: Client,
+    api_url: String,
+    account_sid: String,
+    auth_token: String,
+    our_number: String,
+    listen_address: String,
+    webhook_url: String,
+    default_country_code: Option<String>, // for numbers without a +
+    max_segments: usize,
+}
+
+pub fn build(seed: Seed) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut channel = self::new(seed);
+        channel.start();
+    })
+}
+
+pub fn new(seed: Seed) -> Sms {
+    let extra = &seed.config.extra;
+
+    let config_str = |key| extra.get(key).and_then(|v| v.as_str()).map(String::from);
+
+    let account_sid = config_str("account_sid").expect("no account_sid for sms in config!");
+    let auth_token = config_str("auth_token").expect("no auth_token for sms in config!");
+    let listen_address =
+        config_str("listen_address").expect("no listen_address for sms in config!");
+
+    let default_country_code = config_str("default_country_code");
+
+    let our_number = config_str("from_number")
+        .and_then(|n| normalize_number(&n, default_country_code.as_deref()))
+        .expect("no from_number (or not a phone number) for sms in config!");
+
+    // Always twilio's, outside of tests (which stand in for it).
+    let api_url = config_str("api_url")
+        .unwrap_or_else(|| DEFAULT_API_URL.to_string())
+        .trim_end_matches('/')
+        .to_string();
+
+    // what twilio thinks our webhook is, which matters for signatures
+    let webhook_url =
+        config_str("webhook_url").unwrap_or_else(|| format!("http://{}", listen_address));
+
+    Sms {
+        name: seed.name.clone(),
+        to_hub: seed.output,
+        from_hub: Some(seed.input),
+        http: Client::new(),
+        api_url,
+        account_sid,
+        auth_token,
+        our_number,
+        listen_address,
+        webhook_url,
+        default_country_code,
+        max_segments: extra
+            .get("max_segments")
+            .and_then(|v| v.as_integer())
+            .map_or(DEFAULT_MAX_SEGMENTS, |n| n as usize),
+    }
+}
+
+// "+1 (555) 555-1234", "0015555551234", and (with a default country code of
+// 1) "555-555-1234" are all "+15555551234". Anything that can't be a phone
+// number is None.
+pub fn normalize_number(raw: &str, default_country_code: Option<&str>) -> Option<String> {
+    let raw = raw.trim();
+
+    if raw
+        .chars()
+        .any(|c| !(c.is_ascii_digit() || " ()-.+".contains(c)))
+    {
+        return None;
+    }
+
+    let digits: String = raw.chars().filter(|c| c.is_ascii_digit()).collect();
+
+    let number = if raw.starts_with('+') {
+        digits
+    } else if let Some(rest) = digits.strip_prefix("00") {
+        rest.to_string()
+    } else {
+        // Some places dial a 0 first inside the country; it's not part of
+        // the number.
+        let cc = default_country_code?;
+        let national = digits.strip_prefix('0').unwrap_or(&digits);
+
+        if national.starts_with(cc) && national.len() > 10 {
+            national.to_string()
+        } else {
+            format!("{}{}", cc, national)
+        }
+    };
+
+    // E.164 numbers are at most 15 digits, and nothing real is under 8.
+    if (8..=15).contains(&number.len()) && !number.starts_with('0') {
+        Some(format!("+{}", number))
+    } else {
+        None
+    }
+}
+
+impl Sms {
+    fn start(&mut self) {
+        let (inbox_tx, inbox) = mpsc::channel();
+
+        channel::forward_hub(self.from_hub.take().unwrap(), inbox_tx.clone());
+
+        let _listener = webhook::spawn(
+            &self.listen_address,
+            self.webhook_url.clone(),
+            self.auth_token.clone(),
+            inbox_tx,
+        )
+        .unwrap_or_else(|e| panic!("couldn't listen on {}: {}", self.listen_address, e));
+
+        channel::set_connectivity(&self.to_hub, &self.name, Connectivity::Connected);
+
+        for input in inbox {
+            match input {
+                Input::Hub(Message::Reply(reply)) => self.send_reply(reply),
+                Input::Hub(Message::Hangup) => break,
+                Input::Hub(_) => (),
+                Input::Remote(inbound) => self.handle_inbound(inbound),
+            }
+        }
+    }
+
+    fn handle_inbound(&self, inbound: Inbound) {
+        let cc = self.default_country_code.as_deref();
+
+        let from = match normalize_number(&inbound.from, cc) {
+            Some(n) => n,
+            None => {
+                warn!(
+                    "ignoring text from {:?}, which isn't a number",
+                    inbound.from
+                );
+                return;
+            }
+        };
+
+        // If someone's pointed some other number's webhook at us, we don't
+        // want to answer as if we were it.
+        if normalize_number(&inbound.to, cc).as_ref() != Some(&self.our_number) {
+            warn!("ignoring text to {}, which isn't us", inbound.to);
+            return;
+        }
+
+        let event = Event {
+            was_targeted: true,
+            ..Event::new(&self.name, &from, &from, inbound.body.trim())
+        };
+
+        self.to_hub.send(Message::Event(Arc::new(event))).unwrap();
+    }
+
+    // Every conversation is with one person, so private replies are just
+    // replies. Long ones go out as a few texts, each as long as we'll allow.
+    pub fn send_reply(&self, reply: Reply) {
+        let to = if reply.is_private || reply.is_ephemeral {
+            &reply.from_address
+        } else {
+            &reply.conversation_address
+        };
+
+        let to = match normalize_number(to, self.default_country_code.as_deref()) {
+            Some(n) => n,
+            None => {
+                error!("can't text {}; dropping reply from {}", to, reply.origin);
+                return;
+            }
+        };
+
+        for text in segments::split(&reply.text, self.max_segments) {
+            debug!("texting {} ({} segments)", to, segments::count(&text));
+
+            // Each of these shows up as a text of its own, so carrying on
+            // past a missing one would leave them reading a reply with a hole
+            // in the middle.
+            if let Err(e) = self.send_text(&to, &text) {
+                error!("couldn't text {}: {}", to, e);
+                return;
+            }
+        }
+    }
+
+    fn send_text(&self, to: &str, body: &str) -> Result<(), String> {
+        let url = format!(
+            "{}/2010-04-01/Accounts/{}/Messages.json",
+            self.api_url, self.account_sid
+        );
+
+        let params = [("From", &self.our_number[..]), ("To", to), ("Body", body)];
+
+        let mut retried = false;
+
+        loop {
+            let res = self
+                .http
+                .post(&url)
+                .basic_auth(&self.account_sid, Some(&self.auth_token))
+                .form(&params)
+                .send();
+
+            let status = match res {
+                Ok(res) if res.status().is_success() => return Ok(()),
+                Ok(res) => res.status(),
+                Err(e) => return Err(e.to_string()),
+            };
+
+            let worth_retrying =
+                status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error();
+
+            if retried || !worth_retrying {
+                return Err(format!("got {}", status));
+            }
+
+            retried = true;
+            thread::sleep(RETRY_AFTER);
+        }
+    }
+}
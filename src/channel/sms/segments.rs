@@ -0,0 +1,136 @@
+// Texts go out in 140-byte segments. If everything in a message is in the GSM
+// alphabet, that's 160 characters (7 bits each), and if not, it's 70 UTF-16
+// units. A message that takes more than one segment loses a little room in
+// each to the header that glues them back together. Carriers charge by the
+// segment, so we'd rather know how many we're sending.
+
+use crate::channel::split;
+
+const GSM_BASIC: &str = "@£$¥èéùìòÇ\nØø\rÅåΔ_ΦΓΛΩΠΨΣΘΞÆæßÉ !\"#¤%&'()*+,-./0123456789:;<=>?\
+                         ¡ABCDEFGHIJKLMNOPQRSTUVWXYZÄÖÑÜ§¿abcdefghijklmnopqrstuvwxyzäöñüà";
+
+// these take an escape character first, so they count twice
+const GSM_EXTENDED: &str = "\u{c}^{}\\[~]|€";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding {
+    Gsm,
+    Ucs2,
+}
+
+fn encoding_for(text: &str) -> Encoding {
+    if text
+        .chars()
+        .all(|c| GSM_BASIC.contains(c) || GSM_EXTENDED.contains(c))
+    {
+        Encoding::Gsm
+    } else {
+        Encoding::Ucs2
+    }
+}
+
+// how much of a segment one character takes up
+fn cost(c: char, encoding: Encoding) -> usize {
+    match encoding {
+        Encoding::Gsm if GSM_EXTENDED.contains(c) => 2,
+        Encoding::Gsm => 1,
+        Encoding::Ucs2 => c.len_utf16(),
+    }
+}
+
+fn capacity(encoding: Encoding, segments: usize) -> usize {
+    let (single, each) = match encoding {
+        Encoding::Gsm => (160, 153),
+        Encoding::Ucs2 => (70, 67),
+    };
+
+    if segments <= 1 {
+        single
+    } else {
+        each * segments
+    }
+}
+
+// How many segments it'll take to send this as one message.
+pub fn count(text: &str) -> usize {
+    let encoding = encoding_for(text);
+    let length: usize = text.chars().map(|c| cost(c, encoding)).sum();
+
+    (1..).find(|&n| length <= capacity(encoding, n)).unwrap()
+}
+
+// Break text into messages of no more than max_segments each.
+pub fn split(text: &str, max_segments: usize) -> Vec<String> {
+    // If any of it isn't GSM, we size everything as if none of it is; some
+    // pieces might have fit more, but none of them will fit less.
+    let encoding = encoding_for(text);
+    let budget = capacity(encoding, max_segments.max(1));
+
+    split::split_with(text.trim(), budget, |c| cost(c, encoding))
+        .into_iter()
+        .map(|piece| piece.trim().to_string())
+        .filter(|piece| !piece.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counting() {
+        let cases = vec![
+            ("hello".to_string(), 1),
+            ("a".repeat(160), 1),
+            ("a".repeat(161), 2),
+            ("a".repeat(306), 2),
+            ("a".repeat(307), 3),
+            // the extended characters count twice
+            ("€".repeat(80), 1),
+            ("€".repeat(81), 2),
+            // one character outside GSM, and it's UCS-2 for everything
+            (format!("{}ő", "a".repeat(69)), 1),
+            (format!("{}ő", "a".repeat(70)), 2),
+            // and emoji are two UTF-16 units each
+            ("🙂".repeat(35), 1),
+            ("🙂".repeat(36), 2),
+        ];
+
+        for (text, want) in cases {
+            assert_eq!(count(&text), want, "counting {:?}", text);
+        }
+    }
+
+    #[test]
+    fn splitting() {
+        let words = "word ".repeat(100);
+        let pieces = split(&words, 1);
+        assert!(pieces.len() > 1);
+        for piece in &pieces {
+            assert_eq!(count(piece), 1, "{:?}", piece);
+            assert!(!piece.starts_with(' ') && !piece.ends_with(' '));
+        }
+        assert_eq!(pieces.join(" "), words.trim());
+
+        // lines before words
+        let text = format!("{}\n{}", "a ".repeat(50).trim(), "b ".repeat(50).trim());
+        assert_eq!(
+            split(&text, 1),
+            vec!["a ".repeat(50).trim(), "b ".repeat(50).trim()]
+        );
+
+        // more segments, fewer pieces
+        assert_eq!(split(&words, 4).len(), 1);
+
+        // emoji can't be cut in half
+        let smiles = "🙂".repeat(100);
+        let pieces = split(&smiles, 1);
+        assert_eq!(
+            pieces,
+            vec!["🙂".repeat(35), "🙂".repeat(35), "🙂".repeat(30)]
+        );
+
+        assert_eq!(split("short", 1), vec!["short"]);
+        assert_eq!(split("", 1), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,282 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+use tiny_http::Response;
+
+use super::{normalize_number, segments, webhook};
+use crate::channel::testing;
+use crate::channel::{self, Listener, Type};
+use crate::message::Connectivity;
+
+const ACCOUNT_SID: &str = "AC0123456789";
+const AUTH_TOKEN: &str = "sekrit";
+const OUR_NUMBER: &str = "+15555550100";
+
+// Something that takes texts the way twilio does, and tells us what it got.
+struct StandIn {
+    url: String,
+    texts: mpsc::Receiver<Text>,
+    statuses: Arc<Mutex<VecDeque<u16>>>, // what to answer with; 201 after these
+    _listener: Listener,
+}
+
+#[derive(Debug)]
+struct Text {
+    path: String,
+    auth: String,
+    form: HashMap<String, String>,
+}
+
+fn start_stand_in() -> StandIn {
+    let address = testing::free_address();
+    let (texts_tx, texts) = mpsc::channel();
+    let statuses = Arc::new(Mutex::new(VecDeque::new()));
+
+    let our_statuses = Arc::clone(&statuses);
+    let listener = channel::listen(&address, move |mut request| {
+        let mut body = String::new();
+        request.as_reader().read_to_string(&mut body).unwrap();
+
+        let auth = request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Authorization"))
+            .map(|h| h.value.to_string())
+            .unwrap_or_default();
+
+        let text = Text {
+            path: request.url().to_string(),
+            auth,
+            form: url::form_urlencoded::parse(body.as_bytes())
+                .into_owned()
+                .collect(),
+        };
+
+        let status = our_statuses.lock().unwrap().pop_front().unwrap_or(201);
+        let _ = texts_tx.send(text);
+        let _ = request.respond(Response::from_string("{}").with_status_code(status));
+    })
+    .unwrap();
+
+    StandIn {
+        url: format!("http://{}", address),
+        texts,
+        statuses,
+        _listener: listener,
+    }
+}
+
+impl StandIn {
+    fn next_text(&self) -> Text {
+        self.texts
+            .recv_timeout(Duration::from_secs(5))
+            .expect("nothing got texted")
+    }
+
+    fn nothing_texted(&self) {
+        assert!(self.texts.recv_timeout(Duration::from_millis(300)).is_err());
+    }
+}
+
+// A running sms channel, the stand-in it texts through, and where twilio
+// would find its webhook.
+type Harness = testing::Harness<Fakes>;
+
+struct Fakes {
+    stand_in: StandIn,
+    webhook: String,
+}
+
+impl testing::Fakes for Fakes {}
+
+fn start_channel(extra: Vec<(&str, toml::Value)>) -> Harness {
+    let stand_in = start_stand_in();
+
+    let address = testing::free_address();
+
+    let config = testing::config(
+        vec![
+            ("account_sid", ACCOUNT_SID.into()),
+            ("auth_token", AUTH_TOKEN.into()),
+            ("from_number", "+1 555 555 0100".into()),
+            ("listen_address", address.clone().into()),
+            ("api_url", stand_in.url.clone().into()),
+        ],
+        extra,
+    );
+
+    let channel = testing::start("channel/sms", Type::SmsChannel, config, ":memory:");
+    channel.expect_connectivity(Connectivity::Connected);
+
+    Harness {
+        channel,
+        fakes: Fakes {
+            stand_in,
+            webhook: format!("http://{}/sms", address),
+        },
+    }
+}
+
+impl Harness {
+    // Text us, the way twilio would tell us about it; returns the status.
+    fn text_us(&self, from: &str, to: &str, body: &str) -> u16 {
+        let form: BTreeMap<String, String> = vec![
+            ("AccountSid", ACCOUNT_SID),
+            ("MessageSid", "SM0001"),
+            ("From", from),
+            ("To", to),
+            ("Body", body),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+        let signature = webhook::sign(AUTH_TOKEN, &self.fakes.webhook, &form);
+        self.post(&form, &signature)
+    }
+
+    fn post(&self, form: &BTreeMap<String, String>, signature: &str) -> u16 {
+        reqwest::blocking::Client::new()
+            .post(&self.fakes.webhook)
+            .header("X-Twilio-Signature", signature)
+            .form(form)
+            .send()
+            .unwrap()
+            .status()
+            .as_u16()
+    }
+}
+
+#[test]
+fn round_trip() {
+    let h = start_channel(vec![]);
+
+    assert_eq!(h.text_us("+15555550123", OUR_NUMBER, " clox "), 200);
+
+    let event = h.next_event();
+    assert_eq!(event.text, "clox");
+    assert_eq!(event.from_address, "+15555550123");
+    assert_eq!(event.conversation_address, "+15555550123");
+    assert_eq!(event.origin, "channel/sms");
+    assert!(event.was_targeted);
+    assert!(!event.is_public);
+
+    h.reply(&event, "It's time.");
+
+    let text = h.fakes.stand_in.next_text();
+    assert_eq!(
+        text.path,
+        format!("/2010-04-01/Accounts/{}/Messages.json", ACCOUNT_SID)
+    );
+    assert_eq!(
+        text.auth,
+        format!(
+            "Basic {}",
+            base64::encode(&format!("{}:{}", ACCOUNT_SID, AUTH_TOKEN))
+        )
+    );
+    assert_eq!(text.form["From"], OUR_NUMBER);
+    assert_eq!(text.form["To"], "+15555550123");
+    assert_eq!(text.form["Body"], "It's time.");
+
+    h.hangup();
+}
+
+#[test]
+fn bad_webhooks() {
+    let h = start_channel(vec![]);
+
+    // unsigned, or signed by someone else
+    let mut form = BTreeMap::new();
+    form.insert("From".to_string(), "+15555550123".to_string());
+    form.insert("To".to_string(), OUR_NUMBER.to_string());
+    form.insert("Body".to_string(), "hi".to_string());
+
+    assert_eq!(h.post(&form, ""), 403);
+
+    let forged = webhook::sign("not-our-token", &h.fakes.webhook, &form);
+    assert_eq!(h.post(&form, &forged), 403);
+
+    // signed, but not for us, or not from a phone number
+    assert_eq!(h.text_us("+15555550123", "+15555550199", "hi"), 200);
+    assert_eq!(h.text_us("short code", OUR_NUMBER, "hi"), 200);
+    h.nothing_happened();
+
+    h.hangup();
+}
+
+#[test]
+fn long_replies_are_split_by_segment() {
+    let h = start_channel(vec![("max_segments", 1.into())]);
+
+    h.text_us("+15555550123", OUR_NUMBER, "talk a lot");
+    let event = h.next_event();
+
+    // the flag means UCS-2, so 70 to a segment, not 160
+    let text = format!("🇺🇸 {}", "word ".repeat(40).trim());
+    h.reply(&event, &text);
+
+    let mut bodies = vec![];
+    while bodies.join(" ").len() < text.len() {
+        let body = h.fakes.stand_in.next_text().form["Body"].clone();
+        assert_eq!(segments::count(&body), 1, "{:?}", body);
+        bodies.push(body);
+    }
+
+    assert!(bodies.len() > 2);
+    assert_eq!(bodies.join(" "), text);
+
+    h.hangup();
+}
+
+#[test]
+fn sending_retries_once() {
+    let h = start_channel(vec![]);
+
+    h.text_us("+15555550123", OUR_NUMBER, "hi");
+    let event = h.next_event();
+
+    // one rate limit is worth waiting out
+    h.fakes.stand_in.statuses.lock().unwrap().push_back(429);
+    h.reply(&event, "one");
+    assert_eq!(h.fakes.stand_in.next_text().form["Body"], "one");
+    assert_eq!(h.fakes.stand_in.next_text().form["Body"], "one");
+
+    // but some errors aren't going to get better
+    h.fakes.stand_in.statuses.lock().unwrap().push_back(400);
+    h.reply(&event, "two");
+    assert_eq!(h.fakes.stand_in.next_text().form["Body"], "two");
+    h.fakes.stand_in.nothing_texted();
+
+    h.hangup();
+}
+
+#[test]
+fn phone_numbers() {
+    let cases = vec![
+        // raw, default country code, normalized
+        ("+15555550123", None, Some("+15555550123")),
+        ("+1 (555) 555-0123", None, Some("+15555550123")),
+        ("0015555550123", None, Some("+15555550123")),
+        ("555-555-0123", None, None),
+        ("555-555-0123", Some("1"), Some("+15555550123")),
+        ("1-555-555-0123", Some("1"), Some("+15555550123")),
+        ("07700 900123", Some("44"), Some("+447700900123")),
+        ("+44 7700 900123", Some("1"), Some("+447700900123")),
+        ("+1234", None, None),
+        ("+1234567890123456", None, None),
+        ("call me", Some("1"), None),
+        ("", Some("1"), None),
+    ];
+
+    for (raw, cc, want) in cases {
+        assert_eq!(
+            normalize_number(raw, cc).as_deref(),
+            want,
+            "{:?} with {:?}",
+            raw,
+            cc
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+use std::sync::mpsc;
+
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use tiny_http::{Header, Request, Response};
+
+use crate::channel::{self, Input, Listener};
+
+// An inbound text, as twilio tells us about it.
+#[derive(Debug)]
+pub struct Inbound {
+    pub from: String,
+    pub to: String,
+    pub body: String,
+}
+
+// Twilio POSTs a form to us for every text that comes in, signed with our
+// auth token. This checks the signature and passes the text along.
+//
+// The signature covers the url twilio used, which might not be the address
+// we're listening on (if we're behind a proxy, say), so that's webhook_url.
+pub fn spawn(
+    address: &str,
+    webhook_url: String,
+    auth_token: String,
+    to_channel: mpsc::Sender<Input<Inbound>>,
+) -> Result<Listener, Box<dyn std::error::Error + Send + Sync>> {
+    let listener = channel::listen(address, move |request| {
+        handle(request, &webhook_url, &auth_token, &to_channel);
+    })?;
+
+    info!("listening for texts on {}", address);
+    Ok(listener)
+}
+
+fn handle(
+    mut request: Request,
+    webhook_url: &str,
+    auth_token: &str,
+    to_channel: &mpsc::Sender<Input<Inbound>>,
+) {
+    let signature = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("X-Twilio-Signature"))
+        .map(|h| h.value.as_str().to_string())
+        .unwrap_or_default();
+
+    let mut body = vec![];
+    if let Err(e) = request.as_reader().read_to_end(&mut body) {
+        warn!("error reading sms webhook: {}", e);
+        return;
+    }
+
+    let form: BTreeMap<String, String> = url::form_urlencoded::parse(&body).into_owned().collect();
+
+    // The url has to be the whole thing, query string and all.
+    let url = format!("{}{}", webhook_url.trim_end_matches('/'), request.url());
+
+    if !verify(auth_token, &url, &form, &signature) {
+        warn!("ignoring sms webhook with a bad signature");
+        let _ = request.respond(Response::empty(403));
+        return;
+    }
+
+    // Empty TwiML means "don't reply"; we'll do that ourselves.
+    let header = Header::from_bytes(&b"Content-Type"[..], &b"text/xml"[..]).unwrap();
+    let _ = request.respond(Response::from_string("<Response></Response>").with_header(header));
+
+    let field = |name: &str| form.get(name).cloned().unwrap_or_default();
+
+    let inbound = Inbound {
+        from: field("From"),
+        to: field("To"),
+        body: field("Body"),
+    };
+
+    let _ = to_channel.send(Input::Remote(inbound));
+}
+
+// https://www.twilio.com/docs/usage/security#validating-requests
+pub fn verify(
+    auth_token: &str,
+    url: &str,
+    form: &BTreeMap<String, String>,
+    signature: &str,
+) -> bool {
+    let expected = match base64::decode(signature) {
+        Ok(bytes) => bytes,
+        Err(_) => return false,
+    };
+
+    let mut mac = match Hmac::<Sha1>::new_from_slice(auth_token.as_bytes()) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+
+    mac.update(signing_string(url, form).as_bytes());
+
+    // constant time, so nobody can work it out a byte at a time
+    mac.verify_slice(&expected).is_ok()
+}
+
+// the url, then every param (in order) with its value stuck on the end
+fn signing_string(url: &str, form: &BTreeMap<String, String>) -> String {
+    let mut s = url.to_string();
+
+    for (k, v) in form {
+        s.push_str(k);
+        s.push_str(v);
+    }
+
+    s
+}
+
+// Sign something the way twilio would; the tests need to pretend to be twilio.
+#[cfg(test)]
+pub fn sign(auth_token: &str, url: &str, form: &BTreeMap<String, String>) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(auth_token.as_bytes()).unwrap();
+    mac.update(signing_string(url, form).as_bytes());
+    base64::encode(&mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verification() {
+        // the example from twilio's docs
+        let token = "12345";
+        let url = "https://mycompany.com/myapp.php?foo=1&bar=2";
+        let form: BTreeMap<String, String> = vec![
+            ("CallSid", "CA1234567890ABCDE"),
+            ("Caller", "+12349013030"),
+            ("Digits", "1234"),
+            ("From", "+12349013030"),
+            ("To", "+18005551212"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+        let good = "0/KCTR6DLpKmkAf8muzZqo1nDgQ=";
+        assert_eq!(sign(token, url, &form), good);
+
+        let mut tampered = form.clone();
+        tampered.insert("Digits".into(), "9999".into());
+
+        let cases = vec![
+            // token, url, form, signature, ok?
+            (token, url, &form, good, true),
+            ("54321", url, &form, good, false),
+            (token, "https://mycompany.com/myapp.php", &form, good, false),
+            (token, url, &tampered, good, false),
+            (token, url, &form, "not base64!", false),
+            (token, url, &form, "", false),
+        ];
+
+        for (token, url, form, sig, want) in cases {
+            assert_eq!(
+                verify(token, url, form, sig),
+                want,
+                "token {:?}, url {:?}, sig {:?}",
+                token,
+                url,
+                sig
+            );
+        }
+    }
+}
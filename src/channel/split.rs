@@ -0,0 +1,290 @@
+// Breaking long replies up into pieces a channel can actually send. We try to
+// split somewhere sensible (between paragraphs, around code blocks, then at
+// line ends, then between words) and only chop a word in half if there's
+// really nothing else. If a piece ends in the middle of a ``` block, we close
+// the block there and open it again at the top of the next piece, so every
+// piece renders properly on its own.
+
+const FENCE: &str = "```";
+
+// room for closing a code block at the end of a piece
+const CLOSE_FENCE: &str = "\n```";
+
+// Anything shorter than this and there's no room to do the above.
+const MIN_LENGTH: usize = 20;
+
+// Where a piece can end, and how happy we'd be to end it there.
+#[derive(Debug, Clone, Copy)]
+struct Cut {
+    priority: u8,
+    at: usize,   // where this piece ends
+    skip: usize, // how much whitespace to drop before the next one starts
+}
+
+// max is in characters, not bytes.
+pub fn split(text: &str, max: usize) -> Vec<String> {
+    split_with(text, max, |_| 1)
+}
+
+// Like split, but for channels that count length some other way (like IRC,
+// in bytes): max is in whatever units cost says each character takes up.
+pub fn split_with(text: &str, max: usize, cost: impl Fn(char) -> usize) -> Vec<String> {
+    let max = max.max(MIN_LENGTH);
+    let length = |s: &str| s.chars().map(&cost).sum::<usize>();
+
+    let mut pieces = vec![];
+    let mut rest = text.to_string();
+
+    while length(&rest) > max {
+        // No code, no need to save room for closing it.
+        let reserve = if rest.contains(FENCE) {
+            length(CLOSE_FENCE)
+        } else {
+            0
+        };
+
+        let window = fitting(&rest, max - reserve, &cost);
+        let cut = find_cut(&rest[..window]);
+
+        let mut piece = rest[..cut.at].to_string();
+        let mut next = rest[cut.at + cut.skip..].to_string();
+
+        if piece.matches(FENCE).count() % 2 == 1 {
+            piece.push_str(CLOSE_FENCE);
+            next = format!("{}\n{}", FENCE, next);
+        }
+
+        pieces.push(piece);
+        rest = next;
+    }
+
+    pieces.push(rest);
+    pieces
+}
+
+// Where the longest start of s that fits in max ends, as a byte offset. It's
+// always at least one character, or we'd never get anywhere.
+fn fitting(s: &str, max: usize, cost: &impl Fn(char) -> usize) -> usize {
+    let mut used = 0;
+
+    for (i, c) in s.char_indices() {
+        used += cost(c);
+        if used > max {
+            return if i == 0 { c.len_utf8() } else { i };
+        }
+    }
+
+    s.len()
+}
+
+fn find_cut(window: &str) -> Cut {
+    // Not too close to the start, or we'll make a lot of tiny pieces.
+    let min = window.len() / 4;
+    let mut best: Option<Cut> = None;
+
+    let mut consider = |cut: Cut| {
+        if cut.at < min {
+            return;
+        }
+
+        let better = match best {
+            Some(b) => (cut.priority, cut.at) > (b.priority, b.at),
+            None => true,
+        };
+
+        if better {
+            best = Some(cut);
+        }
+    };
+
+    let mut in_code = false;
+    let mut line_start = 0;
+
+    for (i, _) in window.match_indices('\n') {
+        let line = &window[line_start..i];
+        let next = &window[i + 1..];
+
+        let fences = line.matches(FENCE).count();
+        let was_in_code = in_code;
+        if fences % 2 == 1 {
+            in_code = !in_code;
+        }
+
+        let just_closed = was_in_code && !in_code;
+        let paragraph = !in_code && next.starts_with('\n');
+        let before_code = !in_code && next.starts_with(FENCE);
+
+        let priority = if just_closed || paragraph || before_code {
+            3
+        } else {
+            2
+        };
+
+        // Outside of code, there's no point keeping the blank lines.
+        let skip = if in_code {
+            1
+        } else {
+            1 + next.len() - next.trim_start_matches('\n').len()
+        };
+
+        consider(Cut {
+            priority,
+            at: i,
+            skip,
+        });
+
+        line_start = i + 1;
+    }
+
+    for (i, _) in window.match_indices(' ') {
+        consider(Cut {
+            priority: 1,
+            at: i,
+            skip: 1,
+        });
+    }
+
+    best.unwrap_or(Cut {
+        priority: 0,
+        at: window.len(),
+        skip: 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(pieces: &[String], max: usize) {
+        for piece in pieces {
+            assert!(
+                piece.chars().count() <= max,
+                "piece too long ({}): {:?}",
+                piece.chars().count(),
+                piece
+            );
+            assert_eq!(
+                piece.matches(FENCE).count() % 2,
+                0,
+                "unbalanced: {:?}",
+                piece
+            );
+        }
+    }
+
+    #[test]
+    fn short_text_is_left_alone() {
+        assert_eq!(split("hello", 100), vec!["hello"]);
+        assert_eq!(split("", 100), vec![""]);
+    }
+
+    #[test]
+    fn splitting() {
+        let para = "word ".repeat(10).trim_end().to_string(); // 49 chars
+
+        let cases: Vec<(String, usize, Vec<String>)> = vec![
+            // paragraphs win over lines
+            (
+                format!(
+                    "{}\n{}\n\n{}",
+                    "a".repeat(30),
+                    "b".repeat(30),
+                    "c".repeat(30)
+                ),
+                80,
+                vec![
+                    format!("{}\n{}", "a".repeat(30), "b".repeat(30)),
+                    "c".repeat(30),
+                ],
+            ),
+            // then lines
+            (
+                format!("{}\n{}\n{}", "a".repeat(30), "b".repeat(30), "c".repeat(30)),
+                70,
+                vec![
+                    format!("{}\n{}", "a".repeat(30), "b".repeat(30)),
+                    "c".repeat(30),
+                ],
+            ),
+            // then words
+            (
+                para.clone(),
+                30,
+                vec![
+                    "word word word word word word".into(),
+                    "word word word word".into(),
+                ],
+            ),
+            // and if there's nothing else, chop
+            ("x".repeat(50), 30, vec!["x".repeat(30), "x".repeat(20)]),
+            // characters, not bytes
+            ("é".repeat(50), 30, vec!["é".repeat(30), "é".repeat(20)]),
+            // a code block gets its own piece if it can
+            (
+                format!("{}\n```\n{}\n```\nafter", "a".repeat(30), "b".repeat(30)),
+                50,
+                vec![
+                    "a".repeat(30),
+                    format!("```\n{}\n```\nafter", "b".repeat(30)),
+                ],
+            ),
+            // and if it can't, it's closed and reopened
+            (
+                format!("```\n{}\n{}\n```", "a".repeat(30), "b".repeat(30)),
+                50,
+                vec![
+                    format!("```\n{}\n```", "a".repeat(30)),
+                    format!("```\n{}\n```", "b".repeat(30)),
+                ],
+            ),
+        ];
+
+        for (input, max, want) in cases {
+            let got = split(&input, max);
+            check(&got, max);
+            assert_eq!(got, want, "splitting {:?} at {}", input, max);
+        }
+    }
+
+    #[test]
+    fn splitting_by_cost() {
+        // bytes, and never half of a character
+        let pieces = split_with(&"é".repeat(25), 21, char::len_utf8);
+        assert_eq!(pieces, vec!["é".repeat(10), "é".repeat(10), "é".repeat(5)]);
+
+        // anything that won't fit at all goes on its own
+        let pieces = split_with("ab", 20, |c| if c == 'a' { 50 } else { 1 });
+        assert_eq!(pieces, vec!["a", "b"]);
+
+        // and the room for closing code counts too
+        let text = format!("```\n{}\n{}\n```", "é".repeat(20), "é".repeat(20));
+        for piece in split_with(&text, 50, char::len_utf8) {
+            assert!(piece.len() <= 50, "too long ({}): {:?}", piece.len(), piece);
+            assert_eq!(
+                piece.matches(FENCE).count() % 2,
+                0,
+                "unbalanced: {:?}",
+                piece
+            );
+        }
+    }
+
+    #[test]
+    fn long_code_blocks_stay_balanced() {
+        let lines: Vec<String> = (0..200).map(|i| format!("line {}", i)).collect();
+        let text = format!(
+            "here's the log:\n```\n{}\n```\nthat's all",
+            lines.join("\n")
+        );
+
+        let pieces = split(&text, 100);
+        check(&pieces, 100);
+        assert!(pieces.len() > 10);
+
+        // nothing got lost along the way
+        let joined = pieces.join("\n");
+        for line in lines {
+            assert!(joined.contains(&format!("{}\n", line)), "lost {}", line);
+        }
+    }
+}
@@ -1,25 +1,78 @@
-use std::io::{self, Write};
-use std::sync::{mpsc, Arc};
+use std::collections::BTreeSet;
+use std::env;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
 use colorful::Colorful;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, ExternalPrinter, Helper};
 use toml::value::Value;
 
-use crate::channel::{Channel, ReplyResponse, Seed};
-use crate::message::{Event, Message, Reply};
+use crate::channel::{split, Channel, ReplyResponse, Seed};
+use crate::message::{Callback, Element, Event, Interactive, Message, Reply};
 
 pub struct Term {
     pub name: String,
-    from_addr: String,
-    default_public_reply_addr: String,
+    max_message_length: usize,
     to_hub: mpsc::Sender<Message>,
     from_hub: mpsc::Receiver<Message>,
+    choices: Option<Choices>,
+    history_file: Option<PathBuf>,
+    commands: Arc<Mutex<BTreeSet<String>>>, // what tab completes to
+    printer: Option<Box<dyn ExternalPrinter + Send>>,
+
+    // who we're pretending to be, and where; see Meta
+    from_addr: String,
+    conversation: String,
+    is_public: bool,
+    was_targeted: bool,
+}
+
+// Lines starting with a slash are for us, not the hub: they change what the
+// events we send look like, so you can see how reactors behave for other
+// people, in other places.
+#[derive(Debug, PartialEq)]
+enum Meta {
+    As(String),
+    In(String),
+    Public,
+    Private,
+    Targeted,
+    Untargeted,
+}
+
+const META_COMMANDS: &[&str] = &[
+    "/as",
+    "/in",
+    "/public",
+    "/private",
+    "/targeted",
+    "/untargeted",
+];
+
+// rustyline wants one of these for tab completion. It's shared with the Term
+// itself, which hears about new commands from the hub.
+struct Completions {
+    commands: Arc<Mutex<BTreeSet<String>>>,
+}
+
+// The buttons and menus from the last interactive reply, flattened out into a
+// numbered list; typing one of the numbers picks it.
+struct Choices {
+    callback_id: String,
+    values: Vec<String>,
 }
 
 enum TermValue {
     Text(String),
-    EOF,
+    Eof,
 }
 
 pub fn build(seed: Seed) -> thread::JoinHandle<()> {
@@ -42,12 +95,73 @@ pub fn new(seed: Seed) -> Term {
         None => "#public",
     };
 
+    // A terminal doesn't care how long things are, but this is handy for
+    // seeing what a reply will look like somewhere that does.
+    let max_message_length = match seed.config.extra.get("max_message_length") {
+        Some(Value::Integer(n)) => *n as usize,
+        _ => usize::MAX,
+    };
+
+    // Leave this out, and history only lasts as long as we do.
+    let history_file = match seed.config.extra.get("history_file") {
+        Some(Value::String(s)) => Some(expand_home(s)),
+        _ => None,
+    };
+
     Term {
         name: seed.name.clone(),
         to_hub: seed.output,
         from_hub: seed.input,
+        max_message_length,
+        choices: None,
+        history_file,
+        commands: Arc::new(Mutex::new(
+            META_COMMANDS.iter().map(|c| c.to_string()).collect(),
+        )),
+        printer: None,
         from_addr: from.to_string(),
-        default_public_reply_addr: reply_addr.to_string(),
+        conversation: reply_addr.to_string(),
+        is_public: false,
+        was_targeted: true,
+    }
+}
+
+// None if it's not a meta-command at all, or an error if it's a bad one.
+fn parse_meta(text: &str) -> Option<Result<Meta, String>> {
+    if !text.starts_with('/') {
+        return None;
+    }
+
+    let mut words = text.split_whitespace();
+    let command = words.next().unwrap_or_default();
+    let arg = words.next().map(String::from);
+
+    let needs_arg = |make: fn(String) -> Meta| match arg.clone() {
+        Some(arg) => Ok(make(arg)),
+        None => Err(format!("{} needs something to go with it", command)),
+    };
+
+    let meta = match command {
+        "/as" => needs_arg(Meta::As),
+        "/in" => needs_arg(Meta::In),
+        "/public" => Ok(Meta::Public),
+        "/private" => Ok(Meta::Private),
+        "/targeted" => Ok(Meta::Targeted),
+        "/untargeted" => Ok(Meta::Untargeted),
+        _ => Err(format!(
+            "unknown command {}; try one of: {}",
+            command,
+            META_COMMANDS.join(" ")
+        )),
+    };
+
+    Some(meta)
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
     }
 }
 
@@ -57,52 +171,90 @@ impl Channel for Term {
     }
 
     fn send_reply(&mut self, reply: Reply) {
-        let indented = reply.text.replace("\n", "\n  ");
-        let text = format!(
-            ">> {}!{} |\n  {}",
-            &self.name, &reply.conversation_address, indented,
-        );
+        // there's only one of us here, so private (or ephemeral) just means
+        // addressed to us
+        let conversation = if reply.is_private || reply.is_ephemeral {
+            &reply.from_address
+        } else {
+            &reply.conversation_address
+        };
+
+        for piece in split::split(&reply.text, self.max_message_length) {
+            let indented = piece.replace("\n", "\n  ");
+            let text = format!(">> {}!{} |\n  {}", &self.name, conversation, indented,);
+
+            self.print(text.magenta().to_string());
+        }
+
+        if let Some(interactive) = reply.interactive {
+            self.show_choices(interactive);
+        }
+    }
 
-        println!("{}", text.magenta());
+    fn note_commands(&mut self, commands: Vec<String>) {
+        self.commands.lock().unwrap().extend(commands);
     }
 }
 
 impl Term {
     fn start(&mut self) {
-        // we need to kick off a thread for stdin so that we can read from it
-        // non-blocking
+        let mut editor = self.editor();
+
+        // Replies show up whenever they like, so they go out through the
+        // editor, which puts them above whatever's being typed rather than
+        // on top of it. That only works on a real terminal.
+        self.printer = match editor.create_external_printer() {
+            Ok(printer) => Some(Box::new(printer)),
+            Err(_) => None,
+        };
+
+        // The editor blocks, so it gets its own thread, and we can keep an
+        // eye on the hub in between lines. It waits to hear what the next
+        // prompt is before reading another line, since the last line might
+        // have changed it.
         let (tx, stdin_rx) = mpsc::channel();
-        thread::spawn(move || loop {
-            let mut buffer = String::new();
-            io::stdin().read_line(&mut buffer).unwrap();
-            tx.send(buffer).unwrap();
+        let (prompt_tx, prompt_rx) = mpsc::channel::<String>();
+        let history_file = self.history_file.clone();
+
+        thread::spawn(move || {
+            for prompt in prompt_rx {
+                let value = loop {
+                    match editor.readline(&prompt) {
+                        Ok(line) => {
+                            if !line.trim().is_empty() {
+                                let _ = editor.add_history_entry(line.as_str());
+                                save_history(&mut editor, history_file.as_ref());
+                            }
+
+                            break TermValue::Text(line.trim().to_string());
+                        }
+                        // ^C just throws away the line, like a shell
+                        Err(ReadlineError::Interrupted) => continue,
+                        Err(ReadlineError::Eof) => break TermValue::Eof,
+                        Err(e) => {
+                            error!("error reading from terminal: {}", e);
+                            break TermValue::Eof;
+                        }
+                    }
+                };
+
+                let is_eof = matches!(value, TermValue::Eof);
+
+                if tx.send(value).is_err() || is_eof {
+                    break;
+                }
+            }
         });
 
-        let mut stdout = io::stdout();
-        let mut need_prompt = true;
+        prompt_tx.send(self.prompt()).unwrap();
 
         loop {
-            match self.catch_replies() {
-                ReplyResponse::Sent => need_prompt = true,
-                ReplyResponse::Hangup => break,
-                _ => (),
-            };
-
-            if need_prompt {
-                print!("{}", "rustergy> ".cyan());
-                stdout.flush().unwrap();
-                need_prompt = false;
+            if let ReplyResponse::Hangup = self.catch_replies() {
+                break;
             }
 
             let value = match stdin_rx.recv_timeout(Duration::from_millis(100)) {
-                Ok(s) => {
-                    // 0 bytes here is EOF, blank line is just '\n'
-                    if s.is_empty() {
-                        TermValue::EOF
-                    } else {
-                        TermValue::Text(s.trim().to_string())
-                    }
-                }
+                Ok(value) => value,
                 Err(mpsc::RecvTimeoutError::Timeout) => continue,
                 Err(mpsc::RecvTimeoutError::Disconnected) => {
                     panic!("stdin hung up on us?");
@@ -110,7 +262,7 @@ impl Term {
             };
 
             let text = match value {
-                TermValue::EOF => {
+                TermValue::Eof => {
                     println!(); // so log line doesn't show up on prompt line
                     self.to_hub.send(Message::Hangup).unwrap();
                     break;
@@ -118,23 +270,237 @@ impl Term {
                 TermValue::Text(s) => s,
             };
 
-            if text.is_empty() {
-                continue;
+            if !text.is_empty() {
+                self.handle_line(text);
             }
 
-            let msg = Message::Event(Arc::new(Event {
-                // TODO: fill these in properly
-                text,
-                is_public: false,
-                was_targeted: true,
-                from_address: self.from_addr.clone(),
-                conversation_address: self.default_public_reply_addr.clone(),
-                origin: self.name.clone(),
-                user: None,
-                id: Event::new_id(),
-            }));
-
-            self.to_hub.send(msg).unwrap();
+            // the editor thread is gone if it saw Eof, but we'll hear about
+            // that next time round
+            let _ = prompt_tx.send(self.prompt());
+        }
+    }
+
+    fn handle_line(&mut self, text: String) {
+        if let Some(meta) = parse_meta(&text) {
+            match meta {
+                Ok(meta) => self.apply_meta(meta),
+                Err(e) => self.print(e.red().to_string()),
+            }
+            return;
+        }
+
+        let callback = self.pick_choice(&text);
+
+        let msg = Message::Event(Arc::new(Event {
+            is_public: self.is_public,
+            was_targeted: self.was_targeted,
+            callback,
+            ..Event::new(&self.name, &self.from_addr, &self.conversation, &text)
+        }));
+
+        self.to_hub.send(msg).unwrap();
+    }
+
+    fn apply_meta(&mut self, meta: Meta) {
+        match meta {
+            Meta::As(from) => self.from_addr = from,
+            Meta::In(conversation) => self.conversation = conversation,
+            Meta::Public => self.is_public = true,
+            Meta::Private => self.is_public = false,
+            Meta::Targeted => self.was_targeted = true,
+            Meta::Untargeted => self.was_targeted = false,
+        }
+    }
+
+    // e.g. "[sysop in #public, private] rustergy> "
+    fn prompt(&self) -> String {
+        let mut context = format!(
+            "{} in {}, {}",
+            self.from_addr,
+            self.conversation,
+            if self.is_public { "public" } else { "private" }
+        );
+
+        if !self.was_targeted {
+            context.push_str(", untargeted");
+        }
+
+        format!("[{}] {}", context.yellow(), "rustergy> ".cyan())
+    }
+
+    fn editor(&self) -> Editor<Completions, DefaultHistory> {
+        let mut editor = Editor::new().expect("couldn't set up the terminal");
+
+        editor.set_helper(Some(Completions {
+            commands: Arc::clone(&self.commands),
+        }));
+
+        if let Some(path) = &self.history_file {
+            // not existing yet is fine; we'll make it
+            if let Err(e) = editor.load_history(path) {
+                debug!("no history loaded from {}: {}", path.display(), e);
+            }
+        }
+
+        editor
+    }
+
+    // If the printer's gone (say the editor's already stopped, because we're
+    // shutting down), we just print, and stop trying it.
+    fn print(&mut self, text: String) {
+        if let Some(printer) = &mut self.printer {
+            match printer.print(text.clone()) {
+                Ok(()) => return,
+                Err(e) => {
+                    debug!("couldn't print above the prompt: {}", e);
+                    self.printer = None;
+                }
+            }
+        }
+
+        println!("{}", text);
+    }
+
+    fn show_choices(&mut self, interactive: Interactive) {
+        let mut values = vec![];
+        let mut lines = vec![];
+
+        for element in interactive.elements {
+            match element {
+                Element::Button { label, value } => {
+                    values.push(value);
+                    lines.push(format!("  [{}] {}", values.len(), label));
+                }
+                Element::Menu {
+                    placeholder,
+                    options,
+                } => {
+                    lines.push(format!("  {}:", placeholder));
+                    for (label, value) in options {
+                        values.push(value);
+                        lines.push(format!("    [{}] {}", values.len(), label));
+                    }
+                }
+            }
+        }
+
+        self.print(lines.join("\n").magenta().to_string());
+
+        self.choices = Some(Choices {
+            callback_id: interactive.callback_id,
+            values,
+        });
+    }
+
+    // If there are choices on offer and this is one of their numbers, that's
+    // a callback; anything else is just text.
+    fn pick_choice(&mut self, text: &str) -> Option<Callback> {
+        let n: usize = text.parse().ok()?;
+        let choices = self.choices.as_ref()?;
+        let value = choices.values.get(n.checked_sub(1)?)?.clone();
+
+        let callback_id = self.choices.take().unwrap().callback_id;
+        Some(Callback { callback_id, value })
+    }
+}
+
+fn save_history(editor: &mut Editor<Completions, DefaultHistory>, path: Option<&PathBuf>) {
+    if let Some(path) = path {
+        if let Err(e) = editor.save_history(path) {
+            warn!("couldn't save history to {}: {}", path.display(), e);
+        }
+    }
+}
+
+// Only the first word is a command, so that's all we complete.
+fn complete(commands: &BTreeSet<String>, line: &str, pos: usize) -> (usize, Vec<String>) {
+    let word = &line[..pos];
+
+    if word.contains(char::is_whitespace) {
+        return (pos, vec![]);
+    }
+
+    let matches = commands
+        .iter()
+        .filter(|c| c.starts_with(word))
+        .cloned()
+        .collect();
+
+    (0, matches)
+}
+
+impl Completer for Completions {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(complete(&self.commands.lock().unwrap(), line, pos))
+    }
+}
+
+impl Hinter for Completions {
+    type Hint = String;
+}
+
+impl Highlighter for Completions {}
+
+impl Validator for Completions {}
+
+impl Helper for Completions {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completion() {
+        let commands: BTreeSet<String> = vec!["clox", "echo", "eject"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        let cases = vec![
+            // line, cursor, start, matches
+            ("", 0, 0, vec!["clox", "echo", "eject"]),
+            ("e", 1, 0, vec!["echo", "eject"]),
+            ("ec", 2, 0, vec!["echo"]),
+            ("echo", 4, 0, vec!["echo"]),
+            ("x", 1, 0, vec![]),
+            ("echo e", 6, 6, vec![]),
+            ("ec hi", 2, 0, vec!["echo"]),
+        ];
+
+        for (line, pos, want_start, want) in cases {
+            let (start, got) = complete(&commands, line, pos);
+            assert_eq!(start, want_start, "start for {:?} at {}", line, pos);
+            assert_eq!(got, want, "matches for {:?} at {}", line, pos);
+        }
+    }
+
+    #[test]
+    fn meta_commands() {
+        let ok = |m| Some(Ok(m));
+
+        let cases = vec![
+            ("clox", None),
+            ("/as alice", ok(Meta::As("alice".into()))),
+            ("/in   #general ", ok(Meta::In("#general".into()))),
+            ("/public", ok(Meta::Public)),
+            ("/private", ok(Meta::Private)),
+            ("/targeted", ok(Meta::Targeted)),
+            ("/untargeted", ok(Meta::Untargeted)),
+        ];
+
+        for (text, want) in cases {
+            assert_eq!(parse_meta(text), want, "{:?}", text);
+        }
+
+        for text in &["/as", "/in", "/dance"] {
+            assert!(matches!(parse_meta(text), Some(Err(_))), "{:?}", text);
         }
     }
 }
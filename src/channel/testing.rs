@@ -0,0 +1,156 @@
+// What every channel's tests need: the channel running on its own thread, the
+// way the hub would have started it, and the hub's ends of its pipes. Each
+// channel's tests put that in a Harness alongside whatever fakes it talks to.
+
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::ops::Deref;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use serde_json::Value;
+use tiny_http::{Header, Request, Response};
+
+use crate::channel::{self, ChannelConfig, Type};
+use crate::message::{Connectivity, Event, Message};
+
+pub struct Running {
+    pub name: String,
+    pub from_channel: mpsc::Receiver<Message>,
+    pub to_channel: mpsc::Sender<Message>,
+    handle: thread::JoinHandle<()>,
+}
+
+// A running channel and its Fakes. It derefs to the channel, so tests can play
+// the hub through it; the fakes are whatever stands in for the rest of the
+// world, and each channel's tests add their own helpers for driving them.
+pub struct Harness<F> {
+    pub channel: Running,
+    pub fakes: F,
+}
+
+pub trait Fakes {
+    // For anything that should happen once the channel's gone, like checking
+    // it said goodbye, or cleaning up after it.
+    fn hung_up(&mut self) {}
+}
+
+pub fn start(
+    name: &str,
+    class: Type,
+    extra: HashMap<String, toml::Value>,
+    state_dbfile: &str,
+) -> Running {
+    let (output, from_channel) = mpsc::channel();
+    let (to_channel, input) = mpsc::channel();
+
+    let handle = channel::build(
+        name.to_string(),
+        ChannelConfig { class, extra },
+        state_dbfile.to_string(),
+        output,
+        input,
+    );
+
+    Running {
+        name: name.to_string(),
+        from_channel,
+        to_channel,
+        handle,
+    }
+}
+
+// Config the way a channel's tests spell it: what the channel always needs
+// to start, plus (or instead) whatever this test wants.
+pub fn config(
+    base: Vec<(&str, toml::Value)>,
+    extra: Vec<(&str, toml::Value)>,
+) -> HashMap<String, toml::Value> {
+    base.into_iter()
+        .chain(extra)
+        .map(|(k, v)| (k.to_string(), v))
+        .collect()
+}
+
+// Somewhere nobody's listening, for a channel to listen on. (Someone else
+// could grab it before the channel does, but nobody does.)
+pub fn free_address() -> String {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string()
+}
+
+// Answer the way a JSON API would, for pretend servers.
+pub fn respond_json<K, V>(request: Request, status: u16, headers: Vec<(K, V)>, body: &Value)
+where
+    K: Into<Vec<u8>> + AsRef<[u8]>,
+    V: Into<Vec<u8>> + AsRef<[u8]>,
+{
+    let mut response = Response::from_string(body.to_string()).with_status_code(status);
+
+    response.add_header(Header::from_bytes("Content-Type", "application/json").unwrap());
+    for (k, v) in headers {
+        response.add_header(Header::from_bytes(k, v).unwrap());
+    }
+
+    let _ = request.respond(response);
+}
+
+impl Running {
+    pub fn next_message(&self) -> Message {
+        self.from_channel
+            .recv_timeout(Duration::from_secs(5))
+            .expect("nothing came out of the channel")
+    }
+
+    pub fn next_event(&self) -> Event {
+        match self.next_message() {
+            Message::Event(e) => e.dupe(),
+            other => panic!("expected an event, got {:?}", other),
+        }
+    }
+
+    pub fn expect_connectivity(&self, want: Connectivity) {
+        match self.next_message() {
+            Message::Connectivity(name, got) if name == self.name && got == want => (),
+            other => panic!("expected {:?}, got {:?}", want, other),
+        }
+    }
+
+    pub fn reply(&self, event: &Event, text: &str) {
+        let reply = event.reply(text, "reactor/test");
+        self.to_channel.send(reply).unwrap();
+    }
+
+    pub fn nothing_happened(&self) {
+        assert!(self
+            .from_channel
+            .recv_timeout(Duration::from_millis(300))
+            .is_err());
+    }
+
+    pub fn hangup(self) {
+        self.to_channel.send(Message::Hangup).unwrap();
+        self.handle.join().unwrap();
+    }
+}
+
+impl<F> Deref for Harness<F> {
+    type Target = Running;
+
+    fn deref(&self) -> &Running {
+        &self.channel
+    }
+}
+
+impl<F: Fakes> Harness<F> {
+    // Hands the fakes back, in case the test wants another look at them.
+    pub fn hangup(mut self) -> F {
+        self.channel.hangup();
+        self.fakes.hung_up();
+        self.fakes
+    }
+}
@@ -28,7 +28,7 @@ pub struct ComponentConfig<T> {
 pub fn new(filename: &str) -> Config {
     let path = Path::new(filename);
 
-    let mut file = match File::open(&path) {
+    let mut file = match File::open(path) {
         Ok(file) => file,
         Err(e) => panic!("couldn't open {}: {:?}", filename, e),
     };
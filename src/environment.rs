@@ -1,32 +1,33 @@
 use std::fmt;
-use std::sync::Arc;
+use std::rc::Rc;
 
 use rusqlite::{Connection, NO_PARAMS};
 
 use crate::config::Config;
 use crate::message::Event;
+use crate::state;
 use crate::user::User;
 use crate::user_directory::Directory;
 
 pub struct Environment {
     pub db: Connection,
-    pub user_directory: Arc<Directory>,
+    pub user_directory: Rc<Directory>,
 }
 
-pub fn new(config: &Config) -> Arc<Environment> {
+pub fn new(config: &Config) -> Rc<Environment> {
     let conn = Connection::open(&config.state_dbfile).expect("Could not open dbfile!");
 
     // make the user directory first, with an empty env (internally). Once we
     // have constructed ourselves with a strong ref to the directory, we'll
     // give the directory a weak ref of ourself.
-    let env = Arc::new(Environment {
+    let env = Rc::new(Environment {
         db: conn,
         user_directory: Directory::new(),
     });
 
     // I am a little surprised this works.
-    let ud = Arc::clone(&env.user_directory);
-    *ud.env.borrow_mut() = Arc::downgrade(&env);
+    let ud = Rc::clone(&env.user_directory);
+    *ud.env.borrow_mut() = Rc::downgrade(&env);
 
     env.maybe_create_state_tables();
     ud.load_users();
@@ -46,20 +47,11 @@ impl fmt::Debug for Environment {
 
 impl Environment {
     pub fn resolve_user(&self, event: &Event) -> Option<User> {
-        self.user_directory.resolve_user(&event)
+        self.user_directory.resolve_user(event)
     }
 
     fn maybe_create_state_tables(&self) {
-        self.db
-            .execute(
-                "CREATE TABLE IF NOT EXISTS synergy_state (\n  \
-                    reactor_name TEXT PRIMARY KEY,\n  \
-                    stored_at INTEGER NOT NULL,\n  \
-                    json TEXT NOT NULL\n\
-                    );",
-                NO_PARAMS,
-            )
-            .unwrap();
+        state::create_table(&self.db).unwrap();
 
         self.db
             .execute(
@@ -1,21 +1,25 @@
 use std::collections::HashMap;
-use std::process;
+use std::rc::Rc;
 use std::sync::{mpsc, Arc};
-use std::thread::JoinHandle;
-use std::time::Duration;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 use crate::channel::{self, ChannelConfig};
 use crate::config::Config;
 use crate::environment::{self, Environment};
-use crate::message::{Event, Message};
+use crate::message::{Connectivity, Event, Message};
 use crate::reactor::{self, ReactorConfig};
 
+// How long we'll wait for everyone to clean up after themselves, at the
+// end. Anybody stuck in the middle of some I/O gets left behind.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
 pub struct Hub {
-    child_handles: Vec<JoinHandle<()>>,
+    child_handles: Vec<(String, JoinHandle<()>)>, // name => its thread
     channel_senders: HashMap<String, mpsc::Sender<Message>>,
-    reactor_senders: Vec<mpsc::Sender<Message>>,
-    reactor_count: u32,
-    env: Option<Arc<Environment>>,
+    channel_connectivity: HashMap<String, Connectivity>,
+    reactor_senders: HashMap<String, mpsc::Sender<Message>>,
+    env: Option<Rc<Environment>>,
 
     // channels, which are useful to have as attributes
     channel_tx: mpsc::Sender<Message>,
@@ -30,9 +34,9 @@ pub fn new() -> Hub {
 
     Hub {
         child_handles: vec![],
-        reactor_senders: vec![],
+        reactor_senders: HashMap::new(),
         channel_senders: HashMap::new(),
-        reactor_count: 0,
+        channel_connectivity: HashMap::new(),
         env: None,
 
         channel_tx,
@@ -45,22 +49,41 @@ pub fn new() -> Hub {
 #[derive(Debug)]
 struct PendingReply {
     count: u32,
+    finished: u32,
+    expected: u32,
     will_respond: bool,
     event: Arc<Event>,
 }
 
 impl Hub {
     pub fn run(&mut self, config: Config) {
+        self.assemble(config);
+        self.listen();
+    }
+
+    pub fn assemble(&mut self, config: Config) {
         info!("assembling hub");
 
         self.env = Some(environment::new(&config));
 
         self.assemble_reactors(config.reactors);
-        self.assemble_channels(config.channels);
+        self.assemble_channels(config.channels, &config.state_dbfile);
+    }
 
-        self.listen();
+    // For something that wants to be a channel without being built from
+    // config, like the scenario runner: it gets the pipes a channel would,
+    // and does what it likes with them.
+    #[cfg(test)]
+    pub fn attach_channel(
+        &mut self,
+        name: &str,
+    ) -> (mpsc::Sender<Message>, mpsc::Receiver<Message>) {
+        let (this_tx, this_rx) = mpsc::channel();
+        self.channel_senders.insert(name.to_string(), this_tx);
+        (self.channel_tx.clone(), this_rx)
     }
 
+    // Runs until something hangs up.
     pub fn listen(&mut self) {
         // id => pending
         let mut pending_replies: HashMap<String, PendingReply> = HashMap::new();
@@ -69,7 +92,7 @@ impl Hub {
             // write, then block on read.
             loop {
                 match self.reactor_rx.try_recv() {
-                    Ok(Message::Hangup) => self.shutdown(),
+                    Ok(Message::Hangup) => return self.shutdown(),
                     Ok(Message::Reply(reply)) => {
                         // figure out the destination, then send it along
                         // debug!("sending reply into channel");
@@ -79,6 +102,14 @@ impl Hub {
                     Ok(Message::Ack(id, this_resp)) => {
                         self.handle_ack(&mut pending_replies, id, this_resp);
                     }
+                    Ok(Message::Done(id)) => {
+                        self.handle_done(&mut pending_replies, id);
+                    }
+                    Ok(Message::Commands(commands)) => {
+                        for tx in self.channel_senders.values() {
+                            tx.send(Message::Commands(commands.clone())).unwrap();
+                        }
+                    }
                     Err(mpsc::TryRecvError::Empty) => break,
                     Err(mpsc::TryRecvError::Disconnected) => {
                         panic!("channel hung up on us??");
@@ -89,27 +120,46 @@ impl Hub {
 
             // duration chosen by fair dice roll.
             match self.channel_rx.recv_timeout(Duration::from_millis(15)) {
-                Ok(Message::Hangup) => self.shutdown(),
+                Ok(Message::Hangup) => return self.shutdown(),
                 Ok(Message::Event(channel_event)) => {
                     let event = self.transmogrify_event(channel_event);
 
+                    // Callbacks only go to the reactor they're for; everything
+                    // else goes to everyone.
+                    let recipients: Vec<_> = match &event.callback {
+                        Some(cb) => self.reactor_senders.get(cb.reactor()).into_iter().collect(),
+                        None => self.reactor_senders.values().collect(),
+                    };
+
+                    if recipients.is_empty() {
+                        warn!("nobody to handle event {:?}; dropping it", event);
+                        continue;
+                    }
+
                     // TODO this should be reference counted instead of cloned.
                     pending_replies.insert(
                         event.id.clone(),
                         PendingReply {
                             count: 0,
+                            finished: 0,
+                            expected: recipients.len() as u32,
                             will_respond: false,
                             event: Arc::clone(&event),
                         },
                     );
 
                     // pass it along into reactors
-                    for tx in &self.reactor_senders {
+                    for tx in recipients {
                         let clone = Arc::clone(&event);
                         tx.send(Message::Event(clone)).unwrap();
                     }
                 }
-                Ok(Message::Ack(_, _)) => panic!("events are not meant to send acks"),
+                Ok(Message::Connectivity(name, state)) => {
+                    self.note_connectivity(name, state);
+                }
+                Ok(Message::Ack(_, _)) | Ok(Message::Done(_)) => {
+                    panic!("events are not meant to send acks")
+                }
                 Err(mpsc::RecvTimeoutError::Disconnected) => panic!("channel hung up on us??"),
                 _ => (),
             }
@@ -127,18 +177,61 @@ impl Hub {
         r.will_respond = this_response || r.will_respond;
 
         // hey, everyone has responded!
-        if r.count == self.reactor_count {
+        if r.count == r.expected {
+            // Let the channel know, in case it's waiting to hear (most don't
+            // care). Any replies will follow.
+            let origin = self.channel_senders.get(&r.event.origin);
+            if let Some(tx) = origin {
+                tx.send(Message::Ack(id.clone(), r.will_respond))
+                    .unwrap_or(());
+            }
+
             // if we were targeted and nobody wanted to respond, say something!
-            if r.event.was_targeted && !r.will_respond {
-                let reply = r.event.reply("Does not compute.", "hub");
-                self.reactor_tx.send(reply).unwrap();
+            // (Unless it's old news: nobody's waiting on an answer to something
+            // we're only just catching up on.) This goes straight to the
+            // channel, so it can't end up behind the Done.
+            if r.event.was_targeted && !r.will_respond && !r.event.is_backfilled {
+                if let Some(tx) = origin {
+                    tx.send(r.event.reply("Does not compute.", "hub"))
+                        .unwrap_or(());
+                }
+            }
+        }
+    }
+
+    // Once every reactor's finished with an event, the channel it came from
+    // has heard everything it's going to about it, replies included.
+    fn handle_done(&self, pending: &mut HashMap<String, PendingReply>, id: String) {
+        let r = pending.get_mut(&id).unwrap();
+        r.finished += 1;
+
+        if r.finished == r.expected {
+            if let Some(tx) = self.channel_senders.get(&r.event.origin) {
+                tx.send(Message::Done(id.clone())).unwrap_or(());
             }
 
             pending.remove(&id);
         }
     }
 
-    fn assemble_channels(&mut self, channel_config: HashMap<String, ChannelConfig>) {
+    fn note_connectivity(&mut self, name: String, state: Connectivity) {
+        let prev = self.channel_connectivity.insert(name.clone(), state);
+
+        if prev == Some(state) {
+            return;
+        }
+
+        match state {
+            Connectivity::Connected => info!("{} is connected", name),
+            Connectivity::Disconnected => warn!("{} has lost its connection", name),
+        }
+    }
+
+    fn assemble_channels(
+        &mut self,
+        channel_config: HashMap<String, ChannelConfig>,
+        state_dbfile: &str,
+    ) {
         for (raw_name, config) in channel_config {
             let name = format!("channel/{}", raw_name);
             info!("starting {}", name);
@@ -151,23 +244,27 @@ impl Hub {
             let (this_tx, this_rx) = mpsc::channel();
             self.channel_senders.insert(name.clone(), this_tx);
 
-            let handle = channel::build(name, config, self.channel_tx.clone(), this_rx);
-            self.child_handles.push(handle);
+            let handle = channel::build(
+                name.clone(),
+                config,
+                state_dbfile.to_string(),
+                self.channel_tx.clone(),
+                this_rx,
+            );
+            self.child_handles.push((name, handle));
         }
     }
 
     fn assemble_reactors(&mut self, reactor_config: HashMap<String, ReactorConfig>) {
         for (raw_name, config) in reactor_config {
-            self.reactor_count += 1;
-
             let name = format!("reactor/{}", raw_name);
             info!("starting {}", name);
 
             let (this_tx, this_rx) = mpsc::channel();
-            self.reactor_senders.push(this_tx);
+            self.reactor_senders.insert(name.clone(), this_tx);
 
-            let handle = reactor::build(name, config, self.reactor_tx.clone(), this_rx);
-            self.child_handles.push(handle);
+            let handle = reactor::build(name.clone(), config, self.reactor_tx.clone(), this_rx);
+            self.child_handles.push((name, handle));
         }
     }
 
@@ -175,7 +272,7 @@ impl Hub {
         // we ignore all errors here, because presumably they're just because
         // something has already hung up on us.
         info!("telling reactors to shut down...");
-        for tx in self.reactor_senders.drain(..) {
+        for (_, tx) in self.reactor_senders.drain() {
             tx.send(Message::Hangup).unwrap_or(());
         }
 
@@ -185,15 +282,40 @@ impl Hub {
         }
 
         info!("waiting for cleanup...");
-        for handle in self.child_handles.drain(..) {
-            handle.join().unwrap_or(());
+        let deadline = Instant::now() + SHUTDOWN_GRACE;
+
+        while Instant::now() < deadline {
+            let (done, waiting): (Vec<_>, Vec<_>) = self
+                .child_handles
+                .drain(..)
+                .partition(|(_, handle)| handle.is_finished());
+
+            for (_, handle) in done {
+                handle.join().unwrap_or(());
+            }
+
+            self.child_handles = waiting;
+            if self.child_handles.is_empty() {
+                break;
+            }
+
+            thread::sleep(Duration::from_millis(15));
+        }
+
+        for (name, _) in self.child_handles.drain(..) {
+            warn!("{} didn't stop in time; leaving it behind", name);
         }
 
         info!("goodbye!");
-        process::exit(0);
     }
 
+    // Channels that can vouch for who someone is (like the admin socket)
+    // will have said so already; for everyone else, we look them up.
     fn transmogrify_event(&self, orig: Arc<Event>) -> Arc<Event> {
+        if orig.user.is_some() {
+            return orig;
+        }
+
         let user = self.env.as_ref().unwrap().resolve_user(&orig);
         let mut event = orig.dupe(); // silly, but ok
         event.user = user;
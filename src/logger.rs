@@ -12,7 +12,7 @@ pub fn init() {
 
     // synergy_log will only apply to our module
     if let Ok(ref level) = std::env::var("SYNERGY_LOG") {
-        let level = if level == "" { "info" } else { level };
+        let level = if level.is_empty() { "info" } else { level };
         logger.parse_filters(&format!("synergy_rust={}", level));
     }
 
@@ -11,6 +11,9 @@ mod hub;
 mod logger;
 mod message;
 mod reactor;
+#[cfg(test)]
+mod scenario;
+mod state;
 mod user;
 mod user_directory;
 
@@ -31,7 +34,7 @@ fn main() {
     let args: Vec<String> = env::args().skip(1).collect();
     let matches = match opt.parse(&args) {
         Ok(m) => m,
-        Err(f) => panic!(f.to_string()),
+        Err(f) => panic!("{}", f),
     };
 
     if matches.opt_present("help") {
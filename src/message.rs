@@ -1,16 +1,33 @@
+use std::error::Error;
+use std::fmt;
 use std::sync::Arc;
+
+use serde::Deserialize;
 use uuid::Uuid;
 
 use crate::user::User;
 
+// Replies are most of what goes by here, so there's no point boxing them.
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum Message {
     Event(Arc<Event>),
     Reply(Reply),
     Ack(String, bool),
+    Done(String), // a reactor's said all it's going to about an event
+    Connectivity(String, Connectivity),
+    Commands(Vec<String>), // what a reactor responds to, passed along to channels
     Hangup,
 }
 
+// Channels that talk to the outside world tell the hub when they lose (and
+// regain) their connection, so that it has some idea of what's going on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Connectivity {
+    Connected,
+    Disconnected,
+}
+
 // FIXME all these names are terrible.
 
 #[derive(Debug)]
@@ -23,18 +40,119 @@ pub struct Event {
     pub origin: String,
     pub user: Option<User>,
     pub id: String,
+    pub can_reply_privately: bool, // does origin know how to reach from_address alone?
+    pub workspace: Option<String>, // e.g. slack team id, if origin has such a thing
+    pub callback: Option<Callback>, // someone used a button or menu we sent
+    pub is_backfilled: bool,       // caught up on after a disconnect, so maybe stale
+    pub is_slash_command: bool,    // e.g. /synergy clox on slack
+    pub response_url: Option<String>, // where replies to a slash command go
+    pub is_unverified: bool, // origin can't vouch for from_address, so it's nobody in particular
 }
 
 // I think eventually, I want some sort of unique identifier per [channel]event,
 // and then set reply_to here, so that we can keep track of things that don't
 // get replies (maybe).
 #[derive(Debug, Clone)]
+#[allow(dead_code)]
 pub struct Reply {
     pub text: String,
     pub from_address: String,
     pub conversation_address: String,
     pub origin: String,
     pub destination: String,
+    pub is_private: bool, // goes to from_address alone, wherever that is
+    pub interactive: Option<Interactive>,
+    pub persona: Option<Persona>, // how the reactor would like to look, if the channel can do that
+    pub is_ephemeral: bool,       // only from_address sees it, but it shows up in the conversation
+    pub response_url: Option<String>, // from the event, if it was a slash command
+}
+
+// Some channels (so far, just slack) let a bot post under a different name and
+// icon, which makes it easier to tell which reactor is talking. Reactors get
+// theirs from a persona table in their config.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Persona {
+    pub username: Option<String>,
+    pub icon_emoji: Option<String>,
+}
+
+// Buttons and menus to go along with a reply. When someone picks one, the
+// channel sends an Event with a Callback, and the hub hands it to the reactor
+// that sent the reply (and only that one). The callback id is how it knows
+// which one that was, so don't make your own: use Interactive::new.
+#[derive(Debug, Clone)]
+pub struct Interactive {
+    pub callback_id: String,
+    pub elements: Vec<Element>,
+}
+
+// only the test reactor hands these out, so far
+#[derive(Debug, Clone)]
+#[cfg_attr(not(test), allow(dead_code))]
+pub enum Element {
+    Button {
+        label: String,
+        value: String,
+    },
+    Menu {
+        placeholder: String,
+        options: Vec<(String, String)>, // label, value
+    },
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(not(test), allow(dead_code))]
+pub struct Callback {
+    pub callback_id: String,
+    pub value: String,
+}
+
+impl Interactive {
+    pub fn new(reactor: &str, elements: Vec<Element>) -> Interactive {
+        Interactive {
+            callback_id: format!("{}:{}", reactor, Uuid::new_v4()),
+            elements,
+        }
+    }
+}
+
+impl Callback {
+    // the reactor this belongs to
+    pub fn reactor(&self) -> &str {
+        self.callback_id.split(':').next().unwrap_or_default()
+    }
+}
+
+// What you get for asking for a private reply on a channel that doesn't have
+// such a thing.
+#[derive(Debug)]
+pub struct NoPrivateReplies(pub String);
+
+impl Error for NoPrivateReplies {}
+
+impl fmt::Display for NoPrivateReplies {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} can't send private replies", self.0)
+    }
+}
+
+impl Reply {
+    // A reply to nobody in particular: destination is a channel name (like
+    // "channel/slack") and conversation is wherever in that channel it goes.
+    pub fn new(destination: &str, conversation: &str, text: &str, origin: &str) -> Reply {
+        Reply {
+            text: text.to_string(),
+            from_address: String::new(),
+            conversation_address: conversation.to_string(),
+            origin: origin.to_string(),
+            destination: destination.to_string(),
+            is_private: false,
+            interactive: None,
+            persona: None,
+            is_ephemeral: false,
+            response_url: None,
+        }
+    }
 }
 
 impl Event {
@@ -42,14 +160,82 @@ impl Event {
         format!("{}", Uuid::new_v4())
     }
 
-    pub fn reply(&self, text: &str, origin: &str) -> Message {
-        Message::Reply(Reply {
+    // Something someone said, with nothing special about it: not public, not
+    // for us, and none of the extras. Channels fill in the rest, like:
+    //
+    //   Event {
+    //       was_targeted: true,
+    //       ..Event::new(&self.name, from, conversation, text)
+    //   }
+    pub fn new(origin: &str, from: &str, conversation: &str, text: &str) -> Event {
+        Event {
             text: text.to_string(),
-            from_address: self.from_address.clone(),
-            conversation_address: self.conversation_address.clone(),
+            is_public: false,
+            was_targeted: false,
+            from_address: from.to_string(),
+            conversation_address: conversation.to_string(),
             origin: origin.to_string(),
-            destination: self.origin.clone(),
-        })
+            user: None,
+            id: Event::new_id(),
+            can_reply_privately: true,
+            workspace: None,
+            callback: None,
+            is_backfilled: false,
+            is_slash_command: false,
+            response_url: None,
+            is_unverified: false,
+        }
+    }
+
+    pub fn reply(&self, text: &str, origin: &str) -> Message {
+        self.reply_in(&self.conversation_address, text, origin)
+    }
+
+    // Like reply(), but into some other conversation on the same channel. On
+    // channels that know about names (like slack), that can be "#name".
+    pub fn reply_in(&self, conversation: &str, text: &str, origin: &str) -> Message {
+        Message::Reply(self.make_reply(conversation, text, origin))
+    }
+
+    // A reply with buttons or menus; see Interactive.
+    pub fn interactive_reply(&self, text: &str, origin: &str, interactive: Interactive) -> Message {
+        let mut reply = self.make_reply(&self.conversation_address, text, origin);
+        reply.interactive = Some(interactive);
+        Message::Reply(reply)
+    }
+
+    fn make_reply(&self, conversation: &str, text: &str, origin: &str) -> Reply {
+        let mut reply = Reply::new(&self.origin, conversation, text, origin);
+        reply.from_address = self.from_address.clone();
+
+        // the response url only knows how to get back to where it came from
+        if conversation == self.conversation_address {
+            reply.response_url = self.response_url.clone();
+        }
+
+        reply
+    }
+
+    // A reply only the sender can see, right there in the conversation (on
+    // slack, an ephemeral message). Channels that can't do that treat it like
+    // a private reply.
+    pub fn ephemeral_reply(&self, text: &str, origin: &str) -> Message {
+        let mut reply = self.make_reply(&self.conversation_address, text, origin);
+        reply.is_ephemeral = true;
+        Message::Reply(reply)
+    }
+
+    // A reply just for the person who sent this, even if they said it in
+    // public. The channel works out where that actually goes (on slack, it's
+    // a DM); if it can't, you get an error and can decide what to do instead.
+    pub fn private_reply(&self, text: &str, origin: &str) -> Result<Message, NoPrivateReplies> {
+        if !self.can_reply_privately {
+            return Err(NoPrivateReplies(self.origin.clone()));
+        }
+
+        let mut reply = self.make_reply(&self.conversation_address, text, origin);
+        reply.is_private = true;
+        Ok(Message::Reply(reply))
     }
 
     pub fn dupe(&self) -> Self {
@@ -62,6 +248,13 @@ impl Event {
             origin: self.origin.clone(),
             user: self.user.clone(),
             id: self.id.clone(),
+            can_reply_privately: self.can_reply_privately,
+            workspace: self.workspace.clone(),
+            callback: self.callback.clone(),
+            is_backfilled: self.is_backfilled,
+            is_slash_command: self.is_slash_command,
+            response_url: self.response_url.clone(),
+            is_unverified: self.is_unverified,
         }
     }
 }
@@ -4,7 +4,7 @@ use chrono::{FixedOffset, Timelike, Utc};
 use chrono_tz::Tz;
 
 use crate::message::Event;
-use crate::reactor::{Core, Handler, Reactor, Seed};
+use crate::reactor::{self, Core, Handler, Reactor, Seed};
 
 pub struct Clox {
     core: Core<Dispatch>,
@@ -24,10 +24,13 @@ pub fn build(seed: Seed) -> thread::JoinHandle<()> {
 pub fn new(seed: Seed) -> Clox {
     let core = Core {
         name: seed.name.clone(),
+        persona: reactor::persona(&seed.config),
         output: seed.output,
         input: seed.input,
         handlers: vec![Handler {
-            predicate: |event| event.text.starts_with("clox"),
+            // if we missed it, the time's already wrong
+            predicate: |event| !event.is_backfilled && event.text.starts_with("clox"),
+            commands: &["clox"],
             require_targeted: true,
             will_respond: true,
             key: Dispatch::HandleClox,
@@ -46,7 +49,7 @@ impl Reactor for Clox {
 
     fn dispatch(&self, key: &Dispatch, event: &Event) {
         match key {
-            Dispatch::HandleClox => self.handle_clox(&event),
+            Dispatch::HandleClox => self.handle_clox(event),
         };
     }
 }
@@ -102,6 +105,6 @@ impl Clox {
             ));
         }
 
-        self.reply_to(&event, &text);
+        self.reply_to(event, &text);
     }
 }
@@ -1,7 +1,7 @@
 use std::thread;
 
 use crate::message::Event;
-use crate::reactor::{Core, Handler, Reactor, Seed};
+use crate::reactor::{self, Core, Handler, Reactor, Seed};
 
 pub struct Echo {
     core: Core<Dispatch>,
@@ -21,11 +21,13 @@ pub fn build(seed: Seed) -> thread::JoinHandle<()> {
 pub fn new(seed: Seed) -> Echo {
     let core = Core {
         name: seed.name.clone(),
+        persona: reactor::persona(&seed.config),
         output: seed.output,
         input: seed.input,
         handlers: vec![Handler {
             require_targeted: true,
             predicate: |e| e.text.starts_with("echo"),
+            commands: &["echo"],
             will_respond: true,
             key: Dispatch::HandleEcho,
         }],
@@ -43,7 +45,7 @@ impl Reactor for Echo {
 
     fn dispatch(&self, key: &Dispatch, event: &Event) {
         match key {
-            Dispatch::HandleEcho => self.handle_echo(&event),
+            Dispatch::HandleEcho => self.handle_echo(event),
         };
     }
 }
@@ -56,6 +58,6 @@ impl Echo {
         };
 
         let text = format!("I heard {} say {}", who, event.text);
-        self.reply_to(&event, &text);
+        self.reply_to(event, &text);
     }
 }
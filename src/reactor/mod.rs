@@ -1,5 +1,7 @@
 pub mod clox;
 pub mod echo;
+#[cfg(test)]
+pub mod testing;
 
 use std::sync::mpsc;
 use std::thread;
@@ -7,17 +9,21 @@ use std::thread;
 use serde::Deserialize;
 
 use crate::config::ComponentConfig;
-use crate::message::{Event, Message};
+use crate::message::{Element, Event, Interactive, Message, NoPrivateReplies, Persona, Reply};
 
-// known reactors
+// known reactors (these are the names in config files)
+#[allow(clippy::enum_variant_names)]
 #[derive(Deserialize, Debug)]
 pub enum Type {
     EchoReactor,
     CloxReactor,
+    #[cfg(test)]
+    TestReactor,
 }
 
 pub type ReactorConfig = ComponentConfig<Type>;
 
+#[allow(dead_code)]
 pub struct Seed {
     pub name: String,
     pub config: ReactorConfig,
@@ -34,6 +40,8 @@ pub fn build(
     let builder = match config.class {
         Type::EchoReactor => echo::build,
         Type::CloxReactor => clox::build,
+        #[cfg(test)]
+        Type::TestReactor => testing::build,
     };
 
     let seed = Seed {
@@ -46,9 +54,25 @@ pub fn build(
     builder(seed)
 }
 
+// A reactor's persona comes from its config, like:
+//
+//   [reactors.clox]
+//   class = "CloxReactor"
+//   persona = { username = "clox", icon_emoji = ":clock3:" }
+//
+// Either part can be left out.
+pub fn persona(config: &ReactorConfig) -> Option<Persona> {
+    config.extra.get("persona").map(|v| {
+        v.clone()
+            .try_into()
+            .unwrap_or_else(|e| panic!("bad persona in reactor config: {}", e))
+    })
+}
+
 // Is this abstraction _just_ for the pun? Not quite!
 pub struct Core<D> {
     name: String,
+    persona: Option<Persona>,
     output: mpsc::Sender<Message>,
     input: mpsc::Receiver<Message>,
     handlers: Vec<Handler<D>>,
@@ -56,6 +80,7 @@ pub struct Core<D> {
 
 pub struct Handler<T> {
     predicate: fn(&Event) -> bool,
+    commands: &'static [&'static str], // words it answers to, for channels to offer
     require_targeted: bool,
     will_respond: bool,
     key: T,
@@ -92,6 +117,8 @@ pub trait Reactor {
     fn dispatch(&self, key: &Self::Dispatcher, event: &Event);
 
     fn start(&mut self) {
+        self.announce_commands();
+
         for reactor_event in self.core().input_channel() {
             match reactor_event {
                 Message::Hangup => break,
@@ -102,6 +129,15 @@ pub trait Reactor {
     }
 
     fn dispatch_event(&self, event: &Event) {
+        // The hub only sends us callbacks for things we sent, so they don't
+        // go through the handlers at all.
+        if event.callback.is_some() {
+            let handled = self.handle_callback(event);
+            self.ack(&event.id, handled);
+            self.done(&event.id);
+            return;
+        }
+
         let mut matched_keys = vec![];
         let mut will_respond = false;
 
@@ -114,7 +150,7 @@ pub trait Reactor {
                 continue;
             }
 
-            if handler.matches(&event) {
+            if handler.matches(event) {
                 matched_keys.push(&handler.key);
                 if handler.will_respond {
                     will_respond = true;
@@ -126,11 +162,32 @@ pub trait Reactor {
 
         // now dispatch
         for key in &matched_keys {
-            self.dispatch(&key, &event);
+            self.dispatch(key, event);
+        }
+
+        self.done(&event.id);
+    }
+
+    // Let the hub know what we respond to, so it can tell the channels.
+    fn announce_commands(&self) {
+        let commands: Vec<String> = self
+            .core()
+            .handlers()
+            .iter()
+            .flat_map(|h| h.commands.iter())
+            .map(|c| c.to_string())
+            .collect();
+
+        if !commands.is_empty() {
+            self.send_reply_to_hub(Message::Commands(commands));
         }
     }
 
-    fn send_reply_to_hub(&self, msg: Message) {
+    fn send_reply_to_hub(&self, mut msg: Message) {
+        if let Message::Reply(reply) = &mut msg {
+            reply.persona = self.core().persona.clone();
+        }
+
         self.core().output_channel().send(msg).unwrap();
     }
 
@@ -138,8 +195,62 @@ pub trait Reactor {
         self.send_reply_to_hub(Message::Ack(String::from(id), will_respond));
     }
 
+    // Anything a handler had to say, it's said by the time it returns, so
+    // whoever's waiting on replies can stop.
+    fn done(&self, id: &str) {
+        self.send_reply_to_hub(Message::Done(String::from(id)));
+    }
+
     fn reply_to(&self, event: &Event, text: &str) {
-        let reply = event.reply(text, self.core().name());
+        self.reply_in(event, &event.conversation_address, text);
+    }
+
+    fn reply_in(&self, event: &Event, conversation: &str, text: &str) {
+        let reply = event.reply_in(conversation, text, self.core().name());
         self.send_reply_to_hub(reply);
     }
+
+    // For things that shouldn't be said in public. If the event came from
+    // somewhere without private replies, it's up to you what to do instead.
+    #[cfg_attr(not(test), allow(dead_code))]
+    fn reply_privately(&self, event: &Event, text: &str) -> Result<(), NoPrivateReplies> {
+        let reply = event.private_reply(text, self.core().name())?;
+        self.send_reply_to_hub(reply);
+        Ok(())
+    }
+
+    // For things only the asker needs to see, but that belong where they
+    // asked (like the answer to a slash command nobody else cares about).
+    #[cfg_attr(not(test), allow(dead_code))]
+    fn reply_ephemerally(&self, event: &Event, text: &str) {
+        let reply = event.ephemeral_reply(text, self.core().name());
+        self.send_reply_to_hub(reply);
+    }
+
+    // Reply with some buttons or menus attached. When someone uses one,
+    // handle_callback gets an event whose callback has the id we return here
+    // (so hang onto it if you care which reply it was).
+    #[cfg_attr(not(test), allow(dead_code))]
+    fn reply_with_choices(&self, event: &Event, text: &str, elements: Vec<Element>) -> String {
+        let interactive = Interactive::new(self.core().name(), elements);
+        let callback_id = interactive.callback_id.clone();
+
+        let reply = event.interactive_reply(text, self.core().name(), interactive);
+        self.send_reply_to_hub(reply);
+
+        callback_id
+    }
+
+    // Someone used a button or menu from reply_with_choices. Return whether
+    // you did anything about it.
+    fn handle_callback(&self, _event: &Event) -> bool {
+        false
+    }
+
+    // Say something on a channel without being asked.
+    #[cfg_attr(not(test), allow(dead_code))]
+    fn announce(&self, channel: &str, conversation: &str, text: &str) {
+        let reply = Reply::new(channel, conversation, text, self.core().name());
+        self.send_reply_to_hub(Message::Reply(reply));
+    }
 }
@@ -0,0 +1,148 @@
+// A reactor for scenarios, which does the things no real reactor does yet:
+// whispering, offering choices, and speaking up unprompted. It only exists
+// in tests.
+
+use std::thread;
+
+use crate::message::{Element, Event};
+use crate::reactor::{Core, Handler, Reactor, Seed};
+
+pub struct Tester {
+    core: Core<Dispatch>,
+}
+
+// named like every other reactor's, which clippy doesn't care for
+#[allow(clippy::enum_variant_names)]
+pub enum Dispatch {
+    HandleWhisper,
+    HandleChoose,
+    HandleAnnounce,
+}
+
+// Past this many, buttons get unwieldy, so it's a menu instead.
+const MAX_BUTTONS: usize = 3;
+
+pub fn build(seed: Seed) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut reactor = self::new(seed);
+        reactor.start();
+    })
+}
+
+pub fn new(seed: Seed) -> Tester {
+    let core = Core {
+        name: seed.name.clone(),
+        persona: None,
+        output: seed.output,
+        input: seed.input,
+        handlers: vec![
+            Handler {
+                require_targeted: true,
+                predicate: |e| e.text.starts_with("whisper "),
+                commands: &["whisper"],
+                will_respond: true,
+                key: Dispatch::HandleWhisper,
+            },
+            Handler {
+                require_targeted: true,
+                predicate: |e| e.text.starts_with("choose "),
+                commands: &["choose"],
+                will_respond: true,
+                key: Dispatch::HandleChoose,
+            },
+            Handler {
+                require_targeted: true,
+                predicate: |e| e.text.starts_with("announce "),
+                commands: &["announce"],
+                will_respond: true,
+                key: Dispatch::HandleAnnounce,
+            },
+        ],
+    };
+
+    Tester { core }
+}
+
+impl Reactor for Tester {
+    type Dispatcher = Dispatch;
+
+    fn core(&self) -> &Core<Dispatch> {
+        &self.core
+    }
+
+    fn dispatch(&self, key: &Dispatch, event: &Event) {
+        match key {
+            Dispatch::HandleWhisper => self.handle_whisper(event),
+            Dispatch::HandleChoose => self.handle_choose(event),
+            Dispatch::HandleAnnounce => self.handle_announce(event),
+        };
+    }
+
+    // Only the one who chose needs to know we heard.
+    fn handle_callback(&self, event: &Event) -> bool {
+        let callback = match &event.callback {
+            Some(cb) => cb,
+            None => return false,
+        };
+
+        let text = format!("You picked {}.", callback.value);
+        self.reply_ephemerally(event, &text);
+        true
+    }
+}
+
+impl Tester {
+    pub fn handle_whisper(&self, event: &Event) {
+        let text = event.text.trim_start_matches("whisper ").trim();
+        let reply = format!("I heard you whisper {}", text);
+
+        if self.reply_privately(event, &reply).is_err() {
+            self.reply_to(event, "I can't whisper back here.");
+        }
+    }
+
+    // "choose tea, coffee" offers them as buttons (or a menu, if there are
+    // lots); see handle_callback for what happens next.
+    pub fn handle_choose(&self, event: &Event) {
+        let options: Vec<String> = event
+            .text
+            .trim_start_matches("choose ")
+            .split(',')
+            .map(|o| o.trim().to_string())
+            .filter(|o| !o.is_empty())
+            .collect();
+
+        if options.is_empty() {
+            self.reply_to(event, "Choose from what?");
+            return;
+        }
+
+        let elements = if options.len() <= MAX_BUTTONS {
+            options
+                .into_iter()
+                .map(|o| Element::Button {
+                    label: o.clone(),
+                    value: o,
+                })
+                .collect()
+        } else {
+            vec![Element::Menu {
+                placeholder: "Pick one".to_string(),
+                options: options.into_iter().map(|o| (o.clone(), o)).collect(),
+            }]
+        };
+
+        self.reply_with_choices(event, "Which one?", elements);
+    }
+
+    // "announce #elsewhere hello" says hello in #elsewhere, as if nobody
+    // asked.
+    pub fn handle_announce(&self, event: &Event) {
+        let rest = event.text.trim_start_matches("announce ");
+
+        match rest.split_once(' ') {
+            Some((conversation, text)) => self.announce(&event.origin, conversation, text),
+            None => self.reply_to(event, "Announce what, where?"),
+        }
+    }
+}
@@ -0,0 +1,485 @@
+// Behaviour tests, written as transcripts. Each file in scenarios/ boots a real
+// hub with the reactors from its config, pretends to be a channel, says
+// things, and checks what comes back. A transcript looks like this:
+//
+//   [reactors.echo]
+//   class = "EchoReactor"
+//
+//   [reactors.tester]
+//   class = "TestReactor"
+//   ---
+//   # comments start with a #, right at the start of the line
+//   alice in #general says: echo hi
+//   > I heard someone say echo hi
+//
+//   alice in #general says aside: nobody asked you
+//
+//   bob in dm says: clox
+//   ~ In Internet Time™ it's .*
+//   | 🇺🇸 .*
+//
+//   bob in dm says: choose tea, coffee
+//   > Which one?
+//   | [tea] [coffee]
+//
+//   bob in dm picks: coffee
+//   > [ephemeral] You picked coffee.
+//
+// Everything above the --- is config (reactors, mostly; the channel and state
+// db are taken care of). "says:" is addressed to us, and "says aside:" isn't.
+// "picks:" uses a button or menu from the last reply that had any.
+// Conversations starting with # are public.
+//
+// After each thing someone says come the replies we expect, in any order. A >
+// line has to match exactly, and a ~ line is a regex that has to match the
+// whole thing; either can go on for more lines with |. Replies that go
+// somewhere else start with where, like "[private] " or "[in #other] ", and
+// any buttons or menus come after the text, on a line of their own.
+
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+
+use crate::config::Config;
+use crate::hub;
+use crate::message::{Callback, Element, Event, Message, Reply};
+
+const CHANNEL_NAME: &str = "channel/scenario";
+
+// How long we'll wait for replies we're expecting, and then how long we wait
+// after that to make sure nothing else turns up.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+const SETTLE_TIME: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+struct Scenario {
+    config: String,
+    steps: Vec<Step>,
+}
+
+#[derive(Debug)]
+struct Step {
+    line: String, // as written, for the diff
+    from: String,
+    conversation: String,
+    was_targeted: bool,
+    is_pick: bool,
+    text: String,
+    expected: Vec<Expected>,
+}
+
+#[derive(Debug)]
+enum Expected {
+    Exactly(String),
+    Matching(String),
+}
+
+impl Expected {
+    fn matches(&self, actual: &str) -> bool {
+        match self {
+            Expected::Exactly(s) => s == actual,
+            Expected::Matching(pattern) => Regex::new(&format!("^(?s:{})$", pattern))
+                .map(|re| re.is_match(actual))
+                .unwrap_or(false),
+        }
+    }
+
+    fn render(&self) -> String {
+        match self {
+            Expected::Exactly(s) => render_lines(">", s),
+            Expected::Matching(s) => render_lines("~", s),
+        }
+    }
+}
+
+// "> first line\n| second line"
+fn render_lines(marker: &str, text: &str) -> String {
+    let mut lines = text.lines();
+    let mut out = format!("{} {}", marker, lines.next().unwrap_or_default());
+
+    for line in lines {
+        out.push_str(&format!("\n| {}", line));
+    }
+
+    out
+}
+
+fn parse(source: &str) -> Result<Scenario, String> {
+    lazy_static! {
+        static ref EVENT_RE: Regex =
+            Regex::new(r"^(\S+) in (\S+) (says( aside)?|picks): (.*)$").unwrap();
+    }
+
+    let (config, script) = match source.find("\n---\n") {
+        Some(i) => (&source[..i], &source[i + 5..]),
+        None => ("", source),
+    };
+
+    let mut steps: Vec<Step> = vec![];
+
+    for (n, line) in script.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let err = |what: &str| Err(format!("line {} of script: {}: {:?}", n + 1, what, line));
+
+        if let Some(caps) = EVENT_RE.captures(line) {
+            steps.push(Step {
+                line: line.to_string(),
+                from: caps[1].to_string(),
+                conversation: caps[2].to_string(),
+                was_targeted: caps.get(4).is_none(),
+                is_pick: &caps[3] == "picks",
+                text: caps[5].to_string(),
+                expected: vec![],
+            });
+            continue;
+        }
+
+        let step = match steps.last_mut() {
+            Some(step) => step,
+            None => return err("replies need something to reply to"),
+        };
+
+        let (marker, rest) = line.split_at(line.len().min(2));
+
+        match marker {
+            "> " => step.expected.push(Expected::Exactly(rest.to_string())),
+            "~ " => {
+                if let Err(e) = Regex::new(rest) {
+                    return err(&format!("bad pattern ({})", e));
+                }
+                step.expected.push(Expected::Matching(rest.to_string()));
+            }
+            "| " => match step.expected.last_mut() {
+                Some(Expected::Exactly(s)) | Some(Expected::Matching(s)) => {
+                    s.push('\n');
+                    s.push_str(rest);
+                }
+                None => return err("nothing to continue"),
+            },
+            _ => return err("don't know what to do with this"),
+        }
+    }
+
+    Ok(Scenario {
+        config: config.to_string(),
+        steps,
+    })
+}
+
+// How a reply shows up in a transcript: just the text if it went back where
+// it came from, or with a note about where it went if not.
+fn describe(reply: &Reply, conversation: &str) -> String {
+    let mut out = if reply.is_private {
+        format!("[private] {}", reply.text)
+    } else if reply.is_ephemeral {
+        format!("[ephemeral] {}", reply.text)
+    } else if reply.conversation_address != conversation {
+        format!("[in {}] {}", reply.conversation_address, reply.text)
+    } else {
+        reply.text.clone()
+    };
+
+    if let Some(interactive) = &reply.interactive {
+        let choices: Vec<_> = interactive.elements.iter().map(describe_element).collect();
+        out.push('\n');
+        out.push_str(&choices.join(" "));
+    }
+
+    out
+}
+
+// "[label]" for a button, "[placeholder: one / two]" for a menu
+fn describe_element(element: &Element) -> String {
+    match element {
+        Element::Button { label, .. } => format!("[{}]", label),
+        Element::Menu {
+            placeholder,
+            options,
+        } => {
+            let labels: Vec<_> = options.iter().map(|(label, _)| label.as_str()).collect();
+            format!("[{}: {}]", placeholder, labels.join(" / "))
+        }
+    }
+}
+
+// The channel end of a running hub.
+struct Harness {
+    to_hub: mpsc::Sender<Message>,
+    from_hub: mpsc::Receiver<Message>,
+    hub: thread::JoinHandle<()>,
+    state_dbfile: String,
+    callback_id: Option<String>, // from the last reply with choices
+}
+
+fn start(scenario: &Scenario) -> Harness {
+    let state_dbfile = std::env::temp_dir()
+        .join(format!("synergy-scenario-{}.sqlite", Event::new_id()))
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    let source = format!(
+        "state_dbfile = {:?}\n[channels]\n[reactors]\n{}",
+        state_dbfile, scenario.config
+    );
+    let config: Config = toml::from_str(&source).expect("bad scenario config");
+
+    // The hub can't leave the thread it's made in, so we make it there and
+    // get our pipes back out.
+    let (pipes_tx, pipes_rx) = mpsc::channel();
+
+    let hub = thread::spawn(move || {
+        let mut hub = hub::new();
+        hub.assemble(config);
+        pipes_tx.send(hub.attach_channel(CHANNEL_NAME)).unwrap();
+        hub.listen();
+    });
+
+    let (to_hub, from_hub) = pipes_rx.recv().unwrap();
+
+    Harness {
+        to_hub,
+        from_hub,
+        hub,
+        state_dbfile,
+        callback_id: None,
+    }
+}
+
+impl Harness {
+    // Say something, and collect whatever comes back. Picking when nothing's
+    // been offered is a mistake in the transcript, not something to wait for
+    // an answer to (the hub wouldn't know who to give it to).
+    fn run_step(&mut self, step: &Step) -> Result<Vec<String>, String> {
+        let callback = if step.is_pick {
+            let callback_id = self
+                .callback_id
+                .clone()
+                .ok_or("picks: but no earlier reply offered choices")?;
+
+            Some(Callback {
+                callback_id,
+                value: step.text.clone(),
+            })
+        } else {
+            None
+        };
+
+        let event = Event {
+            is_public: step.conversation.starts_with('#'),
+            was_targeted: step.was_targeted || step.is_pick,
+            callback,
+            ..Event::new(CHANNEL_NAME, &step.from, &step.conversation, &step.text)
+        };
+
+        let id = event.id.clone();
+        self.to_hub.send(Message::Event(event.into())).unwrap();
+
+        let mut replies = vec![];
+        let mut acked = false;
+        let deadline = Instant::now() + REPLY_TIMEOUT;
+
+        // Wait until everyone's acked and we have all the replies we're
+        // expecting (or we give up), and then a little longer, in case
+        // there's more we weren't.
+        loop {
+            let done = acked && replies.len() >= step.expected.len();
+            let timeout = if done {
+                SETTLE_TIME
+            } else {
+                deadline.saturating_duration_since(Instant::now())
+            };
+
+            match self.from_hub.recv_timeout(timeout) {
+                Ok(Message::Reply(reply)) => {
+                    if let Some(interactive) = &reply.interactive {
+                        self.callback_id = Some(interactive.callback_id.clone());
+                    }
+                    replies.push(describe(&reply, &step.conversation));
+                }
+                Ok(Message::Ack(ack_id, _)) if ack_id == id => acked = true,
+                Ok(_) => (),
+                Err(_) => break,
+            }
+        }
+
+        if !acked {
+            replies.push("(the hub never finished with this)".to_string());
+        }
+
+        Ok(replies)
+    }
+
+    fn finish(self) {
+        self.to_hub.send(Message::Hangup).unwrap();
+        self.hub.join().unwrap();
+        let _ = fs::remove_file(&self.state_dbfile);
+    }
+}
+
+// Line up what we got against what we wanted. Replies can come in any order,
+// so each expectation takes the first reply it matches; anything missing or
+// left over shows up in the diff.
+fn compare(step: &Step, actual: Vec<String>) -> (bool, Vec<String>) {
+    let mut lines = vec![format!("  {}", step.line)];
+    let mut leftover: Vec<Option<String>> = actual.into_iter().map(Some).collect();
+    let mut ok = true;
+
+    for expected in &step.expected {
+        let found = leftover
+            .iter_mut()
+            .find(|a| a.as_ref().is_some_and(|a| expected.matches(a)));
+
+        match found {
+            Some(reply) => {
+                reply.take();
+                lines.extend(prefixed("  ", &expected.render()));
+            }
+            None => {
+                ok = false;
+                lines.extend(prefixed("- ", &expected.render()));
+            }
+        }
+    }
+
+    for reply in leftover.into_iter().flatten() {
+        ok = false;
+        lines.extend(prefixed("+ ", &render_lines(">", &reply)));
+    }
+
+    (ok, lines)
+}
+
+fn prefixed(prefix: &str, text: &str) -> Vec<String> {
+    text.lines().map(|l| format!("{}{}", prefix, l)).collect()
+}
+
+// Ok, or the whole transcript with what went wrong marked up, diff-style: -
+// for replies we wanted and didn't get, + for ones we got and didn't want.
+fn run(source: &str) -> Result<(), String> {
+    let scenario = parse(source)?;
+    let mut harness = start(&scenario);
+
+    let mut ok = true;
+    let mut transcript = vec![];
+
+    for step in &scenario.steps {
+        let actual = match harness.run_step(step) {
+            Ok(actual) => actual,
+            Err(e) => {
+                harness.finish();
+                transcript.push(format!("  {}", step.line));
+                transcript.push(format!("! {}", e));
+                return Err(transcript.join("\n"));
+            }
+        };
+
+        let (step_ok, lines) = compare(step, actual);
+        ok = ok && step_ok;
+        transcript.extend(lines);
+    }
+
+    harness.finish();
+
+    if ok {
+        Ok(())
+    } else {
+        Err(transcript.join("\n"))
+    }
+}
+
+#[test]
+fn scenarios() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios");
+    let mut failures = vec![];
+
+    let mut paths: Vec<_> = fs::read_dir(&dir)
+        .expect("no scenarios directory")
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "txt"))
+        .collect();
+
+    paths.sort();
+    assert!(!paths.is_empty(), "no scenarios in {}", dir.display());
+
+    for path in paths {
+        let source = fs::read_to_string(&path).unwrap();
+
+        if let Err(diff) = run(&source) {
+            failures.push(format!("{}:\n{}", path.display(), diff));
+        }
+    }
+
+    if !failures.is_empty() {
+        panic!("\n\n{}\n", failures.join("\n\n"));
+    }
+}
+
+#[test]
+fn failures_are_readable() {
+    let source = "\
+[reactors.echo]
+class = \"EchoReactor\"
+---
+alice in #general says: echo hi
+> I heard someone say echo hello
+~ nothing .*
+";
+
+    let diff = run(source).unwrap_err();
+
+    assert_eq!(
+        diff,
+        "  alice in #general says: echo hi
+- > I heard someone say echo hello
+- ~ nothing .*
++ > I heard someone say echo hi"
+    );
+}
+
+#[test]
+fn picking_from_nothing() {
+    let source = "\
+[reactors.echo]
+class = \"EchoReactor\"
+---
+alice in #general says: echo hi
+> I heard someone say echo hi
+
+alice in #general picks: something
+> You picked something.
+";
+
+    let started = Instant::now();
+    let diff = run(source).unwrap_err();
+
+    assert!(started.elapsed() < REPLY_TIMEOUT);
+    assert_eq!(
+        diff,
+        "  alice in #general says: echo hi
+  > I heard someone say echo hi
+  alice in #general picks: something
+! picks: but no earlier reply offered choices"
+    );
+}
+
+#[test]
+fn bad_transcripts() {
+    let cases = vec![
+        "> a reply to nothing",
+        "alice in #general says: hi\n| nothing to continue",
+        "alice in #general says: hi\n~ (unclosed",
+        "alice says hi",
+    ];
+
+    for source in cases {
+        assert!(parse(source).is_err(), "{:?}", source);
+    }
+}
@@ -0,0 +1,68 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection, OptionalExtension, NO_PARAMS};
+use serde_json::Value;
+
+// The synergy_state table is where components keep little bits of json that
+// they'd like to survive a restart. Channels and reactors live in their own
+// threads and can't share the hub's connection, so each of them opens its own
+// StateDb pointing at the same file; sqlite is fine with that.
+pub struct StateDb {
+    conn: Connection,
+}
+
+pub fn open(filename: &str) -> rusqlite::Result<StateDb> {
+    let conn = Connection::open(filename)?;
+    create_table(&conn)?;
+    Ok(StateDb { conn })
+}
+
+pub fn create_table(conn: &Connection) -> rusqlite::Result<usize> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS synergy_state (\n  \
+            reactor_name TEXT PRIMARY KEY,\n  \
+            stored_at INTEGER NOT NULL,\n  \
+            json TEXT NOT NULL\n\
+            );",
+        NO_PARAMS,
+    )
+}
+
+impl StateDb {
+    pub fn fetch(&self, name: &str) -> Option<Value> {
+        let res = self
+            .conn
+            .query_row(
+                "SELECT json FROM synergy_state WHERE reactor_name = ?",
+                params![name],
+                |row| row.get::<_, String>(0),
+            )
+            .optional();
+
+        match res {
+            Ok(Some(json)) => serde_json::from_str(&json).ok(),
+            Ok(None) => None,
+            Err(e) => {
+                warn!("error fetching state for {}: {}", name, e);
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, name: &str, value: &Value) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        let res = self.conn.execute(
+            "INSERT OR REPLACE INTO synergy_state (reactor_name, stored_at, json) \
+             VALUES (?, ?, ?)",
+            params![name, now, value.to_string()],
+        );
+
+        if let Err(e) = res {
+            warn!("error saving state for {}: {}", name, e);
+        }
+    }
+}
@@ -1,4 +1,5 @@
 #[derive(Debug, Clone)]
+#[allow(dead_code)]
 pub struct User {
     pub username: String,
     pub lp_id: Option<String>,
@@ -1,6 +1,6 @@
 use std::cell::RefCell;
 use std::collections::HashMap;
-use std::sync::{Arc, Weak};
+use std::rc::{Rc, Weak};
 
 use rusqlite::NO_PARAMS;
 
@@ -18,12 +18,20 @@ pub struct Directory {
 // identities: {
 //   channel/name: {
 //      "addr": "username"
+//   },
+//   workspace/T12345: {
+//      "addr": "username"
 //   }
 // }
+//
+// In the database, an identity_name of "slack" is for the channel called
+// channel/slack. One like "slack@T12345" is for slack workspace T12345, no
+// matter which channel it comes in on, which is how someone gets to have an
+// identity in more than one workspace.
 
 impl Directory {
-    pub fn new() -> Arc<Directory> {
-        Arc::new(Directory {
+    pub fn new() -> Rc<Directory> {
+        Rc::new(Directory {
             env: RefCell::new(Weak::new()),
             users: RefCell::new(HashMap::new()),
             identities: RefCell::new(HashMap::new()),
@@ -38,7 +46,10 @@ impl Directory {
             return;
         }
 
-        let db = &env.unwrap().db;
+        self.load_from(&env.unwrap().db);
+    }
+
+    fn load_from(&self, db: &rusqlite::Connection) {
         let mut stmt = db.prepare("SELECT * FROM users").unwrap();
 
         let iter = stmt
@@ -52,7 +63,7 @@ impl Directory {
             self.users.borrow_mut().insert(name, user);
         }
 
-        self.load_identities(&db);
+        self.load_identities(db);
     }
 
     // we pass db here to avoid having to upgrade() it again.
@@ -72,34 +83,131 @@ impl Directory {
         let mut identities = self.identities.borrow_mut();
 
         for identity in identities_iter.unwrap() {
-            let (who, channel_name, addr) = identity.unwrap();
-
-            let munged_name = format!("channel/{}", channel_name);
+            let (who, identity_name, addr) = identity.unwrap();
 
-            let for_channel = if identities.contains_key(&munged_name) {
-                identities.get_mut(&munged_name).unwrap()
-            } else {
-                let key = munged_name.clone();
-                identities.insert(munged_name, HashMap::new());
-                identities.get_mut(&key).unwrap()
+            let munged_name = match identity_name.split_once('@') {
+                Some((_, workspace)) => format!("workspace/{}", workspace),
+                None => format!("channel/{}", identity_name),
             };
 
-            for_channel.insert(addr, who);
+            identities.entry(munged_name).or_default().insert(addr, who);
         }
     }
 
+    // Workspace identities win, but if there isn't one, we'll take one for
+    // the channel. If the channel can't say who it's really from, it's from
+    // nobody we know, whatever it says.
     pub fn resolve_user(&self, event: &Event) -> Option<User> {
+        if event.is_unverified {
+            return None;
+        }
+
         let idents = self.identities.borrow();
 
-        let channel_identities = match idents.get(&event.origin) {
-            Some(i) => i,
-            None => return None,
-        };
+        let by_workspace = event
+            .workspace
+            .as_ref()
+            .and_then(|w| idents.get(&format!("workspace/{}", w)))
+            .and_then(|ids| ids.get(&event.from_address));
+
+        let username = by_workspace.or_else(|| {
+            idents
+                .get(&event.origin)
+                .and_then(|ids| ids.get(&event.from_address))
+        })?;
+
+        self.users.borrow().get(username).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::{params, Connection};
+
+    fn directory() -> Rc<Directory> {
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch(
+            "CREATE TABLE users (username TEXT PRIMARY KEY, lp_id TEXT, \
+                is_master INTEGER DEFAULT 0, is_virtual INTEGER DEFAULT 0, \
+                is_deleted INTEGER DEFAULT 0);
+             CREATE TABLE user_identities (username TEXT, identity_name TEXT, \
+                identity_value TEXT);",
+        )
+        .unwrap();
+
+        for name in &["alice", "bob"] {
+            db.execute("INSERT INTO users (username) VALUES (?)", params![name])
+                .unwrap();
+        }
 
-        let username = channel_identities.get(&event.from_address);
-        match username {
-            Some(name) => Some(self.users.borrow().get(name).unwrap().clone()),
-            None => None,
+        let identities = [
+            ("alice", "slack@T0001", "U0001"),
+            ("alice", "slack@T0002", "U9999"),
+            ("bob", "slack", "U0002"),
+            ("bob", "term", "sysop"),
+        ];
+
+        for (who, name, value) in &identities {
+            db.execute(
+                "INSERT INTO user_identities VALUES (?, ?, ?)",
+                params![who, name, value],
+            )
+            .unwrap();
         }
+
+        let dir = Directory::new();
+        dir.load_from(&db);
+        dir
+    }
+
+    fn event(origin: &str, workspace: Option<&str>, from: &str) -> Event {
+        Event {
+            is_public: true,
+            can_reply_privately: false,
+            workspace: workspace.map(String::from),
+            ..Event::new(origin, from, "C0001", "hi")
+        }
+    }
+
+    #[test]
+    fn identities_by_workspace() {
+        let dir = directory();
+
+        let cases = vec![
+            // origin, workspace, from, who
+            ("channel/slack", Some("T0001"), "U0001", Some("alice")),
+            ("channel/work", Some("T0002"), "U9999", Some("alice")),
+            ("channel/work", Some("T0002"), "U0001", None),
+            ("channel/slack", None, "U0001", None),
+            // plain channel identities still work
+            ("channel/slack", Some("T0001"), "U0002", Some("bob")),
+            ("channel/work", Some("T0002"), "U0002", None),
+            ("channel/term", None, "sysop", Some("bob")),
+        ];
+
+        for (origin, workspace, from, want) in cases {
+            let got = dir.resolve_user(&event(origin, workspace, from));
+            assert_eq!(
+                got.map(|u| u.username).as_deref(),
+                want,
+                "{} from {} in {:?}",
+                origin,
+                from,
+                workspace
+            );
+        }
+    }
+
+    #[test]
+    fn unverified_senders_are_nobody() {
+        let dir = directory();
+
+        let event = Event {
+            is_unverified: true,
+            ..event("channel/term", None, "sysop")
+        };
+
+        assert!(dir.resolve_user(&event).is_none());
     }
 }